@@ -3,7 +3,12 @@
 set -e
 
 # Download whisper.cpp models and setup Ollama
-MODEL_DIR="$HOME/.local/share/y2md/models"
+# The platform data directory y2md looks in (unless models_dir is configured)
+if [ "$(uname)" = "Darwin" ]; then
+    MODEL_DIR="$HOME/Library/Application Support/com.y2md.y2md/models"
+else
+    MODEL_DIR="${XDG_DATA_HOME:-$HOME/.local/share}/y2md/models"
+fi
 
 # Whisper models to download
 MODELS=(
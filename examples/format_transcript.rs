@@ -1,29 +1,32 @@
-use y2md::{format_transcript, clean_transcript, format_paragraphs};
+//! Print what each formatting helper makes of a raw transcript:
+//! `cargo run --example format_transcript`
+
+use y2md::{clean_transcript, format_paragraphs, format_transcript};
 
 fn main() {
-    // Test with sample transcript text
+    // A transcript as speech-to-text returns it, without punctuation
     let sample_transcript = "hello world this is a test sentence how are you doing today i hope you are doing well this is another test sentence to demonstrate the formatting capabilities of our system";
-    
+
     println!("Original transcript:");
     println!("{}", sample_transcript);
     println!();
-    
+
     println!("After clean_transcript:");
     let cleaned = clean_transcript(sample_transcript);
     println!("{}", cleaned);
     println!();
-    
+
     println!("After format_paragraphs (enhanced mode):");
     let formatted_enhanced = format_paragraphs(&cleaned, 4);
     println!("{}", formatted_enhanced);
     println!();
-    
+
     println!("After format_transcript (compact mode):");
-    let formatted_compact = format_transcript(sample_transcript, true);
+    let formatted_compact = format_transcript(sample_transcript, true, 8);
     println!("{}", formatted_compact);
     println!();
-    
+
     println!("After format_transcript (enhanced mode):");
-    let formatted_enhanced = format_transcript(sample_transcript, false);
+    let formatted_enhanced = format_transcript(sample_transcript, false, 4);
     println!("{}", formatted_enhanced);
-}
\ No newline at end of file
+}
@@ -1,13 +1,12 @@
-use crate::{AppConfig, CredentialManager, LlmProviderType, OllamaManager};
-use console::{style, Emoji};
+use crate::ui::{CHECKMARK, CROSS, INFO, RULE, WARNING};
+use crate::{
+    check_llm_provider, check_models_dir, normalize_output_dir, AppConfig, ConfigOverrides,
+    CredentialBackend, CredentialManager, HttpClient, LlmProviderType, OllamaManager,
+};
+use console::style;
 use std::path::PathBuf;
 use std::process::Command;
 
-static CHECKMARK: Emoji = Emoji("✓", "+");
-static CROSS: Emoji = Emoji("✗", "x");
-static WARNING: Emoji = Emoji("⚠", "!");
-static INFO: Emoji = Emoji("ℹ", "i");
-
 #[derive(Debug, Clone)]
 pub enum DiagnosticStatus {
     Success,
@@ -97,29 +96,31 @@ impl DiagnosticReport {
     }
 }
 
-pub async fn run_diagnostics() -> DiagnosticReport {
+pub async fn run_diagnostics(overrides: &ConfigOverrides) -> DiagnosticReport {
     let mut report = DiagnosticReport::new();
 
-    report.dependencies = check_dependencies().await;
-    report.llm_providers = check_llm_providers().await;
-    report.configuration = check_configuration().await;
+    report.dependencies = check_dependencies(overrides).await;
+    report.llm_providers = check_llm_providers(overrides).await;
+    report.configuration = check_configuration(overrides).await;
     report.system = check_system().await;
 
     report
 }
 
-async fn check_dependencies() -> Vec<Diagnostic> {
+async fn check_dependencies(overrides: &ConfigOverrides) -> Vec<Diagnostic> {
     let mut diagnostics = Vec::new();
 
-    diagnostics.push(check_ytdlp());
-    diagnostics.push(check_ffmpeg());
-    diagnostics.push(check_whisper_models());
+    diagnostics.push(check_ytdlp(overrides));
+    diagnostics.push(check_ffmpeg(overrides));
+    diagnostics.push(check_whisper_models(overrides));
 
     diagnostics
 }
 
-fn check_ytdlp() -> Diagnostic {
-    match Command::new("yt-dlp").arg("--version").output() {
+fn check_ytdlp(overrides: &ConfigOverrides) -> Diagnostic {
+    let ytdlp = overrides.read().map(|c| c.ytdlp).unwrap_or_default();
+
+    match Command::new(ytdlp.binary()).arg("--version").output() {
         Ok(output) if output.status.success() => {
             let version = String::from_utf8_lossy(&output.stdout).trim().to_string();
             Diagnostic::success("yt-dlp".to_string(), format!("v{} (installed)", version))
@@ -135,8 +136,13 @@ fn check_ytdlp() -> Diagnostic {
     }
 }
 
-fn check_ffmpeg() -> Diagnostic {
-    match Command::new("ffmpeg").arg("-version").output() {
+fn check_ffmpeg(overrides: &ConfigOverrides) -> Diagnostic {
+    let ffmpeg_path = overrides
+        .read()
+        .map(|c| c.advanced.ffmpeg_binary())
+        .unwrap_or_else(|_| "ffmpeg".to_string());
+
+    match Command::new(&ffmpeg_path).arg("-version").output() {
         Ok(output) if output.status.success() => {
             let output_str = String::from_utf8_lossy(&output.stdout);
             let version = output_str
@@ -145,68 +151,135 @@ fn check_ffmpeg() -> Diagnostic {
                 .and_then(|line| line.split_whitespace().nth(2))
                 .unwrap_or("unknown");
 
-            Diagnostic::success("FFmpeg".to_string(), format!("v{} (installed)", version))
+            if ffmpeg_path == "ffmpeg" {
+                Diagnostic::success("FFmpeg".to_string(), format!("v{} (installed)", version))
+            } else {
+                Diagnostic::success(
+                    "FFmpeg".to_string(),
+                    format!("v{} ({})", version, ffmpeg_path),
+                )
+            }
         }
         _ => {
             let install_help = get_installation_help("ffmpeg");
             Diagnostic::error(
                 "FFmpeg".to_string(),
-                "not found".to_string(),
+                format!("not found at '{}'", ffmpeg_path),
                 Some(install_help),
             )
         }
     }
 }
 
-fn check_whisper_models() -> Diagnostic {
-    let model_dir = shellexpand::tilde("~/.local/share/y2md/models/");
-    let model_path_en = format!("{}ggml-base.en.bin", model_dir);
-    let model_path_multi = format!("{}ggml-base.bin", model_dir);
+fn check_whisper_models(overrides: &ConfigOverrides) -> Diagnostic {
+    let config = overrides.read().unwrap_or_default();
+    let model_dirs = config.model_dirs();
+    if config.models_dir.is_some() {
+        if let Err(message) = check_models_dir(&model_dirs[0]) {
+            return Diagnostic::error(
+                "Whisper models".to_string(),
+                message,
+                Some(
+                    "Fix models_dir in the config, or remove it to use the default directory"
+                        .to_string(),
+                ),
+            );
+        }
+    }
+    let find = |file: &str| {
+        model_dirs
+            .iter()
+            .map(|dir| dir.join(file))
+            .find(|path| path.exists())
+    };
+    let model_path_en = find("ggml-base.en.bin");
+    let model_path_multi = find("ggml-base.bin");
 
-    let en_exists = std::path::Path::new(&model_path_en).exists();
-    let multi_exists = std::path::Path::new(&model_path_multi).exists();
+    let mut models = Vec::new();
+    if model_path_en.is_some() {
+        models.push("base.en");
+    }
+    if model_path_multi.is_some() {
+        models.push("base");
+    }
 
-    if en_exists || multi_exists {
-        let mut models = Vec::new();
-        if en_exists {
-            models.push("base.en");
-        }
-        if multi_exists {
-            models.push("base");
-        }
-        Diagnostic::success(
+    match model_path_en.or(model_path_multi) {
+        Some(path) => Diagnostic::success(
             "Whisper models".to_string(),
-            format!("{} (installed)", models.join(", ")),
-        )
-    } else {
-        Diagnostic::warning(
+            format!(
+                "{} (installed in {})",
+                models.join(", "),
+                path.parent().unwrap_or(&path).display()
+            ),
+        ),
+        None => Diagnostic::warning(
             "Whisper models".to_string(),
             "not found".to_string(),
-            Some("Run ./download_model.sh to download Whisper models".to_string()),
-        )
+            Some(format!(
+                "Run ./download_model.sh to download Whisper models into {}, or set models_dir in the config",
+                model_dirs
+                    .first()
+                    .map(|dir| dir.display().to_string())
+                    .unwrap_or_default()
+            )),
+        ),
     }
 }
 
-async fn check_llm_providers() -> Vec<Diagnostic> {
+async fn check_llm_providers(overrides: &ConfigOverrides) -> Vec<Diagnostic> {
     let mut diagnostics = Vec::new();
 
-    let config = AppConfig::load().ok();
+    let config = overrides.read().ok();
+    let cred_manager = overrides.credentials();
 
     diagnostics.push(check_ollama(&config).await);
-    diagnostics.push(check_api_key("OpenAI", &LlmProviderType::OpenAI));
-    diagnostics.push(check_api_key("Anthropic", &LlmProviderType::Anthropic));
-    diagnostics.push(check_api_key("DeepSeek", &LlmProviderType::DeepSeek));
+    diagnostics.push(check_keyring(&cred_manager));
+    for (name, provider) in [
+        ("OpenAI", LlmProviderType::OpenAI),
+        ("Anthropic", LlmProviderType::Anthropic),
+        ("DeepSeek", LlmProviderType::DeepSeek),
+    ] {
+        diagnostics.push(check_api_key(name, &provider, &cred_manager));
+    }
+    if let Some(config) = &config {
+        diagnostics.push(check_active_provider(config).await);
+    }
 
     diagnostics
 }
 
+async fn check_active_provider(config: &AppConfig) -> Diagnostic {
+    let name = format!("Active provider ({})", config.llm.provider);
+    let model = config.llm.model_for(&config.llm.provider);
+
+    let result = match HttpClient::new(config) {
+        Ok(client) => {
+            check_llm_provider(&client, &config.llm, &CredentialManager::for_config(config)).await
+        }
+        Err(e) => Err(e),
+    };
+
+    match result {
+        Ok(()) => Diagnostic::success(name, format!("reachable, model '{}' available", model)),
+        // A broken provider only matters when LLM formatting is on by default
+        Err(e) if config.llm.enabled => {
+            Diagnostic::error(name, e.to_string(), Some("y2md llm test".to_string()))
+        }
+        Err(e) => Diagnostic::warning(name, e.to_string(), Some("y2md llm test".to_string())),
+    }
+}
+
 async fn check_ollama(config: &Option<AppConfig>) -> Diagnostic {
     let endpoint = config
         .as_ref()
         .map(|c| c.llm.local.endpoint.clone())
         .unwrap_or_else(|| "http://localhost:11434".to_string());
 
-    let ollama = OllamaManager::new(Some(endpoint.clone()));
+    let client = match HttpClient::new(&config.clone().unwrap_or_default()) {
+        Ok(client) => client,
+        Err(e) => return Diagnostic::error("Ollama".to_string(), e.to_string(), None),
+    };
+    let ollama = OllamaManager::with_client(endpoint.clone(), client);
 
     if ollama.is_available().await {
         Diagnostic::success("Ollama".to_string(), format!("running at {}", endpoint))
@@ -218,9 +291,35 @@ async fn check_ollama(config: &Option<AppConfig>) -> Diagnostic {
     }
 }
 
-fn check_api_key(provider_name: &str, provider_type: &LlmProviderType) -> Diagnostic {
-    let cred_manager = CredentialManager::new();
+fn check_keyring(cred_manager: &CredentialManager) -> Diagnostic {
+    if cred_manager.backend() == CredentialBackend::File {
+        return Diagnostic::info(
+            "Credential store".to_string(),
+            "file backend (credentials.json in the config directory)".to_string(),
+        );
+    }
 
+    match cred_manager.check_keyring() {
+        Ok(()) => Diagnostic::success("Keyring".to_string(), "available".to_string()),
+        Err(e) => Diagnostic::warning(
+            "Keyring".to_string(),
+            format!(
+                "unavailable ({})",
+                e.to_string().lines().next().unwrap_or_default()
+            ),
+            Some(
+                "Set credential_backend = \"file\" in your config or use Y2MD_*_API_KEY"
+                    .to_string(),
+            ),
+        ),
+    }
+}
+
+fn check_api_key(
+    provider_name: &str,
+    provider_type: &LlmProviderType,
+    cred_manager: &CredentialManager,
+) -> Diagnostic {
     if cred_manager.has_api_key(provider_type) {
         Diagnostic::success(
             format!("{} API Key", provider_name),
@@ -231,41 +330,41 @@ fn check_api_key(provider_name: &str, provider_type: &LlmProviderType) -> Diagno
     }
 }
 
-async fn check_configuration() -> Vec<Diagnostic> {
+async fn check_configuration(overrides: &ConfigOverrides) -> Vec<Diagnostic> {
     let mut diagnostics = Vec::new();
 
-    match AppConfig::config_path() {
+    match overrides.config_path() {
         Ok(path) => {
             if path.exists() {
-                match AppConfig::load() {
+                match overrides.read() {
                     Ok(config) => {
                         diagnostics.push(Diagnostic::success(
                             "Config file".to_string(),
                             format!("{} (valid)", path.display()),
                         ));
 
-                        let output_dir = PathBuf::from(&config.output_dir);
+                        let output_dir = PathBuf::from(normalize_output_dir(&config.output_dir));
                         if output_dir.exists() {
                             if is_writable(&output_dir) {
                                 diagnostics.push(Diagnostic::success(
                                     "Output dir".to_string(),
-                                    format!("{} (writable)", config.output_dir),
+                                    format!("{} (writable)", output_dir.display()),
                                 ));
                             } else {
                                 diagnostics.push(Diagnostic::error(
                                     "Output dir".to_string(),
-                                    format!("{} (not writable)", config.output_dir),
+                                    format!("{} (not writable)", output_dir.display()),
                                     Some(format!(
                                         "Fix permissions: chmod u+w {}",
-                                        config.output_dir
+                                        output_dir.display()
                                     )),
                                 ));
                             }
                         } else {
                             diagnostics.push(Diagnostic::warning(
                                 "Output dir".to_string(),
-                                format!("{} (does not exist)", config.output_dir),
-                                Some(format!("Create it: mkdir -p {}", config.output_dir)),
+                                format!("{} (does not exist)", output_dir.display()),
+                                Some(format!("Create it: mkdir -p {}", output_dir.display())),
                             ));
                         }
                     }
@@ -424,7 +523,7 @@ pub fn print_diagnostic_report(report: &DiagnosticReport) {
 
     let _ = term.write_line("");
     let _ = term.write_line(&style("y2md System Diagnostics").bold().to_string());
-    let _ = term.write_line(&"━".repeat(60));
+    let _ = term.write_line(&RULE.to_string().repeat(60));
     let _ = term.write_line("");
 
     print_section("Required Dependencies", &report.dependencies, &term);
@@ -432,16 +531,20 @@ pub fn print_diagnostic_report(report: &DiagnosticReport) {
     print_section("Configuration", &report.configuration, &term);
     print_section("System", &report.system, &term);
 
-    let _ = term.write_line(&"━".repeat(60));
+    let _ = term.write_line(&RULE.to_string().repeat(60));
 
     let status_text = if report.has_errors() {
-        style("Overall Status: ✗ Issues found").red().bold()
+        style(format!("Overall Status: {} Issues found", CROSS))
+            .red()
+            .bold()
     } else if report.has_warnings() {
-        style("Overall Status: ⚠ Ready with warnings")
+        style(format!("Overall Status: {} Ready with warnings", WARNING))
             .yellow()
             .bold()
     } else {
-        style("Overall Status: ✓ All systems ready").green().bold()
+        style(format!("Overall Status: {} All systems ready", CHECKMARK))
+            .green()
+            .bold()
     };
 
     let _ = term.write_line(&status_text.to_string());
@@ -1,18 +1,182 @@
-use indicatif::{ProgressBar, ProgressStyle};
+use futures::StreamExt;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::path::PathBuf;
-use std::process::Command;
 use thiserror::Error;
+use tokio::process::Command;
 use url::form_urlencoded;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct VideoMetadata {
     pub title: String,
     pub channel: Option<String>,
     pub duration: Option<String>,
+    /// Video length in whole seconds
+    #[serde(default)]
+    pub duration_seconds: Option<u64>,
     pub video_id: String,
     pub url: String,
+    /// Upload date as YYYY-MM-DD
+    #[serde(default)]
+    pub upload_date: Option<String>,
+    /// Whether any format has an audio stream; `None` when yt-dlp listed no formats
+    #[serde(default)]
+    pub has_audio: Option<bool>,
+    /// Spoken language declared by the uploader, as an ISO 639-1 code
+    #[serde(default)]
+    pub language: Option<String>,
+    /// Video description as written by the uploader
+    #[serde(default)]
+    pub description: Option<String>,
+    /// Chapters from YouTube, or from timestamps in the description when the
+    /// video has none
+    #[serde(default)]
+    pub chapters: Vec<Chapter>,
+}
+
+/// A titled section of a video
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Chapter {
+    /// Start of the chapter, in seconds
+    pub start: f64,
+    pub title: String,
+}
+
+impl Chapter {
+    /// Start time as `MM:SS`, or `HH:MM:SS` for long videos
+    pub fn timestamp(&self) -> String {
+        format_duration(self.start)
+    }
+}
+
+/// A piece of transcript text with its position in the video, in seconds
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TranscriptSegment {
+    pub start: f64,
+    pub end: f64,
+    pub text: String,
+    /// Zero-based speaker index, when diarization is available
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub speaker: Option<u32>,
+    /// Mean log probability of the segment's tokens as reported by
+    /// speech-to-text (closer to 0 is more confident); absent for captions
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub avg_logprob: Option<f32>,
+}
+
+/// Progress events emitted by the transcription pipeline
+#[derive(Debug, Clone, PartialEq)]
+pub enum PipelineEvent {
+    MetadataFetched(VideoMetadata),
+    DownloadStarted,
+    /// Download progress in percent (0-100)
+    DownloadProgress(f32),
+    DownloadFinished,
+    ConversionStarted,
+    ConversionFinished,
+    TranscribeStarted,
+    /// Transcription progress in percent (0-100)
+    TranscribeProgress(f32),
+    TranscribeFinished,
+    /// Whisper detected the spoken language (`--lang auto`)
+    LanguageDetected(LanguageDetection),
+    LlmStarted(LlmProviderType),
+    LlmFinished,
+    Done,
+}
+
+/// Language picked by Whisper auto-detection, with its probability (0-1)
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct LanguageDetection {
+    pub language: String,
+    pub probability: f32,
+}
+
+/// Callback receiving pipeline progress events
+pub type EventHandler = std::sync::Arc<dyn Fn(PipelineEvent) + Send + Sync>;
+
+/// Send an event to the handler, if one was given
+fn emit(on_event: Option<&EventHandler>, event: PipelineEvent) {
+    if let Some(handler) = on_event {
+        handler(event);
+    }
+}
+
+/// Format of the main output document
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum OutputFormat {
+    /// Markdown with YAML front matter
+    #[default]
+    Markdown,
+    /// Plain paragraphs with no markup or front matter
+    Txt,
+}
+
+impl OutputFormat {
+    /// File extension for this format
+    pub fn extension(&self) -> &'static str {
+        match self {
+            OutputFormat::Markdown => "md",
+            OutputFormat::Txt => "txt",
+        }
+    }
+}
+
+impl std::fmt::Display for OutputFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OutputFormat::Markdown => write!(f, "md"),
+            OutputFormat::Txt => write!(f, "txt"),
+        }
+    }
+}
+
+impl std::str::FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "md" | "markdown" => Ok(OutputFormat::Markdown),
+            "txt" | "text" => Ok(OutputFormat::Txt),
+            _ => Err(format!("Unknown output format: {} (expected md or txt)", s)),
+        }
+    }
+}
+
+/// File format used when saving the raw transcript
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum RawFormat {
+    #[default]
+    Txt,
+    Srt,
+    Vtt,
+}
+
+impl RawFormat {
+    /// File extension for this format
+    pub fn extension(&self) -> &'static str {
+        match self {
+            RawFormat::Txt => "txt",
+            RawFormat::Srt => "srt",
+            RawFormat::Vtt => "vtt",
+        }
+    }
+}
+
+impl std::str::FromStr for RawFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "txt" => Ok(RawFormat::Txt),
+            "srt" => Ok(RawFormat::Srt),
+            "vtt" => Ok(RawFormat::Vtt),
+            _ => Err(format!(
+                "Unknown raw format: {} (expected txt, srt or vtt)",
+                s
+            )),
+        }
+    }
 }
 
 #[derive(Error, Debug)]
@@ -33,8 +197,76 @@ pub enum Y2mdError {
     Llm(String),
     #[error("yt-dlp not found\n\n{}", get_installation_help("yt-dlp"))]
     YtDlpNotFound,
-    #[error("FFmpeg not found\n\n{}", get_installation_help("ffmpeg"))]
-    FFmpegNotFound,
+    #[error("FFmpeg not found at '{0}'\n\n{}", get_installation_help("ffmpeg"))]
+    FFmpegNotFound(String),
+    #[error(
+        "Video is private: {0}\n\nIf you have access, pass your browser cookies with --cookies"
+    )]
+    PrivateVideo(String),
+    #[error("Video requires sign-in (age-restricted): {0}\n\nPass cookies from a signed-in browser session with --cookies")]
+    AgeRestricted(String),
+    #[error("Video is members-only: {0}\n\nPass cookies from an account with channel membership with --cookies")]
+    MembersOnly(String),
+    #[error("Video is unavailable: {0}")]
+    VideoUnavailable(String),
+    #[error("yt-dlp failed: {0}")]
+    YtDlp(String),
+    #[error("Skipped: {0}")]
+    Skipped(String),
+    #[error("Timed out: {0}")]
+    Timeout(String),
+    #[error("Transcript is empty: {0}")]
+    EmptyTranscript(String),
+    #[error("Video has no usable audio: {0}")]
+    NoAudio(String),
+    #[error("System keyring unavailable: {0}\n\n{}", keyring_help())]
+    Keyring(String),
+}
+
+/// Exit code for failures without a more specific code
+pub const EXIT_FAILURE: u8 = 1;
+/// Exit code when yt-dlp or FFmpeg is missing
+pub const EXIT_MISSING_DEPENDENCY: u8 = 2;
+/// Exit code when the video is private, removed or otherwise out of reach
+pub const EXIT_VIDEO_UNAVAILABLE: u8 = 3;
+/// Exit code for LLM, configuration and command-line errors
+pub const EXIT_CONFIG: u8 = 4;
+/// Exit code for network failures and timeouts
+pub const EXIT_NETWORK: u8 = 5;
+
+impl Y2mdError {
+    /// Process exit code for this error, so scripts can tell failures apart
+    /// without parsing messages
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            Y2mdError::YtDlpNotFound | Y2mdError::FFmpegNotFound(_) => EXIT_MISSING_DEPENDENCY,
+            Y2mdError::PrivateVideo(_)
+            | Y2mdError::AgeRestricted(_)
+            | Y2mdError::MembersOnly(_)
+            | Y2mdError::VideoUnavailable(_) => EXIT_VIDEO_UNAVAILABLE,
+            Y2mdError::Config(_) | Y2mdError::Llm(_) | Y2mdError::Keyring(_) => EXIT_CONFIG,
+            Y2mdError::Network(_) | Y2mdError::Timeout(_) => EXIT_NETWORK,
+            Y2mdError::YtDlp(message) if is_transient_ytdlp_error(message) => EXIT_NETWORK,
+            _ => EXIT_FAILURE,
+        }
+    }
+}
+
+fn keyring_help() -> String {
+    let service_hint = match std::env::consts::OS {
+        "linux" => "Install and unlock a Secret Service provider (gnome-keyring or KWallet)",
+        "macos" => "Unlock your login keychain (Keychain Access)",
+        _ => "Make sure the system credential store is available",
+    };
+
+    format!(
+        "To continue, either:\n  \
+         - {}\n  \
+         - Export the key instead: export Y2MD_<PROVIDER>_API_KEY='your-api-key-here'\n  \
+         - Store keys in a file: set credential_backend = \"file\" in your config\n\n\
+         Check the keyring with: y2md doctor",
+        service_hint
+    )
 }
 
 fn get_installation_help(tool: &str) -> String {
@@ -78,6 +310,79 @@ fn get_installation_help(tool: &str) -> String {
     }
 }
 
+/// Map yt-dlp's stderr output to a specific error so users can tell an
+/// access problem apart from a network failure
+fn classify_ytdlp_error(stderr: &str) -> Y2mdError {
+    // yt-dlp prefixes fatal problems with "ERROR:"; fall back to the last line
+    let message = stderr
+        .lines()
+        .rev()
+        .find(|line| line.starts_with("ERROR:"))
+        .or_else(|| stderr.lines().rev().find(|line| !line.trim().is_empty()))
+        .unwrap_or("unknown error")
+        .trim_start_matches("ERROR:")
+        .trim()
+        .to_string();
+
+    let lower = message.to_lowercase();
+
+    if lower.contains("private video") {
+        Y2mdError::PrivateVideo(message)
+    } else if lower.contains("members-only")
+        || lower.contains("members only")
+        || lower.contains("join this channel")
+    {
+        Y2mdError::MembersOnly(message)
+    } else if lower.contains("sign in to confirm your age")
+        || lower.contains("age-restricted")
+        || lower.contains("inappropriate for some users")
+    {
+        Y2mdError::AgeRestricted(message)
+    } else if lower.contains("video unavailable")
+        || lower.contains("has been removed")
+        || lower.contains("account associated with this video has been terminated")
+        || lower.contains("no longer available")
+    {
+        Y2mdError::VideoUnavailable(message)
+    } else {
+        Y2mdError::YtDlp(message)
+    }
+}
+
+/// Fragments of yt-dlp errors caused by throttling or a flaky connection
+const TRANSIENT_YTDLP_ERRORS: &[&str] = &[
+    "http error 403",
+    "http error 429",
+    "http error 5",
+    "too many requests",
+    "timed out",
+    "connection reset",
+    "connection refused",
+    "remote end closed connection",
+    "temporary failure in name resolution",
+    "unable to download webpage",
+    "incomplete read",
+];
+
+/// Whether a failed yt-dlp run is worth retrying. Videos that are private,
+/// removed or otherwise out of reach never are.
+fn is_transient_ytdlp_error(stderr: &str) -> bool {
+    match classify_ytdlp_error(stderr) {
+        Y2mdError::YtDlp(message) => {
+            let lower = message.to_lowercase();
+            TRANSIENT_YTDLP_ERRORS
+                .iter()
+                .any(|fragment| lower.contains(fragment))
+        }
+        _ => false,
+    }
+}
+
+/// Delay before the given retry (0-based): 1s, 2s, 4s, ... capped at 32s
+fn retry_delay(retry: u32) -> std::time::Duration {
+    std::time::Duration::from_secs(1 << retry.min(5))
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 #[serde(rename_all = "lowercase")]
 pub enum LlmProviderType {
@@ -155,6 +460,13 @@ impl Default for OpenAiConfig {
 pub struct AnthropicConfig {
     pub endpoint: String,
     pub model: String,
+    /// Upper bound on the length of the formatted output, in tokens
+    #[serde(default = "default_anthropic_max_tokens")]
+    pub max_tokens: u32,
+}
+
+fn default_anthropic_max_tokens() -> u32 {
+    4096
 }
 
 impl Default for AnthropicConfig {
@@ -162,6 +474,7 @@ impl Default for AnthropicConfig {
         AnthropicConfig {
             endpoint: "https://api.anthropic.com/v1".to_string(),
             model: "claude-3-sonnet-20240229".to_string(),
+            max_tokens: default_anthropic_max_tokens(),
         }
     }
 }
@@ -207,6 +520,30 @@ pub struct LlmSettings {
     pub custom: CustomLlmConfig,
 }
 
+impl LlmSettings {
+    /// Get the configured model name for a provider
+    pub fn model_for(&self, provider: &LlmProviderType) -> &str {
+        match provider {
+            LlmProviderType::Local => &self.local.model,
+            LlmProviderType::OpenAI => &self.openai.model,
+            LlmProviderType::Anthropic => &self.anthropic.model,
+            LlmProviderType::DeepSeek => &self.deepseek.model,
+            LlmProviderType::Custom => &self.custom.model,
+        }
+    }
+
+    /// Replace the configured model name for a provider
+    pub fn set_model_for(&mut self, provider: &LlmProviderType, model: String) {
+        match provider {
+            LlmProviderType::Local => self.local.model = model,
+            LlmProviderType::OpenAI => self.openai.model = model,
+            LlmProviderType::Anthropic => self.anthropic.model = model,
+            LlmProviderType::DeepSeek => self.deepseek.model = model,
+            LlmProviderType::Custom => self.custom.model = model,
+        }
+    }
+}
+
 impl Default for LlmSettings {
     fn default() -> Self {
         LlmSettings {
@@ -226,6 +563,8 @@ pub struct AdvancedSettings {
     pub whisper_model: String,
     pub whisper_threads: usize,
     pub cache_audio: bool,
+    #[serde(default)]
+    pub ffmpeg_path: Option<String>,
 }
 
 impl Default for AdvancedSettings {
@@ -234,1691 +573,9145 @@ impl Default for AdvancedSettings {
             whisper_model: "base".to_string(),
             whisper_threads: 4,
             cache_audio: true,
+            ffmpeg_path: None,
         }
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct AppConfig {
-    pub output_dir: String,
-    pub default_language: String,
-    pub prefer_captions: bool,
-    pub timestamps: bool,
-    pub compact: bool,
-    pub paragraph_length: usize,
-    pub llm: LlmSettings,
-    pub advanced: AdvancedSettings,
-}
-
-impl Default for AppConfig {
-    fn default() -> Self {
-        AppConfig {
-            output_dir: ".".to_string(),
-            default_language: "en".to_string(),
-            prefer_captions: true,
-            timestamps: false,
-            compact: false,
-            paragraph_length: 4,
-            llm: LlmSettings::default(),
-            advanced: AdvancedSettings::default(),
+impl AdvancedSettings {
+    /// Resolve the ffmpeg executable to invoke
+    pub fn ffmpeg_binary(&self) -> String {
+        match &self.ffmpeg_path {
+            Some(path) if !path.trim().is_empty() => shellexpand::tilde(path).to_string(),
+            _ => "ffmpeg".to_string(),
         }
     }
 }
 
-impl AppConfig {
-    pub fn load() -> Result<Self, Y2mdError> {
-        let config_dir = directories::ProjectDirs::from("com", "y2md", "y2md")
-            .ok_or_else(|| Y2mdError::Config("Could not determine config directory".to_string()))?;
+/// Retries after a transient yt-dlp failure unless configured otherwise
+const DEFAULT_YTDLP_RETRIES: u32 = 2;
+
+/// Options forwarded to every yt-dlp invocation
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct YtDlpConfig {
+    /// Path to the yt-dlp executable (defaults to `yt-dlp` on PATH)
+    pub path: Option<String>,
+    /// Extra arguments appended to every invocation (e.g. `--limit-rate`)
+    pub extra_args: Vec<String>,
+    /// Netscape-format cookies file passed as `--cookies`
+    pub cookies: Option<String>,
+    /// Browser to read cookies from, passed as `--cookies-from-browser`
+    pub cookies_from_browser: Option<String>,
+    /// Audio format for speech-to-text downloads (`--audio-format`, default `best`)
+    pub audio_format: Option<String>,
+    /// Audio quality for speech-to-text downloads (`--audio-quality`: 0-10 or a
+    /// bitrate such as `64K`, default `0`)
+    pub audio_quality: Option<String>,
+    /// Proxy passed as `--proxy`; set from the top-level `proxy` setting
+    /// (see [`AppConfig::ytdlp_config`])
+    #[serde(skip)]
+    pub proxy: Option<String>,
+    /// Only download the first this many seconds of audio (`--sample`)
+    #[serde(skip)]
+    pub sample_seconds: Option<u64>,
+    /// Directory for cached video metadata, instead of the platform cache
+    /// directory
+    #[serde(skip)]
+    pub cache_dir: Option<PathBuf>,
+    /// Times to retry after a throttled or failed network request (default 2)
+    pub retries: Option<u32>,
+}
 
-        let config_path = config_dir.config_dir().join("config.toml");
+impl YtDlpConfig {
+    /// Retries after a transient failure, see [`YtDlpConfig::retries`]
+    fn retry_count(&self) -> u32 {
+        self.retries.unwrap_or(DEFAULT_YTDLP_RETRIES)
+    }
 
-        if !config_path.exists() {
-            return Ok(AppConfig::default());
+    /// Resolve the yt-dlp executable to invoke
+    pub fn binary(&self) -> String {
+        match &self.path {
+            Some(path) if !path.trim().is_empty() => shellexpand::tilde(path).to_string(),
+            _ => "yt-dlp".to_string(),
         }
-
-        let config_content = std::fs::read_to_string(&config_path)
-            .map_err(|e| Y2mdError::Config(format!("Failed to read config file: {}", e)))?;
-
-        toml::from_str::<AppConfig>(&config_content).map_err(|e| {
-            Y2mdError::Config(format!(
-                "Failed to parse config: {}\n\nPlease check your config file at: {}",
-                e,
-                config_path.display()
-            ))
-        })
     }
 
-    pub fn save(&self) -> Result<(), Y2mdError> {
-        let config_dir = directories::ProjectDirs::from("com", "y2md", "y2md")
-            .ok_or_else(|| Y2mdError::Config("Could not determine config directory".to_string()))?;
+    /// Build the authentication arguments for yt-dlp
+    fn cookie_args(&self) -> Vec<String> {
+        let mut args = Vec::new();
 
-        std::fs::create_dir_all(config_dir.config_dir())
-            .map_err(|e| Y2mdError::Config(format!("Failed to create config directory: {}", e)))?;
+        if let Some(cookies) = &self.cookies {
+            args.push("--cookies".to_string());
+            args.push(shellexpand::tilde(cookies).to_string());
+        }
 
-        let config_path = config_dir.config_dir().join("config.toml");
+        if let Some(browser) = &self.cookies_from_browser {
+            args.push("--cookies-from-browser".to_string());
+            args.push(browser.clone());
+        }
 
-        let header = r#"# =============================================================================
-# Y2MD Configuration
-# Edit this file directly or use: y2md config edit
-# =============================================================================
+        if let Some(proxy) = &self.proxy {
+            args.push("--proxy".to_string());
+            args.push(proxy.clone());
+        }
 
-"#;
+        args
+    }
 
-        let config_toml = toml::to_string_pretty(self)
-            .map_err(|e| Y2mdError::Config(format!("Failed to serialize configuration: {}", e)))?;
+    /// Audio format and quality arguments for `download_audio`
+    fn audio_args(&self) -> Vec<String> {
+        vec![
+            "--audio-format".to_string(),
+            self.audio_format
+                .clone()
+                .unwrap_or_else(|| "best".to_string()),
+            "--audio-quality".to_string(),
+            self.audio_quality
+                .clone()
+                .unwrap_or_else(|| "0".to_string()),
+        ]
+        .into_iter()
+        .chain(self.section_args())
+        .collect()
+    }
 
-        std::fs::write(&config_path, format!("{}{}", header, config_toml))
-            .map_err(|e| Y2mdError::Config(format!("Failed to write configuration file: {}", e)))?;
+    /// `--download-sections` arguments limiting the download to the sample
+    fn section_args(&self) -> Vec<String> {
+        match self.sample_seconds {
+            Some(seconds) => vec!["--download-sections".to_string(), format!("*0-{}", seconds)],
+            None => Vec::new(),
+        }
+    }
 
-        Ok(())
+    /// File name stem of the downloaded audio, kept apart for samples so a
+    /// sample is never reused as the full recording
+    fn audio_file_stem(&self, video_id: &str) -> String {
+        match self.sample_seconds {
+            Some(seconds) => format!("{}_sample{}_audio", video_id, seconds),
+            None => format!("{}_audio", video_id),
+        }
     }
+}
 
-    pub fn config_path() -> Result<PathBuf, Y2mdError> {
-        let config_dir = directories::ProjectDirs::from("com", "y2md", "y2md")
-            .ok_or_else(|| Y2mdError::Config("Could not determine config directory".to_string()))?;
+/// Audio formats yt-dlp can extract to
+const AUDIO_FORMATS: &[&str] = &[
+    "best", "aac", "alac", "flac", "m4a", "mp3", "opus", "vorbis", "wav",
+];
 
-        Ok(config_dir.config_dir().join("config.toml"))
+/// Parse an `--audio-format` value
+pub fn parse_audio_format(input: &str) -> Result<String, String> {
+    let format = input.trim().to_lowercase();
+    if AUDIO_FORMATS.contains(&format.as_str()) {
+        Ok(format)
+    } else {
+        Err(format!(
+            "unsupported audio format '{}' (expected one of: {})",
+            input,
+            AUDIO_FORMATS.join(", ")
+        ))
     }
 }
 
-pub struct CredentialManager {
-    service_name: String,
+/// Parse an `--audio-quality` value: a VBR level from 0 (best) to 10, or a bitrate like `64K`
+pub fn parse_audio_quality(input: &str) -> Result<String, String> {
+    let quality = input.trim().to_uppercase();
+    let valid = match quality.strip_suffix('K') {
+        Some(bitrate) => bitrate.parse::<u32>().is_ok_and(|kbps| kbps > 0),
+        None => quality.parse::<u8>().is_ok_and(|level| level <= 10),
+    };
+    if valid {
+        Ok(quality)
+    } else {
+        Err(format!(
+            "invalid audio quality '{}' (expected 0-10 or a bitrate such as 64K)",
+            input
+        ))
+    }
 }
 
-impl CredentialManager {
-    pub fn new() -> Self {
-        Self {
-            service_name: "y2md".to_string(),
-        }
-    }
+/// Build the base yt-dlp command with the configured binary, cookies, proxy and extra arguments.
+/// The process is killed if its handle is dropped, e.g. when a timeout fires.
+pub fn ytdlp_command(ytdlp: &YtDlpConfig) -> Command {
+    let mut command = Command::new(ytdlp.binary());
+    command
+        .args(ytdlp.cookie_args())
+        .args(&ytdlp.extra_args)
+        .kill_on_drop(true);
+    command
+}
 
-    pub fn get_api_key(
-        &self,
-        provider_type: &LlmProviderType,
-    ) -> Result<Option<String>, Y2mdError> {
-        let provider_name = provider_type.to_string();
-        let env_var_name = format!("Y2MD_{}_API_KEY", provider_name.to_uppercase());
+/// Longest a metadata lookup may take before yt-dlp is killed
+const METADATA_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(2 * 60);
 
-        // First, check Y2MD_<PROVIDER>_API_KEY
-        if let Ok(key) = std::env::var(&env_var_name) {
-            return Ok(Some(key));
-        }
+/// Longest a caption download may take before yt-dlp is killed
+const CAPTIONS_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5 * 60);
 
-        // Then check <PROVIDER>_API_KEY as fallback (for common provider names)
-        let fallback_env_var = format!("{}_API_KEY", provider_name.to_uppercase());
-        if let Ok(key) = std::env::var(&fallback_env_var) {
-            return Ok(Some(key));
-        }
+/// Longest a download or conversion may go without reporting progress before
+/// it is killed; long steps are fine as long as they keep moving
+const STALL_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5 * 60);
 
-        let entry = keyring::Entry::new(&self.service_name, &provider_name)
-            .map_err(|e| Y2mdError::Config(format!("Failed to access keyring: {}", e)))?;
+/// Pass each stdout line of `child` to `on_line` until it closes, killing the
+/// child if no line arrives within [`STALL_TIMEOUT`]
+async fn read_progress_lines(
+    child: &mut tokio::process::Child,
+    step: &str,
+    mut on_line: impl FnMut(&str),
+) -> Result<(), Y2mdError> {
+    use tokio::io::{AsyncBufReadExt, BufReader};
 
-        match entry.get_password() {
-            Ok(password) => Ok(Some(password)),
-            Err(keyring::Error::NoEntry) => Ok(None),
-            Err(e) => Err(Y2mdError::Config(format!(
-                "Failed to retrieve API key from keyring: {}",
-                e
-            ))),
+    let Some(stdout) = child.stdout.take() else {
+        return Ok(());
+    };
+    let mut lines = BufReader::new(stdout).lines();
+    loop {
+        match tokio::time::timeout(STALL_TIMEOUT, lines.next_line()).await {
+            Ok(Ok(Some(line))) => on_line(&line),
+            Ok(_) => return Ok(()),
+            Err(_) => {
+                let _ = child.kill().await;
+                return Err(Y2mdError::Timeout(format!(
+                    "{} made no progress for {} seconds",
+                    step,
+                    STALL_TIMEOUT.as_secs()
+                )));
+            }
         }
     }
+}
 
-    pub fn set_api_key(
-        &self,
-        provider_type: &LlmProviderType,
-        api_key: &str,
-    ) -> Result<(), Y2mdError> {
-        let provider_name = provider_type.to_string();
-
-        // Try keyring first
-        match keyring::Entry::new(&self.service_name, &provider_name) {
-            Ok(entry) => {
-                if let Err(e) = entry.set_password(api_key) {
-                    // Keyring failed, suggest environment variable
-                    let env_var_name = format!("Y2MD_{}_API_KEY", provider_name.to_uppercase());
-                    return Err(Y2mdError::Config(format!(
-                        "Failed to store API key in keyring: {}\n\nTo use environment variable instead, run:\n  export {}='your-api-key-here'",
-                        e, env_var_name
-                    )));
-                }
-                Ok(())
-            }
-            Err(e) => {
-                // Keyring not available, suggest environment variable
-                let env_var_name = format!("Y2MD_{}_API_KEY", provider_name.to_uppercase());
-                Err(Y2mdError::Config(format!(
-                    "Keyring not available: {}\n\nTo use environment variable instead, run:\n  export {}='your-api-key-here'",
-                    e, env_var_name
-                )))
+/// Run a yt-dlp command to completion, killing it if it takes longer than `timeout`
+async fn ytdlp_output(
+    command: &mut Command,
+    timeout: std::time::Duration,
+    step: &str,
+) -> Result<std::process::Output, Y2mdError> {
+    match tokio::time::timeout(timeout, command.output()).await {
+        Ok(output) => output.map_err(|e| {
+            if e.kind() == std::io::ErrorKind::NotFound {
+                Y2mdError::YtDlpNotFound
+            } else {
+                Y2mdError::Io(e)
             }
-        }
+        }),
+        Err(_) => Err(Y2mdError::Timeout(format!(
+            "{} took longer than {} seconds",
+            step,
+            timeout.as_secs()
+        ))),
     }
+}
 
-    pub fn delete_api_key(&self, provider_type: &LlmProviderType) -> Result<(), Y2mdError> {
-        let provider_name = provider_type.to_string();
-        let entry = keyring::Entry::new(&self.service_name, &provider_name)
-            .map_err(|e| Y2mdError::Config(format!("Failed to access keyring: {}", e)))?;
-
-        match entry.delete_password() {
-            Ok(()) => Ok(()),
-            Err(keyring::Error::NoEntry) => Ok(()),
-            Err(e) => Err(Y2mdError::Config(format!(
-                "Failed to delete API key from keyring: {}",
-                e
-            ))),
+/// Run a yt-dlp command like [`ytdlp_output`], retrying with exponential
+/// backoff while it fails with a transient error
+async fn ytdlp_output_with_retry(
+    ytdlp: &YtDlpConfig,
+    mut command: Command,
+    timeout: std::time::Duration,
+    step: &str,
+) -> Result<std::process::Output, Y2mdError> {
+    let retries = ytdlp.retry_count();
+    let mut retry = 0;
+    loop {
+        let output = ytdlp_output(&mut command, timeout, step).await?;
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        if output.status.success() || retry >= retries || !is_transient_ytdlp_error(&stderr) {
+            return Ok(output);
         }
+        wait_before_retry(step, &stderr, retry, retries).await;
+        retry += 1;
     }
+}
 
-    pub fn has_api_key(&self, provider_type: &LlmProviderType) -> bool {
-        self.get_api_key(provider_type).ok().flatten().is_some()
-    }
+/// Report a transient yt-dlp failure and sleep before the next attempt
+async fn wait_before_retry(step: &str, stderr: &str, retry: u32, retries: u32) {
+    let delay = retry_delay(retry);
+    println!(
+        "{} failed ({}), retrying in {}s ({}/{})",
+        step,
+        classify_ytdlp_error(stderr),
+        delay.as_secs(),
+        retry + 1,
+        retries
+    );
+    tokio::time::sleep(delay).await;
 }
 
-pub fn extract_video_id(url: &str) -> Result<String, Y2mdError> {
-    let url = url.trim();
+/// Speech-to-text engine used when captions are not available
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum SttBackend {
+    #[default]
+    Local,
+    OpenAI,
+    Deepgram,
+}
 
-    // Handle youtu.be short URLs
-    if url.contains("youtu.be/") {
-        if let Some(id) = url.split("youtu.be/").nth(1) {
-            return Ok(id.split('?').next().unwrap_or(id).to_string());
+impl std::fmt::Display for SttBackend {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SttBackend::Local => write!(f, "local"),
+            SttBackend::OpenAI => write!(f, "openai"),
+            SttBackend::Deepgram => write!(f, "deepgram"),
         }
     }
+}
 
-    // Handle youtube.com URLs
-    if url.contains("youtube.com") {
-        let parsed_url =
-            reqwest::Url::parse(url).map_err(|_| Y2mdError::InvalidUrl(url.to_string()))?;
-
-        // Handle /watch?v= format
-        if let Some(query) = parsed_url.query() {
-            let params: HashMap<_, _> = form_urlencoded::parse(query.as_bytes()).collect();
-            if let Some(v) = params.get("v") {
-                return Ok(v.to_string());
-            }
-        }
+impl std::str::FromStr for SttBackend {
+    type Err = String;
 
-        // Handle /shorts/ format
-        if let Some(segments) = parsed_url.path_segments() {
-            let segments: Vec<_> = segments.collect();
-            if segments.len() == 2 && segments[0] == "shorts" {
-                return Ok(segments[1].to_string());
-            }
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "local" | "whisper" => Ok(SttBackend::Local),
+            "openai" => Ok(SttBackend::OpenAI),
+            "deepgram" => Ok(SttBackend::Deepgram),
+            _ => Err(format!("Unknown STT backend: {}", s)),
         }
     }
+}
 
-    // Handle direct video ID (11 characters, alphanumeric + underscore)
-    if url.len() == 11
-        && url
-            .chars()
-            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
-    {
-        return Ok(url.to_string());
-    }
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OpenAiSttConfig {
+    pub endpoint: String,
+    pub model: String,
+}
 
-    Err(Y2mdError::VideoIdExtraction)
+impl Default for OpenAiSttConfig {
+    fn default() -> Self {
+        OpenAiSttConfig {
+            endpoint: "https://api.openai.com/v1".to_string(),
+            model: "whisper-1".to_string(),
+        }
+    }
 }
 
-/// Validate YouTube URL format
-pub fn validate_youtube_url(url: &str) -> Result<String, Y2mdError> {
-    let video_id = extract_video_id(url)?;
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeepgramSttConfig {
+    pub endpoint: String,
+    pub model: String,
+    /// Ask for speaker labels (`--diarize`); Deepgram bills them as an add-on
+    #[serde(skip)]
+    pub diarize: bool,
+}
 
-    // YouTube video IDs are typically 11 characters
-    if video_id.len() != 11 {
-        return Err(Y2mdError::InvalidUrl(format!(
-            "Invalid video ID length: {}",
-            video_id
-        )));
+impl Default for DeepgramSttConfig {
+    fn default() -> Self {
+        DeepgramSttConfig {
+            endpoint: "https://api.deepgram.com/v1".to_string(),
+            model: "nova-2".to_string(),
+            diarize: false,
+        }
     }
-
-    Ok(video_id)
 }
 
-/// Fetch video metadata from YouTube
-pub async fn fetch_video_metadata(video_id: &str) -> Result<VideoMetadata, Y2mdError> {
-    let url = format!("https://www.youtube.com/watch?v={}", video_id);
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SttSettings {
+    pub backend: SttBackend,
+    pub openai: OpenAiSttConfig,
+    pub deepgram: DeepgramSttConfig,
+}
 
-    // Use yt-dlp to get video metadata
-    let output = Command::new("yt-dlp")
-        .args(["--dump-json", "--no-download", &url])
-        .output()
-        .map_err(|e| {
-            if e.kind() == std::io::ErrorKind::NotFound {
-                Y2mdError::YtDlpNotFound
-            } else {
-                Y2mdError::Io(e)
-            }
-        })?;
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AppConfig {
+    /// Layout version of the file, see [`migrate_config`]
+    #[serde(default = "default_config_version")]
+    pub version: u32,
+    pub output_dir: String,
+    pub default_language: String,
+    pub prefer_captions: bool,
+    pub timestamps: bool,
+    pub compact: bool,
+    pub paragraph_length: ParagraphLength,
+    /// Paragraph length used instead of `paragraph_length` in compact mode
+    #[serde(default = "default_compact_paragraph_length")]
+    pub compact_paragraph_length: ParagraphLength,
+    #[serde(default)]
+    pub preserve_caption_paragraphs: bool,
+    /// What to do when captions exist, but not in the requested language
+    #[serde(default)]
+    pub caption_lang_fallback: CaptionLangFallback,
+    /// Capitalize "I" and `proper_nouns` in lowercase auto captions
+    #[serde(default)]
+    pub fix_capitalization: bool,
+    /// Names to restore in auto captions, e.g. `["Rust", "New York"]`
+    #[serde(default)]
+    pub proper_nouns: Vec<String>,
+    /// Whole-word substitutions applied to every transcript, e.g.
+    /// `"cube on eddies" = "Kubernetes"` (see [`apply_replacements`])
+    #[serde(default)]
+    pub replacements: HashMap<String, String>,
+    /// Words, phrases and `/regex/` patterns replaced with `[redacted]` before
+    /// the LLM call and before writing (see [`Redactor`])
+    #[serde(default)]
+    pub redact: Vec<String>,
+    /// Also redact email addresses and phone numbers
+    #[serde(default)]
+    pub redact_pii: bool,
+    /// Collapse a sentence speech-to-text repeats more than this many times in
+    /// a row to one occurrence (see [`collapse_repeated_sentences`]); 0 turns it off
+    #[serde(default = "default_max_repeated_sentences")]
+    pub max_repeated_sentences: usize,
+    #[serde(default)]
+    pub append_metadata_footer: bool,
+    /// Hard-wrap the output at this column (see [`wrap_markdown`]); off when unset
+    #[serde(default)]
+    pub line_wrap: Option<usize>,
+    /// Directory holding the Whisper models, instead of the platform data directory
+    #[serde(default)]
+    pub models_dir: Option<String>,
+    /// strftime-style format for `extracted_at` in the front matter, e.g.
+    /// `%Y-%m-%d %H:%M`; RFC 3339 when unset
+    #[serde(default)]
+    pub date_format: Option<String>,
+    /// Timezone for `extracted_at`, the footer date and `{date}` in filenames:
+    /// "utc", "local" or a name such as "Europe/Berlin"
+    #[serde(default)]
+    pub timezone: Timezone,
+    /// Load API keys from a `.env` file in the current or config directory
+    #[serde(default)]
+    pub load_env_file: bool,
+    /// Where `y2md llm set-key` stores API keys
+    #[serde(default)]
+    pub credential_backend: CredentialBackend,
+    /// Output filename without extension (see [`expand_filename_template`])
+    #[serde(default = "default_filename_template")]
+    pub filename_template: String,
+    /// Sampling temperature for LLM formatting (0.0-2.0)
+    #[serde(default = "default_llm_temperature")]
+    pub llm_temperature: f32,
+    /// System prompt sent with every LLM request (tone, language, domain);
+    /// [`DEFAULT_LLM_SYSTEM_PROMPT`] is used where a provider needs one
+    #[serde(default)]
+    pub llm_system_prompt: Option<String>,
+    /// Providers to try in order when the default LLM provider fails
+    #[serde(default)]
+    pub llm_fallback: Vec<LlmProviderType>,
+    /// Use LLM formatting for caption transcripts when it is enabled
+    #[serde(default = "default_llm_for_source")]
+    pub llm_for_captions: bool,
+    /// Use LLM formatting for speech-to-text transcripts when it is enabled
+    #[serde(default = "default_llm_for_source")]
+    pub llm_for_stt: bool,
+    /// Proxy URL for yt-dlp and every HTTP request; `Y2MD_PROXY` takes precedence
+    #[serde(default)]
+    pub proxy: Option<String>,
+    /// Proxy from `--proxy`, ahead of `Y2MD_PROXY` and `proxy`; never saved
+    #[serde(skip)]
+    pub proxy_override: Option<String>,
+    /// File the config was loaded from and is saved to, see
+    /// [`AppConfig::file_path`]; never saved
+    #[serde(skip)]
+    pub path: Option<PathBuf>,
+    pub llm: LlmSettings,
+    pub advanced: AdvancedSettings,
+    #[serde(default)]
+    pub ytdlp: YtDlpConfig,
+    #[serde(default)]
+    pub stt: SttSettings,
+}
 
-    if !output.status.success() {
-        return Err(Y2mdError::Config(
-            "Failed to fetch metadata with yt-dlp".to_string(),
-        ));
+impl Default for AppConfig {
+    fn default() -> Self {
+        AppConfig {
+            version: CONFIG_VERSION,
+            output_dir: ".".to_string(),
+            default_language: "en".to_string(),
+            prefer_captions: true,
+            timestamps: false,
+            compact: false,
+            paragraph_length: ParagraphLength::default(),
+            compact_paragraph_length: default_compact_paragraph_length(),
+            preserve_caption_paragraphs: false,
+            caption_lang_fallback: CaptionLangFallback::default(),
+            fix_capitalization: false,
+            proper_nouns: Vec::new(),
+            replacements: HashMap::new(),
+            redact: Vec::new(),
+            redact_pii: false,
+            max_repeated_sentences: default_max_repeated_sentences(),
+            append_metadata_footer: false,
+            line_wrap: None,
+            models_dir: None,
+            date_format: None,
+            timezone: Timezone::default(),
+            load_env_file: false,
+            credential_backend: CredentialBackend::default(),
+            filename_template: default_filename_template(),
+            llm_temperature: default_llm_temperature(),
+            llm_system_prompt: None,
+            llm_fallback: Vec::new(),
+            llm_for_captions: default_llm_for_source(),
+            llm_for_stt: default_llm_for_source(),
+            proxy: None,
+            proxy_override: None,
+            path: None,
+            llm: LlmSettings::default(),
+            advanced: AdvancedSettings::default(),
+            ytdlp: YtDlpConfig::default(),
+            stt: SttSettings::default(),
+        }
     }
+}
 
-    // Parse JSON output
-    let metadata_json: serde_json::Value = serde_json::from_slice(&output.stdout)
-        .map_err(|e| Y2mdError::Config(format!("Failed to parse metadata JSON: {}", e)))?;
+/// Environment variable that overrides the configured proxy
+pub const PROXY_ENV_VAR: &str = "Y2MD_PROXY";
 
-    // Extract fields from JSON
-    let title = metadata_json["title"]
-        .as_str()
-        .unwrap_or("Unknown Title")
-        .to_string();
+/// Environment variable naming a config file to use instead of the default
+pub const CONFIG_ENV_VAR: &str = "Y2MD_CONFIG";
 
-    let channel = metadata_json["uploader"].as_str().map(|s| s.to_string());
+/// Hosts that never go through the proxy, so a local Ollama keeps working
+const NO_PROXY_HOSTS: &str = "localhost,127.0.0.1,::1";
 
-    let duration_seconds = metadata_json["duration"].as_f64().unwrap_or(0.0);
+/// How long to wait for a TCP/TLS connection before giving up. There is no
+/// overall default timeout because model pulls and audio uploads can run for
+/// a long time; individual requests set their own.
+const HTTP_CONNECT_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(30);
 
-    let duration = if duration_seconds > 0.0 {
-        Some(format_duration(duration_seconds))
-    } else {
-        None
-    };
+/// User-Agent sent with every request, so API and proxy operators can tell y2md traffic apart
+pub const USER_AGENT: &str = concat!("y2md/", env!("CARGO_PKG_VERSION"));
 
-    let metadata = VideoMetadata {
-        title,
-        channel,
-        duration,
-        video_id: video_id.to_string(),
-        url,
-    };
+/// HTTP client shared by the LLM providers, speech-to-text APIs and
+/// [`OllamaManager`]. Cloning is cheap and clones share one connection pool.
+#[derive(Debug, Clone, Default)]
+pub struct HttpClient {
+    client: reqwest::Client,
+}
+
+impl HttpClient {
+    /// Build a new client with the configured proxy and the y2md User-Agent
+    pub fn new(config: &AppConfig) -> Result<Self, Y2mdError> {
+        Self::build(config.proxy_url().as_deref())
+    }
 
-    Ok(metadata)
+    fn build(proxy: Option<&str>) -> Result<Self, Y2mdError> {
+        let mut builder = reqwest::Client::builder()
+            .user_agent(USER_AGENT)
+            .connect_timeout(HTTP_CONNECT_TIMEOUT);
+        if let Some(proxy_url) = proxy {
+            let proxy = reqwest::Proxy::all(proxy_url)
+                .map_err(|e| {
+                    Y2mdError::Config(format!("Invalid proxy URL '{}': {}", proxy_url, e))
+                })?
+                .no_proxy(reqwest::NoProxy::from_string(NO_PROXY_HOSTS));
+            builder = builder.proxy(proxy);
+        }
+        let client = builder
+            .build()
+            .map_err(|e| Y2mdError::Config(format!("Failed to build HTTP client: {}", e)))?;
+        Ok(Self { client })
+    }
 }
 
-/// Format duration in seconds to HH:MM:SS
-fn format_duration(seconds: f64) -> String {
-    let total_seconds = seconds as u64;
-    let hours = total_seconds / 3600;
-    let minutes = (total_seconds % 3600) / 60;
-    let seconds = total_seconds % 60;
+impl std::ops::Deref for HttpClient {
+    type Target = reqwest::Client;
 
-    if hours > 0 {
-        format!("{:02}:{:02}:{:02}", hours, minutes, seconds)
-    } else {
-        format!("{:02}:{:02}", minutes, seconds)
+    fn deref(&self) -> &reqwest::Client {
+        &self.client
     }
 }
 
-/// Check if captions are available for a video
-pub async fn check_captions_available(video_id: &str) -> Result<bool, Y2mdError> {
-    let url = format!("https://www.youtube.com/watch?v={}", video_id);
+fn default_config_version() -> u32 {
+    CONFIG_VERSION
+}
 
-    // Use yt-dlp to list available captions
-    let output = Command::new("yt-dlp")
-        .args(["--list-subs", "--no-download", &url])
-        .output()
-        .map_err(|e| {
-            if e.kind() == std::io::ErrorKind::NotFound {
-                Y2mdError::YtDlpNotFound
-            } else {
-                Y2mdError::Io(e)
-            }
-        })?;
+fn default_compact_paragraph_length() -> ParagraphLength {
+    ParagraphLength::Sentences(8)
+}
 
-    if !output.status.success() {
-        return Ok(false);
-    }
+fn default_max_repeated_sentences() -> usize {
+    2
+}
 
-    let output_str = String::from_utf8_lossy(&output.stdout);
+fn default_llm_for_source() -> bool {
+    true
+}
 
-    // Check if there are any available captions
-    // Look for language codes in the output - both automatic and manual captions
-    Ok(output_str.contains("Available subtitles")
-        && output_str
-            .lines()
-            .any(|line| line.contains("en") || line.contains("English")))
+fn default_filename_template() -> String {
+    DEFAULT_FILENAME_TEMPLATE.to_string()
 }
 
-/// Extract captions from YouTube video
-pub async fn extract_captions(
-    video_id: &str,
-    language: Option<&str>,
-    force_formatting: bool,
-) -> Result<(String, String), Y2mdError> {
-    let url = format!("https://www.youtube.com/watch?v={}", video_id);
-    let lang = language.unwrap_or("en");
+fn default_llm_temperature() -> f32 {
+    0.1
+}
 
-    // Use yt-dlp to download captions
-    let output = Command::new("yt-dlp")
-        .args([
-            "--write-sub",
-            "--write-auto-sub",
-            "--sub-lang",
-            lang,
-            "--skip-download",
-            "--convert-subs",
-            "srt",
-            "-o",
-            "%(id)s_captions",
-            &url,
-        ])
-        .output()
-        .map_err(|e| {
-            if e.kind() == std::io::ErrorKind::NotFound {
-                Y2mdError::YtDlpNotFound
-            } else {
-                Y2mdError::Io(e)
+/// Current layout version of `config.toml`
+pub const CONFIG_VERSION: u32 = 2;
+
+/// Upgrade a config table written by an older y2md to the current layout,
+/// returning whether it needed upgrading. Only version 1 files that still use
+/// the single-provider `[llm]` table (`provider`, `model`, `endpoint`) are
+/// upgraded; any other file without a `version` already reads as the current
+/// layout and is left alone.
+pub fn migrate_config(table: &mut toml::Table) -> bool {
+    let version = table
+        .get("version")
+        .and_then(toml::Value::as_integer)
+        .unwrap_or(1);
+    let v1_layout = matches!(
+        table.get("llm"),
+        Some(toml::Value::Table(llm)) if llm.contains_key("model") || llm.contains_key("endpoint")
+    );
+    if version >= i64::from(CONFIG_VERSION) || !v1_layout {
+        return false;
+    }
+
+    // v1 -> v2: move the flat model and endpoint under the provider's own table
+    if let Some(toml::Value::Table(llm)) = table.get_mut("llm") {
+        let provider = llm
+            .get("provider")
+            .and_then(toml::Value::as_str)
+            .unwrap_or("local")
+            .to_string();
+        let moved: Vec<(String, toml::Value)> = ["model", "endpoint"]
+            .into_iter()
+            .filter_map(|key| llm.remove(key).map(|value| (key.to_string(), value)))
+            .collect();
+        if !moved.is_empty() {
+            let provider_table = llm
+                .entry(provider)
+                .or_insert_with(|| toml::Value::Table(toml::Table::new()));
+            if let toml::Value::Table(provider_table) = provider_table {
+                for (key, value) in moved {
+                    provider_table.entry(key).or_insert(value);
+                }
             }
-        })?;
+        }
+    }
 
-    if !output.status.success() {
-        return Err(Y2mdError::Config("Failed to extract captions".to_string()));
+    // Settings added since then get their defaults
+    if let Ok(toml::Value::Table(defaults)) = toml::Value::try_from(AppConfig::default()) {
+        fill_missing(table, &defaults);
     }
+    table.insert(
+        "version".to_string(),
+        toml::Value::Integer(i64::from(CONFIG_VERSION)),
+    );
+    true
+}
 
-    // Look for the generated caption file
-    let caption_filename = format!("{}_captions.{}.srt", video_id, lang);
+/// Copy keys from `defaults` that `table` lacks, descending into tables
+fn fill_missing(table: &mut toml::Table, defaults: &toml::Table) {
+    for (key, default) in defaults {
+        match (table.get_mut(key), default) {
+            (Some(toml::Value::Table(existing)), toml::Value::Table(default)) => {
+                fill_missing(existing, default)
+            }
+            (Some(_), _) => {}
+            (None, _) => {
+                table.insert(key.clone(), default.clone());
+            }
+        }
+    }
+}
 
-    if !std::path::Path::new(&caption_filename).exists() {
-        return Err(Y2mdError::Config(
-            "Caption file not found after extraction".to_string(),
-        ));
+/// Explain a config parse error by the setting it is about (e.g.
+/// `llm.anthropic.max_tokens`) and its line and column, rather than toml's
+/// multi-line snippet. `parsed` is the text toml read, which differs from the
+/// file as written (`original`) when [`migrate_config`] upgraded it; the line
+/// and column are always those of the setting in `original`, and are left
+/// out when the upgrade moved it.
+fn describe_config_error(original: &str, parsed: &str, error: &toml::de::Error) -> String {
+    let message = error.message().trim();
+    let Some(span) = error.span() else {
+        return format!("Failed to parse config: {}", message);
+    };
+
+    let before = &parsed[..span.start.min(parsed.len())];
+    let line = parsed
+        .lines()
+        .nth(before.matches('\n').count())
+        .unwrap_or_default();
+
+    // The setting is the key on the error's line, inside the last table header above it
+    let key = line
+        .split_once('=')
+        .map(|(key, _)| key.trim().trim_matches('"').to_string());
+    let table = before
+        .lines()
+        .rev()
+        .map(str::trim)
+        .find(|line| line.starts_with('[') && line.ends_with(']'))
+        .map(|header| header.trim_matches(['[', ']']).trim().to_string());
+
+    let position = if parsed == original {
+        Some(span.start)
+    } else {
+        key.as_deref()
+            .and_then(|key| find_config_key(original, table.as_deref(), key))
+    };
+    let location = position
+        .map(|position| {
+            let before = &original[..position.min(original.len())];
+            let line_number = before.matches('\n').count() + 1;
+            let column = before.len() - before.rfind('\n').map_or(0, |i| i + 1) + 1;
+            format!(" (line {}, column {})", line_number, column)
+        })
+        .unwrap_or_default();
+    match (table, key) {
+        (Some(table), Some(key)) => format!(
+            "Invalid value for `{}.{}`{}: {}",
+            table, key, location, message
+        ),
+        (None, Some(key)) => format!("Invalid value for `{}`{}: {}", key, location, message),
+        (Some(table), None) => format!("Invalid `[{}]` table{}: {}", table, location, message),
+        (None, None) => format!("Failed to parse config{}: {}", location, message),
     }
+}
 
-    // Read the caption file
-    let caption_content = std::fs::read_to_string(&caption_filename)?;
+/// Byte offset of `key` under the `[table]` header (or before any header for
+/// `None`) in a config file
+fn find_config_key(content: &str, table: Option<&str>, key: &str) -> Option<usize> {
+    let mut current = None;
+    let mut offset = 0;
+    for line in content.split_inclusive('\n') {
+        let trimmed = line.trim();
+        if trimmed.starts_with('[') && trimmed.ends_with(']') {
+            current = Some(trimmed.trim_matches(['[', ']']).trim());
+        } else if current == table
+            && line
+                .split_once('=')
+                .is_some_and(|(name, _)| name.trim().trim_matches('"') == key)
+        {
+            return Some(offset + line.len() - line.trim_start().len());
+        }
+        offset += line.len();
+    }
+    None
+}
 
-    // Clean up the temporary file
-    let _ = std::fs::remove_file(&caption_filename);
+/// System message for chat-style providers when `llm_system_prompt` is not set
+pub const DEFAULT_LLM_SYSTEM_PROMPT: &str =
+    "You are a helpful assistant that formats transcripts into well-structured markdown.";
 
-    // Convert SRT to plain text
-    let raw_text = srt_to_plain_text(&caption_content);
+/// Parse a `--temperature` value, see [`validate_llm_temperature`]
+pub fn parse_temperature(input: &str) -> Result<f32, String> {
+    let temperature = input
+        .trim()
+        .parse::<f32>()
+        .map_err(|_| format!("'{}' is not a number", input))?;
+    validate_llm_temperature(temperature)
+}
 
-    // Only apply enhanced formatting if the text doesn't contain music notation
-    // or other special formatting that should be preserved
-    let formatted_text = if force_formatting {
-        // Force enhanced formatting regardless of content
-        println!("Applying enhanced formatting to captions...");
-        let result = format_transcript(&raw_text, false, 4);
-        println!("Formatting completed");
-        result
-    } else if raw_text.contains('♪') || raw_text.contains('[') {
-        // Preserve original formatting for music videos and special content
-        println!("Preserving original formatting for music/special content");
-        raw_text.clone()
+/// Check that an LLM sampling temperature is within 0.0-2.0
+pub fn validate_llm_temperature(temperature: f32) -> Result<f32, String> {
+    if (0.0..=2.0).contains(&temperature) {
+        Ok(temperature)
     } else {
-        // Apply enhanced formatting for regular speech
-        println!("Applying enhanced formatting to captions...");
-        let result = format_transcript(&raw_text, false, 4);
-        println!("Formatting completed");
-        result
-    };
-
-    Ok((formatted_text, raw_text))
+        Err(format!(
+            "temperature must be between 0.0 and 2.0, got {}",
+            temperature
+        ))
+    }
 }
 
-/// Convert SRT subtitle format to plain text
-fn srt_to_plain_text(srt_content: &str) -> String {
-    let mut plain_text = String::new();
-    let mut in_text_block = false;
+impl AppConfig {
+    /// Proxy to use, from `--proxy`, `Y2MD_PROXY` or the config file
+    pub fn proxy_url(&self) -> Option<String> {
+        self.proxy_override
+            .clone()
+            .or_else(|| std::env::var(PROXY_ENV_VAR).ok())
+            .or_else(|| self.proxy.clone())
+            .filter(|proxy| !proxy.trim().is_empty())
+    }
 
-    for line in srt_content.lines() {
-        if line.trim().is_empty() {
-            in_text_block = false;
-            continue;
+    /// The default LLM provider followed by the `llm_fallback` providers,
+    /// without repeats
+    pub fn llm_provider_chain(&self) -> Vec<LlmProviderType> {
+        let mut chain = vec![self.llm.provider.clone()];
+        for provider in &self.llm_fallback {
+            if !chain.contains(provider) {
+                chain.push(provider.clone());
+            }
         }
+        chain
+    }
 
-        // Skip subtitle numbers and timestamps
-        if line
-            .trim()
-            .chars()
-            .next()
-            .map(|c| c.is_numeric())
-            .unwrap_or(false)
-        {
-            continue;
+    /// yt-dlp settings with the proxy filled in
+    pub fn ytdlp_config(&self) -> YtDlpConfig {
+        YtDlpConfig {
+            proxy: self.proxy_url(),
+            ..self.ytdlp.clone()
         }
+    }
 
-        // Skip timestamp lines (contain -->)
-        if line.contains("-->") {
-            continue;
+    /// Paragraph length for the chosen formatting mode
+    pub fn paragraph_length_for(&self, compact: bool) -> ParagraphLength {
+        if compact {
+            self.compact_paragraph_length
+        } else {
+            self.paragraph_length
         }
+    }
 
-        // This should be subtitle text
-        if !in_text_block {
-            if !plain_text.is_empty() {
-                plain_text.push(' ');
-            }
-            in_text_block = true;
+    /// Directories searched for Whisper models, in order: `models_dir` when
+    /// set, otherwise the platform data directory and then the old
+    /// `~/.local/share/y2md/models` path that earlier versions always used
+    pub fn model_dirs(&self) -> Vec<PathBuf> {
+        if let Some(dir) = self
+            .models_dir
+            .as_deref()
+            .filter(|dir| !dir.trim().is_empty())
+        {
+            return vec![PathBuf::from(shellexpand::tilde(dir).to_string())];
         }
 
-        plain_text.push_str(line.trim());
-        plain_text.push(' ');
+        let mut dirs: Vec<PathBuf> = default_models_dir().into_iter().collect();
+        let legacy = PathBuf::from(shellexpand::tilde(LEGACY_MODELS_DIR).to_string());
+        if !dirs.contains(&legacy) {
+            dirs.push(legacy);
+        }
+        dirs
     }
 
-    plain_text.trim().to_string()
-}
+    /// Load the config from [`AppConfig::config_path`], or the defaults when
+    /// there is no file yet
+    pub fn load() -> Result<Self, Y2mdError> {
+        Self::load_or_default(&Self::config_path()?)
+    }
 
-/// Download audio from YouTube video
-pub async fn download_audio(video_id: &str, output_dir: &str) -> Result<PathBuf, Y2mdError> {
-    let url = format!("https://www.youtube.com/watch?v={}", video_id);
+    /// Like [`AppConfig::load`], but never writes to the file: a config in
+    /// an older layout is only upgraded in memory. For diagnostics.
+    pub fn read() -> Result<Self, Y2mdError> {
+        Self::read_or_default(&Self::config_path()?)
+    }
 
-    // Create output directory if it doesn't exist
-    let output_path = PathBuf::from(output_dir);
-    if !output_path.exists() {
-        std::fs::create_dir_all(&output_path)?;
+    /// Load the config from `config_path` (e.g. `--config`), or the defaults
+    /// when there is no file yet; either way it is saved back to that file
+    pub fn load_or_default(config_path: &std::path::Path) -> Result<Self, Y2mdError> {
+        if !config_path.exists() {
+            return Ok(Self::default_at(config_path));
+        }
+
+        Self::load_from(config_path)
     }
 
-    // First, check if audio file already exists in cache
-    let _pattern = format!("{}_audio.*", video_id);
-    let mut cached_audio_path = None;
+    /// Like [`AppConfig::load_or_default`], but never writes to the file
+    pub fn read_or_default(config_path: &std::path::Path) -> Result<Self, Y2mdError> {
+        if !config_path.exists() {
+            return Ok(Self::default_at(config_path));
+        }
 
-    for entry in std::fs::read_dir(&output_path)? {
-        let entry = entry?;
-        let file_name = entry.file_name();
-        if let Some(name) = file_name.to_str() {
-            if name.starts_with(&format!("{}_audio.", video_id)) {
-                let path = entry.path();
-                // Check if file is not empty
-                if let Ok(metadata) = std::fs::metadata(&path) {
-                    if metadata.len() > 0 {
-                        cached_audio_path = Some(path);
-                        println!("Using cached audio file: {:?}", cached_audio_path);
-                        break;
-                    }
-                }
-            }
+        Self::read_file(config_path).map(|(config, _)| config)
+    }
+
+    /// The defaults, to be saved to `config_path`
+    pub fn default_at(config_path: &std::path::Path) -> Self {
+        AppConfig {
+            path: Some(config_path.to_path_buf()),
+            ..Default::default()
         }
     }
 
-    if let Some(cached_path) = cached_audio_path {
-        return Ok(cached_path);
+    /// Load and validate a specific config file, upgrading it on disk when
+    /// it uses an older layout
+    pub fn load_from(config_path: &std::path::Path) -> Result<Self, Y2mdError> {
+        let (config, migrated) = Self::read_file(config_path)?;
+        if migrated {
+            Self::save_migrated(config_path, &config)?;
+        }
+        Ok(config)
     }
 
-    // Create progress bar for download
-    let progress_bar = ProgressBar::new_spinner();
-    progress_bar.set_style(
-        ProgressStyle::default_spinner()
-            .template("{spinner:.blue} {msg}")
-            .unwrap()
-            .tick_strings(&["⣾", "⣽", "⣻", "⢿", "⡿", "⣟", "⣯", "⣷"]),
-    );
-    progress_bar.set_message("Downloading audio from YouTube...");
-    progress_bar.enable_steady_tick(std::time::Duration::from_millis(100));
-
-    // Use yt-dlp to download audio as WAV
-    let output_template = output_path.join(format!("{}_audio", video_id));
-
-    let status = Command::new("yt-dlp")
-        .args([
-            "-x", // Extract audio
-            "--audio-format",
-            "best", // Use best available format
-            "--audio-quality",
-            "0", // Best quality
-            "-o",
-            output_template.to_str().unwrap(),
-            &url,
-        ])
-        .status()
-        .map_err(|e| {
-            if e.kind() == std::io::ErrorKind::NotFound {
-                Y2mdError::YtDlpNotFound
-            } else {
-                Y2mdError::Io(e)
-            }
+    /// Read and validate a config file, upgrading an older layout in memory;
+    /// also returns whether it was upgraded
+    fn read_file(config_path: &std::path::Path) -> Result<(Self, bool), Y2mdError> {
+        let config_content = std::fs::read_to_string(config_path).map_err(|e| {
+            Y2mdError::Config(format!(
+                "Failed to read config file {}: {}",
+                config_path.display(),
+                e
+            ))
         })?;
 
-    if !status.success() {
-        return Err(Y2mdError::Config(
-            "Failed to download audio with yt-dlp".to_string(),
-        ));
-    }
+        let parse_error = |parsed: &str, e: toml::de::Error| {
+            Y2mdError::Config(format!(
+                "{}\n\nPlease check your config file at: {}",
+                describe_config_error(&config_content, parsed, &e),
+                config_path.display()
+            ))
+        };
 
-    // Find the downloaded file (yt-dlp adds extension)
-    // Look for files matching the pattern: {video_id}_audio.*
-    let pattern = format!("{}_audio.*", video_id);
-    let mut audio_path = None;
+        let mut table = toml::from_str::<toml::Table>(&config_content)
+            .map_err(|e| parse_error(&config_content, e))?;
+        let migrated = migrate_config(&mut table);
+        let mut config = if migrated {
+            let upgraded = toml::to_string(&table)
+                .map_err(|e| Y2mdError::Config(format!("Failed to migrate config: {}", e)))?;
+            toml::from_str::<AppConfig>(&upgraded).map_err(|e| parse_error(&upgraded, e))?
+        } else {
+            toml::from_str::<AppConfig>(&config_content)
+                .map_err(|e| parse_error(&config_content, e))?
+        };
 
-    println!("Looking for audio files matching pattern: {}", pattern);
-    for entry in std::fs::read_dir(&output_path)? {
-        let entry = entry?;
-        let file_name = entry.file_name();
-        if let Some(name) = file_name.to_str() {
-            println!("Found file: {}", name);
-            if name.starts_with(&format!("{}_audio.", video_id)) {
-                let path = entry.path();
-                // Skip empty files
-                if let Ok(metadata) = std::fs::metadata(&path) {
-                    if metadata.len() > 0 {
-                        audio_path = Some(path);
-                        println!("Selected audio file: {:?}", audio_path);
-                        break;
-                    } else {
-                        println!("Skipping empty file: {:?}", path);
-                    }
-                }
-            }
+        config.validate().map_err(|e| {
+            Y2mdError::Config(format!(
+                "{}\n\nPlease check your config file at: {}",
+                e,
+                config_path.display()
+            ))
+        })?;
+
+        // Store the code Whisper and caption lookups expect, so
+        // `default_language = "pt-BR"` means Portuguese rather than English
+        if let Ok(language) = parse_language_arg(&config.default_language) {
+            config.default_language = language;
         }
-    }
 
-    let audio_path = audio_path.ok_or_else(|| {
-        Y2mdError::Config(format!(
-            "Downloaded audio file not found for pattern: {}",
-            pattern
-        ))
-    })?;
+        config.path = Some(config_path.to_path_buf());
+        Ok((config, migrated))
+    }
 
-    progress_bar.finish_with_message("Audio download completed");
+    /// Check values that parse fine but make no sense
+    pub fn validate(&self) -> Result<(), String> {
+        if self.paragraph_length == ParagraphLength::Sentences(0) {
+            return Err("paragraph_length must be at least 1 or \"auto\"".to_string());
+        }
+        parse_language_arg(&self.default_language)
+            .map_err(|e| format!("default_language: {}", e))?;
+        if self.filename_template.trim().is_empty() {
+            return Err("filename_template must not be empty".to_string());
+        }
+        if self.filename_template.contains(['/', '\\']) {
+            return Err(
+                "filename_template must not contain path separators; use output_dir for folders"
+                    .to_string(),
+            );
+        }
+        if self.compact_paragraph_length == ParagraphLength::Sentences(0) {
+            return Err("compact_paragraph_length must be at least 1 or \"auto\"".to_string());
+        }
+        validate_llm_temperature(self.llm_temperature)
+            .map_err(|e| format!("llm_temperature: {}", e))?;
+        if self.line_wrap.is_some_and(|width| width < MIN_LINE_WRAP) {
+            return Err(format!("line_wrap must be at least {}", MIN_LINE_WRAP));
+        }
+        if let Some(proxy) = &self.proxy {
+            reqwest::Proxy::all(proxy)
+                .map_err(|e| format!("proxy: invalid URL '{}': {}", proxy, e))?;
+        }
+        if let Some(format) = &self.ytdlp.audio_format {
+            parse_audio_format(format).map_err(|e| format!("ytdlp.audio_format: {}", e))?;
+        }
+        if let Some(quality) = &self.ytdlp.audio_quality {
+            parse_audio_quality(quality).map_err(|e| format!("ytdlp.audio_quality: {}", e))?;
+        }
 
-    println!("Audio downloaded to: {:?}", audio_path);
+        Ok(())
+    }
 
-    Ok(audio_path)
-}
+    /// Keep a config upgraded by [`migrate_config`] next to the new one as
+    /// `config.toml.bak` and save the new layout
+    fn save_migrated(config_path: &std::path::Path, config: &Self) -> Result<(), Y2mdError> {
+        let backup_path = config_path.with_extension("toml.bak");
+        std::fs::copy(config_path, &backup_path)
+            .map_err(|e| Y2mdError::Config(format!("Failed to back up config file: {}", e)))?;
+        config.save()?;
+        println!(
+            "Upgraded config to version {} (previous file saved as {})",
+            CONFIG_VERSION,
+            backup_path.display()
+        );
 
-/// Transcribe YouTube video using captions or STT
-pub async fn transcribe_video(
-    video_id: &str,
-    prefer_captions: bool,
-    language: Option<&str>,
-    output_dir: &str,
-    paragraph_length: usize,
-    force_formatting: bool,
-) -> Result<(String, String, String), Y2mdError> {
-    let mut source = "whisper".to_string();
-    let transcript;
+        Ok(())
+    }
 
-    let raw_transcript;
+    /// Write the config to [`AppConfig::file_path`]
+    pub fn save(&self) -> Result<(), Y2mdError> {
+        let config_path = self.file_path()?;
 
-    if prefer_captions {
-        match check_captions_available(video_id).await {
-            Ok(true) => {
-                let (formatted, raw) =
-                    extract_captions(video_id, language, force_formatting).await?;
-                transcript = formatted;
-                raw_transcript = raw;
-                source = "captions".to_string();
-                println!("Using captions for transcription");
-            }
-            Ok(false) => {
-                println!("No captions available, falling back to STT");
-                let audio_path = download_audio(video_id, output_dir).await?;
-                let (formatted, raw) =
-                    transcribe_audio(&audio_path, language, paragraph_length).await?;
-                transcript = formatted;
-                raw_transcript = raw;
-            }
-            Err(e) => {
-                println!("Error checking captions: {}, falling back to STT", e);
-                let audio_path = download_audio(video_id, output_dir).await?;
-                let (formatted, raw) =
-                    transcribe_audio(&audio_path, language, paragraph_length).await?;
-                transcript = formatted;
-                raw_transcript = raw;
-            }
+        if let Some(config_dir) = config_path
+            .parent()
+            .filter(|dir| !dir.as_os_str().is_empty())
+        {
+            std::fs::create_dir_all(config_dir).map_err(|e| {
+                Y2mdError::Config(format!("Failed to create config directory: {}", e))
+            })?;
         }
-    } else {
-        println!("Using STT for transcription");
-        let audio_path = download_audio(video_id, output_dir).await?;
-        let (formatted, raw) = transcribe_audio(&audio_path, language, paragraph_length).await?;
-        transcript = formatted;
-        raw_transcript = raw;
-    }
 
-    Ok((transcript, source, raw_transcript))
-}
+        let header = r#"# =============================================================================
+# Y2MD Configuration
+# Edit this file directly or use: y2md config edit
+# =============================================================================
 
-/// Transcribe audio file using STT
-pub async fn transcribe_audio(
-    audio_path: &PathBuf,
-    language: Option<&str>,
-    paragraph_length: usize,
-) -> Result<(String, String), Y2mdError> {
-    // Check if audio file exists
-    if !audio_path.exists() {
-        return Err(Y2mdError::Config(format!(
-            "Audio file not found: {:?}",
-            audio_path
-        )));
-    }
+"#;
 
-    // Use whisper-rs for real transcription
-    println!("Transcribing audio with Whisper...");
+        let config_toml = toml::to_string_pretty(self)
+            .map_err(|e| Y2mdError::Config(format!("Failed to serialize configuration: {}", e)))?;
 
-    // Create progress bar for transcription
-    let progress_bar = ProgressBar::new_spinner();
-    progress_bar.set_style(
-        ProgressStyle::default_spinner()
-            .template("{spinner:.green} {msg}")
-            .unwrap()
-            .tick_strings(&["⠋", "⠙", "⠹", "⠸", "⠼", "⠴", "⠦", "⠧", "⠇", "⠏"]),
-    );
-    progress_bar.set_message("Transcribing audio...");
-    progress_bar.enable_steady_tick(std::time::Duration::from_millis(100));
+        std::fs::write(&config_path, format!("{}{}", header, config_toml))
+            .map_err(|e| Y2mdError::Config(format!("Failed to write configuration file: {}", e)))?;
 
-    // Determine which model to use based on language
-    let (model_path, whisper_lang) = determine_model_and_language(language)?;
+        Ok(())
+    }
 
-    if !std::path::Path::new(&model_path).exists() {
-        return Err(Y2mdError::Whisper(format!(
-            "Whisper model not found at: {}. Please run download_model.sh",
-            model_path
-        )));
+    /// File this config was loaded from, or [`AppConfig::config_path`] for
+    /// one that was not loaded from a file
+    pub fn file_path(&self) -> Result<PathBuf, Y2mdError> {
+        match &self.path {
+            Some(path) => Ok(path.clone()),
+            None => Self::config_path(),
+        }
     }
 
-    // Load the whisper model
-    let ctx_params = whisper_rs::WhisperContextParameters::default();
-    let ctx = whisper_rs::WhisperContext::new_with_params(&model_path, ctx_params)
-        .map_err(|e| Y2mdError::Whisper(format!("Failed to load whisper model: {}", e)))?;
+    /// Default config file: `Y2MD_CONFIG` or `config.toml` in the platform
+    /// config directory. `--config` picks a file without going through this.
+    pub fn config_path() -> Result<PathBuf, Y2mdError> {
+        if let Some(path) = std::env::var(CONFIG_ENV_VAR)
+            .ok()
+            .filter(|path| !path.trim().is_empty())
+        {
+            return Ok(PathBuf::from(shellexpand::tilde(&path).to_string()));
+        }
 
-    // Create state for transcription
-    let mut state = ctx
-        .create_state()
-        .map_err(|e| Y2mdError::Whisper(format!("Failed to create state: {}", e)))?;
+        let config_dir = directories::ProjectDirs::from("com", "y2md", "y2md")
+            .ok_or_else(|| Y2mdError::Config("Could not determine config directory".to_string()))?;
 
-    // Convert audio to the format whisper expects
-    let audio_data = convert_audio_for_whisper(audio_path).await?;
+        Ok(config_dir.config_dir().join("config.toml"))
+    }
+}
 
-    // Set up transcription parameters
-    let mut params =
-        whisper_rs::FullParams::new(whisper_rs::SamplingStrategy::Greedy { best_of: 1 });
-    params.set_language(Some(&whisper_lang));
-    params.set_print_special(false);
-    params.set_print_progress(false);
-    params.set_print_realtime(false);
-    params.set_print_timestamps(false);
+/// Load variables such as `Y2MD_OPENAI_API_KEY` from a `.env` file into the
+/// process environment. Variables that are already set win. Without an
+/// explicit `path`, `.env` in the current directory is tried first, then
+/// `.env` next to `config_path`. Returns the file that was loaded.
+pub fn load_env_file(
+    path: Option<&std::path::Path>,
+    config_path: &std::path::Path,
+) -> Result<Option<PathBuf>, Y2mdError> {
+    let candidates = match path {
+        Some(path) => {
+            if !path.exists() {
+                return Err(Y2mdError::Config(format!(
+                    "Env file not found: {}",
+                    path.display()
+                )));
+            }
+            vec![path.to_path_buf()]
+        }
+        None => {
+            vec![PathBuf::from(".env"), config_path.with_file_name(".env")]
+        }
+    };
 
-    // Transcribe the audio
-    state
-        .full(params, &audio_data[..])
-        .map_err(|e| Y2mdError::Whisper(format!("Transcription failed: {}", e)))?;
+    let Some(found) = candidates.into_iter().find(|p| p.exists()) else {
+        return Ok(None);
+    };
+
+    dotenvy::from_path(&found).map_err(|e| {
+        Y2mdError::Config(format!(
+            "Failed to read env file {}: {}",
+            found.display(),
+            e
+        ))
+    })?;
+
+    Ok(Some(found))
+}
 
-    // Update progress bar
-    progress_bar.set_message("Processing transcription segments...");
+/// Where API keys set with `y2md llm set-key` are stored
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CredentialBackend {
+    /// The OS keychain (macOS Keychain, Windows Credential Manager, Secret Service)
+    #[default]
+    Keyring,
+    /// `credentials.json` in the config directory, readable only by the owner
+    File,
+}
 
-    // Collect all segments into a transcript
-    let mut raw_transcript = String::new();
-    for segment in state.as_iter() {
-        let segment_text = segment.to_string();
-        if !raw_transcript.is_empty() {
-            raw_transcript.push(' ');
+impl std::fmt::Display for CredentialBackend {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CredentialBackend::Keyring => write!(f, "keyring"),
+            CredentialBackend::File => write!(f, "file"),
         }
-        raw_transcript.push_str(&segment_text);
     }
+}
 
-    // Finish progress bar
-    progress_bar.finish_with_message("Transcription completed");
+impl std::str::FromStr for CredentialBackend {
+    type Err = String;
 
-    if raw_transcript.trim().is_empty() {
-        return Err(Y2mdError::Whisper(
-            "Transcription produced empty result".to_string(),
-        ));
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "keyring" => Ok(CredentialBackend::Keyring),
+            "file" => Ok(CredentialBackend::File),
+            _ => Err(format!(
+                "Unknown credential backend: {} (expected keyring or file)",
+                s
+            )),
+        }
     }
+}
 
-    println!(
-        "Transcription completed successfully (language: {})",
-        whisper_lang
-    );
-
-    // Apply formatting to STT output
-    println!("Applying formatting to transcript...");
-    let formatted_transcript = format_transcript(&raw_transcript, false, paragraph_length);
-    println!("Formatting completed");
-    Ok((formatted_transcript, raw_transcript))
+pub struct CredentialManager {
+    service_name: String,
+    backend: CredentialBackend,
+    /// Credentials file for [`CredentialBackend::File`]
+    file_path: Option<PathBuf>,
 }
 
-/// Determine which whisper model and language to use
-fn determine_model_and_language(language: Option<&str>) -> Result<(String, String), Y2mdError> {
-    let base_model_dir = shellexpand::tilde("~/.local/share/y2md/models/");
-    let base_model_dir = base_model_dir.to_string();
+impl Default for CredentialManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
-    // Default to English if no language specified
-    let lang = language.unwrap_or("en");
+impl CredentialManager {
+    /// Create a manager using the backend from the user's configuration
+    pub fn new() -> Self {
+        Self::for_config(&AppConfig::load().unwrap_or_default())
+    }
 
-    // Map language codes to whisper model names
-    let (model_name, whisper_lang) = match lang {
-        "en" => ("ggml-base.en.bin", "en"),
-        "es" => ("ggml-base.bin", "es"),
-        "fr" => ("ggml-base.bin", "fr"),
-        "de" => ("ggml-base.bin", "de"),
-        "it" => ("ggml-base.bin", "it"),
-        "pt" => ("ggml-base.bin", "pt"),
-        "ru" => ("ggml-base.bin", "ru"),
-        "ja" => ("ggml-base.bin", "ja"),
-        "zh" => ("ggml-base.bin", "zh"),
-        "ko" => ("ggml-base.bin", "ko"),
-        "ar" => ("ggml-base.bin", "ar"),
-        "hi" => ("ggml-base.bin", "hi"),
-        _ => {
-            // For unsupported languages, fall back to English model
-            println!(
-                "Warning: Language '{}' not explicitly supported, falling back to English model",
-                lang
-            );
-            ("ggml-base.en.bin", "en")
+    /// Create a manager using the backend from `config`, keeping the
+    /// credentials file next to the file that config came from
+    pub fn for_config(config: &AppConfig) -> Self {
+        Self {
+            file_path: config
+                .file_path()
+                .ok()
+                .map(|path| path.with_file_name("credentials.json")),
+            ..Self::with_backend(config.credential_backend)
         }
-    };
-
-    let model_path = format!("{}{}", base_model_dir, model_name);
-    Ok((model_path, whisper_lang.to_string()))
-}
+    }
 
-/// Format transcript as Markdown with metadata
-pub async fn format_markdown(
-    metadata: &VideoMetadata,
-    transcript: &str,
-    source: &str,
-    include_timestamps: bool,
-    compact: bool,
-    paragraph_length: usize,
-    use_llm: bool,
-    llm_provider: Option<LlmProviderType>,
-) -> String {
-    let mut markdown = String::new();
+    pub fn with_backend(backend: CredentialBackend) -> Self {
+        // Kept next to the config file
+        let file_path = AppConfig::config_path()
+            .ok()
+            .map(|path| path.with_file_name("credentials.json"));
+        Self {
+            service_name: "y2md".to_string(),
+            backend,
+            file_path,
+        }
+    }
 
-    let config = AppConfig::load().ok();
+    /// Store keys in a specific credentials file (implies the file backend)
+    pub fn with_file(path: PathBuf) -> Self {
+        Self {
+            service_name: "y2md".to_string(),
+            backend: CredentialBackend::File,
+            file_path: Some(path),
+        }
+    }
 
-    // Track formatting method and LLM details
-    let mut formatted_by = "standard";
-    let mut actual_llm_provider: Option<String> = None;
-    let mut actual_llm_model: Option<String> = None;
+    pub fn backend(&self) -> CredentialBackend {
+        self.backend
+    }
 
-    // Add YAML front matter
-    markdown.push_str("---\n");
-    markdown.push_str(&format!(
-        "title: \"{}\"\n",
-        escape_markdown(&metadata.title)
-    ));
-    if let Some(channel) = &metadata.channel {
-        markdown.push_str(&format!("channel: \"{}\"\n", escape_markdown(channel)));
+    /// Credentials file used by [`CredentialBackend::File`]
+    pub fn file_path(&self) -> Option<&std::path::Path> {
+        self.file_path.as_deref()
     }
-    markdown.push_str(&format!("url: \"{}\"\n", metadata.url));
-    markdown.push_str(&format!("video_id: \"{}\"\n", metadata.video_id));
-    if let Some(duration) = &metadata.duration {
-        markdown.push_str(&format!("duration: \"{}\"\n", duration));
+
+    pub fn get_api_key(
+        &self,
+        provider_type: &LlmProviderType,
+    ) -> Result<Option<String>, Y2mdError> {
+        self.get_key(&provider_type.to_string())
     }
-    markdown.push_str(&format!("source: \"{}\"\n", source));
-    markdown.push_str("language: \"en\"\n"); // TODO: Detect actual language from transcription
-    markdown.push_str(&format!(
-        "extracted_at: \"{}\"\n",
-        chrono::Utc::now().to_rfc3339()
-    ));
 
-    // Add title
-    markdown.push_str(&format!("# {}\n\n", escape_markdown(&metadata.title)));
+    /// Look up a key by service name, for providers that are not LLMs (e.g. `deepgram`)
+    pub fn get_key(&self, provider_name: &str) -> Result<Option<String>, Y2mdError> {
+        let env_var_name = format!("Y2MD_{}_API_KEY", provider_name.to_uppercase());
 
-    // Add transcript
-    if include_timestamps {
-        // For now, add placeholder timestamps
-        markdown.push_str("[00:00:00] ");
-    }
+        // First, check Y2MD_<PROVIDER>_API_KEY
+        if let Ok(key) = std::env::var(&env_var_name) {
+            return Ok(Some(key));
+        }
 
-    // Use enhanced formatting for better readability
-    let formatted_transcript = if use_llm {
-        println!("Using LLM for enhanced formatting...");
+        // Then check <PROVIDER>_API_KEY as fallback (for common provider names)
+        let fallback_env_var = format!("{}_API_KEY", provider_name.to_uppercase());
+        if let Ok(key) = std::env::var(&fallback_env_var) {
+            return Ok(Some(key));
+        }
 
-        let provider = if let Some(ref p) = llm_provider {
-            p.clone()
-        } else if let Some(ref cfg) = config {
-            cfg.llm.provider.clone()
-        } else {
-            LlmProviderType::Local
-        };
+        if self.backend == CredentialBackend::File {
+            return Ok(self.read_credentials_file()?.remove(provider_name));
+        }
 
-        match format_with_llm(transcript, Some(provider.clone())).await {
-            Ok(llm_formatted) => {
-                println!("LLM formatting completed successfully");
-                formatted_by = "llm";
-                actual_llm_provider = Some(provider.to_string());
-
-                if let Some(ref cfg) = config {
-                    actual_llm_model = Some(match provider {
-                        LlmProviderType::Local => cfg.llm.local.model.clone(),
-                        LlmProviderType::OpenAI => cfg.llm.openai.model.clone(),
-                        LlmProviderType::Anthropic => cfg.llm.anthropic.model.clone(),
-                        LlmProviderType::DeepSeek => cfg.llm.deepseek.model.clone(),
-                        LlmProviderType::Custom => cfg.llm.custom.model.clone(),
-                    });
-                }
+        let entry = self.keyring_entry(provider_name)?;
 
-                llm_formatted
-            }
-            Err(e) => {
-                println!(
-                    "LLM formatting failed: {}, falling back to standard formatting",
-                    e
-                );
-                println!("Tip: Check your LLM configuration with 'y2md config'");
-                format_transcript(transcript, compact, paragraph_length)
-            }
+        match entry.get_password() {
+            Ok(password) => Ok(Some(password)),
+            Err(keyring::Error::NoEntry) => Ok(None),
+            Err(e) => Err(Y2mdError::Keyring(format!(
+                "failed to read the {} API key: {}",
+                provider_name, e
+            ))),
         }
-    } else {
-        format_transcript(transcript, compact, paragraph_length)
-    };
+    }
 
-    // Now add formatting metadata after we know the results
-    let mut front_matter_addition = String::new();
-    front_matter_addition.push_str(&format!("formatted_by: \"{}\"\n", formatted_by));
-    if let Some(provider) = actual_llm_provider {
-        front_matter_addition.push_str(&format!("llm_provider: \"{}\"\n", provider));
+    fn keyring_entry(&self, provider_name: &str) -> Result<keyring::Entry, Y2mdError> {
+        keyring::Entry::new(&self.service_name, provider_name)
+            .map_err(|e| Y2mdError::Keyring(e.to_string()))
     }
-    if let Some(model) = actual_llm_model {
-        front_matter_addition.push_str(&format!("llm_model: \"{}\"\n", model));
+
+    /// Check that the OS keyring can be reached, for diagnostics
+    pub fn check_keyring(&self) -> Result<(), Y2mdError> {
+        match self.keyring_entry("doctor")?.get_password() {
+            Ok(_) | Err(keyring::Error::NoEntry) => Ok(()),
+            Err(e) => Err(Y2mdError::Keyring(e.to_string())),
+        }
     }
 
-    // Insert the formatting metadata before the closing --- of front matter
-    // Find the position of the closing --- marker
-    if let Some(pos) = markdown.find("---\n\n# ") {
-        // Insert the metadata before the closing ---
-        markdown.insert_str(pos, &front_matter_addition);
+    fn credentials_file(&self) -> Result<&PathBuf, Y2mdError> {
+        self.file_path
+            .as_ref()
+            .ok_or_else(|| Y2mdError::Config("Could not determine config directory".to_string()))
     }
 
-    markdown.push_str(&formatted_transcript);
+    fn read_credentials_file(&self) -> Result<HashMap<String, String>, Y2mdError> {
+        let path = self.credentials_file()?;
+        if !path.exists() {
+            return Ok(HashMap::new());
+        }
 
-    markdown
-}
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| Y2mdError::Config(format!("Failed to read {}: {}", path.display(), e)))?;
+        serde_json::from_str(&content)
+            .map_err(|e| Y2mdError::Config(format!("Failed to parse {}: {}", path.display(), e)))
+    }
 
-/// Convert audio file to format expected by whisper
-async fn convert_audio_for_whisper(audio_path: &PathBuf) -> Result<Vec<f32>, Y2mdError> {
-    // First, try to convert the audio to WAV format using FFmpeg for better compatibility
-    let converted_path = convert_audio_to_wav(audio_path).await?;
+    /// Write the credentials file with owner-only permissions
+    fn write_credentials_file(&self, keys: &HashMap<String, String>) -> Result<(), Y2mdError> {
+        let path = self.credentials_file()?;
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent).map_err(|e| {
+                Y2mdError::Config(format!("Failed to create config directory: {}", e))
+            })?;
+        }
 
-    // Then process the converted WAV file with symphonia
-    use symphonia::core::audio::{AudioBufferRef, Signal};
-    use symphonia::core::codecs::{DecoderOptions, CODEC_TYPE_NULL};
-    use symphonia::core::formats::FormatOptions;
-    use symphonia::core::io::MediaSourceStream;
-    use symphonia::core::meta::MetadataOptions;
-    use symphonia::core::probe::Hint;
+        let content = serde_json::to_string_pretty(keys)
+            .map_err(|e| Y2mdError::Config(format!("Failed to serialize credentials: {}", e)))?;
 
-    // Open the converted audio file
-    let file = std::fs::File::open(&converted_path)
-        .map_err(|e| Y2mdError::Config(format!("Failed to open converted audio file: {}", e)))?;
+        let mut options = std::fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
 
-    let mss = MediaSourceStream::new(Box::new(file), Default::default());
+        let write_error = |e: std::io::Error| {
+            Y2mdError::Config(format!("Failed to write {}: {}", path.display(), e))
+        };
+        let mut file = options.open(path).map_err(write_error)?;
+        std::io::Write::write_all(&mut file, content.as_bytes()).map_err(write_error)?;
 
-    // Create a hint to help the format registry guess the format
-    let mut hint = Hint::new();
-    hint.with_extension("wav");
-
-    // Use the default options for metadata and format
-    let meta_opts: MetadataOptions = Default::default();
-    let fmt_opts: FormatOptions = Default::default();
+        // Tighten files created before the mode was applied
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))
+                .map_err(write_error)?;
+        }
 
-    // Probe the media source
-    let probed = symphonia::default::get_probe()
-        .format(&hint, mss, &fmt_opts, &meta_opts)
-        .map_err(|e| Y2mdError::Config(format!("Failed to probe audio format: {}", e)))?;
+        Ok(())
+    }
 
-    // Get the format reader
-    let mut format = probed.format;
+    pub fn set_api_key(
+        &self,
+        provider_type: &LlmProviderType,
+        api_key: &str,
+    ) -> Result<(), Y2mdError> {
+        self.set_key(&provider_type.to_string(), api_key)
+    }
 
-    // Find the first audio track with a known codec
-    let track = format
-        .tracks()
-        .iter()
-        .find(|t| t.codec_params.codec != CODEC_TYPE_NULL)
-        .ok_or_else(|| Y2mdError::Config("No supported audio tracks found".to_string()))?;
-
-    // Create a decoder for the track
-    let mut decoder = symphonia::default::get_codecs()
-        .make(&track.codec_params, &DecoderOptions::default())
-        .map_err(|e| Y2mdError::Config(format!("Failed to create decoder: {}", e)))?;
-
-    // Store all audio samples
-    let mut all_samples = Vec::new();
-
-    // Decode the audio packets
-    while let Ok(packet) = format.next_packet() {
-        match decoder.decode(&packet) {
-            Ok(decoded) => {
-                match decoded {
-                    AudioBufferRef::F32(buf) => {
-                        // For stereo, average the channels
-                        if buf.spec().channels.count() == 2 {
-                            for i in 0..buf.frames() {
-                                let sample = (buf.chan(0)[i] + buf.chan(1)[i]) / 2.0;
-                                all_samples.push(sample);
-                            }
-                        } else {
-                            // For mono, just copy the samples
-                            for i in 0..buf.frames() {
-                                all_samples.push(buf.chan(0)[i]);
-                            }
-                        }
-                    }
-                    AudioBufferRef::S16(buf) => {
-                        // Convert i16 to f32
-                        if buf.spec().channels.count() == 2 {
-                            for i in 0..buf.frames() {
-                                let sample =
-                                    (buf.chan(0)[i] as f32 + buf.chan(1)[i] as f32) / 2.0 / 32768.0;
-                                all_samples.push(sample);
-                            }
-                        } else {
-                            for i in 0..buf.frames() {
-                                all_samples.push(buf.chan(0)[i] as f32 / 32768.0);
-                            }
-                        }
-                    }
-                    _ => {
-                        return Err(Y2mdError::Config(
-                            "Unsupported audio format (only F32 and S16 are supported)".to_string(),
-                        ));
-                    }
-                }
-            }
-            Err(_) => {
-                // Skip decoding errors
-                continue;
-            }
+    /// Store a key by service name, for providers that are not LLMs (e.g. `deepgram`)
+    pub fn set_key(&self, provider_name: &str, api_key: &str) -> Result<(), Y2mdError> {
+        if self.backend == CredentialBackend::File {
+            let mut keys = self.read_credentials_file()?;
+            keys.insert(provider_name.to_string(), api_key.to_string());
+            return self.write_credentials_file(&keys);
         }
+
+        self.keyring_entry(provider_name)?
+            .set_password(api_key)
+            .map_err(|e| {
+                Y2mdError::Keyring(format!(
+                    "failed to store the {} API key: {}",
+                    provider_name, e
+                ))
+            })
     }
 
-    // Clean up the temporary converted file
-    let _ = std::fs::remove_file(&converted_path);
+    pub fn delete_api_key(&self, provider_type: &LlmProviderType) -> Result<(), Y2mdError> {
+        let provider_name = provider_type.to_string();
 
-    if all_samples.is_empty() {
-        return Err(Y2mdError::Config(
-            "No audio samples were decoded".to_string(),
-        ));
+        if self.backend == CredentialBackend::File {
+            let mut keys = self.read_credentials_file()?;
+            if keys.remove(&provider_name).is_some() {
+                self.write_credentials_file(&keys)?;
+            }
+            return Ok(());
+        }
+
+        match self.keyring_entry(&provider_name)?.delete_password() {
+            Ok(()) => Ok(()),
+            Err(keyring::Error::NoEntry) => Ok(()),
+            Err(e) => Err(Y2mdError::Keyring(format!(
+                "failed to delete the {} API key: {}",
+                provider_name, e
+            ))),
+        }
     }
 
-    Ok(all_samples)
+    pub fn has_api_key(&self, provider_type: &LlmProviderType) -> bool {
+        self.get_api_key(provider_type).ok().flatten().is_some()
+    }
 }
 
-/// Convert audio file to WAV format using FFmpeg for better compatibility
-async fn convert_audio_to_wav(audio_path: &PathBuf) -> Result<PathBuf, Y2mdError> {
-    let temp_dir = std::env::temp_dir();
-    let temp_filename = format!("y2md_converted_{}.wav", uuid::Uuid::new_v4());
-    let output_path = temp_dir.join(temp_filename);
+pub fn extract_video_id(url: &str) -> Result<String, Y2mdError> {
+    let url = url.trim();
 
-    // Create progress bar for conversion
-    let progress_bar = ProgressBar::new_spinner();
-    progress_bar.set_style(
-        ProgressStyle::default_spinner()
-            .template("{spinner:.yellow} {msg}")
-            .unwrap()
-            .tick_strings(&["⠋", "⠙", "⠹", "⠸", "⠼", "⠴", "⠦", "⠧", "⠇", "⠏"]),
-    );
-    progress_bar.set_message("Converting audio format...");
-    progress_bar.enable_steady_tick(std::time::Duration::from_millis(100));
+    // Handle youtu.be short URLs
+    if url.contains("youtu.be/") {
+        if let Some(id) = url.split("youtu.be/").nth(1) {
+            return Ok(id.split('?').next().unwrap_or(id).to_string());
+        }
+    }
 
-    println!(
-        "Converting audio to WAV format: {:?} -> {:?}",
-        audio_path, output_path
-    );
+    // Handle youtube.com URLs
+    if url.contains("youtube.com") {
+        let parsed_url =
+            reqwest::Url::parse(url).map_err(|_| Y2mdError::InvalidUrl(url.to_string()))?;
 
-    // Use FFmpeg to convert to WAV format
-    let status = std::process::Command::new("ffmpeg")
-        .args([
-            "-i",
-            audio_path.to_str().unwrap(),
-            "-ac",
-            "1", // Convert to mono
-            "-ar",
-            "16000", // 16kHz sample rate (optimal for whisper)
-            "-acodec",
-            "pcm_f32le", // 32-bit float PCM
-            "-y",        // Overwrite output file
-            output_path.to_str().unwrap(),
-        ])
-        .stdout(std::process::Stdio::null())
-        .stderr(std::process::Stdio::null())
-        .status()
-        .map_err(|e| {
-            if e.kind() == std::io::ErrorKind::NotFound {
-                Y2mdError::FFmpegNotFound
-            } else {
-                Y2mdError::Io(e)
+        // Handle /watch?v= format
+        if let Some(query) = parsed_url.query() {
+            let params: HashMap<_, _> = form_urlencoded::parse(query.as_bytes()).collect();
+            if let Some(v) = params.get("v") {
+                return Ok(v.to_string());
             }
-        })?;
+        }
 
-    if !status.success() {
-        return Err(Y2mdError::Config("FFmpeg conversion failed".to_string()));
+        // Handle /shorts/ format
+        if let Some(segments) = parsed_url.path_segments() {
+            let segments: Vec<_> = segments.collect();
+            if segments.len() == 2 && segments[0] == "shorts" {
+                return Ok(segments[1].to_string());
+            }
+        }
     }
 
-    // Verify the converted file exists and has content
-    if !output_path.exists() {
-        return Err(Y2mdError::Config(
-            "Converted audio file was not created".to_string(),
-        ));
+    // Handle direct video ID (11 characters, alphanumeric + underscore)
+    if url.len() == 11
+        && url
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+    {
+        return Ok(url.to_string());
     }
 
-    let metadata = std::fs::metadata(&output_path)
-        .map_err(|e| Y2mdError::Config(format!("Failed to get file metadata: {}", e)))?;
+    Err(Y2mdError::VideoIdExtraction)
+}
 
-    if metadata.len() == 0 {
-        return Err(Y2mdError::Config(
-            "Converted audio file is empty".to_string(),
-        ));
+/// Validate YouTube URL format
+pub fn validate_youtube_url(url: &str) -> Result<String, Y2mdError> {
+    let video_id = extract_video_id(url)?;
+
+    // YouTube video IDs are typically 11 characters
+    if video_id.len() != 11 {
+        return Err(Y2mdError::InvalidUrl(format!(
+            "Invalid video ID length: {}",
+            video_id
+        )));
     }
 
-    progress_bar.finish_with_message("Audio conversion completed");
-    println!("Audio conversion successful");
-    Ok(output_path)
+    Ok(video_id)
 }
 
-/// Format transcript for better readability
-pub fn format_transcript(transcript: &str, compact: bool, paragraph_length: usize) -> String {
-    if compact {
-        // Simple paragraph format for compact mode
-        return format_paragraphs(transcript, paragraph_length); // More sentences per paragraph
-    }
+/// How long cached `--dump-json` output is reused before yt-dlp is asked again
+const METADATA_CACHE_TTL: std::time::Duration = std::time::Duration::from_secs(24 * 60 * 60);
 
-    // Enhanced formatting for better readability
-    let cleaned = clean_transcript(transcript);
-    // Use configured paragraph length (default 3-5 sentences per paragraph)
-    format_paragraphs(&cleaned, paragraph_length)
+/// Location of the cached yt-dlp metadata for a video
+fn metadata_cache_path(ytdlp: &YtDlpConfig, video_id: &str) -> Option<PathBuf> {
+    let cache_dir = match &ytdlp.cache_dir {
+        Some(dir) => dir.clone(),
+        None => directories::ProjectDirs::from("com", "y2md", "y2md")?
+            .cache_dir()
+            .to_path_buf(),
+    };
+    Some(
+        cache_dir
+            .join("metadata")
+            .join(format!("{}.json", video_id)),
+    )
 }
 
-pub async fn format_with_llm(
-    transcript: &str,
-    provider_override: Option<LlmProviderType>,
-) -> Result<String, Y2mdError> {
-    let config = AppConfig::load()?;
-    let cred_manager = CredentialManager::new();
-
-    let provider = provider_override.unwrap_or(config.llm.provider.clone());
+/// Read cached metadata if it exists and is younger than the TTL
+fn read_cached_metadata(path: &std::path::Path) -> Option<serde_json::Value> {
+    let modified = std::fs::metadata(path).ok()?.modified().ok()?;
+    if modified.elapsed().ok()? > METADATA_CACHE_TTL {
+        return None;
+    }
+    serde_json::from_slice(&std::fs::read(path).ok()?).ok()
+}
 
-    match provider {
-        LlmProviderType::Local => format_with_local(transcript, &config.llm.local).await,
-        LlmProviderType::OpenAI => {
-            let api_key = cred_manager
-                .get_api_key(&LlmProviderType::OpenAI)?
-                .ok_or_else(|| {
-                    Y2mdError::Llm(
-                        "OpenAI API key not set. Use: y2md llm set-key openai".to_string(),
-                    )
-                })?;
-            format_with_openai(transcript, &config.llm.openai, &api_key).await
-        }
-        LlmProviderType::Anthropic => {
-            let api_key = cred_manager
-                .get_api_key(&LlmProviderType::Anthropic)?
-                .ok_or_else(|| {
-                    Y2mdError::Llm(
-                        "Anthropic API key not set. Use: y2md llm set-key anthropic".to_string(),
-                    )
-                })?;
-            format_with_anthropic(transcript, &config.llm.anthropic, &api_key).await
-        }
-        LlmProviderType::DeepSeek => {
-            let api_key = cred_manager
-                .get_api_key(&LlmProviderType::DeepSeek)?
-                .ok_or_else(|| {
-                    Y2mdError::Llm(
-                        "DeepSeek API key not set. Use: y2md llm set-key deepseek".to_string(),
-                    )
-                })?;
-            format_with_deepseek(transcript, &config.llm.deepseek, &api_key).await
-        }
-        LlmProviderType::Custom => {
-            let api_key = cred_manager.get_api_key(&LlmProviderType::Custom)?;
-            format_with_custom(transcript, &config.llm.custom, api_key.as_deref()).await
+/// Fetch the raw yt-dlp `--dump-json` output for a video, using the on-disk
+/// cache unless `refresh` is set
+pub async fn fetch_metadata_json(
+    video_id: &str,
+    ytdlp: &YtDlpConfig,
+    refresh: bool,
+) -> Result<serde_json::Value, Y2mdError> {
+    let cache_path = metadata_cache_path(ytdlp, video_id);
+
+    if !refresh {
+        if let Some(cached) = cache_path.as_deref().and_then(read_cached_metadata) {
+            return Ok(cached);
         }
     }
-}
 
-async fn format_with_local(
-    transcript: &str,
-    llm_config: &LocalLlmConfig,
-) -> Result<String, Y2mdError> {
-    let client = reqwest::Client::new();
+    let url = format!("https://www.youtube.com/watch?v={}", video_id);
 
-    let health_check = client
-        .get(format!("{}/api/tags", llm_config.endpoint))
-        .send()
-        .await;
+    // Use yt-dlp to get video metadata
+    let mut command = ytdlp_command(ytdlp);
+    command.args(["--dump-json", "--no-download", &url]);
+    let output =
+        ytdlp_output_with_retry(ytdlp, command, METADATA_TIMEOUT, "Fetching video metadata")
+            .await?;
 
-    if health_check.is_err() {
-        return Err(Y2mdError::Llm(format!(
-            "Ollama service not available at {}. Make sure Ollama is running",
-            llm_config.endpoint
+    if !output.status.success() {
+        return Err(classify_ytdlp_error(&String::from_utf8_lossy(
+            &output.stderr,
         )));
     }
 
-    let prompt = format!(
-        "Transform this raw transcript into a polished, well-structured markdown document. 
+    // Parse JSON output
+    let metadata_json: serde_json::Value = serde_json::from_slice(&output.stdout)
+        .map_err(|e| Y2mdError::Config(format!("Failed to parse metadata JSON: {}", e)))?;
 
-**Formatting Guidelines:**
-- **Structure**: Create logical sections with appropriate headings (## for main sections, ### for subsections)
-- **Paragraphs**: Group related thoughts into coherent paragraphs (3-5 sentences each)
-- **Readability**: Fix grammar, punctuation, and sentence structure while preserving meaning
-- **Speaker Handling**: If multiple speakers are present, identify them clearly
-- **Content Enhancement**: 
-  - Remove excessive filler words (um, uh, like, you know)
-  - Improve flow between sentences and paragraphs
-  - Add emphasis with **bold** or *italic* where appropriate
-  - Use bullet points for lists and key takeaways
-  - Maintain the original speaker's tone and style
+    // A failed cache write only costs a yt-dlp call next time
+    if let Some(path) = cache_path {
+        if let Some(parent) = path.parent() {
+            let _ = std::fs::create_dir_all(parent);
+        }
+        let _ = std::fs::write(&path, &output.stdout);
+    }
 
-**Transcript:**
+    Ok(metadata_json)
+}
 
-{}
+/// Fetch video metadata from YouTube
+pub async fn fetch_video_metadata(
+    video_id: &str,
+    ytdlp: &YtDlpConfig,
+    refresh: bool,
+) -> Result<VideoMetadata, Y2mdError> {
+    let metadata_json = fetch_metadata_json(video_id, ytdlp, refresh).await?;
+    Ok(parse_video_metadata(&metadata_json, video_id))
+}
 
-**Formatted Markdown:**",
-        transcript
-    );
+/// Extract the fields y2md uses from yt-dlp's `--dump-json` output
+fn parse_video_metadata(metadata_json: &serde_json::Value, video_id: &str) -> VideoMetadata {
+    let url = format!("https://www.youtube.com/watch?v={}", video_id);
 
-    let request_body = serde_json::json!({
-        "model": llm_config.model,
-        "prompt": prompt,
-        "stream": false
-    });
+    // Extract fields from JSON
+    let title = metadata_json["title"]
+        .as_str()
+        .unwrap_or("Unknown Title")
+        .to_string();
 
-    let response = client
-        .post(format!("{}/api/generate", llm_config.endpoint))
-        .json(&request_body)
-        .timeout(std::time::Duration::from_secs(120))
-        .send()
-        .await
-        .map_err(|e| {
-            if e.is_timeout() {
-                Y2mdError::Llm("LLM request timed out after 2 minutes".to_string())
-            } else {
-                Y2mdError::Llm(format!("Failed to connect to Ollama: {}", e))
-            }
-        })?;
+    let channel = metadata_json["uploader"].as_str().map(|s| s.to_string());
 
-    if !response.status().is_success() {
-        return Err(Y2mdError::Llm(format!(
-            "Ollama API returned error: {}",
-            response.status()
-        )));
-    }
+    let duration_seconds = metadata_json["duration"].as_f64().unwrap_or(0.0);
 
-    let response_json: serde_json::Value = response
-        .json()
-        .await
-        .map_err(|e| Y2mdError::Llm(format!("Failed to parse Ollama response: {}", e)))?;
+    let (duration, duration_seconds) = if duration_seconds > 0.0 {
+        (
+            Some(format_duration(duration_seconds)),
+            Some(duration_seconds as u64),
+        )
+    } else {
+        (None, None)
+    };
 
-    let formatted_text = response_json["response"]
+    // yt-dlp reports the upload date as YYYYMMDD
+    let upload_date = metadata_json["upload_date"]
         .as_str()
-        .ok_or_else(|| Y2mdError::Llm("Invalid response format from Ollama".to_string()))?
-        .trim()
-        .to_string();
+        .and_then(|d| chrono::NaiveDate::parse_from_str(d, "%Y%m%d").ok())
+        .map(|d| d.format("%Y-%m-%d").to_string());
+
+    // Slideshows and silent uploads only have formats with `acodec: none`
+    let has_audio = metadata_json["formats"].as_array().map(|formats| {
+        formats.iter().any(|format| {
+            format["acodec"]
+                .as_str()
+                .is_some_and(|codec| codec != "none")
+        })
+    });
 
-    if formatted_text.is_empty() {
-        return Err(Y2mdError::Llm("Ollama returned empty response".to_string()));
+    // Often missing, and sometimes a regional code such as `en-US`
+    let language = metadata_json["language"]
+        .as_str()
+        .and_then(|code| normalize_language_code(code).ok());
+
+    let description = metadata_json["description"]
+        .as_str()
+        .map(str::trim)
+        .filter(|description| !description.is_empty())
+        .map(str::to_string);
+
+    let native_chapters: Vec<Chapter> = metadata_json["chapters"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|chapter| {
+            Some(Chapter {
+                start: chapter["start_time"].as_f64()?,
+                title: chapter["title"].as_str()?.trim().to_string(),
+            })
+        })
+        .collect();
+    let chapters = if native_chapters.is_empty() {
+        description
+            .as_deref()
+            .map(parse_description_chapters)
+            .unwrap_or_default()
+    } else {
+        native_chapters
+    };
+
+    VideoMetadata {
+        title,
+        channel,
+        duration,
+        duration_seconds,
+        video_id: video_id.to_string(),
+        url,
+        upload_date,
+        has_audio,
+        language,
+        description,
+        chapters,
     }
+}
 
-    Ok(formatted_text)
+/// Separators creators put between a chapter timestamp and its title
+const CHAPTER_SEPARATORS: &[char] = &['-', '\u{2013}', '\u{2014}', ':', '|', '.', ')', ']'];
+
+/// Extract chapters from `0:00 Intro` / `01:02:15 - Topic` lines in a video
+/// description. The timestamp may also end the line (`Intro | 0:00`). Like
+/// YouTube, a list only counts when it starts at 0:00 and runs forward, so
+/// stray timestamps in the text are not mistaken for chapters.
+pub fn parse_description_chapters(description: &str) -> Vec<Chapter> {
+    let chapters: Vec<Chapter> = description.lines().filter_map(parse_chapter_line).collect();
+
+    let starts_at_zero = chapters.first().is_some_and(|chapter| chapter.start == 0.0);
+    let ascending = chapters
+        .windows(2)
+        .all(|pair| pair[0].start < pair[1].start);
+    if chapters.len() >= 2 && starts_at_zero && ascending {
+        chapters
+    } else {
+        Vec::new()
+    }
 }
 
-async fn format_with_openai(
-    transcript: &str,
-    llm_config: &OpenAiConfig,
-    api_key: &str,
-) -> Result<String, Y2mdError> {
-    let client = reqwest::Client::new();
-
-    let prompt = format!(
-        "Transform this raw transcript into a polished, well-structured markdown document. 
+/// Parse one description line holding a timestamp and a title
+fn parse_chapter_line(line: &str) -> Option<Chapter> {
+    let line = line
+        .trim()
+        .trim_start_matches(['-', '*', '\u{2022}'])
+        .trim_start();
+    let is_separator = |c: char| c.is_whitespace() || CHAPTER_SEPARATORS.contains(&c);
+
+    // Timestamp first, e.g. "02:15 - Topic" or "(02:15) Topic"
+    let leading = line.trim_start_matches(['(', '[']);
+    let (first, rest) = leading
+        .split_once(char::is_whitespace)
+        .unwrap_or((leading, ""));
+    if let Some(start) = parse_chapter_timestamp(first.trim_end_matches(CHAPTER_SEPARATORS)) {
+        let title = rest.trim_start_matches(is_separator).trim();
+        return (!title.is_empty()).then(|| Chapter {
+            start,
+            title: title.to_string(),
+        });
+    }
 
-**Formatting Guidelines:**
-- **Structure**: Create logical sections with appropriate headings (## for main sections, ### for subsections)
-- **Paragraphs**: Group related thoughts into coherent paragraphs (3-5 sentences each)
-- **Readability**: Fix grammar, punctuation, and sentence structure while preserving meaning
-- **Speaker Handling**: If multiple speakers are present, identify them clearly
-- **Content Enhancement**: 
-  - Remove excessive filler words (um, uh, like, you know)
-  - Improve flow between sentences and paragraphs
-  - Add emphasis with **bold** or *italic* where appropriate
-  - Use bullet points for lists and key takeaways
-  - Maintain the original speaker's tone and style
+    // Timestamp last, e.g. "Topic - 02:15"
+    let (rest, last) = line.rsplit_once(char::is_whitespace)?;
+    let start = parse_chapter_timestamp(last.trim_matches(['(', ')', '[', ']']))?;
+    let title = rest.trim_end_matches(is_separator).trim();
+    (!title.is_empty()).then(|| Chapter {
+        start,
+        title: title.to_string(),
+    })
+}
 
-**Transcript:**
+/// Parse `M:SS`, `MM:SS` or `H:MM:SS` into seconds
+fn parse_chapter_timestamp(timestamp: &str) -> Option<f64> {
+    let parts: Vec<&str> = timestamp.split(':').collect();
+    if !(2..=3).contains(&parts.len())
+        || parts.iter().any(|part| {
+            part.is_empty() || part.len() > 2 || !part.bytes().all(|b| b.is_ascii_digit())
+        })
+    {
+        return None;
+    }
 
-{}",
-        transcript
-    );
+    let values: Vec<u64> = parts.iter().map(|part| part.parse().unwrap_or(0)).collect();
+    // Everything after the leading field is minutes or seconds
+    if values[1..].iter().any(|value| *value >= 60) || parts[1..].iter().any(|part| part.len() != 2)
+    {
+        return None;
+    }
 
-    let request_body = serde_json::json!({
-        "model": llm_config.model,
-        "messages": [
-            {
-                "role": "system",
-                "content": "You are a helpful assistant that formats transcripts into well-structured markdown."
-            },
-            {
-                "role": "user",
-                "content": prompt
-            }
-        ],
-        "temperature": 0.1
-    });
+    Some(values.iter().fold(0, |total, value| total * 60 + value) as f64)
+}
 
-    let response = client
-        .post(format!("{}/chat/completions", llm_config.endpoint))
-        .header("Authorization", format!("Bearer {}", api_key))
-        .json(&request_body)
-        .timeout(std::time::Duration::from_secs(120))
-        .send()
-        .await
-        .map_err(|e| {
-            if e.is_timeout() {
-                Y2mdError::Llm("LLM request timed out after 2 minutes".to_string())
+/// Replace characters that are unsafe in file and directory names
+pub fn sanitize_filename(name: &str) -> String {
+    name.chars()
+        .map(|c| {
+            if c.is_alphanumeric() || c == '-' || c == '_' {
+                c
             } else {
-                Y2mdError::Llm(format!("Failed to connect to OpenAI API: {}", e))
+                '_'
             }
-        })?;
+        })
+        .collect()
+}
 
-    if !response.status().is_success() {
-        return Err(Y2mdError::Llm(format!(
-            "OpenAI API returned error: {}",
-            response.status()
-        )));
+/// Output directory setting with `~` expanded; an empty one means the
+/// current directory
+pub fn normalize_output_dir(dir: &str) -> String {
+    let dir = dir.trim();
+    if dir.is_empty() {
+        ".".to_string()
+    } else {
+        shellexpand::tilde(dir).to_string()
     }
+}
 
-    let response_json: serde_json::Value = response
-        .json()
-        .await
-        .map_err(|e| Y2mdError::Llm(format!("Failed to parse OpenAI response: {}", e)))?;
-
-    let formatted_text = response_json["choices"][0]["message"]["content"]
-        .as_str()
-        .ok_or_else(|| Y2mdError::Llm("Invalid response format from OpenAI".to_string()))?
-        .trim()
-        .to_string();
+/// Create the output directory when it is missing, naming it in the error
+pub fn ensure_output_dir(dir: impl AsRef<std::path::Path>) -> Result<(), Y2mdError> {
+    let dir = dir.as_ref();
+    std::fs::create_dir_all(dir).map_err(|e| {
+        Y2mdError::Config(format!(
+            "Cannot create output directory '{}': {}",
+            dir.display(),
+            e
+        ))
+    })
+}
 
-    if formatted_text.is_empty() {
-        return Err(Y2mdError::Llm("OpenAI returned empty response".to_string()));
+/// Expand `{channel}`, `{video_id}`, `{year}`, `{month}` and `{upload_date}`
+/// placeholders in an output directory template.
+///
+/// Dates come from the video's upload date, falling back to today when
+/// yt-dlp did not report one. Plain paths are returned unchanged.
+pub fn expand_output_dir(template: &str, metadata: &VideoMetadata) -> String {
+    if !template.contains('{') {
+        return template.to_string();
     }
 
-    Ok(formatted_text)
+    let date = metadata
+        .upload_date
+        .as_deref()
+        .and_then(|d| chrono::NaiveDate::parse_from_str(d, "%Y-%m-%d").ok())
+        .unwrap_or_else(|| chrono::Utc::now().date_naive());
+    let channel = sanitize_filename(metadata.channel.as_deref().unwrap_or("unknown_channel"));
+
+    template
+        .replace("{channel}", &channel)
+        .replace("{video_id}", &sanitize_filename(&metadata.video_id))
+        .replace("{year}", &date.format("%Y").to_string())
+        .replace("{month}", &date.format("%m").to_string())
+        .replace("{upload_date}", &date.format("%Y-%m-%d").to_string())
 }
 
-async fn format_with_anthropic(
-    transcript: &str,
-    llm_config: &AnthropicConfig,
-    api_key: &str,
-) -> Result<String, Y2mdError> {
-    let client = reqwest::Client::new();
+/// Default output filename (without extension)
+pub const DEFAULT_FILENAME_TEMPLATE: &str = "{date}_{video_id}_{title}";
 
-    let prompt = format!(
-        "Transform this raw transcript into a polished, well-structured markdown document. 
+/// Expand `{date}`, `{video_id}`, `{title}`, `{channel}` and `{upload_date}`
+/// placeholders in a filename template (without extension).
+///
+/// `{date}` is the extraction date; `{upload_date}` falls back to it when
+/// yt-dlp did not report one. Substituted values are sanitized.
+pub fn expand_filename_template(
+    template: &str,
+    metadata: &VideoMetadata,
+    extracted_on: chrono::NaiveDate,
+) -> String {
+    let upload_date = metadata
+        .upload_date
+        .as_deref()
+        .and_then(|d| chrono::NaiveDate::parse_from_str(d, "%Y-%m-%d").ok())
+        .unwrap_or(extracted_on);
+    let channel = sanitize_filename(metadata.channel.as_deref().unwrap_or("unknown_channel"));
+
+    template
+        .replace("{date}", &extracted_on.format("%Y-%m-%d").to_string())
+        .replace("{video_id}", &sanitize_filename(&metadata.video_id))
+        .replace("{title}", &sanitize_filename(&metadata.title))
+        .replace("{channel}", &channel)
+        .replace("{upload_date}", &upload_date.format("%Y-%m-%d").to_string())
+}
 
-**Formatting Guidelines:**
-- **Structure**: Create logical sections with appropriate headings (## for main sections, ### for subsections)
-- **Paragraphs**: Group related thoughts into coherent paragraphs (3-5 sentences each)
-- **Readability**: Fix grammar, punctuation, and sentence structure while preserving meaning
-- **Speaker Handling**: If multiple speakers are present, identify them clearly
-- **Content Enhancement**: 
-  - Remove excessive filler words (um, uh, like, you know)
-  - Improve flow between sentences and paragraphs
-  - Add emphasis with **bold** or *italic* where appropriate
-  - Use bullet points for lists and key takeaways
-  - Maintain the original speaker's tone and style
+/// Parse a duration such as `90`, `45m`, `3h` or `1h30m` into seconds.
+/// Bare numbers are seconds.
+pub fn parse_duration_arg(input: &str) -> Result<u64, String> {
+    let input = input.trim().to_lowercase();
+    if input.is_empty() {
+        return Err("Duration cannot be empty".to_string());
+    }
 
-**Transcript:**
+    if let Ok(seconds) = input.parse::<u64>() {
+        return Ok(seconds);
+    }
 
-{}",
-        transcript
-    );
+    let mut total = 0u64;
+    let mut number = String::new();
 
-    let request_body = serde_json::json!({
-        "model": llm_config.model,
-        "max_tokens": 4096,
-        "messages": [
-            {
-                "role": "user",
-                "content": prompt
-            }
-        ]
-    });
+    for c in input.chars() {
+        if c.is_ascii_digit() {
+            number.push(c);
+            continue;
+        }
 
-    let response = client
-        .post(format!("{}/messages", llm_config.endpoint))
-        .header("anthropic-version", "2023-06-01")
-        .header("x-api-key", api_key)
-        .json(&request_body)
-        .timeout(std::time::Duration::from_secs(120))
-        .send()
-        .await
-        .map_err(|e| {
-            if e.is_timeout() {
-                Y2mdError::Llm("LLM request timed out after 2 minutes".to_string())
-            } else {
-                Y2mdError::Llm(format!("Failed to connect to Anthropic API: {}", e))
+        let multiplier = match c {
+            'h' => 3600,
+            'm' => 60,
+            's' => 1,
+            _ => {
+                return Err(format!(
+                    "Invalid duration '{}': unknown unit '{}'",
+                    input, c
+                ))
             }
+        };
+
+        let value: u64 = number.parse().map_err(|_| {
+            format!(
+                "Invalid duration '{}': expected a number before '{}'",
+                input, c
+            )
         })?;
+        total = value
+            .checked_mul(multiplier)
+            .and_then(|seconds| total.checked_add(seconds))
+            .ok_or_else(|| format!("Invalid duration '{}': too long", input))?;
+        number.clear();
+    }
 
-    if !response.status().is_success() {
-        let status = response.status();
-        let error_text = response.text().await.unwrap_or_default();
-        return Err(Y2mdError::Llm(format!(
-            "Anthropic API returned error {}: {}",
-            status, error_text
-        )));
+    if !number.is_empty() {
+        return Err(format!(
+            "Invalid duration '{}': missing unit after '{}' (use h, m or s)",
+            input, number
+        ));
     }
 
-    let response_json: serde_json::Value = response
-        .json()
-        .await
-        .map_err(|e| Y2mdError::Llm(format!("Failed to parse Anthropic response: {}", e)))?;
+    Ok(total)
+}
 
-    let formatted_text = response_json["content"][0]["text"]
-        .as_str()
-        .ok_or_else(|| Y2mdError::Llm("Invalid response format from Anthropic".to_string()))?
+/// ISO 639-1 two-letter language codes
+const ISO_639_1_CODES: &[&str] = &[
+    "aa", "ab", "ae", "af", "ak", "am", "an", "ar", "as", "av", "ay", "az", "ba", "be", "bg", "bh",
+    "bi", "bm", "bn", "bo", "br", "bs", "ca", "ce", "ch", "co", "cr", "cs", "cu", "cv", "cy", "da",
+    "de", "dv", "dz", "ee", "el", "en", "eo", "es", "et", "eu", "fa", "ff", "fi", "fj", "fo", "fr",
+    "fy", "ga", "gd", "gl", "gn", "gu", "gv", "ha", "he", "hi", "ho", "hr", "ht", "hu", "hy", "hz",
+    "ia", "id", "ie", "ig", "ii", "ik", "io", "is", "it", "iu", "ja", "jv", "ka", "kg", "ki", "kj",
+    "kk", "kl", "km", "kn", "ko", "kr", "ks", "ku", "kv", "kw", "ky", "la", "lb", "lg", "li", "ln",
+    "lo", "lt", "lu", "lv", "mg", "mh", "mi", "mk", "ml", "mn", "mr", "ms", "mt", "my", "na", "nb",
+    "nd", "ne", "ng", "nl", "nn", "no", "nr", "nv", "ny", "oc", "oj", "om", "or", "os", "pa", "pi",
+    "pl", "ps", "pt", "qu", "rm", "rn", "ro", "ru", "rw", "sa", "sc", "sd", "se", "sg", "si", "sk",
+    "sl", "sm", "sn", "so", "sq", "sr", "ss", "st", "su", "sv", "sw", "ta", "te", "tg", "th", "ti",
+    "tk", "tl", "tn", "to", "tr", "ts", "tt", "tw", "ty", "ug", "uk", "ur", "uz", "ve", "vi", "vo",
+    "wa", "wo", "xh", "yi", "yo", "za", "zh", "zu",
+];
+
+/// Languages with a dedicated whisper model mapping, suggested when `--lang` is invalid
+const COMMON_LANGUAGES: &[&str] = &[
+    "en", "es", "fr", "de", "it", "pt", "ru", "ja", "zh", "ko", "ar", "hi",
+];
+
+/// Validate a language code against ISO 639-1, normalizing case and region
+/// subtags (`en-US` and `pt_BR` become `en` and `pt`)
+pub fn normalize_language_code(input: &str) -> Result<String, String> {
+    let code = input
         .trim()
-        .to_string();
+        .split(['-', '_'])
+        .next()
+        .unwrap_or_default()
+        .to_lowercase();
 
-    if formatted_text.is_empty() {
-        return Err(Y2mdError::Llm(
-            "Anthropic returned empty response".to_string(),
-        ));
+    if ISO_639_1_CODES.contains(&code.as_str()) {
+        Ok(code)
+    } else {
+        Err(format!(
+            "Unknown language code '{}': use a two-letter ISO 639-1 code (supported: {})",
+            input,
+            COMMON_LANGUAGES.join(", ")
+        ))
     }
-
-    Ok(formatted_text)
 }
 
-async fn format_with_deepseek(
-    transcript: &str,
-    llm_config: &DeepSeekConfig,
-    api_key: &str,
-) -> Result<String, Y2mdError> {
-    let client = reqwest::Client::new();
-
-    let prompt = format!(
-        "Please format the following transcript into well-structured markdown. 
-        Keep the original content but improve readability by:
-        - Organizing into logical paragraphs
-        - Fixing any grammar or punctuation issues
-        - Removing filler words if appropriate
-        - Maintaining the original meaning and tone
-        
-        Transcript:\n\n{}",
-        transcript
-    );
+/// Language value that asks Whisper to detect the spoken language
+pub const AUTO_LANGUAGE: &str = "auto";
 
-    let request_body = serde_json::json!({
-        "model": llm_config.model,
-        "messages": [
-            {
-                "role": "system",
-                "content": "You are a helpful assistant that formats transcripts into well-structured markdown."
-            },
-            {
-                "role": "user",
-                "content": prompt
-            }
-        ],
-        "temperature": 0.1
-    });
+/// Parse a `--lang` value: [`AUTO_LANGUAGE`] or an ISO 639-1 code
+pub fn parse_language_arg(input: &str) -> Result<String, String> {
+    if input.trim().eq_ignore_ascii_case(AUTO_LANGUAGE) {
+        return Ok(AUTO_LANGUAGE.to_string());
+    }
+    normalize_language_code(input)
+}
 
-    let response = client
-        .post(format!("{}/chat/completions", llm_config.endpoint))
-        .header("Authorization", format!("Bearer {}", api_key))
-        .json(&request_body)
-        .timeout(std::time::Duration::from_secs(120))
-        .send()
-        .await
-        .map_err(|e| {
-            if e.is_timeout() {
-                Y2mdError::Llm("LLM request timed out after 2 minutes".to_string())
-            } else {
-                Y2mdError::Llm(format!("Failed to connect to DeepSeek API: {}", e))
-            }
-        })?;
+/// Validate an `--output-name`: a bare file name with no directory part
+pub fn parse_output_name(input: &str) -> Result<String, String> {
+    let name = input.trim();
+    if name.is_empty() || name == "." || name == ".." {
+        return Err(format!("Invalid output name '{}'", input));
+    }
+    if name.contains(['/', '\\']) {
+        return Err(format!(
+            "Output name '{}' must not contain path separators; use --out-dir for the directory",
+            input
+        ));
+    }
+    Ok(name.to_string())
+}
 
-    if !response.status().is_success() {
-        return Err(Y2mdError::Llm(format!(
-            "DeepSeek API returned error: {}",
-            response.status()
-        )));
+/// Parse a sentences-per-paragraph count, rejecting zero
+pub fn parse_paragraph_length(input: &str) -> Result<usize, String> {
+    match input.trim().parse::<usize>() {
+        Ok(0) => Err("Paragraph length must be at least 1".to_string()),
+        Ok(length) => Ok(length),
+        Err(_) => Err(format!(
+            "Invalid paragraph length '{}': expected a positive number",
+            input
+        )),
     }
+}
 
-    let response_json: serde_json::Value = response
-        .json()
-        .await
-        .map_err(|e| Y2mdError::Llm(format!("Failed to parse DeepSeek response: {}", e)))?;
+/// How sentences are grouped into paragraphs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParagraphLength {
+    /// A fixed number of sentences per paragraph
+    Sentences(usize),
+    /// Roughly equal word counts per paragraph, whatever the sentence length
+    Auto,
+}
 
-    let formatted_text = response_json["choices"][0]["message"]["content"]
-        .as_str()
-        .ok_or_else(|| Y2mdError::Llm("Invalid response format from DeepSeek".to_string()))?
-        .trim()
-        .to_string();
+impl Default for ParagraphLength {
+    fn default() -> Self {
+        ParagraphLength::Sentences(4)
+    }
+}
 
-    if formatted_text.is_empty() {
-        return Err(Y2mdError::Llm(
-            "DeepSeek returned empty response".to_string(),
-        ));
+impl std::fmt::Display for ParagraphLength {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ParagraphLength::Sentences(count) => write!(f, "{}", count),
+            ParagraphLength::Auto => write!(f, "auto"),
+        }
     }
+}
+
+impl std::str::FromStr for ParagraphLength {
+    type Err = String;
 
-    Ok(formatted_text)
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.trim().eq_ignore_ascii_case("auto") {
+            Ok(ParagraphLength::Auto)
+        } else {
+            parse_paragraph_length(s).map(ParagraphLength::Sentences)
+        }
+    }
 }
 
-async fn format_with_custom(
-    transcript: &str,
-    llm_config: &CustomLlmConfig,
-    api_key: Option<&str>,
-) -> Result<String, Y2mdError> {
-    if llm_config.endpoint.is_empty() {
-        return Err(Y2mdError::Llm(
-            "Custom LLM endpoint not configured. Please set it in your config file.".to_string(),
-        ));
+// Stored in the config as either a number or "auto"
+impl Serialize for ParagraphLength {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            ParagraphLength::Sentences(count) => serializer.serialize_u64(*count as u64),
+            ParagraphLength::Auto => serializer.serialize_str("auto"),
+        }
     }
+}
 
-    let client = reqwest::Client::new();
+impl<'de> Deserialize<'de> for ParagraphLength {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Raw {
+            Count(usize),
+            Text(String),
+        }
 
-    let prompt = format!(
-        "Please format the following transcript into well-structured markdown. 
-        Keep the original content but improve readability by:
-        - Organizing into logical paragraphs
-        - Fixing any grammar or punctuation issues
-        - Removing filler words if appropriate
-        - Maintaining the original meaning and tone
-        
-        Transcript:\n\n{}",
-        transcript
-    );
+        match Raw::deserialize(deserializer)? {
+            Raw::Count(count) => Ok(ParagraphLength::Sentences(count)),
+            Raw::Text(text) => text.parse().map_err(serde::de::Error::custom),
+        }
+    }
+}
 
-    let request_body = serde_json::json!({
-        "model": llm_config.model,
-        "messages": [
-            {
-                "role": "system",
-                "content": "You are a helpful assistant that formats transcripts into well-structured markdown."
-            },
+/// Timezone for the extraction date in front matter, footers and filenames
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Timezone {
+    #[default]
+    Utc,
+    /// The system's timezone
+    Local,
+    /// An IANA timezone such as `Europe/Berlin`
+    Named(chrono_tz::Tz),
+}
+
+impl Timezone {
+    /// The current time in this timezone
+    pub fn now(&self) -> chrono::DateTime<chrono::FixedOffset> {
+        let now = chrono::Utc::now();
+        match self {
+            Timezone::Utc => now.fixed_offset(),
+            Timezone::Local => now.with_timezone(&chrono::Local).fixed_offset(),
+            Timezone::Named(tz) => now.with_timezone(tz).fixed_offset(),
+        }
+    }
+}
+
+impl std::fmt::Display for Timezone {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Timezone::Utc => write!(f, "utc"),
+            Timezone::Local => write!(f, "local"),
+            Timezone::Named(tz) => write!(f, "{}", tz),
+        }
+    }
+}
+
+impl std::str::FromStr for Timezone {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "utc" => Ok(Timezone::Utc),
+            "local" => Ok(Timezone::Local),
+            _ => s
+                .trim()
+                .parse::<chrono_tz::Tz>()
+                .map(Timezone::Named)
+                .map_err(|_| {
+                    format!(
+                    "Unknown timezone: {} (expected utc, local or a name such as Europe/Berlin)",
+                    s
+                )
+                }),
+        }
+    }
+}
+
+// Stored in the config as "utc", "local" or the timezone name
+impl Serialize for Timezone {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Timezone {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+/// Check a strftime-style `date_format` such as `%Y-%m-%d %H:%M`. The date is
+/// written inside a quoted YAML string, so quotes, backslashes, tabs and line
+/// breaks (including `%n` and `%t`) are rejected.
+pub fn check_date_format(format: &str) -> Result<(), String> {
+    use chrono::format::Item;
+
+    for item in chrono::format::StrftimeItems::new(format) {
+        match item {
+            Item::Error => return Err(format!("Invalid date_format '{}'", format)),
+            Item::Literal(text) | Item::Space(text)
+                if text.contains(['"', '\\']) || text.contains(char::is_control) =>
             {
-                "role": "user",
-                "content": prompt
+                return Err(format!(
+                    "Invalid date_format '{}': quotes, backslashes, tabs and line breaks (%n, %t) would break the front matter",
+                    format
+                ));
             }
-        ],
-        "temperature": 0.1
-    });
+            _ => {}
+        }
+    }
+    Ok(())
+}
 
-    let mut request_builder = client
-        .post(format!("{}/chat/completions", llm_config.endpoint))
-        .json(&request_body)
-        .timeout(std::time::Duration::from_secs(120));
+/// Check a video's length against optional bounds, returning the reason to skip it
+pub fn check_duration_limits(
+    metadata: &VideoMetadata,
+    min_duration: Option<u64>,
+    max_duration: Option<u64>,
+) -> Result<(), Y2mdError> {
+    let Some(seconds) = metadata.duration_seconds else {
+        return Ok(());
+    };
 
-    if let Some(key) = api_key {
-        request_builder = request_builder.header("Authorization", format!("Bearer {}", key));
+    if let Some(min) = min_duration {
+        if seconds < min {
+            return Err(Y2mdError::Skipped(format!(
+                "'{}' is {} long, shorter than the minimum of {}",
+                metadata.title,
+                format_duration(seconds as f64),
+                format_duration(min as f64)
+            )));
+        }
     }
 
-    let response = request_builder.send().await.map_err(|e| {
-        if e.is_timeout() {
-            Y2mdError::Llm("LLM request timed out after 2 minutes".to_string())
-        } else {
-            Y2mdError::Llm(format!("Failed to connect to custom LLM API: {}", e))
+    if let Some(max) = max_duration {
+        if seconds > max {
+            return Err(Y2mdError::Skipped(format!(
+                "'{}' is {} long, longer than the maximum of {}",
+                metadata.title,
+                format_duration(seconds as f64),
+                format_duration(max as f64)
+            )));
         }
-    })?;
+    }
 
-    if !response.status().is_success() {
-        return Err(Y2mdError::Llm(format!(
-            "Custom LLM API returned error: {}",
-            response.status()
-        )));
+    Ok(())
+}
+
+/// Find a transcript of `video_id` in `dir` in the given output format.
+/// Markdown files match on their front matter, whatever their file name, and
+/// only the front matter is read; plain text has none, so a `.txt` file
+/// matches when its name contains the video ID (as the default template does).
+pub fn find_existing_transcript(
+    dir: &std::path::Path,
+    video_id: &str,
+    format: OutputFormat,
+) -> Option<PathBuf> {
+    use std::io::BufRead;
+
+    let quoted = format!("video_id: \"{}\"", video_id);
+    let bare = format!("video_id: {}", video_id);
+
+    let mut entries: Vec<PathBuf> = std::fs::read_dir(dir)
+        .ok()?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| {
+            path.extension()
+                .is_some_and(|ext| ext == format.extension())
+        })
+        .collect();
+    entries.sort();
+
+    entries.into_iter().find(|path| {
+        if format == OutputFormat::Txt {
+            return path
+                .file_stem()
+                .is_some_and(|stem| stem.to_string_lossy().contains(video_id));
+        }
+        let Ok(file) = std::fs::File::open(path) else {
+            return false;
+        };
+        let mut lines = std::io::BufReader::new(file).lines().map_while(Result::ok);
+        if lines.next().as_deref() != Some("---") {
+            return false;
+        }
+        lines
+            .take_while(|line| line != "---")
+            .any(|line| line.trim() == quoted || line.trim() == bare)
+    })
+}
+
+/// Format duration in seconds to HH:MM:SS
+fn format_duration(seconds: f64) -> String {
+    let total_seconds = seconds as u64;
+    let hours = total_seconds / 3600;
+    let minutes = (total_seconds % 3600) / 60;
+    let seconds = total_seconds % 60;
+
+    if hours > 0 {
+        format!("{:02}:{:02}:{:02}", hours, minutes, seconds)
+    } else {
+        format!("{:02}:{:02}", minutes, seconds)
     }
+}
 
-    let response_json: serde_json::Value = response
-        .json()
-        .await
-        .map_err(|e| Y2mdError::Llm(format!("Failed to parse custom LLM response: {}", e)))?;
+/// What to do when a video has captions, but not in the requested language
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum CaptionLangFallback {
+    /// Transcribe the audio in the requested language
+    #[default]
+    Stt,
+    /// Use the captions in a language the video has
+    Available,
+}
 
-    let formatted_text = response_json["choices"][0]["message"]["content"]
-        .as_str()
-        .ok_or_else(|| Y2mdError::Llm("Invalid response format from custom LLM".to_string()))?
-        .trim()
-        .to_string();
+impl std::fmt::Display for CaptionLangFallback {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CaptionLangFallback::Stt => write!(f, "stt"),
+            CaptionLangFallback::Available => write!(f, "available"),
+        }
+    }
+}
 
-    if formatted_text.is_empty() {
-        return Err(Y2mdError::Llm(
-            "Custom LLM returned empty response".to_string(),
-        ));
+impl std::str::FromStr for CaptionLangFallback {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "stt" => Ok(CaptionLangFallback::Stt),
+            "available" => Ok(CaptionLangFallback::Available),
+            _ => Err(format!(
+                "Unknown caption language fallback: {} (expected stt or available)",
+                s
+            )),
+        }
     }
+}
 
-    Ok(formatted_text)
+/// Caption tracks YouTube offers for a video, as language codes
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CaptionTracks {
+    /// Subtitles uploaded by the creator
+    pub manual: Vec<String>,
+    /// Automatically generated captions, excluding machine translations
+    pub automatic: Vec<String>,
 }
 
-/// Clean and normalize transcript text
-fn clean_transcript(text: &str) -> String {
-    let mut result = String::new();
-    let words: Vec<&str> = text.split_whitespace().collect();
+impl CaptionTracks {
+    /// Read the `subtitles` and `automatic_captions` maps from yt-dlp's `--dump-json` output
+    pub fn from_metadata_json(metadata_json: &serde_json::Value) -> Self {
+        let languages = |key: &str, skip_translations: bool| -> Vec<String> {
+            let mut languages: Vec<String> = metadata_json[key]
+                .as_object()
+                .into_iter()
+                .flatten()
+                .filter(|(lang, _)| lang.as_str() != "live_chat")
+                .filter(|(_, formats)| {
+                    // YouTube offers every auto caption translated into dozens of
+                    // languages; translated tracks carry a `tlang` parameter
+                    !skip_translations
+                        || formats.as_array().into_iter().flatten().any(|format| {
+                            format["url"]
+                                .as_str()
+                                .is_some_and(|url| !url.contains("tlang="))
+                        })
+                })
+                .map(|(lang, _)| lang.clone())
+                .collect();
+            languages.sort();
+            languages
+        };
 
-    for (i, word) in words.iter().enumerate() {
-        if !result.is_empty() {
-            result.push(' ');
+        CaptionTracks {
+            manual: languages("subtitles", false),
+            automatic: languages("automatic_captions", true),
         }
+    }
 
-        // Capitalize first word of sentence
-        if i == 0 || result.ends_with(['.', '!', '?']) {
-            result.push_str(&capitalize_first_letter(word));
-        } else {
-            result.push_str(word);
+    /// Whether any track matches `language`, including regional variants (`en` matches `en-US`)
+    pub fn has_language(&self, language: &str) -> bool {
+        matches_language(self.manual.iter().chain(&self.automatic), language)
+    }
+
+    /// Whether creator-uploaded subtitles exist for `language`
+    pub fn has_manual(&self, language: &str) -> bool {
+        matches_language(self.manual.iter(), language)
+    }
+
+    /// Every language with a track, without duplicates
+    pub fn languages(&self) -> Vec<&str> {
+        let mut languages: Vec<&str> = self
+            .manual
+            .iter()
+            .chain(&self.automatic)
+            .map(String::as_str)
+            .collect();
+        languages.sort_unstable();
+        languages.dedup();
+        languages
+    }
+
+    /// Caption language to download for `requested`: the requested one when it
+    /// has a track, otherwise another language if `fallback` allows it
+    /// (creator subtitles first). `None` means speech-to-text.
+    pub fn language_for(&self, requested: &str, fallback: CaptionLangFallback) -> Option<String> {
+        if self.has_language(requested) {
+            return Some(requested.to_string());
+        }
+        match fallback {
+            CaptionLangFallback::Stt => None,
+            CaptionLangFallback::Available => {
+                self.manual.first().or(self.automatic.first()).cloned()
+            }
         }
+    }
+}
 
-        // Add punctuation if missing at natural breaks
-        if should_add_punctuation(word, i, words.len()) {
-            result.push('.');
+fn matches_language<'a>(mut tracks: impl Iterator<Item = &'a String>, language: &str) -> bool {
+    let language = language.to_lowercase();
+    tracks.any(|lang| {
+        let lang = lang.to_lowercase();
+        lang == language || lang.starts_with(&format!("{}-", language))
+    })
+}
+
+/// List the caption tracks for a video from its (cached) metadata
+pub async fn list_caption_tracks(
+    video_id: &str,
+    ytdlp: &YtDlpConfig,
+) -> Result<CaptionTracks, Y2mdError> {
+    let metadata_json = fetch_metadata_json(video_id, ytdlp, false).await?;
+    Ok(CaptionTracks::from_metadata_json(&metadata_json))
+}
+
+/// The table of download formats yt-dlp offers for a video (`yt-dlp -F`),
+/// for choosing an `audio_format` that needs no conversion
+pub async fn list_download_formats(
+    video_id: &str,
+    ytdlp: &YtDlpConfig,
+) -> Result<String, Y2mdError> {
+    let url = format!("https://www.youtube.com/watch?v={}", video_id);
+
+    let mut command = ytdlp_command(ytdlp);
+    command.args(["-F", "--no-download", &url]);
+    let output =
+        ytdlp_output_with_retry(ytdlp, command, METADATA_TIMEOUT, "Listing formats").await?;
+
+    if !output.status.success() {
+        return Err(classify_ytdlp_error(&String::from_utf8_lossy(
+            &output.stderr,
+        )));
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Keep only the audio-only rows of a `yt-dlp -F` table, with its header
+pub fn audio_only_formats(table: &str) -> String {
+    let mut lines = Vec::new();
+    let mut in_rows = false;
+    for line in table.lines() {
+        if in_rows {
+            if line.contains("audio only") {
+                lines.push(line);
+            }
+        } else if line.starts_with("ID ") || line.starts_with('-') {
+            // Column names and the separator line under them
+            lines.push(line);
+            in_rows = line.starts_with('-');
         }
     }
+    lines.join("\n")
+}
 
-    result
+/// Check whether captions exist in `language` (English when `None`)
+pub async fn check_captions_available(
+    video_id: &str,
+    language: Option<&str>,
+    ytdlp: &YtDlpConfig,
+) -> Result<bool, Y2mdError> {
+    let tracks = list_caption_tracks(video_id, ytdlp).await?;
+    Ok(tracks.has_language(language.unwrap_or("en")))
 }
 
-/// Format text into readable paragraphs
-fn format_paragraphs(text: &str, sentences_per_paragraph: usize) -> String {
-    let mut result = String::new();
-    let sentences: Vec<&str> = text
-        .split(['.', '!', '?'])
-        .filter(|s| !s.trim().is_empty())
+/// Extract captions from YouTube video
+///
+/// With `capitalization` set, auto-caption text goes through
+/// [`fix_caption_capitalization`] with those proper nouns before formatting;
+/// manual captions are left as the creator wrote them.
+pub async fn extract_captions(
+    video_id: &str,
+    language: Option<&str>,
+    force_formatting: bool,
+    preserve_paragraphs: bool,
+    capitalization: Option<&[String]>,
+    ytdlp: &YtDlpConfig,
+) -> Result<(String, String, Vec<TranscriptSegment>), Y2mdError> {
+    let url = format!("https://www.youtube.com/watch?v={}", video_id);
+    let lang = language.unwrap_or("en");
+
+    // Use yt-dlp to download captions into the temp directory, so a read-only
+    // working directory does not matter, under a name concurrent runs do not share
+    let download = TempDownload::new(std::env::temp_dir(), &format!("{}_captions", video_id));
+    let output_template = download.template().to_string_lossy().into_owned();
+    let mut command = ytdlp_command(ytdlp);
+    command.args([
+        "--write-sub",
+        "--write-auto-sub",
+        "--sub-lang",
+        lang,
+        "--skip-download",
+        "--convert-subs",
+        "srt",
+        "-o",
+        &output_template,
+        &url,
+    ]);
+    let output =
+        ytdlp_output_with_retry(ytdlp, command, CAPTIONS_TIMEOUT, "Downloading captions").await?;
+
+    if !output.status.success() {
+        return Err(classify_ytdlp_error(&String::from_utf8_lossy(
+            &output.stderr,
+        )));
+    }
+
+    // Look for the generated caption file; yt-dlp leaves VTT in place when
+    // conversion is unavailable or overridden through extra arguments
+    let Some(caption_path) = [SubtitleFormat::Srt, SubtitleFormat::Vtt]
+        .iter()
+        .map(|format| {
+            download
+                .dir
+                .join(format!("{}.{}.{}", download.stem, lang, format.extension()))
+        })
+        .find(|path| path.exists())
+    else {
+        return Err(Y2mdError::YtDlp(format!(
+            "no {} caption file was written",
+            lang
+        )));
+    };
+
+    // Read the caption file; `download` removes it when dropped
+    let caption_content = std::fs::read_to_string(&caption_path)?;
+
+    // Convert to plain text, keeping the timed cues for subtitle output
+    let format = SubtitleFormat::detect(&caption_content, Some(&caption_path));
+    let raw_text = match format {
+        SubtitleFormat::Srt => srt_to_plain_text(&caption_content),
+        SubtitleFormat::Vtt => vtt_to_plain_text(&caption_content),
+    };
+    let segments: Vec<TranscriptSegment> = parse_subtitles(&caption_content, format)
+        .into_iter()
+        .map(TranscriptSegment::from)
         .collect();
 
-    let mut sentence_count = 0;
-    let mut current_paragraph = String::new();
+    // Human-authored captions carry their own structure and capitalization;
+    // auto captions do not
+    let manual = (preserve_paragraphs || capitalization.is_some())
+        && list_caption_tracks(video_id, ytdlp)
+            .await
+            .is_ok_and(|tracks| tracks.has_manual(lang));
+    let manual_captions = preserve_paragraphs && manual;
+
+    // Auto captions tend to be all lowercase
+    let text = match capitalization {
+        Some(proper_nouns) if !manual => {
+            fix_caption_capitalization(&raw_text, proper_nouns, lang.starts_with("en"))
+        }
+        _ => raw_text.clone(),
+    };
+
+    // Only apply enhanced formatting if the text doesn't contain music notation
+    // or other special formatting that should be preserved
+    let formatted_text = if manual_captions {
+        println!("Keeping paragraph structure from manual captions");
+        caption_paragraphs(&segments)
+    } else if force_formatting {
+        // Force enhanced formatting regardless of content
+        println!("Applying enhanced formatting to captions...");
+        let result = format_transcript(&text, false, 4);
+        println!("Formatting completed");
+        result
+    } else if text.contains('♪') || text.contains('[') {
+        // Preserve original formatting for music videos and special content
+        println!("Preserving original formatting for music/special content");
+        text
+    } else {
+        // Apply enhanced formatting for regular speech
+        println!("Applying enhanced formatting to captions...");
+        let result = format_transcript(&text, false, 4);
+        println!("Formatting completed");
+        result
+    };
+
+    Ok((formatted_text, raw_text, segments))
+}
+
+/// Contractions of "I" that auto captions write in lowercase
+const LOWERCASE_I_FORMS: &[&str] = &["i", "i'm", "i've", "i'll", "i'd"];
+
+/// Restore capitalization that auto captions lose: a standalone "i" (and its
+/// contractions) becomes "I" when `fix_pronoun_i` is set, and words matching an
+/// entry of `proper_nouns` case-insensitively take that entry's spelling.
+/// Entries may span several words ("New York"); a trailing "'s" still matches.
+/// Whitespace and punctuation around words are kept as they are.
+pub fn fix_caption_capitalization(
+    text: &str,
+    proper_nouns: &[String],
+    fix_pronoun_i: bool,
+) -> String {
+    let nouns: Vec<Vec<&str>> = proper_nouns
+        .iter()
+        .map(|noun| noun.split_whitespace().collect::<Vec<_>>())
+        .filter(|parts| !parts.is_empty())
+        .collect();
+
+    // Byte range of each word's letters, without surrounding punctuation
+    let cores: Vec<Option<std::ops::Range<usize>>> = text
+        .split_whitespace()
+        .map(|word| {
+            let offset = word.as_ptr() as usize - text.as_ptr() as usize;
+            let start = word.find(char::is_alphanumeric)?;
+            let end = word
+                .char_indices()
+                .rev()
+                .find(|(_, c)| c.is_alphanumeric())
+                .map(|(i, c)| i + c.len_utf8())?;
+            Some(offset + start..offset + end)
+        })
+        .collect();
+
+    let mut replacements: Vec<(std::ops::Range<usize>, &str)> = Vec::new();
+    let mut i = 0;
+    while i < cores.len() {
+        let matched = nouns.iter().find_map(|parts| {
+            let words = cores.get(i..i + parts.len())?;
+            let mut spans = Vec::with_capacity(parts.len());
+            for (j, (core, part)) in words.iter().zip(parts).enumerate() {
+                let core = core.clone()?;
+                let word = &text[core.clone()];
+                let possessive = j == parts.len() - 1
+                    && word
+                        .get(..word.len().saturating_sub(2))
+                        .is_some_and(|stem| stem.to_lowercase() == part.to_lowercase())
+                    && word.to_lowercase().ends_with("'s");
+                if word.to_lowercase() == part.to_lowercase() {
+                    spans.push((core, *part));
+                } else if possessive {
+                    spans.push((core.start..core.end - 2, *part));
+                } else {
+                    return None;
+                }
+            }
+            Some(spans)
+        });
 
-    for sentence in sentences {
-        let trimmed = sentence.trim();
-        if trimmed.is_empty() {
+        if let Some(spans) = matched {
+            i += spans.len();
+            replacements.extend(spans);
             continue;
         }
 
-        if !current_paragraph.is_empty() {
-            current_paragraph.push(' ');
+        if let Some(core) = cores[i].clone() {
+            if fix_pronoun_i && LOWERCASE_I_FORMS.contains(&&text[core.clone()]) {
+                replacements.push((core.start..core.start + 1, "I"));
+            }
         }
-        current_paragraph.push_str(&capitalize_first_letter(trimmed));
-        current_paragraph.push('.');
+        i += 1;
+    }
 
-        sentence_count += 1;
+    let mut result = String::with_capacity(text.len());
+    let mut last = 0;
+    for (range, replacement) in replacements {
+        result.push_str(&text[last..range.start]);
+        result.push_str(replacement);
+        last = range.end;
+    }
+    result.push_str(&text[last..]);
+    result
+}
 
-        // Start new paragraph after N sentences
-        if sentence_count >= sentences_per_paragraph {
-            if !result.is_empty() {
-                result.push_str("\n\n");
-            }
-            result.push_str(&current_paragraph);
-            current_paragraph.clear();
-            sentence_count = 0;
+/// Parse a `--replace` entry of the form `wrong=right`
+pub fn parse_replacement(input: &str) -> Result<(String, String), String> {
+    match input.split_once('=') {
+        Some((from, to)) if !from.trim().is_empty() => {
+            Ok((from.trim().to_string(), to.trim().to_string()))
         }
+        _ => Err(format!(
+            "Invalid replacement '{}': expected wrong=right",
+            input
+        )),
     }
+}
 
-    // Add remaining sentences
-    if !current_paragraph.is_empty() {
-        if !result.is_empty() {
-            result.push_str("\n\n");
+/// Substitute whole words and phrases, matched case-insensitively, e.g.
+/// `("cube on eddies", "Kubernetes")`. Words inside longer words are left
+/// alone, the longest phrase wins where several match, and a replacement
+/// starting a sentence keeps the capital letter.
+///
+/// ```
+/// use y2md::apply_replacements;
+///
+/// let replacements = vec![("cube".to_string(), "kube".to_string())];
+/// assert_eq!(
+///     apply_replacements("Cube and uncube", &replacements),
+///     "Kube and uncube"
+/// );
+/// ```
+pub fn apply_replacements(text: &str, replacements: &[(String, String)]) -> String {
+    let mut phrases: Vec<(Vec<&str>, &str)> = replacements
+        .iter()
+        .map(|(from, to)| (from.split_whitespace().collect::<Vec<_>>(), to.as_str()))
+        .filter(|(words, _)| !words.is_empty())
+        .collect();
+    if phrases.is_empty() {
+        return text.to_string();
+    }
+    phrases.sort_by_key(|(words, _)| std::cmp::Reverse(words.concat().chars().count()));
+
+    let mut result = String::with_capacity(text.len());
+    let mut rest = text;
+    let mut previous: Option<char> = None;
+    while let Some(c) = rest.chars().next() {
+        if !previous.is_some_and(char::is_alphanumeric) {
+            let matched = phrases
+                .iter()
+                .find_map(|(words, to)| Some((match_phrase(rest, words)?, *to)));
+            if let Some((len, to)) = matched {
+                let mut to_chars = to.chars();
+                match to_chars.next() {
+                    Some(first) if c.is_uppercase() && first.is_lowercase() => {
+                        result.extend(first.to_uppercase());
+                        result.push_str(to_chars.as_str());
+                    }
+                    _ => result.push_str(to),
+                }
+                previous = rest[..len].chars().last();
+                rest = &rest[len..];
+                continue;
+            }
         }
-        result.push_str(&current_paragraph);
+        result.push(c);
+        previous = Some(c);
+        rest = &rest[c.len_utf8()..];
     }
+    result
+}
+
+/// Hesitations [`remove_filler_words`] drops. Words that are often meant
+/// ("like", "you know") are left alone.
+const FILLER_WORDS: &[&str] = &["um", "umm", "uh", "uhh", "uhm", "er", "erm", "hmm"];
+
+/// Remove hesitations ("um", "uh") from a transcript, keeping any sentence
+/// punctuation attached to them. Line breaks are kept.
+///
+/// ```
+/// use y2md::remove_filler_words;
+///
+/// assert_eq!(
+///     remove_filler_words("Um, so we, uh, ship it. Uh."),
+///     "so we, ship it."
+/// );
+/// ```
+pub fn remove_filler_words(text: &str) -> String {
+    const SENTENCE_END: [char; 4] = ['.', '?', '!', '…'];
+
+    text.split('\n')
+        .map(|line| {
+            let mut words: Vec<String> = Vec::new();
+            for word in line.split_whitespace() {
+                let core = word.trim_end_matches([',', '.', '?', '!', '…']);
+                if !FILLER_WORDS.contains(&core.to_lowercase().as_str()) {
+                    words.push(word.to_string());
+                    continue;
+                }
+                // "it was, uh." still ends the sentence
+                let ending = &word[core.len()..];
+                if let Some(previous) = words.last_mut() {
+                    if ending.contains(SENTENCE_END) && !previous.ends_with(SENTENCE_END) {
+                        let kept = previous.trim_end_matches([',', ';']).len();
+                        previous.truncate(kept);
+                        previous.push_str(ending.trim_start_matches(','));
+                    }
+                }
+            }
+            words.join(" ")
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Text that replaces every redacted match
+pub const REDACTED: &str = "[redacted]";
+
+/// Patterns `--redact-pii` adds: email addresses, and phone numbers of at
+/// least seven digits that start with `+` (+44 20 7946 0958), an area code in
+/// parentheses ((555) 123-4567) or use `-`/`.` separators (555-123-4567).
+/// Numbers split by spaces alone are too often years or amounts.
+const PII_PATTERNS: &[&str] = &[
+    r"(?i)[a-z0-9._%+-]+@[a-z0-9.-]+\.[a-z]{2,}\b",
+    concat!(
+        r"(?:\+\d{1,3}[\s.-]?(?:\(\d{1,4}\)|\d{1,4})[\s.-]?\d{3,4}[\s.-]?\d{3,4}",
+        r"|\(\d{2,4}\)\s?\d{3,4}[\s.-]\d{3,4}",
+        r"|\b\d{2,4}[.-]\d{3,4}[.-]\d{4})\b",
+    ),
+];
+
+/// Replaces words, phrases and patterns with [`REDACTED`] so they are never
+/// written or sent to an LLM provider
+#[derive(Debug, Clone, Default)]
+pub struct Redactor {
+    patterns: Vec<regex::Regex>,
+}
+
+impl Redactor {
+    /// Build from `redact` entries: `/.../` is a regular expression, anything
+    /// else a literal matched as whole words ignoring case. `pii` adds the
+    /// built-in email and phone number patterns.
+    pub fn new(entries: &[String], pii: bool) -> Result<Self, Y2mdError> {
+        let mut patterns = Vec::new();
+        for entry in entries.iter().map(|entry| entry.trim()) {
+            if entry.is_empty() {
+                continue;
+            }
+            let pattern = match entry.strip_prefix('/').and_then(|e| e.strip_suffix('/')) {
+                Some(regex) if !regex.is_empty() => regex.to_string(),
+                _ => {
+                    let boundary = |c: Option<char>| {
+                        if c.is_some_and(char::is_alphanumeric) {
+                            r"\b"
+                        } else {
+                            ""
+                        }
+                    };
+                    format!(
+                        "(?i){}{}{}",
+                        boundary(entry.chars().next()),
+                        regex::escape(entry),
+                        boundary(entry.chars().last())
+                    )
+                }
+            };
+            patterns.push(regex::Regex::new(&pattern).map_err(|e| {
+                Y2mdError::Config(format!("Invalid redact pattern '{}': {}", entry, e))
+            })?);
+        }
+        if pii {
+            patterns.extend(
+                PII_PATTERNS
+                    .iter()
+                    .map(|pattern| regex::Regex::new(pattern).expect("valid PII pattern")),
+            );
+        }
+        Ok(Redactor { patterns })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.patterns.is_empty()
+    }
+
+    /// `text` with every match replaced by [`REDACTED`]
+    pub fn redact(&self, text: &str) -> String {
+        self.patterns
+            .iter()
+            .fold(text.to_string(), |text, pattern| {
+                pattern.replace_all(&text, REDACTED).into_owned()
+            })
+    }
+}
+
+/// Byte length of `words` at the start of `text`, compared case-insensitively
+/// with any whitespace but a paragraph break between them, when the match
+/// ends on a word boundary
+fn match_phrase(text: &str, words: &[&str]) -> Option<usize> {
+    let mut len = 0;
+    for (i, word) in words.iter().enumerate() {
+        if i > 0 {
+            let rest = &text[len..];
+            let gap = &rest[..rest.len() - rest.trim_start().len()];
+            if gap.is_empty() || gap.matches('\n').count() > 1 {
+                return None;
+            }
+            len += gap.len();
+        }
+        let mut chars = text[len..].chars();
+        for expected in word.chars() {
+            let actual = chars.next()?;
+            if !actual.to_lowercase().eq(expected.to_lowercase()) {
+                return None;
+            }
+            len += actual.len_utf8();
+        }
+    }
+
+    if text[len..]
+        .chars()
+        .next()
+        .is_some_and(char::is_alphanumeric)
+    {
+        None
+    } else {
+        Some(len)
+    }
+}
+
+/// Silence between cues that marks a paragraph break in manual captions
+const CAPTION_PARAGRAPH_PAUSE_SECONDS: f64 = 2.0;
+
+/// Build paragraphs from caption cues as written, breaking where the captioner
+/// marked a new speaker (`>>` or a leading `- `) or where a finished sentence
+/// is followed by a long pause
+fn caption_paragraphs(segments: &[TranscriptSegment]) -> String {
+    let mut paragraphs: Vec<String> = Vec::new();
+    let mut previous: Option<&TranscriptSegment> = None;
+
+    for segment in segments {
+        let text = segment.text.trim();
+        if text.is_empty() {
+            continue;
+        }
+
+        let speaker_change = text.starts_with(">>") || text.starts_with("- ");
+        let pause_break = previous.is_some_and(|prev| {
+            prev.text.trim_end().ends_with(['.', '?', '!'])
+                && segment.start - prev.end >= CAPTION_PARAGRAPH_PAUSE_SECONDS
+        });
+
+        match paragraphs.last_mut() {
+            Some(paragraph) if !speaker_change && !pause_break => {
+                paragraph.push(' ');
+                paragraph.push_str(text);
+            }
+            _ => paragraphs.push(text.to_string()),
+        }
+        previous = Some(segment);
+    }
+
+    paragraphs.join("\n\n")
+}
+
+/// Subtitle file formats yt-dlp can hand back
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SubtitleFormat {
+    Srt,
+    Vtt,
+}
+
+impl SubtitleFormat {
+    /// Detect the format from the file content, falling back to the extension
+    pub fn detect(content: &str, path: Option<&std::path::Path>) -> Self {
+        if content
+            .trim_start_matches('\u{feff}')
+            .trim_start()
+            .starts_with("WEBVTT")
+        {
+            return SubtitleFormat::Vtt;
+        }
+        match path
+            .and_then(|path| path.extension())
+            .and_then(|ext| ext.to_str())
+        {
+            Some(ext) if ext.eq_ignore_ascii_case("vtt") => SubtitleFormat::Vtt,
+            _ => SubtitleFormat::Srt,
+        }
+    }
+
+    /// File extension for this format
+    pub fn extension(&self) -> &'static str {
+        match self {
+            SubtitleFormat::Srt => "srt",
+            SubtitleFormat::Vtt => "vtt",
+        }
+    }
+}
+
+/// A timed subtitle cue, in seconds
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cue {
+    pub start: f64,
+    pub end: f64,
+    pub text: String,
+    /// VTT cue settings such as `align:start position:0%`; SRT has none
+    pub settings: Option<String>,
+}
+
+impl From<Cue> for TranscriptSegment {
+    fn from(cue: Cue) -> Self {
+        TranscriptSegment {
+            start: cue.start,
+            end: cue.end,
+            text: cue.text,
+            speaker: None,
+            avg_logprob: None,
+        }
+    }
+}
+
+/// Parse SRT or WebVTT content into cues. Cue numbers and identifiers, the
+/// VTT header and NOTE/STYLE/REGION blocks are skipped and inline markup is
+/// stripped from the text (see [`strip_subtitle_markup`]). YouTube's rolling
+/// VTT auto-captions repeat the previous cue's last line at the top of the
+/// next one; those repeats are dropped so the text reads once.
+pub fn parse_subtitles(content: &str, format: SubtitleFormat) -> Vec<Cue> {
+    let mut cues = Vec::new();
+    let mut previous_line: Option<String> = None;
+    let mut lines = content.lines().map(str::trim).peekable();
+
+    while let Some(line) = lines.next() {
+        let Some((start, rest)) = line.split_once("-->") else {
+            continue;
+        };
+
+        let mut timing = rest.split_whitespace();
+        let (Some(start), Some(end)) = (
+            parse_subtitle_timestamp(start),
+            timing.next().and_then(parse_subtitle_timestamp),
+        ) else {
+            continue;
+        };
+        let settings = timing.collect::<Vec<_>>().join(" ");
+
+        let mut text_lines = Vec::new();
+        while let Some(text) = lines.peek() {
+            if text.is_empty() {
+                break;
+            }
+            let text = strip_subtitle_markup(text);
+            if !text.is_empty() {
+                text_lines.push(text);
+            }
+            lines.next();
+        }
+
+        if format == SubtitleFormat::Vtt {
+            while !text_lines.is_empty() && previous_line.as_ref() == Some(&text_lines[0]) {
+                text_lines.remove(0);
+            }
+            if let Some(last) = text_lines.last() {
+                previous_line = Some(last.clone());
+            }
+        }
+
+        let text = text_lines.join(" ");
+        if !text.is_empty() {
+            cues.push(Cue {
+                start,
+                end,
+                text,
+                settings: (format == SubtitleFormat::Vtt && !settings.is_empty())
+                    .then_some(settings),
+            });
+        }
+    }
+
+    cues
+}
+
+/// Remove inline markup from a subtitle line, keeping the enclosed text:
+/// tags such as `<i>`, `<font color="...">`, `<c>` and `<v Speaker>`, inline
+/// word timestamps (`<00:00:01.234>`), positioning overrides (`{\an8}`) and
+/// leading `>>` speaker-change markers
+fn strip_subtitle_markup(line: &str) -> String {
+    let mut output = String::with_capacity(line.len());
+    let mut rest = line;
+
+    while let Some(index) = rest.find(['<', '{']) {
+        output.push_str(&rest[..index]);
+        let tail = &rest[index..];
+        // A lone `<` or `{` in the text (e.g. "a < b") is not markup
+        let (close, is_markup) = if tail.starts_with('<') {
+            (
+                '>',
+                tail[1..]
+                    .chars()
+                    .next()
+                    .is_some_and(|c| c == '/' || c.is_ascii_alphanumeric()),
+            )
+        } else {
+            ('}', tail[1..].starts_with('\\'))
+        };
+        match tail.find(close) {
+            Some(end) if is_markup => rest = &tail[end + 1..],
+            _ => {
+                output.push_str(&tail[..1]);
+                rest = &tail[1..];
+            }
+        }
+    }
+    output.push_str(rest);
+
+    let text = output.trim();
+    let text = text.strip_prefix(">>").unwrap_or(text);
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Join the cue text of a subtitle file into one line of plain text
+fn subtitles_to_plain_text(content: &str, format: SubtitleFormat) -> String {
+    parse_subtitles(content, format)
+        .into_iter()
+        .map(|cue| cue.text)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Format an SRT or WebVTT file from anywhere as markdown paragraphs: cues
+/// are parsed (dropping repeated rolling-caption lines), joined and grouped
+/// with `options`, the same as YouTube captions
+pub fn subtitles_to_markdown(
+    content: &str,
+    format: SubtitleFormat,
+    options: &FormatOptions,
+) -> String {
+    let text = subtitles_to_plain_text(content, format);
+    let mut markdown = format_transcript_with(&text, options);
+    markdown.push('\n');
+    markdown
+}
+
+/// Convert SRT subtitle format to plain text
+fn srt_to_plain_text(srt_content: &str) -> String {
+    subtitles_to_plain_text(srt_content, SubtitleFormat::Srt)
+}
+
+fn vtt_to_plain_text(vtt_content: &str) -> String {
+    subtitles_to_plain_text(vtt_content, SubtitleFormat::Vtt)
+}
+
+/// Parse an `HH:MM:SS,mmm` (SRT) or `HH:MM:SS.mmm` (VTT) timestamp into seconds
+fn parse_subtitle_timestamp(timestamp: &str) -> Option<f64> {
+    // VTT cue settings may follow the end timestamp
+    let timestamp = timestamp.split_whitespace().next()?.replace(',', ".");
+    let parts: Vec<&str> = timestamp.split(':').collect();
+
+    let (hours, minutes, seconds) = match parts.as_slice() {
+        [h, m, s] => (
+            h.parse::<f64>().ok()?,
+            m.parse::<f64>().ok()?,
+            s.parse::<f64>().ok()?,
+        ),
+        [m, s] => (0.0, m.parse::<f64>().ok()?, s.parse::<f64>().ok()?),
+        _ => return None,
+    };
+
+    Some(hours * 3600.0 + minutes * 60.0 + seconds)
+}
+
+/// Format seconds as a subtitle timestamp using the given millisecond separator
+fn format_subtitle_timestamp(seconds: f64, separator: char) -> String {
+    let total_millis = (seconds.max(0.0) * 1000.0).round() as u64;
+    let hours = total_millis / 3_600_000;
+    let minutes = (total_millis % 3_600_000) / 60_000;
+    let secs = (total_millis % 60_000) / 1000;
+    let millis = total_millis % 1000;
+
+    format!(
+        "{:02}:{:02}:{:02}{}{:03}",
+        hours, minutes, secs, separator, millis
+    )
+}
+
+/// Render timed segments as an SRT or WebVTT document, or as plain text lines
+pub fn render_segments(segments: &[TranscriptSegment], format: RawFormat) -> String {
+    let mut output = String::new();
+
+    match format {
+        RawFormat::Txt => {
+            for segment in segments {
+                output.push_str(&segment.text);
+                output.push('\n');
+            }
+        }
+        RawFormat::Srt => {
+            for (i, segment) in segments.iter().enumerate() {
+                output.push_str(&format!(
+                    "{}\n{} --> {}\n{}\n\n",
+                    i + 1,
+                    format_subtitle_timestamp(segment.start, ','),
+                    format_subtitle_timestamp(segment.end, ','),
+                    segment.text
+                ));
+            }
+        }
+        RawFormat::Vtt => {
+            output.push_str("WEBVTT\n\n");
+            for segment in segments {
+                output.push_str(&format!(
+                    "{} --> {}\n{}\n\n",
+                    format_subtitle_timestamp(segment.start, '.'),
+                    format_subtitle_timestamp(segment.end, '.'),
+                    segment.text
+                ));
+            }
+        }
+    }
+
+    output
+}
+/// Silence long enough for the fallback diarization to assume a new speaker
+const SPEAKER_CHANGE_PAUSE_SECONDS: f64 = 2.0;
+
+/// Guess speakers from pauses when the transcript has no speaker labels.
+///
+/// Every long silence is treated as a hand-over between two speakers, so this
+/// only gives usable results for two-person conversations with clear turns.
+pub fn assign_speakers_by_pauses(segments: &mut [TranscriptSegment]) {
+    let mut speaker = 0;
+    let mut previous_end: Option<f64> = None;
+
+    for segment in segments.iter_mut() {
+        if previous_end.is_some_and(|end| segment.start - end >= SPEAKER_CHANGE_PAUSE_SECONDS) {
+            speaker = 1 - speaker;
+        }
+        segment.speaker = Some(speaker);
+        previous_end = Some(segment.end);
+    }
+}
+
+/// Render segments as `**Speaker N:**` blocks, merging consecutive segments from the same speaker
+pub fn format_speaker_blocks(
+    segments: &[TranscriptSegment],
+    paragraph_length: ParagraphLength,
+) -> String {
+    speaker_blocks(segments, paragraph_length, |speaker| {
+        format!("**Speaker {}:**", speaker + 1)
+    })
+}
+
+/// Group consecutive segments by speaker, prefixing each block with `label`
+fn speaker_blocks(
+    segments: &[TranscriptSegment],
+    paragraph_length: ParagraphLength,
+    label: impl Fn(u32) -> String,
+) -> String {
+    let mut blocks: Vec<(Option<u32>, String)> = Vec::new();
+    for segment in segments {
+        match blocks.last_mut() {
+            Some((speaker, text)) if *speaker == segment.speaker => {
+                text.push(' ');
+                text.push_str(&segment.text);
+            }
+            _ => blocks.push((segment.speaker, segment.text.clone())),
+        }
+    }
+
+    blocks
+        .iter()
+        .map(|(speaker, text)| {
+            let body = group_paragraphs(text, paragraph_length);
+            match speaker {
+                Some(speaker) => format!("{} {}", label(*speaker), body),
+                None => body,
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n\n")
+}
+
+/// Output name for one yt-dlp run that no other run shares, so concurrent
+/// runs on the same video cannot clobber each other's files. Whatever yt-dlp
+/// wrote under it is removed when this is dropped.
+struct TempDownload {
+    dir: PathBuf,
+    stem: String,
+}
+
+impl TempDownload {
+    fn new(dir: impl Into<PathBuf>, name: &str) -> Self {
+        TempDownload {
+            dir: dir.into(),
+            stem: format!(".y2md-{}-{}", uuid::Uuid::new_v4(), name),
+        }
+    }
+
+    /// Path to pass to yt-dlp's `-o`; it appends extensions to it
+    fn template(&self) -> PathBuf {
+        self.dir.join(&self.stem)
+    }
+
+    /// Files yt-dlp has written so far
+    fn files(&self) -> Vec<PathBuf> {
+        let prefix = format!("{}.", self.stem);
+        std::fs::read_dir(&self.dir)
+            .into_iter()
+            .flatten()
+            .flatten()
+            .filter(|entry| entry.file_name().to_string_lossy().starts_with(&prefix))
+            .map(|entry| entry.path())
+            .collect()
+    }
+}
+
+impl Drop for TempDownload {
+    fn drop(&mut self) {
+        for path in self.files() {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+/// Download audio from YouTube video
+pub async fn download_audio(
+    video_id: &str,
+    output_dir: &str,
+    ytdlp: &YtDlpConfig,
+    on_event: Option<&EventHandler>,
+) -> Result<PathBuf, Y2mdError> {
+    use tokio::io::AsyncReadExt;
+
+    let url = format!("https://www.youtube.com/watch?v={}", video_id);
+
+    // Create output directory if it doesn't exist
+    let output_path = PathBuf::from(output_dir);
+    ensure_output_dir(&output_path)?;
+
+    // First, check if audio file already exists in cache
+    let file_stem = ytdlp.audio_file_stem(video_id);
+    let mut cached_audio_path = None;
+
+    for entry in std::fs::read_dir(&output_path)? {
+        let entry = entry?;
+        let file_name = entry.file_name();
+        if let Some(name) = file_name.to_str() {
+            if name.starts_with(&format!("{}.", file_stem)) {
+                let path = entry.path();
+                // Check if file is not empty
+                if let Ok(metadata) = std::fs::metadata(&path) {
+                    if metadata.len() > 0 {
+                        cached_audio_path = Some(path);
+                        println!("Using cached audio file: {:?}", cached_audio_path);
+                        break;
+                    }
+                }
+            }
+        }
+    }
+
+    if let Some(cached_path) = cached_audio_path {
+        return Ok(cached_path);
+    }
+
+    emit(on_event, PipelineEvent::DownloadStarted);
+
+    // Download under a unique name and move the result into the cache once
+    // complete, so a concurrent run never picks up a partial file
+    let download = TempDownload::new(&output_path, &file_stem);
+    let output_template = download.template();
+
+    let retries = ytdlp.retry_count();
+    let mut retry = 0;
+    loop {
+        let mut child = ytdlp_command(ytdlp)
+            .arg("-x") // Extract audio
+            .args(ytdlp.audio_args())
+            .args([
+                "--newline", // One progress line per update so it can be parsed
+                "-o",
+                output_template.to_str().unwrap(),
+                &url,
+            ])
+            .stdout(std::process::Stdio::piped())
+            .stderr(std::process::Stdio::piped())
+            .spawn()
+            .map_err(|e| {
+                if e.kind() == std::io::ErrorKind::NotFound {
+                    Y2mdError::YtDlpNotFound
+                } else {
+                    Y2mdError::Io(e)
+                }
+            })?;
+
+        // Drain stderr in its own task so yt-dlp never blocks on a full pipe
+        let stderr = child.stderr.take();
+        let stderr_reader = tokio::spawn(async move {
+            let mut buffer = String::new();
+            if let Some(mut stderr) = stderr {
+                let _ = stderr.read_to_string(&mut buffer).await;
+            }
+            buffer
+        });
+
+        read_progress_lines(&mut child, "Audio download", |line| {
+            if let Some(percent) = parse_ytdlp_progress(line) {
+                emit(on_event, PipelineEvent::DownloadProgress(percent));
+            }
+        })
+        .await?;
+
+        let status = child.wait().await?;
+        let stderr_output = stderr_reader.await.unwrap_or_default();
+
+        if !status.success() {
+            if retry < retries && is_transient_ytdlp_error(&stderr_output) {
+                wait_before_retry("Audio download", &stderr_output, retry, retries).await;
+                retry += 1;
+                continue;
+            }
+            return Err(classify_ytdlp_error(&stderr_output));
+        }
+
+        break;
+    }
+
+    // Find the downloaded file (yt-dlp adds the extension), skipping empty
+    // files and leftover partial downloads
+    let downloaded = download
+        .files()
+        .into_iter()
+        .filter(|path| {
+            path.extension()
+                .is_some_and(|ext| ext != "part" && ext != "ytdl")
+        })
+        .find(|path| std::fs::metadata(path).is_ok_and(|metadata| metadata.len() > 0))
+        .ok_or_else(|| {
+            Y2mdError::Config(format!(
+                "Downloaded audio file not found for pattern: {}.*",
+                output_template.display()
+            ))
+        })?;
+    let extension = downloaded
+        .extension()
+        .map(|ext| ext.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let audio_path = output_path.join(format!("{}.{}", file_stem, extension));
+    std::fs::rename(&downloaded, &audio_path)?;
+
+    emit(on_event, PipelineEvent::DownloadFinished);
+
+    println!("Audio downloaded to: {:?}", audio_path);
+
+    Ok(audio_path)
+}
+
+/// Parse the percentage from a yt-dlp `[download]  42.5% of ...` progress line
+fn parse_ytdlp_progress(line: &str) -> Option<f32> {
+    line.trim()
+        .strip_prefix("[download]")?
+        .split_whitespace()
+        .next()?
+        .strip_suffix('%')?
+        .parse()
+        .ok()
+}
+
+/// How a transcript was produced, recorded as `source` in the front matter
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TranscriptionSource {
+    /// Subtitles uploaded by the creator
+    ManualCaptions { language: String },
+    /// YouTube's automatically generated captions
+    AutoCaptions { language: String },
+    /// Local Whisper with this model (e.g. `base.en`)
+    LocalWhisper { model: String },
+    /// A speech-to-text API (`openai` or `deepgram`)
+    RemoteWhisper { provider: String },
+}
+
+impl TranscriptionSource {
+    /// Source for audio transcribed with `backend`
+    fn speech_to_text(backend: &SttBackend, language: Option<&str>) -> Self {
+        match backend {
+            SttBackend::Local => TranscriptionSource::LocalWhisper {
+                model: whisper_model_name(language),
+            },
+            remote => TranscriptionSource::RemoteWhisper {
+                provider: remote.to_string(),
+            },
+        }
+    }
+
+    pub fn is_captions(&self) -> bool {
+        matches!(
+            self,
+            TranscriptionSource::ManualCaptions { .. } | TranscriptionSource::AutoCaptions { .. }
+        )
+    }
+
+    /// Language of the caption track, `None` for speech-to-text
+    pub fn caption_language(&self) -> Option<&str> {
+        match self {
+            TranscriptionSource::ManualCaptions { language }
+            | TranscriptionSource::AutoCaptions { language } => Some(language),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for TranscriptionSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TranscriptionSource::ManualCaptions { language } => {
+                write!(f, "manual-captions ({})", language)
+            }
+            TranscriptionSource::AutoCaptions { language } => {
+                write!(f, "auto-captions ({})", language)
+            }
+            TranscriptionSource::LocalWhisper { model } => write!(f, "whisper ({})", model),
+            TranscriptionSource::RemoteWhisper { provider } => {
+                write!(f, "remote-stt ({})", provider)
+            }
+        }
+    }
+}
+
+/// Transcribe YouTube video using captions or STT
+#[allow(clippy::too_many_arguments)]
+pub async fn transcribe_video(
+    video_id: &str,
+    prefer_captions: bool,
+    language: Option<&str>,
+    caption_lang_fallback: CaptionLangFallback,
+    output_dir: &str,
+    paragraph_length: ParagraphLength,
+    force_formatting: bool,
+    preserve_caption_paragraphs: bool,
+    capitalization: Option<&[String]>,
+    ytdlp: &YtDlpConfig,
+    ffmpeg_path: &str,
+    stt: &SttSettings,
+    client: &HttpClient,
+    credentials: &CredentialManager,
+    resume: bool,
+    max_repeated_sentences: usize,
+    model_dirs: &[PathBuf],
+    on_event: Option<&EventHandler>,
+) -> Result<(String, TranscriptionSource, String, Vec<TranscriptSegment>), Y2mdError> {
+    let mut source = TranscriptionSource::speech_to_text(&stt.backend, language);
+    // A sample is short and must not leave a checkpoint behind for the full run
+    let checkpoint_id = (resume && ytdlp.sample_seconds.is_none()).then_some(video_id);
+    let transcript;
+
+    let raw_transcript;
+    let segments;
+
+    // Caption tracks are picked by language, so auto-detection needs Whisper
+    let auto_language = language == Some(AUTO_LANGUAGE);
+    if prefer_captions && auto_language {
+        println!("Language set to auto, skipping captions and detecting it with Whisper");
+    }
+    // Captions need no preview, a sample is for checking the speech-to-text settings
+    let prefer_captions = prefer_captions && ytdlp.sample_seconds.is_none();
+
+    let caption_source = if prefer_captions && !auto_language {
+        choose_caption_track(
+            video_id,
+            language.unwrap_or("en"),
+            caption_lang_fallback,
+            ytdlp,
+        )
+        .await
+    } else {
+        println!("Using STT for transcription");
+        None
+    };
+
+    if let Some(caption_source) = caption_source {
+        let (formatted, raw, timed) = extract_captions(
+            video_id,
+            caption_source.caption_language(),
+            force_formatting,
+            preserve_caption_paragraphs,
+            capitalization,
+            ytdlp,
+        )
+        .await?;
+        transcript = formatted;
+        raw_transcript = raw;
+        segments = timed;
+        source = caption_source;
+        println!("Using captions for transcription");
+    } else {
+        let audio_path = download_audio(video_id, output_dir, ytdlp, on_event).await?;
+        let (formatted, raw, timed) = transcribe_with_backend(
+            stt,
+            client,
+            credentials,
+            &audio_path,
+            language,
+            paragraph_length,
+            ffmpeg_path,
+            checkpoint_id,
+            ytdlp.sample_seconds,
+            max_repeated_sentences,
+            model_dirs,
+            on_event,
+        )
+        .await?;
+        transcript = formatted;
+        raw_transcript = raw;
+        segments = timed;
+    }
+
+    ensure_transcript_not_empty(&transcript, &source)?;
+
+    Ok((transcript, source, raw_transcript, segments))
+}
+
+/// Pick the caption track to transcribe from, logging why when it is not the
+/// requested language; `None` means speech-to-text
+async fn choose_caption_track(
+    video_id: &str,
+    requested: &str,
+    fallback: CaptionLangFallback,
+    ytdlp: &YtDlpConfig,
+) -> Option<TranscriptionSource> {
+    let tracks = match list_caption_tracks(video_id, ytdlp).await {
+        Ok(tracks) => tracks,
+        Err(e) => {
+            println!("Error checking captions: {}, falling back to STT", e);
+            return None;
+        }
+    };
+
+    let available = tracks.languages();
+    if available.is_empty() {
+        println!("No captions available, falling back to STT");
+        return None;
+    }
+
+    let Some(language) = tracks.language_for(requested, fallback) else {
+        println!(
+            "Warning: No '{}' captions (available: {}), falling back to STT in '{}' (--caption-lang-fallback {})",
+            requested,
+            available.join(", "),
+            requested,
+            fallback
+        );
+        return None;
+    };
+    if language != requested {
+        println!(
+            "Warning: No '{}' captions, using the '{}' captions instead (--caption-lang-fallback {})",
+            requested, language, fallback
+        );
+    }
+
+    Some(if tracks.has_manual(&language) {
+        TranscriptionSource::ManualCaptions { language }
+    } else {
+        TranscriptionSource::AutoCaptions { language }
+    })
+}
+
+/// Refuse to continue with a transcript that has no text, so no markdown
+/// file is written with only front matter
+fn ensure_transcript_not_empty(
+    transcript: &str,
+    source: &TranscriptionSource,
+) -> Result<(), Y2mdError> {
+    if !transcript.trim().is_empty() {
+        return Ok(());
+    }
+
+    let reason = if source.is_captions() {
+        "caption file was empty"
+    } else {
+        "no speech detected in the audio"
+    };
+    Err(Y2mdError::EmptyTranscript(reason.to_string()))
+}
+
+/// Sample rate of the audio handed to Whisper
+const WHISPER_SAMPLE_RATE: usize = 16_000;
+
+/// Length of each chunk of audio decoded and handed to Whisper at a time
+const WHISPER_CHUNK_SECONDS: usize = 5 * 60;
+
+/// Audio shorter than this is transcribed in a single pass; longer (or
+/// unknown length) audio is split into chunks to bound memory use
+const WHISPER_SINGLE_PASS_SECONDS: usize = 30 * 60;
+
+/// Stretch at the end of a chunk searched for a pause to cut at
+const CHUNK_CUT_SEARCH_SECONDS: usize = 10;
+
+/// Most text from the end of one chunk passed to Whisper as context for the next
+const CHUNK_PROMPT_CHARS: usize = 400;
+
+/// Opening stretch of audio Whisper listens to when detecting the language
+const LANGUAGE_DETECTION_SECONDS: usize = 30;
+
+/// Shortest audio that gets a transcription checkpoint; anything shorter is
+/// quick enough to transcribe again from the start
+const CHECKPOINT_MIN_SECONDS: usize = 30 * 60;
+
+/// Whisper segments finished so far for a video, so an interrupted
+/// transcription can pick up where it stopped
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct TranscriptionCheckpoint {
+    language: String,
+    /// Audio samples (at 16kHz) already transcribed
+    completed_samples: usize,
+    segments: Vec<TranscriptSegment>,
+}
+
+/// Where to end a chunk of audio: the middle of the quietest tenth of a
+/// second within its last [`CHUNK_CUT_SEARCH_SECONDS`]
+fn quietest_cut(samples: &[f32]) -> usize {
+    let window = WHISPER_SAMPLE_RATE / 10;
+    let search = CHUNK_CUT_SEARCH_SECONDS * WHISPER_SAMPLE_RATE;
+    if samples.len() < search + window {
+        return samples.len();
+    }
+    let energy = |start: usize| -> f32 {
+        samples[start..start + window]
+            .iter()
+            .map(|sample| sample * sample)
+            .sum()
+    };
+    (samples.len() - search..=samples.len() - window)
+        .step_by(window / 2)
+        .min_by(|&a, &b| energy(a).total_cmp(&energy(b)))
+        .map_or(samples.len(), |start| start + window / 2)
+}
+
+/// The last segments transcribed, up to [`CHUNK_PROMPT_CHARS`], to prompt
+/// Whisper with for the next chunk
+fn chunk_prompt(segments: &[TranscriptSegment]) -> String {
+    let mut texts = Vec::new();
+    let mut length = 0;
+    for segment in segments.iter().rev() {
+        length += segment.text.len() + 1;
+        if length > CHUNK_PROMPT_CHARS {
+            break;
+        }
+        texts.push(segment.text.as_str());
+    }
+    texts.reverse();
+    texts.join(" ")
+}
+
+/// Location of the transcription checkpoint for a video transcribed with
+/// `model`, so switching models starts over instead of mixing their segments
+fn checkpoint_path(video_id: &str, model: &str) -> Option<PathBuf> {
+    directories::ProjectDirs::from("com", "y2md", "y2md").map(|dirs| {
+        dirs.cache_dir()
+            .join("checkpoints")
+            .join(format!("{}.{}.json", video_id, model))
+    })
+}
+
+/// Read a checkpoint, ignoring one made for a different language
+fn load_checkpoint(path: &std::path::Path, language: &str) -> Option<TranscriptionCheckpoint> {
+    let checkpoint: TranscriptionCheckpoint =
+        serde_json::from_slice(&std::fs::read(path).ok()?).ok()?;
+    (checkpoint.language == language).then_some(checkpoint)
+}
+
+/// Write a file through a hidden temporary file in the same directory and
+/// rename it into place, so readers (and sync tools watching the folder)
+/// see either the old contents or the new ones, never a partial file.
+pub fn write_atomic(
+    path: impl AsRef<std::path::Path>,
+    contents: impl AsRef<[u8]>,
+) -> std::io::Result<()> {
+    use std::io::Write;
+
+    let path = path.as_ref();
+    let file_name = path.file_name().ok_or_else(|| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("not a file path: {}", path.display()),
+        )
+    })?;
+    let temp_path = path.with_file_name(format!(
+        ".{}.{}.tmp",
+        file_name.to_string_lossy(),
+        std::process::id()
+    ));
+
+    let result = std::fs::File::create(&temp_path)
+        .and_then(|mut file| {
+            file.write_all(contents.as_ref())?;
+            file.sync_all()
+        })
+        .and_then(|()| std::fs::rename(&temp_path, path));
+    if result.is_err() {
+        let _ = std::fs::remove_file(&temp_path);
+    }
+    result
+}
+
+/// Write a checkpoint atomically, so an interruption mid-write leaves the
+/// previous one intact. Failures only cost the ability to resume.
+fn save_checkpoint(path: &std::path::Path, checkpoint: &TranscriptionCheckpoint) {
+    let Some(parent) = path.parent() else {
+        return;
+    };
+    let Ok(json) = serde_json::to_vec(checkpoint) else {
+        return;
+    };
+    if std::fs::create_dir_all(parent).is_ok() {
+        let _ = write_atomic(path, json);
+    }
+}
+
+/// Transcribe audio file using STT. With a `checkpoint_id` (the video ID),
+/// finished chunks of long audio are saved to the cache directory and a later
+/// run with the same ID and model resumes after the last saved chunk.
+pub async fn transcribe_audio(
+    audio_path: &PathBuf,
+    language: Option<&str>,
+    paragraph_length: ParagraphLength,
+    ffmpeg_path: &str,
+    checkpoint_id: Option<&str>,
+    max_seconds: Option<u64>,
+    model_dirs: &[PathBuf],
+    on_event: Option<&EventHandler>,
+) -> Result<(String, String, Vec<TranscriptSegment>), Y2mdError> {
+    // Check if audio file exists
+    if !audio_path.exists() {
+        return Err(Y2mdError::Config(format!(
+            "Audio file not found: {:?}",
+            audio_path
+        )));
+    }
+
+    // Use whisper-rs for real transcription
+    println!("Transcribing audio with Whisper...");
+
+    // Determine which model to use based on language
+    let (model_path, whisper_lang) = determine_model_and_language(language, model_dirs)?;
+
+    if !std::path::Path::new(&model_path).exists() {
+        return Err(Y2mdError::Whisper(format!(
+            "Whisper model not found at: {}. Please run download_model.sh, or set models_dir in the config to where your models are",
+            model_path
+        )));
+    }
+
+    // Load the whisper model
+    let ctx_params = whisper_rs::WhisperContextParameters::default();
+    let ctx = whisper_rs::WhisperContext::new_with_params(&model_path, ctx_params)
+        .map_err(|e| Y2mdError::Whisper(format!("Failed to load whisper model: {}", e)))?;
+
+    // Create state for transcription
+    let mut state = ctx
+        .create_state()
+        .map_err(|e| Y2mdError::Whisper(format!("Failed to create state: {}", e)))?;
+
+    // Convert audio to the format whisper expects; it is decoded chunk by chunk
+    let mut audio = WhisperAudio::open(audio_path, ffmpeg_path, on_event).await?;
+
+    // yt-dlp cuts sections at keyframes, so a sample can run a little long
+    let max_samples = max_seconds.map(|seconds| seconds as usize * WHISPER_SAMPLE_RATE);
+    let audio_samples = match (audio.total_samples, max_samples) {
+        (Some(total), Some(max)) => Some(total.min(max)),
+        (total, max) => total.or(max),
+    };
+
+    let mut whisper_lang = whisper_lang;
+    if whisper_lang == AUTO_LANGUAGE {
+        // Whisper only looks at the first 30 seconds to detect the language
+        let opening = audio.peek(LANGUAGE_DETECTION_SECONDS * WHISPER_SAMPLE_RATE)?;
+        let detection = detect_language(&mut state, opening)?;
+        println!(
+            "Detected language: {} ({:.0}% probability)",
+            detection.language,
+            detection.probability * 100.0
+        );
+        whisper_lang = detection.language.clone();
+        emit(on_event, PipelineEvent::LanguageDetected(detection));
+    }
+
+    // Long audio goes to Whisper in chunks; with a checkpoint the segments
+    // are saved after each one. Short audio is not worth checkpointing.
+    let model_name = std::path::Path::new(&model_path)
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let checkpoint_path = checkpoint_id
+        .filter(|_| {
+            audio_samples
+                .is_some_and(|samples| samples >= CHECKPOINT_MIN_SECONDS * WHISPER_SAMPLE_RATE)
+        })
+        .and_then(|video_id| checkpoint_path(video_id, &model_name));
+    let chunk_samples = if audio_samples
+        .is_some_and(|samples| samples < WHISPER_SINGLE_PASS_SECONDS * WHISPER_SAMPLE_RATE)
+    {
+        usize::MAX
+    } else {
+        WHISPER_CHUNK_SECONDS * WHISPER_SAMPLE_RATE
+    };
+    let mut checkpoint = checkpoint_path
+        .as_deref()
+        .and_then(|path| load_checkpoint(path, &whisper_lang))
+        .filter(|checkpoint| {
+            audio_samples.is_none_or(|samples| checkpoint.completed_samples <= samples)
+        })
+        .unwrap_or_else(|| TranscriptionCheckpoint {
+            language: whisper_lang.clone(),
+            ..Default::default()
+        });
+    if checkpoint.completed_samples > 0 {
+        println!(
+            "Resuming transcription from {}",
+            format_duration((checkpoint.completed_samples / WHISPER_SAMPLE_RATE) as f64)
+        );
+        if audio.skip(checkpoint.completed_samples)? < checkpoint.completed_samples {
+            return Err(Y2mdError::Whisper(
+                "Audio is shorter than the saved transcription checkpoint".to_string(),
+            ));
+        }
+    }
+
+    emit(on_event, PipelineEvent::TranscribeStarted);
+
+    let total_samples = audio_samples.unwrap_or(0).max(1);
+    loop {
+        let start = checkpoint.completed_samples;
+        let wanted = match max_samples {
+            Some(max) => chunk_samples.min(max.saturating_sub(start)),
+            None => chunk_samples,
+        };
+        // A full chunk ends at a pause, so no word is split between two chunks
+        let available = audio.peek(wanted)?;
+        let cut = if available.len() == chunk_samples {
+            quietest_cut(available)
+        } else {
+            available.len()
+        };
+        let chunk = audio.read(cut)?;
+        if chunk.is_empty() {
+            break;
+        }
+        // Carry the end of the previous chunk over as context, as Whisper does
+        // between its own 30-second windows
+        let prompt = chunk_prompt(&checkpoint.segments);
+        let end = start + chunk.len();
+        let offset = start as f64 / WHISPER_SAMPLE_RATE as f64;
+
+        // Set up transcription parameters
+        let mut params =
+            whisper_rs::FullParams::new(whisper_rs::SamplingStrategy::Greedy { best_of: 1 });
+        params.set_language(Some(&whisper_lang));
+        params.set_print_special(false);
+        params.set_print_progress(false);
+        params.set_print_realtime(false);
+        params.set_print_timestamps(false);
+        if !prompt.is_empty() {
+            params.set_initial_prompt(&prompt);
+        }
+
+        if let Some(handler) = on_event {
+            let handler = std::sync::Arc::clone(handler);
+            // Whisper reports progress within the chunk; scale it to the whole file
+            let done = start as f32 / total_samples as f32 * 100.0;
+            let share = (end - start) as f32 / total_samples as f32;
+            params.set_progress_callback_safe(move |progress: i32| {
+                handler(PipelineEvent::TranscribeProgress(
+                    (done + progress as f32 * share).min(100.0),
+                ));
+            });
+        }
+
+        // Transcribe the chunk
+        state
+            .full(params, &chunk)
+            .map_err(|e| Y2mdError::Whisper(format!("Transcription failed: {}", e)))?;
+
+        // Whisper timestamps are in centiseconds, relative to the chunk
+        for segment in state.as_iter() {
+            checkpoint.segments.push(TranscriptSegment {
+                start: offset + segment.start_timestamp() as f64 / 100.0,
+                end: offset + segment.end_timestamp() as f64 / 100.0,
+                text: segment.to_string().trim().to_string(),
+                speaker: None,
+                avg_logprob: segment_avg_logprob(&segment, ctx.token_eot()),
+            });
+        }
+        checkpoint.completed_samples = end;
+
+        if let Some(path) = checkpoint_path.as_deref() {
+            save_checkpoint(path, &checkpoint);
+        }
+    }
+    drop(audio);
+
+    emit(on_event, PipelineEvent::TranscribeFinished);
+
+    // The transcript is complete, so there is nothing left to resume
+    if let Some(path) = checkpoint_path.as_deref() {
+        let _ = std::fs::remove_file(path);
+    }
+
+    let segments = checkpoint.segments;
+    let raw_transcript = segments
+        .iter()
+        .map(|segment| segment.text.as_str())
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    if raw_transcript.trim().is_empty() {
+        return Err(Y2mdError::EmptyTranscript(
+            "no speech detected in the audio".to_string(),
+        ));
+    }
+
+    println!(
+        "Transcription completed successfully (language: {})",
+        whisper_lang
+    );
+
+    // Apply formatting to STT output
+    println!("Applying formatting to transcript...");
+    let formatted_transcript = format_transcript_by(&raw_transcript, false, paragraph_length);
+    println!("Formatting completed");
+    Ok((formatted_transcript, raw_transcript, segments))
+}
+
+/// Mean log probability of a Whisper segment's text tokens. Special and
+/// timestamp tokens (ids from `token_eot` up) are left out, as their
+/// probabilities say nothing about the words.
+fn segment_avg_logprob(
+    segment: &whisper_rs::WhisperSegment,
+    token_eot: whisper_rs::WhisperTokenId,
+) -> Option<f32> {
+    let logprobs: Vec<f32> = (0..segment.n_tokens())
+        .filter_map(|token| segment.get_token(token))
+        .filter(|token| token.token_id() < token_eot)
+        .map(|token| token.token_probability().max(f32::MIN_POSITIVE).ln())
+        .collect();
+    (!logprobs.is_empty()).then(|| logprobs.iter().sum::<f32>() / logprobs.len() as f32)
+}
+
+/// Detect the spoken language from the first 30 seconds of audio
+fn detect_language(
+    state: &mut whisper_rs::WhisperState,
+    audio: &[f32],
+) -> Result<LanguageDetection, Y2mdError> {
+    let threads = std::thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(1);
+
+    state
+        .pcm_to_mel(audio, threads)
+        .map_err(|e| Y2mdError::Whisper(format!("Failed to compute mel spectrogram: {}", e)))?;
+    let (id, probabilities) = state
+        .lang_detect(0, threads)
+        .map_err(|e| Y2mdError::Whisper(format!("Language detection failed: {}", e)))?;
+
+    let language = whisper_rs::get_lang_str(id)
+        .ok_or_else(|| Y2mdError::Whisper(format!("Unknown language id {}", id)))?;
+    let probability = usize::try_from(id)
+        .ok()
+        .and_then(|index| probabilities.get(index).copied())
+        .unwrap_or_default();
+
+    Ok(LanguageDetection {
+        language: language.to_string(),
+        probability,
+    })
+}
+
+/// Transcribe an audio file with the selected speech-to-text backend
+#[allow(clippy::too_many_arguments)]
+async fn transcribe_with_backend(
+    stt: &SttSettings,
+    client: &HttpClient,
+    credentials: &CredentialManager,
+    audio_path: &PathBuf,
+    language: Option<&str>,
+    paragraph_length: ParagraphLength,
+    ffmpeg_path: &str,
+    checkpoint_id: Option<&str>,
+    max_seconds: Option<u64>,
+    max_repeated_sentences: usize,
+    model_dirs: &[PathBuf],
+    on_event: Option<&EventHandler>,
+) -> Result<(String, String, Vec<TranscriptSegment>), Y2mdError> {
+    // yt-dlp cuts `--download-sections` at keyframes and can keep more than
+    // the sample; local Whisper stops at `max_seconds` itself, the APIs do not
+    let sample = match (&stt.backend, max_seconds) {
+        (SttBackend::OpenAI | SttBackend::Deepgram, Some(seconds)) => {
+            Some(trim_audio(audio_path, ffmpeg_path, seconds).await?)
+        }
+        _ => None,
+    };
+    let upload_path = sample.as_ref().map_or(audio_path, |(_, path)| path);
+
+    let (formatted, raw_transcript, segments) = match &stt.backend {
+        SttBackend::Local => {
+            transcribe_audio(
+                audio_path,
+                language,
+                paragraph_length,
+                ffmpeg_path,
+                checkpoint_id,
+                max_seconds,
+                model_dirs,
+                on_event,
+            )
+            .await
+        }
+        SttBackend::OpenAI => {
+            // Leaving the language out lets the API detect it
+            transcribe_audio_openai(
+                upload_path,
+                language.filter(|lang| *lang != AUTO_LANGUAGE),
+                paragraph_length,
+                ffmpeg_path,
+                &stt.openai,
+                client,
+                credentials,
+                on_event,
+            )
+            .await
+        }
+        SttBackend::Deepgram => {
+            transcribe_audio_deepgram(
+                upload_path,
+                language.filter(|lang| *lang != AUTO_LANGUAGE),
+                paragraph_length,
+                &stt.deepgram,
+                client,
+                credentials,
+                on_event,
+            )
+            .await
+        }
+    }?;
+
+    // Whisper tends to loop on silence ("Thank you. Thank you. Thank you.")
+    let collapsed = collapse_repeated_sentences(&raw_transcript, max_repeated_sentences);
+    if collapsed == raw_transcript {
+        return Ok((formatted, raw_transcript, segments));
+    }
+    println!(
+        "Collapsed sentences repeated more than {} times in a row",
+        max_repeated_sentences
+    );
+    let formatted = format_transcript_by(&collapsed, false, paragraph_length);
+    let segments = collapse_repeated_segments(segments, max_repeated_sentences);
+    Ok((formatted, collapsed, segments))
+}
+
+/// Normalized form of a sentence for spotting repeats: case and closing punctuation ignored
+fn repeat_key(text: &str) -> String {
+    text.trim()
+        .trim_end_matches(|c: char| c.is_ascii_punctuation() || c == '\u{2026}')
+        .to_lowercase()
+}
+
+/// Collapse any sentence repeated more than `max_repeats` times in a row to
+/// one occurrence. Shorter runs, which may well be real speech ("No. No."),
+/// are kept; a `max_repeats` of 0 leaves the text untouched.
+pub fn collapse_repeated_sentences(text: &str, max_repeats: usize) -> String {
+    if max_repeats == 0 {
+        return text.to_string();
+    }
+
+    let sentences = segment_sentences(text);
+    let mut kept: Vec<&str> = Vec::new();
+    let mut collapsed = false;
+    let mut i = 0;
+    while i < sentences.len() {
+        let key = repeat_key(&sentences[i].text);
+        let run = sentences[i..]
+            .iter()
+            .take_while(|sentence| repeat_key(&sentence.text) == key)
+            .count();
+        if run > max_repeats {
+            kept.push(&sentences[i].text);
+            collapsed = true;
+        } else {
+            kept.extend(
+                sentences[i..i + run]
+                    .iter()
+                    .map(|sentence| sentence.text.as_str()),
+            );
+        }
+        i += run;
+    }
+
+    if collapsed {
+        kept.join(" ")
+    } else {
+        text.to_string()
+    }
+}
+
+/// Segment-level counterpart of [`collapse_repeated_sentences`]: a run of
+/// more than `max_repeats` segments with the same text becomes its first
+/// segment, stretched to the end of the run
+fn collapse_repeated_segments(
+    segments: Vec<TranscriptSegment>,
+    max_repeats: usize,
+) -> Vec<TranscriptSegment> {
+    let mut result: Vec<TranscriptSegment> = Vec::new();
+    let mut i = 0;
+    while i < segments.len() {
+        let key = repeat_key(&segments[i].text);
+        let run = segments[i..]
+            .iter()
+            .take_while(|segment| repeat_key(&segment.text) == key)
+            .count();
+        if max_repeats > 0 && run > max_repeats {
+            let mut segment = segments[i].clone();
+            segment.end = segments[i + run - 1].end;
+            result.push(segment);
+        } else {
+            result.extend_from_slice(&segments[i..i + run]);
+        }
+        i += run;
+    }
+    result
+}
+
+/// Maximum upload size accepted by the OpenAI transcription endpoint
+const OPENAI_UPLOAD_LIMIT: u64 = 25 * 1024 * 1024;
+
+/// Length of each piece when audio has to be split for upload
+const UPLOAD_CHUNK_SECONDS: u64 = 20 * 60;
+
+/// A piece of audio to upload, with its position in the original recording
+struct AudioChunk {
+    path: PathBuf,
+    offset: f64,
+    temporary: bool,
+}
+
+/// Transcribe an audio file with the OpenAI Whisper API
+#[allow(clippy::too_many_arguments)]
+pub async fn transcribe_audio_openai(
+    audio_path: &PathBuf,
+    language: Option<&str>,
+    paragraph_length: ParagraphLength,
+    ffmpeg_path: &str,
+    settings: &OpenAiSttConfig,
+    client: &HttpClient,
+    credentials: &CredentialManager,
+    on_event: Option<&EventHandler>,
+) -> Result<(String, String, Vec<TranscriptSegment>), Y2mdError> {
+    if !audio_path.exists() {
+        return Err(Y2mdError::Config(format!(
+            "Audio file not found: {:?}",
+            audio_path
+        )));
+    }
+
+    let api_key = credentials
+        .get_api_key(&LlmProviderType::OpenAI)?
+        .ok_or_else(|| {
+            Y2mdError::Whisper("OpenAI API key not set. Use: y2md llm set-key openai".to_string())
+        })?;
+
+    println!("Transcribing audio with the OpenAI Whisper API...");
+
+    let chunks = prepare_upload_chunks(audio_path, ffmpeg_path, OPENAI_UPLOAD_LIMIT).await?;
+
+    emit(on_event, PipelineEvent::TranscribeStarted);
+
+    let mut segments = Vec::new();
+    let mut result = Ok(());
+    for (index, chunk) in chunks.iter().enumerate() {
+        match request_openai_transcription(client, settings, &api_key, chunk, language).await {
+            Ok(chunk_segments) => segments.extend(chunk_segments),
+            Err(e) => {
+                result = Err(e);
+                break;
+            }
+        }
+        let progress = (index + 1) as f32 / chunks.len() as f32 * 100.0;
+        emit(on_event, PipelineEvent::TranscribeProgress(progress));
+    }
+
+    for chunk in chunks.iter().filter(|chunk| chunk.temporary) {
+        let _ = std::fs::remove_file(&chunk.path);
+    }
+    result?;
+
+    emit(on_event, PipelineEvent::TranscribeFinished);
+
+    let raw_transcript = segments
+        .iter()
+        .map(|segment| segment.text.as_str())
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    if raw_transcript.trim().is_empty() {
+        return Err(Y2mdError::EmptyTranscript(
+            "no speech detected in the audio".to_string(),
+        ));
+    }
+
+    println!("Transcription completed successfully");
+
+    println!("Applying formatting to transcript...");
+    let formatted_transcript = format_transcript_by(&raw_transcript, false, paragraph_length);
+    println!("Formatting completed");
+    Ok((formatted_transcript, raw_transcript, segments))
+}
+
+/// Upload one audio chunk and return its segments shifted to the chunk's offset
+async fn request_openai_transcription(
+    client: &HttpClient,
+    stt_config: &OpenAiSttConfig,
+    api_key: &str,
+    chunk: &AudioChunk,
+    language: Option<&str>,
+) -> Result<Vec<TranscriptSegment>, Y2mdError> {
+    let bytes = std::fs::read(&chunk.path)?;
+    let file_name = chunk
+        .path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or("audio.mp3")
+        .to_string();
+
+    let mut form = reqwest::multipart::Form::new()
+        .part(
+            "file",
+            reqwest::multipart::Part::bytes(bytes).file_name(file_name),
+        )
+        .text("model", stt_config.model.clone())
+        .text("response_format", "verbose_json");
+    if let Some(lang) = language {
+        form = form.text("language", lang.to_string());
+    }
+
+    let response = client
+        .post(format!("{}/audio/transcriptions", stt_config.endpoint))
+        .header("Authorization", format!("Bearer {}", api_key))
+        .multipart(form)
+        .timeout(std::time::Duration::from_secs(600))
+        .send()
+        .await
+        .map_err(|e| {
+            if e.is_timeout() {
+                Y2mdError::Whisper("OpenAI transcription timed out after 10 minutes".to_string())
+            } else {
+                Y2mdError::Whisper(format!("Failed to connect to OpenAI API: {}", e))
+            }
+        })?;
+
+    if !response.status().is_success() {
+        let status = response.status();
+        let body = response.text().await.unwrap_or_default();
+        return Err(Y2mdError::Whisper(format!(
+            "OpenAI API returned error: {} {}",
+            status,
+            body.trim()
+        )));
+    }
+
+    let response_json: serde_json::Value = response
+        .json()
+        .await
+        .map_err(|e| Y2mdError::Whisper(format!("Failed to parse OpenAI response: {}", e)))?;
+
+    Ok(parse_openai_segments(&response_json, chunk.offset))
+}
+
+/// Extract timed segments from a `verbose_json` transcription response
+fn parse_openai_segments(response: &serde_json::Value, offset: f64) -> Vec<TranscriptSegment> {
+    let segments: Vec<TranscriptSegment> = response["segments"]
+        .as_array()
+        .map(|segments| {
+            segments
+                .iter()
+                .filter_map(|segment| {
+                    let text = segment["text"].as_str()?.trim();
+                    if text.is_empty() {
+                        return None;
+                    }
+                    Some(TranscriptSegment {
+                        start: segment["start"].as_f64().unwrap_or(0.0) + offset,
+                        end: segment["end"].as_f64().unwrap_or(0.0) + offset,
+                        text: text.to_string(),
+                        speaker: None,
+                        avg_logprob: segment["avg_logprob"]
+                            .as_f64()
+                            .map(|logprob| logprob as f32),
+                    })
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+
+    if !segments.is_empty() {
+        return segments;
+    }
+
+    // Fall back to the plain text when the model returns no segment timings
+    match response["text"].as_str().map(str::trim) {
+        Some(text) if !text.is_empty() => vec![TranscriptSegment {
+            start: offset,
+            end: offset + response["duration"].as_f64().unwrap_or(0.0),
+            text: text.to_string(),
+            speaker: None,
+            avg_logprob: None,
+        }],
+        _ => Vec::new(),
+    }
+}
+
+/// Transcribe an audio file with Deepgram's prerecorded audio API
+pub async fn transcribe_audio_deepgram(
+    audio_path: &PathBuf,
+    language: Option<&str>,
+    paragraph_length: ParagraphLength,
+    settings: &DeepgramSttConfig,
+    client: &HttpClient,
+    credentials: &CredentialManager,
+    on_event: Option<&EventHandler>,
+) -> Result<(String, String, Vec<TranscriptSegment>), Y2mdError> {
+    if !audio_path.exists() {
+        return Err(Y2mdError::Config(format!(
+            "Audio file not found: {:?}",
+            audio_path
+        )));
+    }
+
+    let api_key = credentials.get_key("deepgram")?.ok_or_else(|| {
+        Y2mdError::Whisper("Deepgram API key not set. Use: y2md llm set-key deepgram".to_string())
+    })?;
+
+    println!("Transcribing audio with Deepgram...");
+    emit(on_event, PipelineEvent::TranscribeStarted);
+
+    let mut query = vec![
+        ("model", settings.model.clone()),
+        ("smart_format", "true".to_string()),
+        ("punctuate", "true".to_string()),
+    ];
+    if settings.diarize {
+        query.push(("diarize", "true".to_string()));
+    }
+    if let Some(lang) = language {
+        query.push(("language", lang.to_string()));
+    }
+
+    let bytes = std::fs::read(audio_path)?;
+    let response = client
+        .post(format!("{}/listen", settings.endpoint))
+        .query(&query)
+        .header("Authorization", format!("Token {}", api_key))
+        .header("Content-Type", audio_content_type(audio_path))
+        .body(bytes)
+        .timeout(std::time::Duration::from_secs(600))
+        .send()
+        .await
+        .map_err(|e| {
+            if e.is_timeout() {
+                Y2mdError::Whisper("Deepgram transcription timed out after 10 minutes".to_string())
+            } else {
+                Y2mdError::Whisper(format!("Failed to connect to Deepgram API: {}", e))
+            }
+        })?;
+
+    if !response.status().is_success() {
+        let status = response.status();
+        let body = response.text().await.unwrap_or_default();
+        return Err(Y2mdError::Whisper(format!(
+            "Deepgram API returned error: {} {}",
+            status,
+            body.trim()
+        )));
+    }
+
+    let response_json: serde_json::Value = response
+        .json()
+        .await
+        .map_err(|e| Y2mdError::Whisper(format!("Failed to parse Deepgram response: {}", e)))?;
+
+    emit(on_event, PipelineEvent::TranscribeFinished);
+
+    let alternative = &response_json["results"]["channels"][0]["alternatives"][0];
+    let raw_transcript = alternative["transcript"]
+        .as_str()
+        .unwrap_or_default()
+        .trim()
+        .to_string();
+
+    if raw_transcript.is_empty() {
+        return Err(Y2mdError::EmptyTranscript(
+            "no speech detected in the audio".to_string(),
+        ));
+    }
+
+    let segments = parse_deepgram_words(alternative);
+
+    println!("Transcription completed successfully");
+
+    println!("Applying formatting to transcript...");
+    let formatted_transcript = format_transcript_by(&raw_transcript, false, paragraph_length);
+    println!("Formatting completed");
+    Ok((formatted_transcript, raw_transcript, segments))
+}
+
+/// Longest span covered by one segment built from word timings
+const MAX_SEGMENT_SECONDS: f64 = 10.0;
+
+/// Group Deepgram word timings into sentence-sized segments, split on speaker changes
+fn parse_deepgram_words(alternative: &serde_json::Value) -> Vec<TranscriptSegment> {
+    let mut segments = Vec::new();
+    let mut current: Option<TranscriptSegment> = None;
+
+    for word in alternative["words"].as_array().into_iter().flatten() {
+        let Some(text) = word["punctuated_word"]
+            .as_str()
+            .or_else(|| word["word"].as_str())
+        else {
+            continue;
+        };
+        let start = word["start"].as_f64().unwrap_or(0.0);
+        let end = word["end"].as_f64().unwrap_or(start);
+        let speaker = word["speaker"].as_u64().map(|speaker| speaker as u32);
+
+        // A new speaker always starts a new segment
+        if current
+            .as_ref()
+            .is_some_and(|segment| segment.speaker != speaker)
+        {
+            segments.extend(current.take());
+        }
+
+        let segment = current.get_or_insert_with(|| TranscriptSegment {
+            start,
+            end,
+            text: String::new(),
+            speaker,
+            avg_logprob: None,
+        });
+        if !segment.text.is_empty() {
+            segment.text.push(' ');
+        }
+        segment.text.push_str(text);
+        segment.end = end;
+
+        let sentence_end = text.ends_with(['.', '?', '!']);
+        if sentence_end || segment.end - segment.start >= MAX_SEGMENT_SECONDS {
+            segments.extend(current.take());
+        }
+    }
+
+    segments.extend(current);
+    segments
+}
+
+/// Guess the MIME type of a downloaded audio file from its extension
+fn audio_content_type(audio_path: &std::path::Path) -> &'static str {
+    match audio_path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| ext.to_lowercase())
+        .as_deref()
+    {
+        Some("mp3") => "audio/mpeg",
+        Some("m4a") | Some("mp4") => "audio/mp4",
+        Some("webm") => "audio/webm",
+        Some("ogg") | Some("opus") => "audio/ogg",
+        Some("wav") => "audio/wav",
+        Some("flac") => "audio/flac",
+        _ => "application/octet-stream",
+    }
+}
+
+/// Copy the first `seconds` of `audio_path` for a `--sample` upload; the
+/// copy is removed when the returned [`TempDownload`] is dropped
+async fn trim_audio(
+    audio_path: &PathBuf,
+    ffmpeg_path: &str,
+    seconds: u64,
+) -> Result<(TempDownload, PathBuf), Y2mdError> {
+    let extension = audio_path
+        .extension()
+        .map(|ext| ext.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "mp3".to_string());
+    let sample = TempDownload::new(std::env::temp_dir(), "sample");
+    let path = PathBuf::from(format!("{}.{}", sample.template().display(), extension));
+
+    run_ffmpeg(
+        ffmpeg_path,
+        &[
+            "-i",
+            &audio_path.to_string_lossy(),
+            "-t",
+            &seconds.to_string(),
+            "-c",
+            "copy",
+            "-y",
+            &path.to_string_lossy(),
+        ],
+    )
+    .await?;
+
+    Ok((sample, path))
+}
+
+/// Shrink or split audio so every piece fits under the upload size limit
+async fn prepare_upload_chunks(
+    audio_path: &PathBuf,
+    ffmpeg_path: &str,
+    max_bytes: u64,
+) -> Result<Vec<AudioChunk>, Y2mdError> {
+    if std::fs::metadata(audio_path)?.len() <= max_bytes {
+        return Ok(vec![AudioChunk {
+            path: audio_path.clone(),
+            offset: 0.0,
+            temporary: false,
+        }]);
+    }
+
+    println!("Audio exceeds the upload limit, re-encoding at a lower bitrate...");
+
+    let temp_dir = std::env::temp_dir();
+    let stem = format!("y2md_upload_{}", uuid::Uuid::new_v4());
+    let compressed = temp_dir.join(format!("{}.mp3", stem));
+
+    // Mono 16kHz at 32kbps keeps speech intelligible at a fraction of the size
+    run_ffmpeg(
+        ffmpeg_path,
+        &[
+            "-i",
+            &audio_path.to_string_lossy(),
+            "-vn",
+            "-ac",
+            "1",
+            "-ar",
+            "16000",
+            "-b:a",
+            "32k",
+            "-y",
+            &compressed.to_string_lossy(),
+        ],
+    )
+    .await?;
+
+    if std::fs::metadata(&compressed)?.len() <= max_bytes {
+        return Ok(vec![AudioChunk {
+            path: compressed,
+            offset: 0.0,
+            temporary: true,
+        }]);
+    }
+
+    println!("Audio is still too large, splitting into smaller pieces...");
+
+    let pattern = temp_dir.join(format!("{}_%03d.mp3", stem));
+    let split = run_ffmpeg(
+        ffmpeg_path,
+        &[
+            "-i",
+            &compressed.to_string_lossy(),
+            "-f",
+            "segment",
+            "-segment_time",
+            &UPLOAD_CHUNK_SECONDS.to_string(),
+            "-c",
+            "copy",
+            "-y",
+            &pattern.to_string_lossy(),
+        ],
+    )
+    .await;
+    let _ = std::fs::remove_file(&compressed);
+    split?;
+
+    let prefix = format!("{}_", stem);
+    let mut pieces: Vec<PathBuf> = std::fs::read_dir(&temp_dir)?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| {
+            path.file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| name.starts_with(&prefix))
+        })
+        .collect();
+    pieces.sort();
+
+    let chunks: Vec<AudioChunk> = pieces
+        .into_iter()
+        .enumerate()
+        .map(|(index, path)| AudioChunk {
+            path,
+            offset: (index as u64 * UPLOAD_CHUNK_SECONDS) as f64,
+            temporary: true,
+        })
+        .collect();
+
+    if let Some(chunk) = chunks
+        .iter()
+        .find(|chunk| std::fs::metadata(&chunk.path).map_or(true, |m| m.len() > max_bytes))
+    {
+        let path = chunk.path.clone();
+        for chunk in &chunks {
+            let _ = std::fs::remove_file(&chunk.path);
+        }
+        return Err(Y2mdError::Whisper(format!(
+            "Audio piece {:?} is still larger than the 25MB upload limit",
+            path
+        )));
+    }
+
+    if chunks.is_empty() {
+        return Err(Y2mdError::Whisper(
+            "Splitting audio for upload produced no files".to_string(),
+        ));
+    }
+
+    Ok(chunks)
+}
+
+/// Run ffmpeg quietly with the given arguments
+async fn run_ffmpeg(ffmpeg_path: &str, args: &[&str]) -> Result<(), Y2mdError> {
+    // `-progress` reports on stdout every half second, which tells a slow
+    // conversion apart from a stuck one
+    let mut child = Command::new(ffmpeg_path)
+        .args(["-nostats", "-progress", "pipe:1"])
+        .args(args)
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::null())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|e| {
+            if e.kind() == std::io::ErrorKind::NotFound {
+                Y2mdError::FFmpegNotFound(ffmpeg_path.to_string())
+            } else {
+                Y2mdError::Io(e)
+            }
+        })?;
+
+    read_progress_lines(&mut child, "FFmpeg", |_| {}).await?;
+    let status = child.wait().await?;
+
+    if !status.success() {
+        return Err(Y2mdError::Config("FFmpeg conversion failed".to_string()));
+    }
+
+    Ok(())
+}
+
+/// Map a language code to a whisper model file, `None` when it has no mapping
+fn whisper_model_file(lang: &str) -> Option<&'static str> {
+    match lang {
+        // The multilingual model is needed to detect the language
+        AUTO_LANGUAGE => Some("ggml-base.bin"),
+        "en" => Some("ggml-base.en.bin"),
+        "es" | "fr" | "de" | "it" | "pt" | "ru" | "ja" | "zh" | "ko" | "ar" | "hi" => {
+            Some("ggml-base.bin")
+        }
+        _ => None,
+    }
+}
+
+/// Short model name for `language` as shown in the front matter (e.g. `base.en`)
+fn whisper_model_name(language: Option<&str>) -> String {
+    whisper_model_file(language.unwrap_or("en"))
+        .unwrap_or("ggml-base.en.bin")
+        .trim_start_matches("ggml-")
+        .trim_end_matches(".bin")
+        .to_string()
+}
+
+/// Models directory of earlier versions on every platform
+const LEGACY_MODELS_DIR: &str = "~/.local/share/y2md/models";
+
+/// Platform data directory for Whisper models: `$XDG_DATA_HOME/y2md/models`
+/// on Linux, `~/Library/Application Support/com.y2md.y2md/models` on macOS
+/// and `%APPDATA%\y2md\y2md\data\models` on Windows
+pub fn default_models_dir() -> Option<PathBuf> {
+    directories::ProjectDirs::from("com", "y2md", "y2md").map(|dirs| dirs.data_dir().join("models"))
+}
+
+/// Whether a file name looks like a whisper.cpp model (`ggml-*.bin`)
+fn is_whisper_model_file(name: &str) -> bool {
+    name.starts_with("ggml-") && name.ends_with(".bin")
+}
+
+/// Check that a models directory exists and holds at least one whisper.cpp
+/// model (`ggml-*.bin`), so a wrong path fails before any download
+pub fn check_models_dir(dir: &std::path::Path) -> Result<(), String> {
+    let entries = std::fs::read_dir(dir)
+        .map_err(|e| format!("Models directory {} is not readable: {}", dir.display(), e))?;
+    let has_model = entries
+        .filter_map(Result::ok)
+        .any(|entry| is_whisper_model_file(&entry.file_name().to_string_lossy()));
+    if has_model {
+        Ok(())
+    } else {
+        Err(format!(
+            "No Whisper models (ggml-*.bin) found in {}",
+            dir.display()
+        ))
+    }
+}
+
+/// Parse a `--models-dir` path, expanding `~` and checking it with [`check_models_dir`]
+pub fn parse_models_dir(input: &str) -> Result<String, String> {
+    let dir = shellexpand::tilde(input.trim()).to_string();
+    check_models_dir(std::path::Path::new(&dir))?;
+    Ok(dir)
+}
+
+/// The first of `dirs` that holds `model_file`, or where it belongs in the
+/// first directory when none does
+pub fn locate_model(dirs: &[PathBuf], model_file: &str) -> PathBuf {
+    dirs.iter()
+        .map(|dir| dir.join(model_file))
+        .find(|path| path.exists())
+        .or_else(|| dirs.first().map(|dir| dir.join(model_file)))
+        .unwrap_or_else(|| PathBuf::from(model_file))
+}
+
+/// Determine which whisper model and language to use, looking for the model in `model_dirs`
+fn determine_model_and_language(
+    language: Option<&str>,
+    model_dirs: &[PathBuf],
+) -> Result<(String, String), Y2mdError> {
+    // Default to English if no language specified
+    let lang = language.unwrap_or("en");
+
+    let (model_name, whisper_lang) = match whisper_model_file(lang) {
+        Some(model_name) => (model_name, lang),
+        None => {
+            // For unsupported languages, fall back to English model
+            println!(
+                "Warning: Language '{}' not explicitly supported, falling back to English model",
+                lang
+            );
+            ("ggml-base.en.bin", "en")
+        }
+    };
+
+    let model_path = locate_model(model_dirs, model_name);
+    Ok((
+        model_path.to_string_lossy().into_owned(),
+        whisper_lang.to_string(),
+    ))
+}
+
+/// Format transcript as Markdown with metadata. Also returns the LLM output
+/// when an LLM formatted the transcript, for its provider, model and usage.
+#[allow(clippy::too_many_arguments)]
+pub async fn format_markdown(
+    metadata: &VideoMetadata,
+    transcript: &str,
+    source: &TranscriptionSource,
+    language: &str,
+    language_probability: Option<f32>,
+    include_timestamps: bool,
+    timestamp_interval: Option<u64>,
+    segments: &[TranscriptSegment],
+    compact: bool,
+    paragraph_length: ParagraphLength,
+    use_llm: bool,
+    llm_config: &AppConfig,
+    client: &HttpClient,
+    llm_provider: Option<LlmProviderType>,
+    llm_model: Option<&str>,
+    llm_temperature: Option<f32>,
+    speaker_segments: Option<&[TranscriptSegment]>,
+    append_footer: bool,
+    include_description: bool,
+    date_format: Option<&str>,
+    timezone: Timezone,
+) -> (String, Option<LlmOutput>) {
+    let mut markdown = String::new();
+    let extracted_at = timezone.now();
+
+    // Set once the LLM has formatted the transcript
+    let mut llm_result: Option<LlmOutput> = None;
+
+    // Add YAML front matter
+    markdown.push_str("---\n");
+    markdown.push_str(&format!(
+        "title: \"{}\"\n",
+        escape_markdown(&metadata.title)
+    ));
+    if let Some(channel) = &metadata.channel {
+        markdown.push_str(&format!("channel: \"{}\"\n", escape_markdown(channel)));
+    }
+    markdown.push_str(&format!("url: \"{}\"\n", metadata.url));
+    markdown.push_str(&format!("video_id: \"{}\"\n", metadata.video_id));
+    if let Some(duration) = &metadata.duration {
+        markdown.push_str(&format!("duration: \"{}\"\n", duration));
+    }
+    markdown.push_str(&format!("source: \"{}\"\n", source));
+    markdown.push_str(&format!("language: \"{}\"\n", language));
+    if let Some(probability) = language_probability {
+        markdown.push_str(&format!("language_probability: {:.2}\n", probability));
+    }
+    markdown.push_str(&format!(
+        "extracted_at: \"{}\"\n",
+        date_format
+            .filter(|format| check_date_format(format).is_ok())
+            .map(|format| extracted_at.format(format).to_string())
+            .unwrap_or_else(|| extracted_at.to_rfc3339())
+    ));
+    markdown.push_str("---\n\n");
+
+    // Add title
+    markdown.push_str(&format!("# {}\n\n", escape_markdown(&metadata.title)));
+
+    if include_description {
+        if let Some(description) = &metadata.description {
+            markdown.push_str(&format_description(description));
+        }
+    }
+
+    // Add transcript
+    if include_timestamps && timestamp_interval.is_none() {
+        // For now, add placeholder timestamps
+        markdown.push_str("[00:00:00] ");
+    }
+
+    // With diarization, speaker-labelled blocks replace the plain transcript
+    let labelled_transcript =
+        speaker_segments.map(|segments| format_speaker_blocks(segments, paragraph_length));
+
+    // Use enhanced formatting for better readability
+    let formatted_transcript = if use_llm {
+        println!("Using LLM for enhanced formatting...");
+
+        let llm_input = labelled_transcript.as_deref().unwrap_or(transcript);
+        match format_with_llm(
+            llm_input,
+            llm_config,
+            client,
+            llm_provider,
+            llm_model,
+            llm_temperature,
+        )
+        .await
+        {
+            Ok(llm_output) => {
+                println!(
+                    "LLM formatting completed successfully with {}",
+                    llm_output.provider
+                );
+                let text = llm_output.text.clone();
+                llm_result = Some(llm_output);
+                text
+            }
+            Err(e) => {
+                println!(
+                    "LLM formatting failed: {}, falling back to standard formatting",
+                    e
+                );
+                println!("Tip: Check your LLM configuration with 'y2md config'");
+                labelled_transcript
+                    .unwrap_or_else(|| format_transcript_by(transcript, compact, paragraph_length))
+            }
+        }
+    } else if let Some(labelled) = labelled_transcript {
+        labelled
+    } else {
+        format_transcript_by(transcript, compact, paragraph_length)
+    };
+    let formatted_transcript = match timestamp_interval {
+        Some(interval) if !segments.is_empty() => {
+            insert_timestamp_markers(&formatted_transcript, segments, interval)
+        }
+        Some(_) => {
+            println!("No segment timings for this transcript, leaving out timestamp markers");
+            formatted_transcript
+        }
+        None => formatted_transcript,
+    };
+
+    // Now add formatting metadata after we know the results
+    let mut front_matter_addition = String::new();
+    match &llm_result {
+        Some(llm_output) => {
+            front_matter_addition.push_str("formatted_by: \"llm\"\n");
+            front_matter_addition.push_str(&format!("llm_provider: \"{}\"\n", llm_output.provider));
+            front_matter_addition.push_str(&format!("llm_model: \"{}\"\n", llm_output.model));
+        }
+        None => front_matter_addition.push_str("formatted_by: \"standard\"\n"),
+    }
+
+    // Insert the formatting metadata before the closing --- of front matter
+    // Find the position of the closing --- marker
+    if let Some(pos) = markdown.find("---\n\n# ") {
+        // Insert the metadata before the closing ---
+        markdown.insert_str(pos, &front_matter_addition);
+    }
+
+    markdown.push_str(&formatted_transcript);
+
+    if append_footer {
+        markdown.push_str(&metadata_footer(metadata, source, &extracted_at));
+    }
+
+    (markdown, llm_result)
+}
+
+/// Prefix a paragraph with its `[HH:MM:SS]` start time each time the
+/// transcript crosses a multiple of `interval` seconds. Paragraphs are placed
+/// on the segment timeline by word position, which is exact for standard
+/// formatting and close enough for LLM output; headings are left unmarked.
+pub fn insert_timestamp_markers(
+    text: &str,
+    segments: &[TranscriptSegment],
+    interval: u64,
+) -> String {
+    let interval = interval.max(1) as f64;
+    let is_prose = |paragraph: &str| !paragraph.starts_with('#');
+
+    // Start time of each word of the segments, in order
+    let word_starts: Vec<f64> = segments
+        .iter()
+        .flat_map(|segment| {
+            std::iter::repeat_n(segment.start, segment.text.split_whitespace().count())
+        })
+        .collect();
+    let paragraphs: Vec<&str> = text.split("\n\n").collect();
+    let total_words: usize = paragraphs
+        .iter()
+        .filter(|paragraph| is_prose(paragraph))
+        .map(|paragraph| paragraph.split_whitespace().count())
+        .sum();
+    if word_starts.is_empty() || total_words == 0 {
+        return text.to_string();
+    }
+
+    let mut words_before = 0;
+    let mut last_mark = None;
+    let mut marked = Vec::with_capacity(paragraphs.len());
+    for paragraph in paragraphs {
+        if !is_prose(paragraph) {
+            marked.push(paragraph.to_string());
+            continue;
+        }
+
+        let index = words_before * word_starts.len() / total_words;
+        let start = word_starts[index.min(word_starts.len() - 1)];
+        let mark = (start / interval).floor() as u64;
+        words_before += paragraph.split_whitespace().count();
+
+        if last_mark.is_some_and(|last| mark <= last) {
+            marked.push(paragraph.to_string());
+            continue;
+        }
+        last_mark = Some(mark);
+        let seconds = start as u64;
+        marked.push(format!(
+            "[{:02}:{:02}:{:02}] {}",
+            seconds / 3600,
+            seconds % 3600 / 60,
+            seconds % 60,
+            paragraph
+        ));
+    }
+
+    marked.join("\n\n")
+}
+
+/// Render the transcript as plain text for `--format txt`: paragraphs only,
+/// with no front matter, headings or markdown escaping. The video title is
+/// used as the first line when `include_title` is set.
+pub fn format_plain_text(
+    metadata: &VideoMetadata,
+    transcript: &str,
+    compact: bool,
+    paragraph_length: ParagraphLength,
+    speaker_segments: Option<&[TranscriptSegment]>,
+    include_title: bool,
+) -> String {
+    let body = match speaker_segments {
+        Some(segments) => speaker_blocks(segments, paragraph_length, |speaker| {
+            format!("Speaker {}:", speaker + 1)
+        }),
+        None => format_transcript_by(transcript, compact, paragraph_length),
+    };
+
+    let mut text = String::new();
+    if include_title {
+        text.push_str(&metadata.title);
+        text.push_str("\n\n");
+    }
+    text.push_str(body.trim());
+    text.push('\n');
+    text
+}
+
+/// `## Description` section with the escaped description; line breaks are
+/// kept as markdown hard breaks so chapter lists stay one entry per line
+fn format_description(description: &str) -> String {
+    let mut paragraphs = Vec::new();
+    let mut lines = Vec::new();
+    for line in description.lines().map(str::trim_end) {
+        if line.trim().is_empty() {
+            if !lines.is_empty() {
+                paragraphs.push(lines.join("  \n"));
+                lines.clear();
+            }
+        } else {
+            lines.push(escape_markdown(line));
+        }
+    }
+    if !lines.is_empty() {
+        paragraphs.push(lines.join("  \n"));
+    }
+
+    format!("## Description\n\n{}\n\n", paragraphs.join("\n\n"))
+}
+
+/// Attribution block appended with `--append-metadata-footer`
+fn metadata_footer(
+    metadata: &VideoMetadata,
+    source: &TranscriptionSource,
+    extracted_at: &chrono::DateTime<chrono::FixedOffset>,
+) -> String {
+    let by_channel = metadata
+        .channel
+        .as_ref()
+        .map(|channel| format!(" by {}", escape_markdown(channel)))
+        .unwrap_or_default();
+    let method = match source {
+        TranscriptionSource::ManualCaptions { .. } | TranscriptionSource::AutoCaptions { .. } => {
+            "YouTube captions".to_string()
+        }
+        TranscriptionSource::LocalWhisper { model } => {
+            format!("speech-to-text (Whisper {})", model)
+        }
+        TranscriptionSource::RemoteWhisper { provider } => {
+            format!("speech-to-text ({})", provider)
+        }
+    };
+
+    format!(
+        "\n\n---\n\n*Transcribed from [{}]({}){} on {}. Transcript source: {}.*\n",
+        escape_markdown(&metadata.title),
+        metadata.url,
+        by_channel,
+        extracted_at.format("%Y-%m-%d"),
+        method
+    )
+}
+
+/// Narrowest column accepted for `--wrap`
+pub const MIN_LINE_WRAP: usize = 20;
+
+/// Parse a `--wrap` column, rejecting widths too narrow to be readable
+pub fn parse_line_wrap(input: &str) -> Result<usize, String> {
+    match input.trim().parse::<usize>() {
+        Ok(width) if width >= MIN_LINE_WRAP => Ok(width),
+        Ok(width) => Err(format!(
+            "Line width {} is too narrow: use at least {}",
+            width, MIN_LINE_WRAP
+        )),
+        Err(_) => Err(format!(
+            "Invalid line width '{}': expected a positive number",
+            input
+        )),
+    }
+}
+
+/// Hard-wrap prose lines at `width` columns on word boundaries so that a
+/// changed word only changes one line in a diff. Front matter, headings,
+/// tables, HTML and fenced code are left alone; list items and block quotes
+/// keep their marker and get an aligned continuation indent. Words longer
+/// than `width` (e.g. URLs) are never split.
+pub fn wrap_markdown(markdown: &str, width: usize) -> String {
+    let mut result = String::with_capacity(markdown.len() + markdown.len() / width.max(1));
+    let mut in_front_matter = false;
+    let mut in_code_block = false;
+
+    for (index, line) in markdown.lines().enumerate() {
+        let trimmed = line.trim_start();
+        if index == 0 && line == "---" {
+            in_front_matter = true;
+        } else if in_front_matter {
+            in_front_matter = line != "---";
+        } else if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+            in_code_block = !in_code_block;
+        } else if !in_code_block
+            && line.chars().count() > width
+            && !trimmed.starts_with(['#', '|', '<'])
+        {
+            wrap_line(line, width, &mut result);
+            continue;
+        }
+
+        result.push_str(line);
+        result.push('\n');
+    }
+
+    if !markdown.ends_with('\n') {
+        result.pop();
+    }
+    result
+}
+
+/// Wrap one line into `out`, repeating its list or quote prefix as indentation
+fn wrap_line(line: &str, width: usize, out: &mut String) {
+    let (first_prefix, continuation) = line_prefixes(line);
+    let mut current = first_prefix.to_string();
+    let mut current_width = current.chars().count();
+    let mut has_words = false;
+
+    for word in line[first_prefix.len()..].split_whitespace() {
+        let word_width = word.chars().count();
+        if has_words && current_width + 1 + word_width > width {
+            out.push_str(&current);
+            out.push('\n');
+            current = continuation.clone();
+            current_width = current.chars().count();
+            has_words = false;
+        }
+        if has_words {
+            current.push(' ');
+            current_width += 1;
+        }
+        current.push_str(word);
+        current_width += word_width;
+        has_words = true;
+    }
+
+    out.push_str(&current);
+    out.push('\n');
+}
+
+/// The prefix of a markdown line that precedes its text, and the indentation
+/// its wrapped continuation lines need
+fn line_prefixes(line: &str) -> (&str, String) {
+    let indent = line.len() - line.trim_start().len();
+    let rest = &line[indent..];
+
+    let marker_len = if rest.starts_with("> ") {
+        // Quoted continuation lines repeat the quote marker
+        let prefix = &line[..indent + 2];
+        return (prefix, prefix.to_string());
+    } else if rest.starts_with("- ") || rest.starts_with("* ") || rest.starts_with("+ ") {
+        2
+    } else {
+        let digits = rest.chars().take_while(char::is_ascii_digit).count();
+        if digits > 0 && rest[digits..].starts_with(". ") {
+            digits + 2
+        } else {
+            0
+        }
+    };
+
+    let prefix = &line[..indent + marker_len];
+    (prefix, " ".repeat(indent + marker_len))
+}
+
+/// Join several rendered transcripts into one document with a table of
+/// contents. Each video's front matter is dropped and replaced by a short
+/// byline under its `# Title` heading.
+pub fn combine_markdown(outputs: &[ProcessOutput]) -> String {
+    let mut anchors: Vec<String> = Vec::new();
+    for output in outputs {
+        let base = heading_anchor(&output.metadata.title);
+        let mut anchor = base.clone();
+        let mut suffix = 1;
+        while anchors.contains(&anchor) {
+            anchor = format!("{}-{}", base, suffix);
+            suffix += 1;
+        }
+        anchors.push(anchor);
+    }
+
+    let mut combined = String::from("# Contents\n\n");
+    for (i, (output, anchor)) in outputs.iter().zip(&anchors).enumerate() {
+        combined.push_str(&format!(
+            "{}. [{}](#{})\n",
+            i + 1,
+            escape_markdown(&output.metadata.title),
+            anchor
+        ));
+    }
+
+    for output in outputs {
+        let metadata = &output.metadata;
+        combined.push_str(&format!("\n# {}\n\n", escape_markdown(&metadata.title)));
+        combined.push_str(&format!("*{}*\n\n", byline(metadata)));
+
+        combined.push_str(markdown_body(&output.markdown).trim());
+        combined.push('\n');
+    }
+
+    combined
+}
+
+/// Channel, duration and URL of a video joined for an italic byline
+fn byline(metadata: &VideoMetadata) -> String {
+    let parts: Vec<String> = [
+        metadata.channel.as_deref().map(escape_markdown),
+        metadata.duration.clone(),
+        Some(metadata.url.clone()),
+    ]
+    .into_iter()
+    .flatten()
+    .collect();
+    parts.join(" · ")
+}
+
+/// Render a transcript as a section for `--append`: a `## Title` heading and
+/// byline instead of front matter, with the body's own headings one level
+/// deeper. A hidden `video_id` marker lets [`note_contains_video`] find it.
+pub fn append_section(output: &ProcessOutput) -> String {
+    let metadata = &output.metadata;
+    let mut section = format!("## {}\n\n", escape_markdown(&metadata.title));
+    section.push_str(&format!("<!-- video_id: \"{}\" -->\n", metadata.video_id));
+    section.push_str(&format!("*{}*\n\n", byline(metadata)));
+
+    let mut in_fence = false;
+    for line in markdown_body(&output.markdown).trim().lines() {
+        let trimmed = line.trim_start();
+        if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+            in_fence = !in_fence;
+        }
+        if !in_fence && line.starts_with('#') {
+            section.push('#');
+        }
+        section.push_str(line);
+        section.push('\n');
+    }
+
+    section
+}
+
+/// Add a section to the end of a note, separated from what is there by a blank line
+pub fn append_to_note(note: &str, section: &str) -> String {
+    let note = note.trim_end();
+    if note.is_empty() {
+        section.to_string()
+    } else {
+        format!("{}\n\n{}", note, section)
+    }
+}
+
+/// Whether a note already holds the transcript of `video_id`, either as an
+/// appended section or in its own front matter
+pub fn note_contains_video(note: &str, video_id: &str) -> bool {
+    note.contains(&format!("video_id: \"{}\"", video_id))
+}
+
+/// The part of a rendered document after its front matter and `# Title` line
+fn markdown_body(markdown: &str) -> &str {
+    let heading = if markdown.starts_with("# ") {
+        Some(0)
+    } else {
+        markdown.find("\n# ").map(|pos| pos + 1)
+    };
+
+    match heading {
+        Some(start) => markdown[start..]
+            .split_once('\n')
+            .map_or("", |(_, body)| body),
+        None => markdown,
+    }
+}
+
+/// GitHub-style anchor for a heading: lowercase, punctuation dropped, spaces as hyphens
+fn heading_anchor(title: &str) -> String {
+    title
+        .to_lowercase()
+        .chars()
+        .filter_map(|c| match c {
+            ' ' => Some('-'),
+            c if c.is_alphanumeric() || c == '-' || c == '_' => Some(c),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Options for [`process_video`], mirroring the CLI flags
+#[derive(Debug, Clone)]
+pub struct ProcessOptions {
+    pub prefer_captions: bool,
+    pub language: Option<String>,
+    /// What to do when captions exist, but not in `language`
+    pub caption_lang_fallback: CaptionLangFallback,
+    /// Output directory, may contain placeholders (see [`expand_output_dir`])
+    pub output_dir: String,
+    /// Paragraph length for the output; in compact mode this should be the
+    /// configured `compact_paragraph_length`
+    pub paragraph_length: ParagraphLength,
+    /// Markdown document or plain text; LLM formatting only applies to markdown
+    pub format: OutputFormat,
+    /// Put the video title on the first line of plain-text output
+    pub txt_title: bool,
+    /// Output the raw transcript as captions or speech-to-text produced it,
+    /// without paragraphs, formatting, markdown, `replacements` or repeat
+    /// collapsing; `redact` still applies
+    pub raw_only: bool,
+    pub force_formatting: bool,
+    /// Keep the paragraph structure of manual captions instead of re-flowing them
+    pub preserve_caption_paragraphs: bool,
+    /// Restore "I" and proper nouns in auto captions (see [`fix_caption_capitalization`])
+    pub fix_capitalization: bool,
+    /// Spellings to restore when `fix_capitalization` is set
+    pub proper_nouns: Vec<String>,
+    /// Whole-word substitutions applied after transcription (see [`apply_replacements`])
+    pub replacements: Vec<(String, String)>,
+    /// Entries to redact from the transcript and description (see [`Redactor::new`])
+    pub redact: Vec<String>,
+    /// Also redact email addresses and phone numbers
+    pub redact_pii: bool,
+    /// Collapse speech-to-text sentences repeated more than this many times in a row; 0 keeps them
+    pub max_repeated_sentences: usize,
+    /// Directories searched for Whisper models (see [`AppConfig::model_dirs`])
+    pub model_dirs: Vec<PathBuf>,
+    pub timestamps: bool,
+    /// Mark the first paragraph after every this many seconds with its time (`[00:05:00]`)
+    pub timestamp_interval: Option<u64>,
+    pub compact: bool,
+    pub use_llm: bool,
+    /// Whether `use_llm` applies to caption transcripts
+    pub llm_for_captions: bool,
+    /// Whether `use_llm` applies to speech-to-text transcripts
+    pub llm_for_stt: bool,
+    /// LLM provider override; the configured default is used when `None`
+    pub llm_provider: Option<LlmProviderType>,
+    /// Model override for the LLM provider; the configured model is used when `None`
+    pub llm_model: Option<String>,
+    /// Sampling temperature override; the configured `llm_temperature` is used when `None`
+    pub llm_temperature: Option<f32>,
+    /// Client for LLM and speech-to-text requests, so a batch reuses its
+    /// connections; one is built from `config` when `None`
+    pub http_client: Option<HttpClient>,
+    pub ytdlp: YtDlpConfig,
+    pub ffmpeg_path: String,
+    /// Speech-to-text engine used when captions are not available
+    pub stt_backend: SttBackend,
+    /// Configuration the options were built from; supplies the LLM provider
+    /// settings and fallbacks and the remote speech-to-text endpoints
+    pub config: AppConfig,
+    /// Resume an interrupted local Whisper transcription from its checkpoint
+    pub resume: bool,
+    /// Ignore cached yt-dlp metadata and fetch it again
+    pub refresh_metadata: bool,
+    /// Give up on the whole job after this many seconds
+    pub timeout: Option<u64>,
+    /// Append an attribution footer with the source video and extraction date
+    pub append_metadata_footer: bool,
+    /// Hard-wrap the output at this column (see [`wrap_markdown`])
+    pub line_wrap: Option<usize>,
+    /// Label speakers in the markdown output (see [`assign_speakers_by_pauses`])
+    pub diarize: bool,
+    /// Skip videos shorter than this many seconds
+    pub min_duration: Option<u64>,
+    /// Skip videos longer than this many seconds
+    pub max_duration: Option<u64>,
+    /// Use the language declared in the video's metadata, when there is one,
+    /// instead of `language`
+    pub lang_from_metadata: bool,
+    /// Put the video description in a `## Description` section before the transcript
+    pub include_description: bool,
+    /// strftime-style format for `extracted_at`; RFC 3339 when `None`
+    pub date_format: Option<String>,
+    /// Timezone for `extracted_at` and the footer date
+    pub timezone: Timezone,
+    /// Transcribe a video again even if the output directory already holds a
+    /// transcript of it (see [`find_existing_transcript`])
+    pub overwrite: bool,
+}
+
+impl ProcessOptions {
+    /// Build options from the user's configuration
+    pub fn from_config(config: &AppConfig) -> Self {
+        Self {
+            prefer_captions: config.prefer_captions,
+            language: Some(config.default_language.clone()),
+            caption_lang_fallback: config.caption_lang_fallback,
+            output_dir: normalize_output_dir(&config.output_dir),
+            paragraph_length: config.paragraph_length_for(config.compact),
+            format: OutputFormat::Markdown,
+            txt_title: false,
+            raw_only: false,
+            force_formatting: false,
+            preserve_caption_paragraphs: config.preserve_caption_paragraphs,
+            fix_capitalization: config.fix_capitalization,
+            proper_nouns: config.proper_nouns.clone(),
+            replacements: config.replacements.clone().into_iter().collect(),
+            redact: config.redact.clone(),
+            redact_pii: config.redact_pii,
+            max_repeated_sentences: config.max_repeated_sentences,
+            model_dirs: config.model_dirs(),
+            timestamps: config.timestamps,
+            timestamp_interval: None,
+            compact: config.compact,
+            use_llm: config.llm.enabled,
+            llm_for_captions: config.llm_for_captions,
+            llm_for_stt: config.llm_for_stt,
+            llm_provider: None,
+            llm_model: None,
+            llm_temperature: None,
+            http_client: None,
+            ytdlp: config.ytdlp_config(),
+            ffmpeg_path: config.advanced.ffmpeg_binary(),
+            stt_backend: config.stt.backend.clone(),
+            config: config.clone(),
+            resume: true,
+            refresh_metadata: false,
+            timeout: None,
+            append_metadata_footer: config.append_metadata_footer,
+            line_wrap: config.line_wrap,
+            diarize: false,
+            min_duration: None,
+            max_duration: None,
+            lang_from_metadata: false,
+            include_description: false,
+            date_format: config.date_format.clone(),
+            timezone: config.timezone,
+            overwrite: false,
+        }
+    }
+}
+
+impl ProcessOptions {
+    /// The client from `http_client`, or a new one for `config`
+    fn client(&self) -> Result<HttpClient, Y2mdError> {
+        match &self.http_client {
+            Some(client) => Ok(client.clone()),
+            None => HttpClient::new(&self.config),
+        }
+    }
+}
+
+impl Default for ProcessOptions {
+    fn default() -> Self {
+        Self::from_config(&AppConfig::default())
+    }
+}
+
+/// Result of running the full pipeline with [`process_video`]
+#[derive(Debug, Clone)]
+pub struct ProcessOutput {
+    pub metadata: VideoMetadata,
+    /// Complete output document: markdown including front matter, or plain
+    /// text with [`OutputFormat::Txt`]
+    pub markdown: String,
+    /// Transcript after standard formatting, before markdown rendering
+    pub transcript: String,
+    pub raw_transcript: String,
+    pub segments: Vec<TranscriptSegment>,
+    pub source: TranscriptionSource,
+    /// Output directory with placeholders expanded
+    pub output_dir: String,
+    /// LLM model that formatted the output, if LLM formatting succeeded
+    pub llm_model: Option<String>,
+    /// Time spent in each pipeline stage
+    pub timings: StageTimings,
+    /// Language code of the transcript
+    pub language: String,
+    /// Whisper's detection result, with `--lang auto`
+    pub detected_language: Option<LanguageDetection>,
+}
+
+/// Wall-clock time spent in each pipeline stage, in seconds. Stages that did
+/// not run (e.g. the audio download when captions were used) stay at zero.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct StageTimings {
+    pub download_seconds: f64,
+    pub conversion_seconds: f64,
+    pub transcription_seconds: f64,
+    pub llm_seconds: f64,
+    /// The whole run, including metadata and caption lookups
+    pub total_seconds: f64,
+}
+
+/// Pipeline stages timed from their start/finish events
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Stage {
+    Download,
+    Conversion,
+    Transcription,
+    Llm,
+}
+
+/// Collects what the pipeline reports through its events: stage timings and
+/// the detected language
+#[derive(Debug, Default)]
+struct PipelineRecorder {
+    started: HashMap<Stage, std::time::Instant>,
+    timings: StageTimings,
+    language: Option<LanguageDetection>,
+}
+
+impl PipelineRecorder {
+    fn record(&mut self, event: &PipelineEvent) {
+        let (stage, finished) = match event {
+            PipelineEvent::LanguageDetected(detection) => {
+                self.language = Some(detection.clone());
+                return;
+            }
+            PipelineEvent::DownloadStarted => (Stage::Download, false),
+            PipelineEvent::DownloadFinished => (Stage::Download, true),
+            PipelineEvent::ConversionStarted => (Stage::Conversion, false),
+            PipelineEvent::ConversionFinished => (Stage::Conversion, true),
+            PipelineEvent::TranscribeStarted => (Stage::Transcription, false),
+            PipelineEvent::TranscribeFinished => (Stage::Transcription, true),
+            PipelineEvent::LlmStarted(_) => (Stage::Llm, false),
+            PipelineEvent::LlmFinished => (Stage::Llm, true),
+            _ => return,
+        };
+
+        if !finished {
+            self.started.insert(stage, std::time::Instant::now());
+            return;
+        }
+        let Some(start) = self.started.remove(&stage) else {
+            return;
+        };
+        let seconds = start.elapsed().as_secs_f64();
+        match stage {
+            Stage::Download => self.timings.download_seconds += seconds,
+            Stage::Conversion => self.timings.conversion_seconds += seconds,
+            Stage::Transcription => self.timings.transcription_seconds += seconds,
+            Stage::Llm => self.timings.llm_seconds += seconds,
+        }
+    }
+}
+
+/// Machine-readable summary of one processed video, written by `--stats-json`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RunStats {
+    pub video_id: String,
+    pub title: String,
+    /// Transcript source, e.g. `auto-captions (en)` or `whisper (base.en)`
+    pub source: String,
+    pub llm_model: Option<String>,
+    pub language: String,
+    /// Probability of the detected language, with `--lang auto`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub language_probability: Option<f32>,
+    /// Mean of the segments' `avg_logprob`, when speech-to-text reports it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub avg_logprob: Option<f32>,
+    pub word_count: usize,
+    pub character_count: usize,
+    pub paragraph_count: usize,
+    pub timings: StageTimings,
+    /// Confidence of each speech-to-text segment, for flagging passages to
+    /// review; empty (and left out) for captions
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub segments: Vec<SegmentConfidence>,
+}
+
+/// Speech-to-text confidence for one segment in [`RunStats`]
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SegmentConfidence {
+    pub start: f64,
+    pub end: f64,
+    pub avg_logprob: f32,
+}
+
+impl RunStats {
+    pub fn from_output(output: &ProcessOutput) -> Self {
+        let segments: Vec<SegmentConfidence> = output
+            .segments
+            .iter()
+            .filter_map(|segment| {
+                Some(SegmentConfidence {
+                    start: segment.start,
+                    end: segment.end,
+                    avg_logprob: segment.avg_logprob?,
+                })
+            })
+            .collect();
+
+        Self {
+            video_id: output.metadata.video_id.clone(),
+            title: output.metadata.title.clone(),
+            source: output.source.to_string(),
+            llm_model: output.llm_model.clone(),
+            language: output.language.clone(),
+            language_probability: output
+                .detected_language
+                .as_ref()
+                .map(|detection| detection.probability),
+            avg_logprob: (!segments.is_empty()).then(|| {
+                segments
+                    .iter()
+                    .map(|segment| segment.avg_logprob)
+                    .sum::<f32>()
+                    / segments.len() as f32
+            }),
+            word_count: output.transcript.split_whitespace().count(),
+            character_count: output.transcript.chars().count(),
+            paragraph_count: output.markdown.matches("\n\n").count() + 1,
+            timings: output.timings.clone(),
+            segments,
+        }
+    }
+}
+
+/// Run the whole pipeline for a video: validate the URL, fetch metadata,
+/// transcribe and render markdown. Nothing is written to the output directory.
+///
+/// Progress is reported through `on_event` when a handler is given.
+pub async fn process_video(
+    url: &str,
+    options: &ProcessOptions,
+    on_event: Option<&EventHandler>,
+) -> Result<ProcessOutput, Y2mdError> {
+    let Some(timeout) = options.timeout else {
+        return run_pipeline(url, options, on_event).await;
+    };
+
+    tokio::time::timeout(
+        std::time::Duration::from_secs(timeout),
+        run_pipeline(url, options, on_event),
+    )
+    .await
+    .map_err(|_| {
+        Y2mdError::Timeout(format!(
+            "processing did not finish within {} seconds",
+            timeout
+        ))
+    })?
+}
+
+/// One provider's result from [`compare_llm_providers`]
+#[derive(Debug, Clone)]
+pub struct ProviderComparison {
+    pub provider: LlmProviderType,
+    /// The rendered document; standard formatting if the provider failed
+    pub markdown: String,
+    /// Whether the LLM formatted the document
+    pub succeeded: bool,
+    pub llm_model: Option<String>,
+    /// Time spent formatting with this provider
+    pub seconds: f64,
+    /// Tokens used, when the provider reports them
+    pub usage: Option<TokenUsage>,
+    /// Estimated cost in USD (see [`TokenUsage::estimated_cost`])
+    pub cost: Option<f64>,
+}
+
+/// Format an already extracted transcript with each provider in turn, for
+/// `--compare`. The transcript is reused, so only LLM formatting repeats.
+/// A failing provider does not stop the others.
+pub async fn compare_llm_providers(
+    output: &ProcessOutput,
+    options: &ProcessOptions,
+    providers: &[LlmProviderType],
+) -> Result<Vec<ProviderComparison>, Y2mdError> {
+    let mut comparisons = Vec::new();
+    for provider in providers {
+        comparisons.push(compare_llm_provider(output, options, provider).await?);
+    }
+    Ok(comparisons)
+}
+
+/// Format an already extracted transcript with one provider, the step
+/// [`compare_llm_providers`] repeats; lets callers report each provider as it starts
+pub async fn compare_llm_provider(
+    output: &ProcessOutput,
+    options: &ProcessOptions,
+    provider: &LlmProviderType,
+) -> Result<ProviderComparison, Y2mdError> {
+    let speaker_segments =
+        (options.diarize && !output.segments.is_empty()).then_some(output.segments.as_slice());
+
+    let client = options.client()?;
+
+    let started = std::time::Instant::now();
+    let (markdown, llm_output) = format_markdown(
+        &output.metadata,
+        &output.transcript,
+        &output.source,
+        &output.language,
+        output
+            .detected_language
+            .as_ref()
+            .map(|detection| detection.probability),
+        options.timestamps,
+        options.timestamp_interval,
+        &output.segments,
+        options.compact,
+        options.paragraph_length,
+        true,
+        &options.config,
+        &client,
+        Some(provider.clone()),
+        None,
+        options.llm_temperature,
+        speaker_segments,
+        options.append_metadata_footer,
+        options.include_description,
+        options.date_format.as_deref(),
+        options.timezone,
+    )
+    .await;
+    let seconds = started.elapsed().as_secs_f64();
+    let markdown = match options.line_wrap {
+        Some(width) => wrap_markdown(&markdown, width),
+        None => markdown,
+    };
+
+    let usage = llm_output.as_ref().and_then(|llm| llm.usage);
+    let cost = llm_output
+        .as_ref()
+        .and_then(|llm| llm.usage?.estimated_cost(&llm.provider, &llm.model));
+    Ok(ProviderComparison {
+        provider: provider.clone(),
+        succeeded: llm_output.is_some(),
+        llm_model: llm_output.map(|llm| llm.model),
+        markdown,
+        seconds,
+        usage,
+        cost,
+    })
+}
+
+/// Run the pipeline stages, timing them from their progress events
+async fn run_pipeline(
+    url: &str,
+    options: &ProcessOptions,
+    on_event: Option<&EventHandler>,
+) -> Result<ProcessOutput, Y2mdError> {
+    let started = std::time::Instant::now();
+    let recorder = Arc::new(std::sync::Mutex::new(PipelineRecorder::default()));
+
+    let forward = on_event.cloned();
+    let handler_recorder = recorder.clone();
+    let handler: EventHandler = Arc::new(move |event: PipelineEvent| {
+        handler_recorder
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .record(&event);
+        if let Some(forward) = &forward {
+            forward(event);
+        }
+    });
+
+    let mut output = run_stages(url, options, &recorder, Some(&handler)).await?;
+    output.timings = recorder
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .timings
+        .clone();
+    output.timings.total_seconds = started.elapsed().as_secs_f64();
+    Ok(output)
+}
+
+async fn run_stages(
+    url: &str,
+    options: &ProcessOptions,
+    recorder: &std::sync::Mutex<PipelineRecorder>,
+    on_event: Option<&EventHandler>,
+) -> Result<ProcessOutput, Y2mdError> {
+    let video_id = validate_youtube_url(url)?;
+    let redactor = Redactor::new(&options.redact, options.redact_pii)?;
+    let mut metadata =
+        fetch_video_metadata(&video_id, &options.ytdlp, options.refresh_metadata).await?;
+    emit(on_event, PipelineEvent::MetadataFetched(metadata.clone()));
+
+    // Bail out before downloading anything if the video is out of bounds
+    check_duration_limits(&metadata, options.min_duration, options.max_duration)?;
+
+    let requested_language = match &metadata.language {
+        Some(declared) if options.lang_from_metadata => {
+            println!("Using the video's declared language: {}", declared);
+            Some(declared.clone())
+        }
+        _ => options.language.clone(),
+    };
+
+    // Match on the video ID rather than the file name, which changes with the title
+    let output_dir = expand_output_dir(&options.output_dir, &metadata);
+    if !options.overwrite {
+        if let Some(existing) =
+            find_existing_transcript(std::path::Path::new(&output_dir), &video_id, options.format)
+        {
+            return Err(Y2mdError::Skipped(format!(
+                "'{}' was already transcribed to {} (use --overwrite to redo it)",
+                metadata.title,
+                existing.display()
+            )));
+        }
+    }
+
+    // Captions can still cover a silent video, but there is nothing to transcribe
+    if metadata.has_audio == Some(false) {
+        let captions = options.prefer_captions
+            && requested_language.as_deref() != Some(AUTO_LANGUAGE)
+            && list_caption_tracks(&video_id, &options.ytdlp)
+                .await
+                .is_ok_and(|tracks| {
+                    tracks
+                        .language_for(
+                            requested_language.as_deref().unwrap_or("en"),
+                            options.caption_lang_fallback,
+                        )
+                        .is_some()
+                });
+        if !captions {
+            return Err(Y2mdError::NoAudio(format!(
+                "'{}' has no audio track and no captions",
+                metadata.title
+            )));
+        }
+    }
+
+    println!("Transcribing: {}", metadata.title);
+    println!(
+        "Channel: {}",
+        metadata.channel.as_deref().unwrap_or("Unknown")
+    );
+    println!("Video ID: {}", video_id);
+    println!("Output directory: {}", output_dir);
+
+    let client = options.client()?;
+    let mut stt = SttSettings {
+        backend: options.stt_backend.clone(),
+        ..options.config.stt.clone()
+    };
+    stt.deepgram.diarize = options.diarize;
+    let (transcript, source, raw_transcript, mut segments) = transcribe_video(
+        &video_id,
+        options.prefer_captions,
+        requested_language.as_deref(),
+        options.caption_lang_fallback,
+        &output_dir,
+        options.paragraph_length,
+        options.force_formatting,
+        options.preserve_caption_paragraphs,
+        options
+            .fix_capitalization
+            .then_some(options.proper_nouns.as_slice()),
+        &options.ytdlp,
+        &options.ffmpeg_path,
+        &stt,
+        &client,
+        &CredentialManager::for_config(&options.config),
+        options.resume,
+        // Raw output keeps every word as it was produced
+        if options.raw_only {
+            0
+        } else {
+            options.max_repeated_sentences
+        },
+        &options.model_dirs,
+        on_event,
+    )
+    .await?;
+
+    // Redact before anything is formatted, so no LLM provider sees the text.
+    // Repeated sentences were already collapsed while transcribing. Raw
+    // output skips the replacements but is still redacted.
+    let replacements = if options.raw_only {
+        Vec::new()
+    } else {
+        options.replacements.clone()
+    };
+    let text_options = FormatOptions::new()
+        .replacements(replacements)
+        .redactor(redactor.clone());
+    for segment in &mut segments {
+        segment.text = text_options.transform(&segment.text);
+    }
+    if !redactor.is_empty() {
+        metadata.description = metadata
+            .description
+            .map(|description| redactor.redact(&description));
+    }
+    let transcript = text_options.transform(&transcript);
+    let raw_transcript = text_options.transform(&raw_transcript);
+
+    let detected_language = recorder
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .language
+        .clone();
+    let language = match (&detected_language, requested_language.as_deref()) {
+        (Some(detection), _) => detection.language.clone(),
+        // Captions in another language under --caption-lang-fallback available
+        (None, _) if source.is_captions() => source.caption_language().unwrap_or("en").to_string(),
+        // Stays `auto` when the backend detected the language without reporting it
+        (None, Some(requested)) => requested.to_string(),
+        (None, None) => "en".to_string(),
+    };
+
+    let diarize = options.diarize && !segments.is_empty();
+    if diarize && segments.iter().all(|segment| segment.speaker.is_none()) {
+        println!("Transcript has no speaker labels, guessing speakers from pauses");
+        assign_speakers_by_pauses(&mut segments);
+    }
+
+    if options.raw_only {
+        emit(on_event, PipelineEvent::Done);
+
+        return Ok(ProcessOutput {
+            metadata,
+            markdown: format!("{}\n", raw_transcript.trim_end()),
+            transcript,
+            raw_transcript,
+            segments,
+            source,
+            output_dir,
+            llm_model: None,
+            timings: StageTimings::default(),
+            language,
+            detected_language,
+        });
+    }
+
+    if options.format == OutputFormat::Txt {
+        let text = format_plain_text(
+            &metadata,
+            &transcript,
+            options.compact,
+            options.paragraph_length,
+            diarize.then_some(segments.as_slice()),
+            options.txt_title,
+        );
+        let text = match options.line_wrap {
+            Some(width) => wrap_markdown(&text, width),
+            None => text,
+        };
+        emit(on_event, PipelineEvent::Done);
+
+        return Ok(ProcessOutput {
+            metadata,
+            markdown: text,
+            transcript,
+            raw_transcript,
+            segments,
+            source,
+            output_dir,
+            llm_model: None,
+            timings: StageTimings::default(),
+            language,
+            detected_language,
+        });
+    }
+
+    // Clean captions may not be worth an LLM call while messy STT output is
+    let (llm_for_source, setting) = if source.is_captions() {
+        (options.llm_for_captions, "llm_for_captions")
+    } else {
+        (options.llm_for_stt, "llm_for_stt")
+    };
+    let use_llm = options.use_llm && llm_for_source;
+    if options.use_llm && !use_llm {
+        println!(
+            "Skipping LLM formatting for {} transcript ({} = false)",
+            source, setting
+        );
+    }
+
+    if use_llm {
+        let provider = options
+            .llm_provider
+            .clone()
+            .unwrap_or_else(|| options.config.llm.provider.clone());
+        emit(on_event, PipelineEvent::LlmStarted(provider));
+    }
+
+    let (markdown, llm_output) = format_markdown(
+        &metadata,
+        &transcript,
+        &source,
+        &language,
+        detected_language
+            .as_ref()
+            .map(|detection| detection.probability),
+        options.timestamps,
+        options.timestamp_interval,
+        &segments,
+        options.compact,
+        options.paragraph_length,
+        use_llm,
+        &options.config,
+        &client,
+        options.llm_provider.clone(),
+        options.llm_model.as_deref(),
+        options.llm_temperature,
+        diarize.then_some(segments.as_slice()),
+        options.append_metadata_footer,
+        options.include_description,
+        options.date_format.as_deref(),
+        options.timezone,
+    )
+    .await;
+    let markdown = match options.line_wrap {
+        Some(width) => wrap_markdown(&markdown, width),
+        None => markdown,
+    };
+
+    if use_llm {
+        emit(on_event, PipelineEvent::LlmFinished);
+    }
+    emit(on_event, PipelineEvent::Done);
+
+    Ok(ProcessOutput {
+        metadata,
+        markdown,
+        transcript,
+        raw_transcript,
+        segments,
+        source,
+        output_dir,
+        llm_model: llm_output.map(|llm| llm.model),
+        timings: StageTimings::default(),
+        language,
+        detected_language,
+    })
+}
+
+/// Whisper-ready audio (16kHz mono samples) decoded from the converted WAV
+/// file a chunk at a time, so multi-hour recordings are never held in memory
+/// whole. The converted file is removed when this is dropped.
+struct WhisperAudio {
+    converted_path: PathBuf,
+    format: Box<dyn symphonia::core::formats::FormatReader>,
+    decoder: Box<dyn symphonia::core::codecs::Decoder>,
+    /// Sample count from the WAV header, when it has one
+    total_samples: Option<usize>,
+    /// Samples decoded but not yet read
+    pending: Vec<f32>,
+    exhausted: bool,
+}
+
+impl WhisperAudio {
+    /// Convert the download to WAV with FFmpeg and open it for decoding
+    async fn open(
+        audio_path: &PathBuf,
+        ffmpeg_path: &str,
+        on_event: Option<&EventHandler>,
+    ) -> Result<Self, Y2mdError> {
+        // First, try to convert the audio to WAV format using FFmpeg for better compatibility
+        let converted_path = convert_audio_to_wav(audio_path, ffmpeg_path, on_event).await?;
+
+        // Then process the converted WAV file with symphonia
+        use symphonia::core::codecs::{DecoderOptions, CODEC_TYPE_NULL};
+        use symphonia::core::formats::FormatOptions;
+        use symphonia::core::io::MediaSourceStream;
+        use symphonia::core::meta::MetadataOptions;
+        use symphonia::core::probe::Hint;
+
+        // Open the converted audio file
+        let file = std::fs::File::open(&converted_path).map_err(|e| {
+            Y2mdError::Config(format!("Failed to open converted audio file: {}", e))
+        })?;
+
+        let mss = MediaSourceStream::new(Box::new(file), Default::default());
+
+        // Create a hint to help the format registry guess the format
+        let mut hint = Hint::new();
+        hint.with_extension("wav");
+
+        // Use the default options for metadata and format
+        let meta_opts: MetadataOptions = Default::default();
+        let fmt_opts: FormatOptions = Default::default();
+
+        // Probe the media source
+        let probed = symphonia::default::get_probe()
+            .format(&hint, mss, &fmt_opts, &meta_opts)
+            .map_err(|e| Y2mdError::Config(format!("Failed to probe audio format: {}", e)))?;
+
+        // Get the format reader
+        let format = probed.format;
+
+        // Find the first audio track with a known codec
+        let track = format
+            .tracks()
+            .iter()
+            .find(|t| t.codec_params.codec != CODEC_TYPE_NULL)
+            .ok_or_else(|| Y2mdError::Config("No supported audio tracks found".to_string()))?;
+        let total_samples = track.codec_params.n_frames.map(|frames| frames as usize);
+
+        // Create a decoder for the track
+        let decoder = symphonia::default::get_codecs()
+            .make(&track.codec_params, &DecoderOptions::default())
+            .map_err(|e| Y2mdError::Config(format!("Failed to create decoder: {}", e)))?;
+
+        let mut audio = WhisperAudio {
+            converted_path,
+            format,
+            decoder,
+            total_samples,
+            pending: Vec::new(),
+            exhausted: false,
+        };
+
+        if audio.peek(1)?.is_empty() {
+            return Err(Y2mdError::NoAudio(
+                "no audio samples could be decoded from the download".to_string(),
+            ));
+        }
+
+        Ok(audio)
+    }
+
+    /// Decode packets until `count` samples are pending or the audio ends
+    fn fill(&mut self, count: usize) -> Result<(), Y2mdError> {
+        use symphonia::core::audio::{AudioBufferRef, Signal};
+
+        while self.pending.len() < count && !self.exhausted {
+            let Ok(packet) = self.format.next_packet() else {
+                self.exhausted = true;
+                break;
+            };
+            // Skip decoding errors
+            let Ok(decoded) = self.decoder.decode(&packet) else {
+                continue;
+            };
+            match decoded {
+                AudioBufferRef::F32(buf) => {
+                    // For stereo, average the channels
+                    if buf.spec().channels.count() == 2 {
+                        for i in 0..buf.frames() {
+                            let sample = (buf.chan(0)[i] + buf.chan(1)[i]) / 2.0;
+                            self.pending.push(sample);
+                        }
+                    } else {
+                        // For mono, just copy the samples
+                        self.pending.extend_from_slice(&buf.chan(0)[..buf.frames()]);
+                    }
+                }
+                AudioBufferRef::S16(buf) => {
+                    // Convert i16 to f32
+                    if buf.spec().channels.count() == 2 {
+                        for i in 0..buf.frames() {
+                            let sample =
+                                (buf.chan(0)[i] as f32 + buf.chan(1)[i] as f32) / 2.0 / 32768.0;
+                            self.pending.push(sample);
+                        }
+                    } else {
+                        for i in 0..buf.frames() {
+                            self.pending.push(buf.chan(0)[i] as f32 / 32768.0);
+                        }
+                    }
+                }
+                _ => {
+                    return Err(Y2mdError::Config(
+                        "Unsupported audio format (only F32 and S16 are supported)".to_string(),
+                    ));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The next `count` samples without consuming them (fewer at the end)
+    fn peek(&mut self, count: usize) -> Result<&[f32], Y2mdError> {
+        self.fill(count)?;
+        Ok(&self.pending[..count.min(self.pending.len())])
+    }
+
+    /// Take the next `count` samples (fewer at the end, none once the audio is exhausted)
+    fn read(&mut self, count: usize) -> Result<Vec<f32>, Y2mdError> {
+        self.fill(count)?;
+        let count = count.min(self.pending.len());
+        Ok(self.pending.drain(..count).collect())
+    }
+
+    /// Skip `count` samples a chunk at a time, returning how many were skipped
+    fn skip(&mut self, count: usize) -> Result<usize, Y2mdError> {
+        let mut skipped = 0;
+        while skipped < count {
+            let chunk =
+                self.read((count - skipped).min(WHISPER_CHUNK_SECONDS * WHISPER_SAMPLE_RATE))?;
+            if chunk.is_empty() {
+                break;
+            }
+            skipped += chunk.len();
+        }
+        Ok(skipped)
+    }
+}
+
+impl Drop for WhisperAudio {
+    fn drop(&mut self) {
+        // Clean up the temporary converted file
+        let _ = std::fs::remove_file(&self.converted_path);
+    }
+}
+
+/// Convert audio file to WAV format using FFmpeg for better compatibility
+async fn convert_audio_to_wav(
+    audio_path: &PathBuf,
+    ffmpeg_path: &str,
+    on_event: Option<&EventHandler>,
+) -> Result<PathBuf, Y2mdError> {
+    let temp_dir = std::env::temp_dir();
+    let temp_filename = format!("y2md_converted_{}.wav", uuid::Uuid::new_v4());
+    let output_path = temp_dir.join(temp_filename);
+
+    emit(on_event, PipelineEvent::ConversionStarted);
+
+    println!(
+        "Converting audio to WAV format: {:?} -> {:?}",
+        audio_path, output_path
+    );
+
+    // Use FFmpeg to convert to WAV format
+    run_ffmpeg(
+        ffmpeg_path,
+        &[
+            "-i",
+            audio_path.to_str().unwrap(),
+            "-ac",
+            "1", // Convert to mono
+            "-ar",
+            "16000", // 16kHz sample rate (optimal for whisper)
+            "-acodec",
+            "pcm_f32le", // 32-bit float PCM
+            "-y",        // Overwrite output file
+            output_path.to_str().unwrap(),
+        ],
+    )
+    .await?;
+
+    // Verify the converted file exists and has content
+    if !output_path.exists() {
+        return Err(Y2mdError::Config(
+            "Converted audio file was not created".to_string(),
+        ));
+    }
+
+    let metadata = std::fs::metadata(&output_path)
+        .map_err(|e| Y2mdError::Config(format!("Failed to get file metadata: {}", e)))?;
+
+    if metadata.len() == 0 {
+        return Err(Y2mdError::Config(
+            "Converted audio file is empty".to_string(),
+        ));
+    }
+
+    emit(on_event, PipelineEvent::ConversionFinished);
+    println!("Audio conversion successful");
+    Ok(output_path)
+}
+
+/// Format transcript for better readability
+///
+/// `paragraph_length` is the sentence count for the chosen mode; callers pass
+/// the config's `compact_paragraph_length` when `compact` is set. Prefer
+/// [`format_transcript_with`] for anything beyond these two options.
+pub fn format_transcript(transcript: &str, compact: bool, paragraph_length: usize) -> String {
+    format_transcript_with(
+        transcript,
+        &FormatOptions::new()
+            .compact(compact)
+            .paragraph_length(ParagraphLength::Sentences(paragraph_length)),
+    )
+}
+
+/// Like [`format_transcript`], with a choice of how sentences are grouped into paragraphs
+pub fn format_transcript_by(
+    transcript: &str,
+    compact: bool,
+    paragraph_length: ParagraphLength,
+) -> String {
+    format_transcript_with(
+        transcript,
+        &FormatOptions::new()
+            .compact(compact)
+            .paragraph_length(paragraph_length),
+    )
+}
+
+/// Options for [`format_transcript_with`]. New options are added here rather
+/// than as positional arguments; the struct is non-exhaustive, so start from
+/// [`FormatOptions::new`] and set what you need with the builder methods.
+/// Everything is off by default.
+#[derive(Debug, Clone, Default)]
+#[non_exhaustive]
+pub struct FormatOptions {
+    /// Skip [`clean_transcript`], so unpunctuated text is not broken into
+    /// sentences every dozen or so words. Sentences are still capitalized and
+    /// closed with a period when grouped into paragraphs.
+    pub compact: bool,
+    /// How sentences are grouped into paragraphs
+    pub paragraph_length: ParagraphLength,
+    /// Drop hesitations such as "um" and "uh" (see [`remove_filler_words`])
+    pub remove_fillers: bool,
+    /// Collapse sentences repeated more than this many times in a row; 0
+    /// keeps them (see [`collapse_repeated_sentences`])
+    pub max_repeated_sentences: usize,
+    /// Whole-word substitutions (see [`apply_replacements`])
+    pub replacements: Vec<(String, String)>,
+    /// Applied after the replacements, so they cannot bring redacted text back
+    pub redactor: Redactor,
+}
+
+impl FormatOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn compact(mut self, compact: bool) -> Self {
+        self.compact = compact;
+        self
+    }
+
+    pub fn paragraph_length(mut self, paragraph_length: ParagraphLength) -> Self {
+        self.paragraph_length = paragraph_length;
+        self
+    }
+
+    pub fn remove_fillers(mut self, remove_fillers: bool) -> Self {
+        self.remove_fillers = remove_fillers;
+        self
+    }
+
+    pub fn max_repeated_sentences(mut self, max_repeats: usize) -> Self {
+        self.max_repeated_sentences = max_repeats;
+        self
+    }
+
+    pub fn replacements(mut self, replacements: Vec<(String, String)>) -> Self {
+        self.replacements = replacements;
+        self
+    }
+
+    pub fn redactor(mut self, redactor: Redactor) -> Self {
+        self.redactor = redactor;
+        self
+    }
+
+    /// Apply the text transforms (fillers, repeats, replacements and
+    /// redaction, in that order) without splitting into paragraphs, for raw
+    /// transcripts and timed segments
+    pub fn transform(&self, text: &str) -> String {
+        let mut text = text.to_string();
+        if self.remove_fillers {
+            text = remove_filler_words(&text);
+        }
+        if self.max_repeated_sentences > 0 {
+            text = collapse_repeated_sentences(&text, self.max_repeated_sentences);
+        }
+        if !self.replacements.is_empty() {
+            text = apply_replacements(&text, &self.replacements);
+        }
+        if !self.redactor.is_empty() {
+            text = self.redactor.redact(&text);
+        }
+        text
+    }
+}
+
+/// Format transcript for better readability with the given options
+pub fn format_transcript_with(transcript: &str, options: &FormatOptions) -> String {
+    let transcript = options.transform(transcript);
+    let paragraph_length = options.paragraph_length;
+    if options.compact {
+        // Simple paragraph format for compact mode
+        return group_paragraphs(&transcript, paragraph_length);
+    }
+
+    // Enhanced formatting for better readability
+    let cleaned = clean_transcript(&transcript);
+    // Use configured paragraph length (default 3-5 sentences per paragraph)
+    group_paragraphs(&cleaned, paragraph_length)
+}
+
+/// Split text into paragraphs using the chosen grouping strategy
+fn group_paragraphs(text: &str, paragraph_length: ParagraphLength) -> String {
+    match paragraph_length {
+        ParagraphLength::Sentences(count) => format_paragraphs(text, count),
+        ParagraphLength::Auto => format_paragraphs_by_words(text, AUTO_PARAGRAPH_WORDS),
+    }
+}
+
+/// Target paragraph size in words for [`ParagraphLength::Auto`]
+const AUTO_PARAGRAPH_WORDS: usize = 80;
+
+/// Group sentences into paragraphs of roughly `target_words` words each, so
+/// short Q&A sentences and long monologue sentences both read naturally
+fn format_paragraphs_by_words(text: &str, target_words: usize) -> String {
+    let mut paragraphs = Vec::new();
+    let mut current_paragraph = String::new();
+    let mut word_count = 0;
+
+    for sentence in segment_sentences(text) {
+        if !current_paragraph.is_empty() {
+            current_paragraph.push(' ');
+        }
+        current_paragraph.push_str(&finish_sentence(&sentence.text));
+
+        word_count += sentence.text.split_whitespace().count();
+        if word_count >= target_words {
+            paragraphs.push(std::mem::take(&mut current_paragraph));
+            word_count = 0;
+        }
+    }
+
+    if !current_paragraph.is_empty() {
+        paragraphs.push(current_paragraph);
+    }
+
+    paragraphs.join("\n\n")
+}
+
+/// List prices in USD per million input and output tokens, matched by model
+/// name prefix with more specific names first. Only used for the `--compare`
+/// estimate; providers change their prices, so treat it as a rough guide.
+const LLM_PRICES: &[(&str, f64, f64)] = &[
+    ("gpt-4o-mini", 0.15, 0.60),
+    ("gpt-4o", 2.50, 10.00),
+    ("gpt-4-turbo", 10.00, 30.00),
+    ("gpt-4", 30.00, 60.00),
+    ("gpt-3.5-turbo", 0.50, 1.50),
+    ("claude-3-haiku", 0.25, 1.25),
+    ("claude-3-5-haiku", 0.80, 4.00),
+    ("claude-3-opus", 15.00, 75.00),
+    ("claude-opus", 15.00, 75.00),
+    ("claude-3-sonnet", 3.00, 15.00),
+    ("claude-3-5-sonnet", 3.00, 15.00),
+    ("claude-3-7-sonnet", 3.00, 15.00),
+    ("claude-sonnet", 3.00, 15.00),
+    ("deepseek-chat", 0.27, 1.10),
+    ("deepseek-reasoner", 0.55, 2.19),
+];
+
+/// Transcript formatted by an LLM, with the provider and model that produced it
+#[derive(Debug, Clone, PartialEq)]
+pub struct LlmOutput {
+    pub text: String,
+    pub provider: LlmProviderType,
+    pub model: String,
+    /// Token counts, when the provider reports them
+    pub usage: Option<TokenUsage>,
+}
+
+/// Tokens an LLM request consumed, as reported by the provider
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TokenUsage {
+    pub input: u64,
+    pub output: u64,
+}
+
+impl TokenUsage {
+    /// Read usage from a response body: OpenAI-compatible `usage.prompt_tokens`,
+    /// Anthropic `usage.input_tokens` or Ollama `prompt_eval_count`
+    pub fn from_response(response: &serde_json::Value) -> Option<Self> {
+        let usage = &response["usage"];
+        [
+            (&usage["prompt_tokens"], &usage["completion_tokens"]),
+            (&usage["input_tokens"], &usage["output_tokens"]),
+            (&response["prompt_eval_count"], &response["eval_count"]),
+        ]
+        .into_iter()
+        .find_map(|(input, output)| {
+            Some(TokenUsage {
+                input: input.as_u64()?,
+                output: output.as_u64()?,
+            })
+        })
+    }
+
+    /// Estimated cost in USD of this usage with `model`; local models are
+    /// free and models missing from the price list have no estimate
+    pub fn estimated_cost(&self, provider: &LlmProviderType, model: &str) -> Option<f64> {
+        if *provider == LlmProviderType::Local {
+            return Some(0.0);
+        }
+        let (_, input, output) = LLM_PRICES
+            .iter()
+            .find(|(prefix, _, _)| model.starts_with(prefix))?;
+        Some((self.input as f64 * input + self.output as f64 * output) / 1_000_000.0)
+    }
+}
+
+impl std::fmt::Display for TokenUsage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} in, {} out", self.input, self.output)
+    }
+}
+
+/// Format a transcript with an LLM. Without `provider_override` the default
+/// provider is tried first, then each of `llm_fallback` until one succeeds.
+pub async fn format_with_llm(
+    transcript: &str,
+    config: &AppConfig,
+    client: &HttpClient,
+    provider_override: Option<LlmProviderType>,
+    model_override: Option<&str>,
+    temperature_override: Option<f32>,
+) -> Result<LlmOutput, Y2mdError> {
+    let mut config = config.clone();
+    let cred_manager = CredentialManager::for_config(&config);
+
+    // An explicit provider is used alone; the default provider falls back in order
+    let chain = match provider_override {
+        Some(provider) => vec![provider],
+        None => config.llm_provider_chain(),
+    };
+    if let Some(model) = model_override {
+        config.llm.set_model_for(&chain[0], model.to_string());
+    }
+    let temperature =
+        validate_llm_temperature(temperature_override.unwrap_or(config.llm_temperature))
+            .map_err(Y2mdError::Config)?;
+    let system_prompt = config
+        .llm_system_prompt
+        .as_deref()
+        .filter(|prompt| !prompt.trim().is_empty());
+
+    let mut last_error = None;
+    for (i, provider) in chain.iter().enumerate() {
+        match format_with_provider(
+            client,
+            &config,
+            &cred_manager,
+            provider,
+            transcript,
+            temperature,
+            system_prompt,
+        )
+        .await
+        {
+            Ok((formatted, usage)) => {
+                return Ok(LlmOutput {
+                    text: clean_llm_output(&formatted),
+                    provider: provider.clone(),
+                    model: config.llm.model_for(provider).to_string(),
+                    usage,
+                })
+            }
+            Err(e) => {
+                if let Some(next) = chain.get(i + 1) {
+                    println!("LLM provider {} failed: {}, trying {}", provider, e, next);
+                }
+                last_error = Some(e);
+            }
+        }
+    }
+
+    Err(last_error.expect("provider chain is never empty"))
+}
+
+/// Format a transcript with one provider, returning the raw response and its token usage
+async fn format_with_provider(
+    client: &HttpClient,
+    config: &AppConfig,
+    cred_manager: &CredentialManager,
+    provider: &LlmProviderType,
+    transcript: &str,
+    temperature: f32,
+    system_prompt: Option<&str>,
+) -> Result<(String, Option<TokenUsage>), Y2mdError> {
+    match provider {
+        LlmProviderType::Local => {
+            format_with_local(
+                client,
+                transcript,
+                &config.llm.local,
+                temperature,
+                system_prompt,
+            )
+            .await
+        }
+        LlmProviderType::OpenAI => {
+            let api_key = required_api_key(cred_manager, &LlmProviderType::OpenAI, "OpenAI")?;
+            format_with_openai(
+                client,
+                transcript,
+                &config.llm.openai,
+                &api_key,
+                temperature,
+                system_prompt,
+            )
+            .await
+        }
+        LlmProviderType::Anthropic => {
+            let api_key = required_api_key(cred_manager, &LlmProviderType::Anthropic, "Anthropic")?;
+            format_with_anthropic(
+                client,
+                transcript,
+                &config.llm.anthropic,
+                &api_key,
+                temperature,
+                system_prompt,
+            )
+            .await
+        }
+        LlmProviderType::DeepSeek => {
+            let api_key = required_api_key(cred_manager, &LlmProviderType::DeepSeek, "DeepSeek")?;
+            format_with_deepseek(
+                client,
+                transcript,
+                &config.llm.deepseek,
+                &api_key,
+                temperature,
+                system_prompt,
+            )
+            .await
+        }
+        LlmProviderType::Custom => {
+            let api_key = cred_manager.get_api_key(&LlmProviderType::Custom)?;
+            format_with_custom(
+                client,
+                transcript,
+                &config.llm.custom,
+                api_key.as_deref(),
+                temperature,
+                system_prompt,
+            )
+            .await
+        }
+    }
+}
+
+/// Openers models put in front of the document despite the prompt
+const LLM_PREAMBLE_PREFIXES: &[&str] = &[
+    "here is",
+    "here's",
+    "below is",
+    "sure",
+    "certainly",
+    "of course",
+    "okay",
+];
+
+/// Tidy an LLM response: drop a conversational first line ("Here is the
+/// formatted markdown:"), unwrap an outer ```markdown fence and cut runs of
+/// three or more blank lines down to two. Trailing double spaces are kept, as
+/// they are markdown line breaks.
+pub fn clean_llm_output(output: &str) -> String {
+    let mut text = output.trim();
+
+    if let Some((first_line, rest)) = text.split_once('\n') {
+        if is_llm_preamble(first_line) {
+            text = rest.trim();
+        }
+    }
+
+    if text.starts_with("```") && text.ends_with("```") && text.len() > 6 {
+        let inner = &text[..text.len() - 3];
+        if let Some((_, body)) = inner.split_once('\n') {
+            text = body.trim();
+        }
+    }
+
+    let mut cleaned = String::with_capacity(text.len());
+    let mut blank_lines = 0;
+    for line in text.lines() {
+        if line.trim().is_empty() {
+            blank_lines += 1;
+            if blank_lines > 2 {
+                continue;
+            }
+        } else {
+            blank_lines = 0;
+        }
+        cleaned.push_str(line);
+        cleaned.push('\n');
+    }
+
+    cleaned.trim_end().to_string()
+}
+
+/// Whether a line is chatter addressed to the user rather than transcript
+fn is_llm_preamble(line: &str) -> bool {
+    let line = line.trim().to_lowercase();
+    let talks_about_output = ["transcript", "markdown", "formatted", "version"]
+        .iter()
+        .any(|word| line.contains(word));
+
+    line.ends_with(':')
+        && (talks_about_output
+            || LLM_PREAMBLE_PREFIXES
+                .iter()
+                .any(|prefix| line.starts_with(prefix)))
+}
+
+/// Timeout for provider health checks; they only list models
+const LLM_CHECK_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(15);
+
+/// Verify that the configured LLM provider is reachable, accepts the stored
+/// API key and serves the configured model, so problems surface before a
+/// long transcription instead of after it
+pub async fn check_llm_provider(
+    client: &HttpClient,
+    settings: &LlmSettings,
+    cred_manager: &CredentialManager,
+) -> Result<(), Y2mdError> {
+    let model = settings.model_for(&settings.provider);
+
+    match &settings.provider {
+        LlmProviderType::Local => {
+            let models =
+                list_provider_models(client, settings, cred_manager, &LlmProviderType::Local)
+                    .await?;
+
+            if models.iter().any(|name| same_ollama_model(name, model)) {
+                Ok(())
+            } else {
+                Err(Y2mdError::Llm(format!(
+                    "Model '{}' is not installed in Ollama. Run: y2md llm pull {}",
+                    model, model
+                )))
+            }
+        }
+        provider => {
+            let (name, endpoint, headers) =
+                model_listing_request(settings, cred_manager, provider)?;
+            check_model_listing(client, name, &endpoint, model, &headers).await
+        }
+    }
+}
+
+/// Whether an installed Ollama model is the configured one; a name without a
+/// `:tag` means `:latest`, as in `ollama pull`
+pub fn same_ollama_model(installed: &str, wanted: &str) -> bool {
+    let with_tag = |name: &str| {
+        if name.contains(':') {
+            name.to_string()
+        } else {
+            format!("{}:latest", name)
+        }
+    };
+    with_tag(installed.trim()) == with_tag(wanted.trim())
+}
+
+/// Name, endpoint and auth headers for querying a provider's `/models` listing.
+/// Ollama is always listed through its own API (see [`list_provider_models`]).
+#[allow(clippy::type_complexity)]
+fn model_listing_request(
+    settings: &LlmSettings,
+    cred_manager: &CredentialManager,
+    provider: &LlmProviderType,
+) -> Result<(&'static str, String, Vec<(&'static str, String)>), Y2mdError> {
+    match provider {
+        LlmProviderType::Local => unreachable!("Ollama models are listed with OllamaManager"),
+        LlmProviderType::OpenAI => {
+            let api_key = required_api_key(cred_manager, &LlmProviderType::OpenAI, "OpenAI")?;
+            Ok((
+                "OpenAI API",
+                settings.openai.endpoint.clone(),
+                vec![("Authorization", format!("Bearer {}", api_key))],
+            ))
+        }
+        LlmProviderType::Anthropic => {
+            let api_key = required_api_key(cred_manager, &LlmProviderType::Anthropic, "Anthropic")?;
+            Ok((
+                "Anthropic API",
+                settings.anthropic.endpoint.clone(),
+                vec![
+                    ("x-api-key", api_key),
+                    ("anthropic-version", "2023-06-01".to_string()),
+                ],
+            ))
+        }
+        LlmProviderType::DeepSeek => {
+            let api_key = required_api_key(cred_manager, &LlmProviderType::DeepSeek, "DeepSeek")?;
+            Ok((
+                "DeepSeek API",
+                settings.deepseek.endpoint.clone(),
+                vec![("Authorization", format!("Bearer {}", api_key))],
+            ))
+        }
+        LlmProviderType::Custom => {
+            if settings.custom.endpoint.is_empty() {
+                return Err(Y2mdError::Llm(
+                    "Custom LLM endpoint not configured. Please set it in your config file."
+                        .to_string(),
+                ));
+            }
+            let headers = cred_manager
+                .get_api_key(&LlmProviderType::Custom)?
+                .map(|key| ("Authorization", format!("Bearer {}", key)))
+                .into_iter()
+                .collect();
+            Ok(("custom LLM API", settings.custom.endpoint.clone(), headers))
+        }
+    }
+}
+
+/// List the model ids a provider serves: installed models for Ollama, the
+/// `/models` listing for remote APIs
+pub async fn list_provider_models(
+    client: &HttpClient,
+    settings: &LlmSettings,
+    cred_manager: &CredentialManager,
+    provider: &LlmProviderType,
+) -> Result<Vec<String>, Y2mdError> {
+    if *provider == LlmProviderType::Local {
+        let endpoint = &settings.local.endpoint;
+        return OllamaManager::with_client(endpoint.clone(), client.clone())
+            .get_local_models()
+            .await
+            .map_err(|_| {
+                Y2mdError::Llm(format!(
+                    "Ollama service not available at {}. Make sure Ollama is running",
+                    endpoint
+                ))
+            });
+    }
+
+    let (name, endpoint, headers) = model_listing_request(settings, cred_manager, provider)?;
+    let listing = fetch_model_listing(client, name, &endpoint, &headers)
+        .await?
+        .ok_or_else(|| Y2mdError::Llm(format!("{} does not list its models", name)))?;
+    Ok(listed_model_ids(&listing))
+}
+
+/// Look up a provider's API key, with a hint on how to set it when missing
+fn required_api_key(
+    cred_manager: &CredentialManager,
+    provider: &LlmProviderType,
+    name: &str,
+) -> Result<String, Y2mdError> {
+    cred_manager.get_api_key(provider)?.ok_or_else(|| {
+        Y2mdError::Llm(format!(
+            "{} API key not set. Use: y2md llm set-key {}",
+            name, provider
+        ))
+    })
+}
+
+/// Query an API's `/models` listing and make sure `model` is in it. Endpoints
+/// without a listing (404) only count as reachable.
+async fn check_model_listing(
+    client: &HttpClient,
+    name: &str,
+    endpoint: &str,
+    model: &str,
+    headers: &[(&str, String)],
+) -> Result<(), Y2mdError> {
+    let Some(listing) = fetch_model_listing(client, name, endpoint, headers).await? else {
+        return Ok(());
+    };
+
+    if model_listed(&listing, model) {
+        Ok(())
+    } else {
+        Err(Y2mdError::Llm(format!(
+            "Model '{}' is not available from {}. Check the model name in your config",
+            model, name
+        )))
+    }
+}
+
+/// Fetch an API's `/models` listing, `None` when the endpoint has none (404)
+async fn fetch_model_listing(
+    client: &HttpClient,
+    name: &str,
+    endpoint: &str,
+    headers: &[(&str, String)],
+) -> Result<Option<serde_json::Value>, Y2mdError> {
+    let mut request = client
+        .get(format!("{}/models", endpoint))
+        .timeout(LLM_CHECK_TIMEOUT);
+    for (header, value) in headers {
+        request = request.header(*header, value);
+    }
+
+    let response = request
+        .send()
+        .await
+        .map_err(|e| Y2mdError::Llm(format!("Failed to connect to {}: {}", name, e)))?;
+
+    let status = response.status();
+    if status == reqwest::StatusCode::UNAUTHORIZED || status == reqwest::StatusCode::FORBIDDEN {
+        return Err(Y2mdError::Llm(format!(
+            "{} rejected the API key ({}). Update it with: y2md llm set-key",
+            name, status
+        )));
+    }
+    if status == reqwest::StatusCode::NOT_FOUND {
+        return Ok(None);
+    }
+    if !status.is_success() {
+        return Err(Y2mdError::Llm(format!(
+            "{} returned error: {}",
+            name, status
+        )));
+    }
+
+    let listing: serde_json::Value = response
+        .json()
+        .await
+        .map_err(|e| Y2mdError::Llm(format!("Failed to parse {} model list: {}", name, e)))?;
+
+    Ok(Some(listing))
+}
+
+/// Model ids in a `{"data": [{"id": ...}]}` listing, sorted
+fn listed_model_ids(listing: &serde_json::Value) -> Vec<String> {
+    let mut ids: Vec<String> = listing["data"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| entry["id"].as_str().map(str::to_string))
+        .collect();
+    ids.sort();
+    ids
+}
+
+/// Whether a `{"data": [{"id": ...}]}` model listing contains `model`
+fn model_listed(listing: &serde_json::Value, model: &str) -> bool {
+    listing["data"]
+        .as_array()
+        .map(|models| {
+            models
+                .iter()
+                .any(|entry| entry["id"].as_str() == Some(model))
+        })
+        .unwrap_or(false)
+}
+
+async fn format_with_local(
+    client: &HttpClient,
+    transcript: &str,
+    llm_config: &LocalLlmConfig,
+    temperature: f32,
+    system_prompt: Option<&str>,
+) -> Result<(String, Option<TokenUsage>), Y2mdError> {
+    let health_check = client
+        .get(format!("{}/api/tags", llm_config.endpoint))
+        .send()
+        .await;
+
+    if health_check.is_err() {
+        return Err(Y2mdError::Llm(format!(
+            "Ollama service not available at {}. Make sure Ollama is running",
+            llm_config.endpoint
+        )));
+    }
+
+    let prompt = format!(
+        "Transform this raw transcript into a polished, well-structured markdown document. 
+
+**Formatting Guidelines:**
+- **Structure**: Create logical sections with appropriate headings (## for main sections, ### for subsections)
+- **Paragraphs**: Group related thoughts into coherent paragraphs (3-5 sentences each)
+- **Readability**: Fix grammar, punctuation, and sentence structure while preserving meaning
+- **Speaker Handling**: If multiple speakers are present, identify them clearly
+- **Content Enhancement**: 
+  - Remove excessive filler words (um, uh, like, you know)
+  - Improve flow between sentences and paragraphs
+  - Add emphasis with **bold** or *italic* where appropriate
+  - Use bullet points for lists and key takeaways
+  - Maintain the original speaker's tone and style
+
+**Transcript:**
+
+{}
+
+**Formatted Markdown:**",
+        transcript
+    );
+    // Ollama's generate endpoint has no system role, so the prompt carries it
+    let prompt = match system_prompt {
+        Some(system) => format!("{}\n\n{}", system, prompt),
+        None => prompt,
+    };
+
+    let request_body = serde_json::json!({
+        "model": llm_config.model,
+        "prompt": prompt,
+        "stream": false,
+        "options": {
+            "temperature": temperature
+        }
+    });
+
+    let response = client
+        .post(format!("{}/api/generate", llm_config.endpoint))
+        .json(&request_body)
+        .timeout(std::time::Duration::from_secs(120))
+        .send()
+        .await
+        .map_err(|e| {
+            if e.is_timeout() {
+                Y2mdError::Llm("LLM request timed out after 2 minutes".to_string())
+            } else {
+                Y2mdError::Llm(format!("Failed to connect to Ollama: {}", e))
+            }
+        })?;
+
+    if !response.status().is_success() {
+        return Err(Y2mdError::Llm(format!(
+            "Ollama API returned error: {}",
+            response.status()
+        )));
+    }
+
+    let response_json: serde_json::Value = response
+        .json()
+        .await
+        .map_err(|e| Y2mdError::Llm(format!("Failed to parse Ollama response: {}", e)))?;
+
+    let formatted_text = response_json["response"]
+        .as_str()
+        .ok_or_else(|| Y2mdError::Llm("Invalid response format from Ollama".to_string()))?
+        .trim()
+        .to_string();
+
+    if formatted_text.is_empty() {
+        return Err(Y2mdError::Llm("Ollama returned empty response".to_string()));
+    }
+
+    Ok((formatted_text, TokenUsage::from_response(&response_json)))
+}
+
+async fn format_with_openai(
+    client: &HttpClient,
+    transcript: &str,
+    llm_config: &OpenAiConfig,
+    api_key: &str,
+    temperature: f32,
+    system_prompt: Option<&str>,
+) -> Result<(String, Option<TokenUsage>), Y2mdError> {
+    let prompt = format!(
+        "Transform this raw transcript into a polished, well-structured markdown document. 
+
+**Formatting Guidelines:**
+- **Structure**: Create logical sections with appropriate headings (## for main sections, ### for subsections)
+- **Paragraphs**: Group related thoughts into coherent paragraphs (3-5 sentences each)
+- **Readability**: Fix grammar, punctuation, and sentence structure while preserving meaning
+- **Speaker Handling**: If multiple speakers are present, identify them clearly
+- **Content Enhancement**: 
+  - Remove excessive filler words (um, uh, like, you know)
+  - Improve flow between sentences and paragraphs
+  - Add emphasis with **bold** or *italic* where appropriate
+  - Use bullet points for lists and key takeaways
+  - Maintain the original speaker's tone and style
+
+**Transcript:**
+
+{}",
+        transcript
+    );
+
+    let request_body = serde_json::json!({
+        "model": llm_config.model,
+        "messages": [
+            {
+                "role": "system",
+                "content": system_prompt.unwrap_or(DEFAULT_LLM_SYSTEM_PROMPT)
+            },
+            {
+                "role": "user",
+                "content": prompt
+            }
+        ],
+        "temperature": temperature
+    });
+
+    let response = client
+        .post(format!("{}/chat/completions", llm_config.endpoint))
+        .header("Authorization", format!("Bearer {}", api_key))
+        .json(&request_body)
+        .timeout(std::time::Duration::from_secs(120))
+        .send()
+        .await
+        .map_err(|e| {
+            if e.is_timeout() {
+                Y2mdError::Llm("LLM request timed out after 2 minutes".to_string())
+            } else {
+                Y2mdError::Llm(format!("Failed to connect to OpenAI API: {}", e))
+            }
+        })?;
+
+    if !response.status().is_success() {
+        return Err(Y2mdError::Llm(format!(
+            "OpenAI API returned error: {}",
+            response.status()
+        )));
+    }
+
+    let response_json: serde_json::Value = response
+        .json()
+        .await
+        .map_err(|e| Y2mdError::Llm(format!("Failed to parse OpenAI response: {}", e)))?;
+
+    let formatted_text = response_json["choices"][0]["message"]["content"]
+        .as_str()
+        .ok_or_else(|| Y2mdError::Llm("Invalid response format from OpenAI".to_string()))?
+        .trim()
+        .to_string();
+
+    if formatted_text.is_empty() {
+        return Err(Y2mdError::Llm("OpenAI returned empty response".to_string()));
+    }
+
+    Ok((formatted_text, TokenUsage::from_response(&response_json)))
+}
+
+async fn format_with_anthropic(
+    client: &HttpClient,
+    transcript: &str,
+    llm_config: &AnthropicConfig,
+    api_key: &str,
+    temperature: f32,
+    system_prompt: Option<&str>,
+) -> Result<(String, Option<TokenUsage>), Y2mdError> {
+    let prompt = format!(
+        "Transform this raw transcript into a polished, well-structured markdown document. 
+
+**Formatting Guidelines:**
+- **Structure**: Create logical sections with appropriate headings (## for main sections, ### for subsections)
+- **Paragraphs**: Group related thoughts into coherent paragraphs (3-5 sentences each)
+- **Readability**: Fix grammar, punctuation, and sentence structure while preserving meaning
+- **Speaker Handling**: If multiple speakers are present, identify them clearly
+- **Content Enhancement**: 
+  - Remove excessive filler words (um, uh, like, you know)
+  - Improve flow between sentences and paragraphs
+  - Add emphasis with **bold** or *italic* where appropriate
+  - Use bullet points for lists and key takeaways
+  - Maintain the original speaker's tone and style
+
+**Transcript:**
+
+{}",
+        transcript
+    );
+
+    let mut request_body = serde_json::json!({
+        "model": llm_config.model,
+        "max_tokens": llm_config.max_tokens,
+        // Anthropic only accepts 0.0-1.0
+        "temperature": temperature.min(1.0),
+        "messages": [
+            {
+                "role": "user",
+                "content": prompt
+            }
+        ]
+    });
+    if let Some(system) = system_prompt {
+        request_body["system"] = serde_json::Value::from(system);
+    }
+
+    let response = client
+        .post(format!("{}/messages", llm_config.endpoint))
+        .header("anthropic-version", "2023-06-01")
+        .header("x-api-key", api_key)
+        .json(&request_body)
+        .timeout(std::time::Duration::from_secs(120))
+        .send()
+        .await
+        .map_err(|e| {
+            if e.is_timeout() {
+                Y2mdError::Llm("LLM request timed out after 2 minutes".to_string())
+            } else {
+                Y2mdError::Llm(format!("Failed to connect to Anthropic API: {}", e))
+            }
+        })?;
+
+    if !response.status().is_success() {
+        let status = response.status();
+        let error_text = response.text().await.unwrap_or_default();
+        return Err(Y2mdError::Llm(format!(
+            "Anthropic API returned error {}: {}",
+            status, error_text
+        )));
+    }
+
+    let response_json: serde_json::Value = response
+        .json()
+        .await
+        .map_err(|e| Y2mdError::Llm(format!("Failed to parse Anthropic response: {}", e)))?;
+
+    let formatted_text = response_json["content"][0]["text"]
+        .as_str()
+        .ok_or_else(|| Y2mdError::Llm("Invalid response format from Anthropic".to_string()))?
+        .trim()
+        .to_string();
+
+    if formatted_text.is_empty() {
+        return Err(Y2mdError::Llm(
+            "Anthropic returned empty response".to_string(),
+        ));
+    }
+
+    // The model ran out of output tokens: keep what we got but say so loudly
+    if response_json["stop_reason"].as_str() == Some("max_tokens") {
+        println!(
+            "Warning: Anthropic stopped at max_tokens ({}), the formatted transcript is cut off.",
+            llm_config.max_tokens
+        );
+        println!(
+            "Tip: Raise llm.anthropic.max_tokens in your config, or split long videos into shorter parts"
+        );
+        let truncated = format!(
+            "{}\n\n*[Formatting stopped here: the LLM reached its max_tokens limit of {}]*",
+            formatted_text, llm_config.max_tokens
+        );
+        return Ok((truncated, TokenUsage::from_response(&response_json)));
+    }
+
+    Ok((formatted_text, TokenUsage::from_response(&response_json)))
+}
+
+async fn format_with_deepseek(
+    client: &HttpClient,
+    transcript: &str,
+    llm_config: &DeepSeekConfig,
+    api_key: &str,
+    temperature: f32,
+    system_prompt: Option<&str>,
+) -> Result<(String, Option<TokenUsage>), Y2mdError> {
+    let prompt = format!(
+        "Please format the following transcript into well-structured markdown. 
+        Keep the original content but improve readability by:
+        - Organizing into logical paragraphs
+        - Fixing any grammar or punctuation issues
+        - Removing filler words if appropriate
+        - Maintaining the original meaning and tone
+        
+        Transcript:\n\n{}",
+        transcript
+    );
+
+    let request_body = serde_json::json!({
+        "model": llm_config.model,
+        "messages": [
+            {
+                "role": "system",
+                "content": system_prompt.unwrap_or(DEFAULT_LLM_SYSTEM_PROMPT)
+            },
+            {
+                "role": "user",
+                "content": prompt
+            }
+        ],
+        "temperature": temperature
+    });
+
+    let response = client
+        .post(format!("{}/chat/completions", llm_config.endpoint))
+        .header("Authorization", format!("Bearer {}", api_key))
+        .json(&request_body)
+        .timeout(std::time::Duration::from_secs(120))
+        .send()
+        .await
+        .map_err(|e| {
+            if e.is_timeout() {
+                Y2mdError::Llm("LLM request timed out after 2 minutes".to_string())
+            } else {
+                Y2mdError::Llm(format!("Failed to connect to DeepSeek API: {}", e))
+            }
+        })?;
+
+    if !response.status().is_success() {
+        return Err(Y2mdError::Llm(format!(
+            "DeepSeek API returned error: {}",
+            response.status()
+        )));
+    }
+
+    let response_json: serde_json::Value = response
+        .json()
+        .await
+        .map_err(|e| Y2mdError::Llm(format!("Failed to parse DeepSeek response: {}", e)))?;
+
+    let formatted_text = response_json["choices"][0]["message"]["content"]
+        .as_str()
+        .ok_or_else(|| Y2mdError::Llm("Invalid response format from DeepSeek".to_string()))?
+        .trim()
+        .to_string();
+
+    if formatted_text.is_empty() {
+        return Err(Y2mdError::Llm(
+            "DeepSeek returned empty response".to_string(),
+        ));
+    }
+
+    Ok((formatted_text, TokenUsage::from_response(&response_json)))
+}
+
+async fn format_with_custom(
+    client: &HttpClient,
+    transcript: &str,
+    llm_config: &CustomLlmConfig,
+    api_key: Option<&str>,
+    temperature: f32,
+    system_prompt: Option<&str>,
+) -> Result<(String, Option<TokenUsage>), Y2mdError> {
+    if llm_config.endpoint.is_empty() {
+        return Err(Y2mdError::Llm(
+            "Custom LLM endpoint not configured. Please set it in your config file.".to_string(),
+        ));
+    }
+
+    let prompt = format!(
+        "Please format the following transcript into well-structured markdown. 
+        Keep the original content but improve readability by:
+        - Organizing into logical paragraphs
+        - Fixing any grammar or punctuation issues
+        - Removing filler words if appropriate
+        - Maintaining the original meaning and tone
+        
+        Transcript:\n\n{}",
+        transcript
+    );
+
+    let request_body = serde_json::json!({
+        "model": llm_config.model,
+        "messages": [
+            {
+                "role": "system",
+                "content": system_prompt.unwrap_or(DEFAULT_LLM_SYSTEM_PROMPT)
+            },
+            {
+                "role": "user",
+                "content": prompt
+            }
+        ],
+        "temperature": temperature
+    });
+
+    let mut request_builder = client
+        .post(format!("{}/chat/completions", llm_config.endpoint))
+        .json(&request_body)
+        .timeout(std::time::Duration::from_secs(120));
+
+    if let Some(key) = api_key {
+        request_builder = request_builder.header("Authorization", format!("Bearer {}", key));
+    }
+
+    let response = request_builder.send().await.map_err(|e| {
+        if e.is_timeout() {
+            Y2mdError::Llm("LLM request timed out after 2 minutes".to_string())
+        } else {
+            Y2mdError::Llm(format!("Failed to connect to custom LLM API: {}", e))
+        }
+    })?;
+
+    if !response.status().is_success() {
+        return Err(Y2mdError::Llm(format!(
+            "Custom LLM API returned error: {}",
+            response.status()
+        )));
+    }
+
+    let response_json: serde_json::Value = response
+        .json()
+        .await
+        .map_err(|e| Y2mdError::Llm(format!("Failed to parse custom LLM response: {}", e)))?;
+
+    let formatted_text = response_json["choices"][0]["message"]["content"]
+        .as_str()
+        .ok_or_else(|| Y2mdError::Llm("Invalid response format from custom LLM".to_string()))?
+        .trim()
+        .to_string();
+
+    if formatted_text.is_empty() {
+        return Err(Y2mdError::Llm(
+            "Custom LLM returned empty response".to_string(),
+        ));
+    }
+
+    Ok((formatted_text, TokenUsage::from_response(&response_json)))
+}
+
+/// Clean and normalize raw transcript text.
+///
+/// Collapses whitespace, capitalizes the first word of each sentence and adds
+/// a period roughly every twelve words and at the end when punctuation is
+/// missing. Existing punctuation is kept as is.
+///
+/// ```
+/// assert_eq!(y2md::clean_transcript("so  it begins. the end"), "So it begins. The end.");
+/// ```
+pub fn clean_transcript(text: &str) -> String {
+    let mut result = String::new();
+    let words: Vec<&str> = text.split_whitespace().collect();
+
+    for (i, word) in words.iter().enumerate() {
+        // Capitalize first word of sentence
+        let sentence_start = i == 0 || result.ends_with(['.', '!', '?']);
+        if !result.is_empty() {
+            result.push(' ');
+        }
+
+        if sentence_start {
+            result.push_str(&capitalize_first_letter(word));
+        } else {
+            result.push_str(word);
+        }
+
+        // Add punctuation if missing at natural breaks
+        if should_add_punctuation(word, i, words.len()) {
+            result.push('.');
+        }
+    }
+
+    result
+}
+
+/// A sentence found by [`segment_sentences`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SentenceSpan {
+    /// The sentence including its closing punctuation and quotes
+    pub text: String,
+    /// Where the sentence sits in the segmented text
+    pub byte_range: std::ops::Range<usize>,
+}
+
+/// Abbreviations whose trailing period does not end a sentence (lowercase, without the final period)
+const SENTENCE_ABBREVIATIONS: &[&str] = &[
+    "mr", "mrs", "ms", "dr", "prof", "sr", "jr", "st", "vs", "etc", "e.g", "i.e", "a.m", "p.m",
+    "u.s", "u.k", "inc", "ltd", "co", "corp", "approx", "fig", "vol", "jan", "feb", "mar", "apr",
+    "jun", "jul", "aug", "sep", "sept", "oct", "nov", "dec",
+];
+
+/// Closing quotes and brackets that belong to the sentence before them
+const SENTENCE_CLOSERS: &[char] = &['"', '\'', '\u{201D}', '\u{2019}', ')', ']'];
+
+/// Split text into sentences with their byte offsets.
+///
+/// A sentence ends at `.`, `!`, `?` or an ellipsis followed by whitespace;
+/// closing quotes and brackets stay with it. Periods after known
+/// abbreviations ("Dr.", "e.g.") and single-letter initials do not end a
+/// sentence, and neither does an ellipsis followed by a lowercase word.
+/// Spans are trimmed, and trailing text without punctuation is the last span.
+pub fn segment_sentences(text: &str) -> Vec<SentenceSpan> {
+    let mut spans = Vec::new();
+    let mut start = 0;
+    let mut chars = text.char_indices().peekable();
+
+    while let Some((index, c)) = chars.next() {
+        if !matches!(c, '.' | '!' | '?' | '\u{2026}') {
+            continue;
+        }
+
+        // Take the whole run of terminators ("?!", "...") and any closers
+        let mut end = index + c.len_utf8();
+        let mut periods_only = c == '.';
+        let mut ellipsis = c == '\u{2026}';
+        let mut run = 1;
+        while let Some(&(i, next)) = chars.peek() {
+            if !matches!(next, '.' | '!' | '?' | '\u{2026}') {
+                break;
+            }
+            periods_only &= next == '.';
+            ellipsis |= next == '\u{2026}';
+            run += 1;
+            end = i + next.len_utf8();
+            chars.next();
+        }
+        ellipsis |= periods_only && run >= 3;
+        while let Some(&(i, next)) = chars.peek() {
+            if !SENTENCE_CLOSERS.contains(&next) {
+                break;
+            }
+            end = i + next.len_utf8();
+            chars.next();
+        }
+
+        // Mid-word punctuation such as "3.14" or "example.com"
+        let Some(&(_, next)) = chars.peek() else {
+            break;
+        };
+        if !next.is_whitespace() {
+            continue;
+        }
+
+        if periods_only && run == 1 && is_abbreviation(&text[start..index]) {
+            continue;
+        }
+        if ellipsis
+            && text[end..]
+                .trim_start()
+                .starts_with(|c: char| c.is_lowercase())
+        {
+            continue;
+        }
+
+        push_sentence(&mut spans, text, start..end);
+        start = end;
+    }
+
+    push_sentence(&mut spans, text, start..text.len());
+    spans
+}
+
+/// Whether `before` (the text up to a period) ends with an abbreviation or an initial
+fn is_abbreviation(before: &str) -> bool {
+    let word = before
+        .rsplit(char::is_whitespace)
+        .next()
+        .unwrap_or_default()
+        .trim_start_matches(['"', '\'', '(', '[', '\u{201C}', '\u{2018}'])
+        .to_lowercase();
+    let mut letters = word.chars();
+    // "J. Smith", but not "so do I."
+    let initial = matches!(
+        (letters.next(), letters.next()),
+        (Some(c), None) if c.is_alphabetic() && c != 'i'
+    ) && before.ends_with(char::is_uppercase);
+    initial || SENTENCE_ABBREVIATIONS.contains(&word.as_str())
+}
+
+/// Add the trimmed text in `range` as a sentence unless it is blank
+fn push_sentence(spans: &mut Vec<SentenceSpan>, text: &str, range: std::ops::Range<usize>) {
+    let slice = &text[range.clone()];
+    let trimmed = slice.trim();
+    if trimmed.is_empty() {
+        return;
+    }
+    let start = range.start + (slice.len() - slice.trim_start().len());
+    spans.push(SentenceSpan {
+        text: trimmed.to_string(),
+        byte_range: start..start + trimmed.len(),
+    });
+}
+
+/// Capitalize a sentence and give it a closing period if it has no punctuation
+fn finish_sentence(sentence: &str) -> String {
+    let mut finished = capitalize_first_letter(sentence);
+    if !sentence
+        .trim_end_matches(SENTENCE_CLOSERS)
+        .ends_with(['.', '!', '?', '\u{2026}'])
+    {
+        finished.push('.');
+    }
+    finished
+}
+
+/// Group sentences into paragraphs of `sentences_per_paragraph` sentences
+/// (at least one), separated by blank lines.
+///
+/// Sentences are split with [`segment_sentences`], capitalized and closed
+/// with a period when they lack end punctuation.
+///
+/// ```
+/// assert_eq!(y2md::format_paragraphs("one. two. three", 2), "One. Two.\n\nThree.");
+/// ```
+pub fn format_paragraphs(text: &str, sentences_per_paragraph: usize) -> String {
+    let sentences_per_paragraph = sentences_per_paragraph.max(1);
+    let mut result = String::new();
+
+    let mut sentence_count = 0;
+    let mut current_paragraph = String::new();
+
+    for sentence in segment_sentences(text) {
+        if !current_paragraph.is_empty() {
+            current_paragraph.push(' ');
+        }
+        current_paragraph.push_str(&finish_sentence(&sentence.text));
+
+        sentence_count += 1;
+
+        // Start new paragraph after N sentences
+        if sentence_count >= sentences_per_paragraph {
+            if !result.is_empty() {
+                result.push_str("\n\n");
+            }
+            result.push_str(&current_paragraph);
+            current_paragraph.clear();
+            sentence_count = 0;
+        }
+    }
+
+    // Add remaining sentences
+    if !current_paragraph.is_empty() {
+        if !result.is_empty() {
+            result.push_str("\n\n");
+        }
+        result.push_str(&current_paragraph);
+    }
+
+    result
+}
+
+/// Capitalize first letter of a string
+fn capitalize_first_letter(s: &str) -> String {
+    let mut chars = s.chars();
+    match chars.next() {
+        None => String::new(),
+        Some(first) => first.to_uppercase().collect::<String>() + chars.as_str(),
+    }
+}
+
+/// Determine if punctuation should be added
+fn should_add_punctuation(word: &str, index: usize, total_words: usize) -> bool {
+    // Don't add punctuation if it already ends with one
+    if word.ends_with(['.', '!', '?']) {
+        return false;
+    }
+
+    // Add punctuation at natural sentence boundaries
+    let is_long_phrase = index > 0 && index.is_multiple_of(12); // Every ~12 words
+    let is_near_end = index == total_words - 1;
+
+    is_long_phrase || is_near_end
+}
+
+/// Escape Markdown special characters
+fn escape_markdown(text: &str) -> String {
+    text.replace('*', "\\*")
+        .replace('_', "\\_")
+        .replace('`', "\\`")
+        .replace('[', "\\[")
+        .replace(']', "\\]")
+        .replace('(', "\\(")
+        .replace(')', "\\)")
+        .replace('#', "\\#")
+        .replace('+', "\\+")
+        .replace('-', "\\-")
+        .replace('.', "\\.")
+        .replace('!', "\\!")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_extract_video_id_youtube_com() {
+        let url = "https://www.youtube.com/watch?v=dQw4w9WgXcQ";
+        assert_eq!(extract_video_id(url).unwrap(), "dQw4w9WgXcQ");
+    }
+
+    #[test]
+    fn test_extract_video_id_youtu_be() {
+        let url = "https://youtu.be/dQw4w9WgXcQ";
+        assert_eq!(extract_video_id(url).unwrap(), "dQw4w9WgXcQ");
+    }
+
+    #[test]
+    fn test_extract_video_id_shorts() {
+        let url = "https://www.youtube.com/shorts/abc123def45";
+        assert_eq!(extract_video_id(url).unwrap(), "abc123def45");
+    }
+
+    #[test]
+    fn test_extract_video_id_with_params() {
+        let url = "https://www.youtube.com/watch?v=dQw4w9WgXcQ&t=42";
+        assert_eq!(extract_video_id(url).unwrap(), "dQw4w9WgXcQ");
+    }
+
+    #[test]
+    fn test_extract_video_id_direct() {
+        let url = "dQw4w9WgXcQ";
+        assert_eq!(extract_video_id(url).unwrap(), "dQw4w9WgXcQ");
+    }
+
+    #[test]
+    fn test_validate_youtube_url() {
+        let url = "https://www.youtube.com/watch?v=dQw4w9WgXcQ";
+        assert_eq!(validate_youtube_url(url).unwrap(), "dQw4w9WgXcQ");
+    }
+
+    #[test]
+    fn test_invalid_url() {
+        let url = "https://example.com";
+        assert!(extract_video_id(url).is_err());
+    }
+
+    #[test]
+    fn test_classify_ytdlp_error() {
+        let err = classify_ytdlp_error(
+            "WARNING: something\nERROR: [youtube] abc123def45: Private video. Sign in if you've been granted access to this video",
+        );
+        assert!(matches!(err, Y2mdError::PrivateVideo(_)));
+
+        let err = classify_ytdlp_error(
+            "ERROR: [youtube] abc123def45: Sign in to confirm your age. This video may be inappropriate for some users.",
+        );
+        assert!(matches!(err, Y2mdError::AgeRestricted(_)));
+
+        let err = classify_ytdlp_error(
+            "ERROR: [youtube] abc123def45: Join this channel to get access to members-only content like this video",
+        );
+        assert!(matches!(err, Y2mdError::MembersOnly(_)));
+
+        let err = classify_ytdlp_error(
+            "ERROR: [youtube] abc123def45: Video unavailable. This video has been removed by the uploader",
+        );
+        assert!(matches!(err, Y2mdError::VideoUnavailable(_)));
+
+        let err = classify_ytdlp_error("ERROR: Unable to download webpage: timed out");
+        match err {
+            Y2mdError::YtDlp(msg) => assert_eq!(msg, "Unable to download webpage: timed out"),
+            other => panic!("unexpected error: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_transient_ytdlp_errors() {
+        assert!(is_transient_ytdlp_error(
+            "ERROR: Unable to download webpage: timed out"
+        ));
+        assert!(is_transient_ytdlp_error(
+            "ERROR: [youtube] abc123def45: HTTP Error 429: Too Many Requests"
+        ));
+        assert!(!is_transient_ytdlp_error(
+            "ERROR: [youtube] abc123def45: Video unavailable"
+        ));
+        assert!(!is_transient_ytdlp_error(
+            "ERROR: [youtube] abc123def45: Private video. Sign in if you've been granted access"
+        ));
+        assert!(!is_transient_ytdlp_error("ERROR: Unsupported URL"));
+
+        assert_eq!(retry_delay(0).as_secs(), 1);
+        assert_eq!(retry_delay(2).as_secs(), 4);
+        assert_eq!(retry_delay(10).as_secs(), 32);
+        assert_eq!(YtDlpConfig::default().retry_count(), 2);
+    }
+
+    #[test]
+    fn test_sanitize_filename() {
+        assert_eq!(sanitize_filename("Rick Astley"), "Rick_Astley");
+        assert_eq!(sanitize_filename("a/b: c?"), "a_b__c_");
+        assert_eq!(sanitize_filename("keep-this_one"), "keep-this_one");
+    }
+
+    #[test]
+    fn test_expand_output_dir() {
+        let metadata = VideoMetadata {
+            title: "Test".to_string(),
+            channel: Some("Some Channel/Name".to_string()),
+            duration: None,
+            duration_seconds: None,
+            video_id: "dQw4w9WgXcQ".to_string(),
+            url: "https://www.youtube.com/watch?v=dQw4w9WgXcQ".to_string(),
+            upload_date: Some("2009-10-25".to_string()),
+            has_audio: None,
+            language: None,
+            description: None,
+            chapters: Vec::new(),
+        };
+
+        assert_eq!(
+            expand_output_dir("~/notes/{channel}/{year}/{month}", &metadata),
+            "~/notes/Some_Channel_Name/2009/10"
+        );
+        assert_eq!(
+            expand_output_dir("out/{upload_date}_{video_id}", &metadata),
+            "out/2009-10-25_dQw4w9WgXcQ"
+        );
+        assert_eq!(
+            expand_output_dir("./transcripts", &metadata),
+            "./transcripts"
+        );
+    }
+
+    #[test]
+    fn test_fix_caption_capitalization() {
+        let nouns = vec!["Rust".to_string(), "New York".to_string()];
+        assert_eq!(
+            fix_caption_capitalization(
+                "so i think i'm moving to new york, rust's great",
+                &nouns,
+                true
+            ),
+            "so I think I'm moving to New York, Rust's great"
+        );
+        assert_eq!(
+            fix_caption_capitalization("iris is in new yorker", &nouns, true),
+            "iris is in new yorker"
+        );
+        assert_eq!(
+            fix_caption_capitalization("i ragazzi", &[], false),
+            "i ragazzi"
+        );
+    }
+
+    #[test]
+    fn test_audio_format_and_quality() {
+        assert_eq!(parse_audio_format("M4A"), Ok("m4a".to_string()));
+        assert!(parse_audio_format("avi").is_err());
+        assert_eq!(parse_audio_quality("5"), Ok("5".to_string()));
+        assert_eq!(parse_audio_quality("64k"), Ok("64K".to_string()));
+        assert!(parse_audio_quality("11").is_err());
+        assert!(parse_audio_quality("fast").is_err());
+
+        assert_eq!(
+            YtDlpConfig::default().audio_args(),
+            ["--audio-format", "best", "--audio-quality", "0"]
+        );
+        let ytdlp = YtDlpConfig {
+            audio_format: Some("m4a".to_string()),
+            audio_quality: Some("5".to_string()),
+            ..YtDlpConfig::default()
+        };
+        assert_eq!(
+            ytdlp.audio_args(),
+            ["--audio-format", "m4a", "--audio-quality", "5"]
+        );
+    }
+
+    #[test]
+    fn test_proxy_config() {
+        let config = AppConfig {
+            proxy: Some("http://proxy.example:8080".to_string()),
+            ..AppConfig::default()
+        };
+        assert!(config.validate().is_ok());
+        if std::env::var(PROXY_ENV_VAR).is_err() {
+            let args = config.ytdlp_config().cookie_args();
+            assert_eq!(args, ["--proxy", "http://proxy.example:8080"]);
+            assert!(HttpClient::new(&config).is_ok());
+        }
+
+        // --proxy wins over the environment and the file, and is not saved
+        let config = AppConfig {
+            proxy_override: Some("http://cli.example:3128".to_string()),
+            ..config
+        };
+        assert_eq!(
+            config.proxy_url().as_deref(),
+            Some("http://cli.example:3128")
+        );
+        assert!(!toml::to_string(&config).unwrap().contains("cli.example"));
+
+        let config = AppConfig {
+            proxy: Some("not a url".to_string()),
+            ..AppConfig::default()
+        };
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_wrap_markdown() {
+        let markdown = "---\ntitle: \"a title that is longer than twenty columns\"\n---\n# A heading longer than twenty columns\n\nThe quick brown fox jumps over the lazy dog.\n\n- a list item that wraps past the width\n```\ncode that is long and must stay as it is\n```\n";
+        let wrapped = wrap_markdown(markdown, 20);
+
+        assert!(wrapped.contains("title: \"a title that is longer than twenty columns\"\n"));
+        assert!(wrapped.contains("# A heading longer than twenty columns\n"));
+        assert!(wrapped.contains("The quick brown fox\njumps over the lazy\ndog.\n"));
+        assert!(wrapped.contains("- a list item that\n  wraps past the\n  width\n"));
+        assert!(wrapped.contains("code that is long and must stay as it is\n"));
+        assert_eq!(wrap_markdown("short line", 20), "short line");
+
+        assert_eq!(parse_line_wrap("80"), Ok(80));
+        assert!(parse_line_wrap("10").is_err());
+        assert!(parse_line_wrap("wide").is_err());
+    }
+
+    #[test]
+    fn test_run_stats() {
+        let mut clock = PipelineRecorder::default();
+        clock.record(&PipelineEvent::TranscribeStarted);
+        clock.record(&PipelineEvent::TranscribeFinished);
+        // A finish without a start is ignored
+        clock.record(&PipelineEvent::LlmFinished);
+        assert!(clock.timings.transcription_seconds >= 0.0);
+        assert_eq!(clock.timings.llm_seconds, 0.0);
+        assert!(clock.started.is_empty());
+
+        let markdown = "---\ntitle: \"Talk\"\nformatted_by: \"llm\"\nllm_model: \"gpt-4o\"\n---\n\n# Talk\n\nOne two.\n\nThree.";
+
+        let output = ProcessOutput {
+            metadata: VideoMetadata {
+                title: "Talk".to_string(),
+                channel: None,
+                duration: None,
+                duration_seconds: None,
+                video_id: "abc".to_string(),
+                url: "https://www.youtube.com/watch?v=abc".to_string(),
+                upload_date: None,
+                has_audio: None,
+                language: None,
+                description: None,
+                chapters: Vec::new(),
+            },
+            markdown: markdown.to_string(),
+            transcript: "One two.\n\nThree.".to_string(),
+            raw_transcript: String::new(),
+            segments: Vec::new(),
+            source: TranscriptionSource::AutoCaptions {
+                language: "de".to_string(),
+            },
+            output_dir: ".".to_string(),
+            llm_model: Some("gpt-4o".to_string()),
+            timings: StageTimings::default(),
+            language: "de".to_string(),
+            detected_language: Some(LanguageDetection {
+                language: "de".to_string(),
+                probability: 0.94,
+            }),
+        };
+        let stats = RunStats::from_output(&output);
+        assert_eq!(stats.word_count, 3);
+        assert_eq!(stats.llm_model.as_deref(), Some("gpt-4o"));
+        let json = serde_json::to_value(&stats).unwrap();
+        assert_eq!(json["timings"]["llm_seconds"], 0.0);
+        assert_eq!(json["source"], "auto-captions (de)");
+        assert_eq!(json["language"], "de");
+        // Captions have no confidence to report
+        assert!(json.get("avg_logprob").is_none());
+        assert!(json.get("segments").is_none());
+
+        let segment = |start: f64, avg_logprob: Option<f32>| TranscriptSegment {
+            start,
+            end: start + 1.0,
+            text: "Hi.".to_string(),
+            speaker: None,
+            avg_logprob,
+        };
+        let output = ProcessOutput {
+            segments: vec![
+                segment(0.0, Some(-0.2)),
+                segment(1.0, None),
+                segment(2.0, Some(-0.6)),
+            ],
+            ..output
+        };
+        let stats = RunStats::from_output(&output);
+        assert!((stats.avg_logprob.unwrap() + 0.4).abs() < 1e-6);
+        assert_eq!(stats.segments.len(), 2);
+        let json = serde_json::to_value(&stats).unwrap();
+        assert_eq!(json["segments"][1]["start"], 2.0);
+        assert_eq!(
+            json["language_probability"].as_f64().map(|p| p > 0.9),
+            Some(true)
+        );
+    }
+
+    #[test]
+    fn test_sample_download_args() {
+        let mut ytdlp = YtDlpConfig::default();
+        assert!(!ytdlp
+            .audio_args()
+            .contains(&"--download-sections".to_string()));
+        assert_eq!(ytdlp.audio_file_stem("abc"), "abc_audio");
+
+        ytdlp.sample_seconds = Some(90);
+        let args = ytdlp.audio_args();
+        assert!(args.ends_with(&["--download-sections".to_string(), "*0-90".to_string()]));
+        assert_eq!(ytdlp.audio_file_stem("abc"), "abc_sample90_audio");
+    }
+
+    #[test]
+    fn test_quietest_cut() {
+        let second = WHISPER_SAMPLE_RATE;
+        // Speech throughout, with a pause 4 seconds before the end
+        let mut samples = vec![0.5_f32; 60 * second];
+        samples[56 * second..56 * second + second / 2].fill(0.0);
+        let cut = quietest_cut(&samples);
+        assert!(
+            (56 * second..56 * second + second / 2).contains(&cut),
+            "{}",
+            cut
+        );
+
+        // Too short to search is taken whole
+        assert_eq!(quietest_cut(&samples[..second]), second);
+    }
+
+    #[test]
+    fn test_chunk_prompt() {
+        let segment = |text: &str| TranscriptSegment {
+            start: 0.0,
+            end: 1.0,
+            text: text.to_string(),
+            speaker: None,
+            avg_logprob: None,
+        };
+        assert_eq!(chunk_prompt(&[]), "");
+        let segments = vec![
+            segment(&"x".repeat(CHUNK_PROMPT_CHARS)),
+            segment("Welcome back."),
+            segment("Where were we?"),
+        ];
+        assert_eq!(chunk_prompt(&segments), "Welcome back. Where were we?");
+    }
+
+    #[test]
+    fn test_transcription_checkpoint() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("checkpoints").join("abc.json");
+        let checkpoint = TranscriptionCheckpoint {
+            language: "en".to_string(),
+            completed_samples: WHISPER_CHUNK_SECONDS * WHISPER_SAMPLE_RATE,
+            segments: vec![TranscriptSegment {
+                start: 0.0,
+                end: 2.5,
+                text: "Hello there.".to_string(),
+                speaker: None,
+                avg_logprob: None,
+            }],
+        };
+        save_checkpoint(&path, &checkpoint);
+
+        let loaded = load_checkpoint(&path, "en").unwrap();
+        assert_eq!(loaded.completed_samples, checkpoint.completed_samples);
+        assert_eq!(loaded.segments, checkpoint.segments);
+        // A checkpoint for another language is not resumed
+        assert!(load_checkpoint(&path, "de").is_none());
+        assert!(load_checkpoint(&dir.path().join("missing.json"), "en").is_none());
+        // Each model keeps its own checkpoint
+        assert_ne!(
+            checkpoint_path("abc", "ggml-base.en"),
+            checkpoint_path("abc", "ggml-small.en")
+        );
+    }
+
+    #[test]
+    fn test_language_auto() {
+        assert_eq!(parse_language_arg("auto"), Ok(AUTO_LANGUAGE.to_string()));
+        assert_eq!(parse_language_arg("AUTO"), Ok(AUTO_LANGUAGE.to_string()));
+        assert_eq!(parse_language_arg("en-US"), Ok("en".to_string()));
+        assert!(parse_language_arg("automatic").is_err());
+
+        let (model, lang) =
+            determine_model_and_language(Some(AUTO_LANGUAGE), &AppConfig::default().model_dirs())
+                .unwrap();
+        assert!(model.ends_with("ggml-base.bin"));
+        assert_eq!(lang, AUTO_LANGUAGE);
+
+        let mut recorder = PipelineRecorder::default();
+        let detection = LanguageDetection {
+            language: "fr".to_string(),
+            probability: 0.87,
+        };
+        recorder.record(&PipelineEvent::LanguageDetected(detection.clone()));
+        assert_eq!(recorder.language, Some(detection));
+
+        let mut config = AppConfig::default();
+        config.default_language = "auto".to_string();
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn test_parse_temperature() {
+        assert_eq!(parse_temperature("0.7"), Ok(0.7));
+        assert_eq!(parse_temperature("0"), Ok(0.0));
+        assert_eq!(parse_temperature("2.0"), Ok(2.0));
+        assert!(parse_temperature("2.5").is_err());
+        assert!(parse_temperature("-0.1").is_err());
+        assert!(parse_temperature("warm").is_err());
+        assert!(parse_temperature("NaN").is_err());
+
+        let mut config = AppConfig::default();
+        assert_eq!(config.llm_temperature, 0.1);
+        config.llm_temperature = 3.0;
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_llm_provider_chain() {
+        let mut config = AppConfig::default();
+        assert_eq!(config.llm_provider_chain(), vec![LlmProviderType::Local]);
+
+        config.llm_fallback = vec![
+            LlmProviderType::OpenAI,
+            LlmProviderType::Local,
+            LlmProviderType::Anthropic,
+        ];
+        assert_eq!(
+            config.llm_provider_chain(),
+            vec![
+                LlmProviderType::Local,
+                LlmProviderType::OpenAI,
+                LlmProviderType::Anthropic
+            ]
+        );
+    }
+
+    #[test]
+    fn test_format_transcript_with() {
+        let transcript = "first sentence here. second one follows. third is last";
+        assert_eq!(
+            format_transcript_with(transcript, &FormatOptions::default()),
+            format_transcript(transcript, false, 4)
+        );
+
+        let options = FormatOptions::new()
+            .compact(true)
+            .paragraph_length(ParagraphLength::Sentences(1));
+        assert_eq!(
+            format_transcript_with(transcript, &options),
+            "First sentence here.\n\nSecond one follows.\n\nThird is last."
+        );
+
+        let options = options
+            .remove_fillers(true)
+            .max_repeated_sentences(2)
+            .replacements(vec![("cube".to_string(), "Kubernetes".to_string())])
+            .redactor(Redactor::new(&["acme".to_string()], false).unwrap());
+        assert_eq!(
+            format_transcript_with("um, acme runs cube. thanks. thanks. thanks. uh.", &options),
+            "[redacted] runs Kubernetes.\n\nThanks."
+        );
+    }
+
+    #[test]
+    fn test_remove_filler_words() {
+        assert_eq!(
+            remove_filler_words("Um, so we, uh, ship it. Uh. Erm what?"),
+            "so we, ship it. what?"
+        );
+        assert_eq!(remove_filler_words("it was, uh."), "it was.");
+        assert_eq!(
+            remove_filler_words("Umbrella and hummus\n\nuh next"),
+            "Umbrella and hummus\n\nnext"
+        );
+    }
+
+    #[test]
+    fn test_token_usage_from_response() {
+        let openai = serde_json::json!({"usage": {"prompt_tokens": 12, "completion_tokens": 30}});
+        let anthropic = serde_json::json!({"usage": {"input_tokens": 15, "output_tokens": 25}});
+        let ollama = serde_json::json!({"prompt_eval_count": 20, "eval_count": 40});
+        assert_eq!(
+            TokenUsage::from_response(&openai),
+            Some(TokenUsage {
+                input: 12,
+                output: 30
+            })
+        );
+        assert_eq!(
+            TokenUsage::from_response(&anthropic),
+            Some(TokenUsage {
+                input: 15,
+                output: 25
+            })
+        );
+        assert_eq!(
+            TokenUsage::from_response(&ollama).map(|usage| usage.to_string()),
+            Some("20 in, 40 out".to_string())
+        );
+        assert_eq!(
+            TokenUsage::from_response(&serde_json::json!({"response": "hi"})),
+            None
+        );
+
+        let usage = TokenUsage {
+            input: 2_000_000,
+            output: 500_000,
+        };
+        let cost = usage.estimated_cost(&LlmProviderType::OpenAI, "gpt-4o-mini-2024-07-18");
+        assert!((cost.unwrap() - 0.6).abs() < 1e-9);
+        assert_eq!(
+            usage.estimated_cost(&LlmProviderType::Local, "llama3.2:3b"),
+            Some(0.0)
+        );
+        assert_eq!(
+            usage.estimated_cost(&LlmProviderType::Custom, "my-model"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_write_atomic() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("Talk.md");
+
+        write_atomic(&path, "first").unwrap();
+        write_atomic(&path, "second").unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "second");
+
+        // No temporary files are left behind
+        let entries: Vec<_> = std::fs::read_dir(dir.path()).unwrap().collect();
+        assert_eq!(entries.len(), 1);
+
+        assert!(write_atomic(dir.path().join("missing/Talk.md"), "x").is_err());
+    }
+
+    #[test]
+    fn test_append_section() {
+        let output = ProcessOutput {
+            metadata: VideoMetadata {
+                title: "Daily Talk".to_string(),
+                channel: Some("Course".to_string()),
+                duration: Some("10:00".to_string()),
+                duration_seconds: Some(600),
+                video_id: "abc123def45".to_string(),
+                url: "https://www.youtube.com/watch?v=abc123def45".to_string(),
+                upload_date: None,
+                has_audio: None,
+                language: None,
+                description: None,
+                chapters: Vec::new(),
+            },
+            markdown: "---\ntitle: \"Daily Talk\"\n---\n\n# Daily Talk\n\n## Intro\n\nHello.\n\n```\n# not a heading\n```\n"
+                .to_string(),
+            transcript: String::new(),
+            raw_transcript: String::new(),
+            segments: Vec::new(),
+            source: TranscriptionSource::ManualCaptions {
+                language: "en".to_string(),
+            },
+            output_dir: ".".to_string(),
+            llm_model: None,
+            timings: StageTimings::default(),
+            language: "en".to_string(),
+            detected_language: None,
+        };
+
+        let section = append_section(&output);
+        assert_eq!(
+            section,
+            "## Daily Talk\n\n<!-- video_id: \"abc123def45\" -->\n\
+             *Course · 10:00 · https://www.youtube.com/watch?v=abc123def45*\n\n\
+             ### Intro\n\nHello.\n\n```\n# not a heading\n```\n"
+        );
+
+        let note = append_to_note("# 2026-10-16\n\n- standup\n\n", &section);
+        assert!(note.starts_with("# 2026-10-16\n\n- standup\n\n## Daily Talk\n"));
+        assert_eq!(append_to_note("", &section), section);
+
+        assert!(note_contains_video(&note, "abc123def45"));
+        assert!(!note_contains_video(&note, "abc123def4"));
+    }
+
+    #[test]
+    fn test_format_duration() {
+        assert_eq!(format_duration(0.0), "00:00");
+        assert_eq!(format_duration(59.9), "00:59");
+        assert_eq!(format_duration(3599.0), "59:59");
+        assert_eq!(format_duration(3600.0), "01:00:00");
+        // Multi-hour stream VODs
+        assert_eq!(format_duration(8.0 * 3600.0 + 61.0), "08:01:01");
+        assert_eq!(format_duration(36_000.0), "10:00:00");
+        assert_eq!(format_duration(100.0 * 3600.0 + 3599.0), "100:59:59");
+    }
+
+    #[test]
+    fn test_collapse_repeated_sentences() {
+        let text = "That's all for today. Thank you. Thank you. thank you! Thank you.";
+        assert_eq!(
+            collapse_repeated_sentences(text, 2),
+            "That's all for today. Thank you."
+        );
+        // Runs within the threshold and a threshold of 0 are left alone
+        assert_eq!(
+            collapse_repeated_sentences("No. No. Go on.", 2),
+            "No. No. Go on."
+        );
+        assert_eq!(collapse_repeated_sentences(text, 0), text);
+
+        let segment = |start: f64, text: &str| TranscriptSegment {
+            start,
+            end: start + 1.0,
+            text: text.to_string(),
+            speaker: None,
+            avg_logprob: None,
+        };
+        let segments = vec![
+            segment(0.0, "Bye."),
+            segment(1.0, "Thank you."),
+            segment(2.0, "Thank you."),
+            segment(3.0, "Thank you."),
+        ];
+        let collapsed = collapse_repeated_segments(segments, 2);
+        assert_eq!(collapsed.len(), 2);
+        assert_eq!((collapsed[1].start, collapsed[1].end), (1.0, 4.0));
+    }
+
+    #[test]
+    fn test_insert_timestamp_markers() {
+        let segment = |start: f64, text: &str| TranscriptSegment {
+            start,
+            end: start + 100.0,
+            text: text.to_string(),
+            speaker: None,
+            avg_logprob: None,
+        };
+        let segments = vec![
+            segment(0.0, "one two"),
+            segment(100.0, "three four"),
+            segment(320.0, "five six"),
+            segment(400.0, "seven eight"),
+        ];
+        let text = "One two.\n\n## Later\n\nThree four.\n\nFive six.\n\nSeven eight.";
+
+        assert_eq!(
+            insert_timestamp_markers(text, &segments, 300),
+            "[00:00:00] One two.\n\n## Later\n\nThree four.\n\n\
+             [00:05:20] Five six.\n\nSeven eight."
+        );
+        assert_eq!(insert_timestamp_markers(text, &[], 300), text);
+    }
+
+    #[test]
+    fn test_subtitles_to_markdown() {
+        let srt = "1\n00:00:00,000 --> 00:00:02,000\nhello there\n\n\
+                   2\n00:00:02,000 --> 00:00:04,000\n<i>this is a test.</i> and\n\n\
+                   3\n00:00:04,000 --> 00:00:06,000\nit works\n";
+        let options = FormatOptions::new().paragraph_length(ParagraphLength::Sentences(1));
+        assert_eq!(
+            subtitles_to_markdown(srt, SubtitleFormat::Srt, &options),
+            "Hello there this is a test.\n\nAnd it works.\n"
+        );
+    }
+
+    #[test]
+    fn test_audio_only_formats() {
+        let table = "[youtube] Extracting URL: https://www.youtube.com/watch?v=abc123def45\n\
+                     [info] Available formats for abc123def45:\n\
+                     ID  EXT   RESOLUTION FPS | FILESIZE   TBR PROTO | VCODEC       ACODEC\n\
+                     ---------------------------------------------------------------------\n\
+                     139 m4a   audio only     |    1.2MiB  49k https | audio only   mp4a.40.5\n\
+                     251 webm  audio only     |    3.1MiB 130k https | audio only   opus\n\
+                     18  mp4   640x360     30 |   10.0MiB 400k https | avc1.42001E  mp4a.40.2";
+
+        let audio = audio_only_formats(table);
+        let lines: Vec<&str> = audio.lines().collect();
+        assert_eq!(lines.len(), 4);
+        assert!(lines[0].starts_with("ID "));
+        assert!(lines[2].starts_with("139 "));
+        assert!(lines[3].starts_with("251 "));
+    }
+
+    #[test]
+    fn test_model_dirs() {
+        let dirs = AppConfig::default().model_dirs();
+        assert!(dirs
+            .iter()
+            .any(|dir| dir.ends_with(".local/share/y2md/models")));
+
+        let dir = tempfile::tempdir().unwrap();
+        let config = AppConfig {
+            models_dir: Some(dir.path().to_string_lossy().into_owned()),
+            ..AppConfig::default()
+        };
+        assert_eq!(config.model_dirs(), vec![dir.path().to_path_buf()]);
+
+        // A model found in a later directory wins over the first directory
+        let empty = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("ggml-base.bin"), b"model").unwrap();
+        let dirs = vec![empty.path().to_path_buf(), dir.path().to_path_buf()];
+        assert_eq!(
+            locate_model(&dirs, "ggml-base.bin"),
+            dir.path().join("ggml-base.bin")
+        );
+        assert_eq!(
+            locate_model(&dirs, "ggml-base.en.bin"),
+            empty.path().join("ggml-base.en.bin")
+        );
+
+        assert!(check_models_dir(dir.path()).is_ok());
+        let message = check_models_dir(empty.path()).unwrap_err();
+        assert!(message.contains("No Whisper models"), "{}", message);
+        assert!(check_models_dir(&dir.path().join("missing")).is_err());
+    }
+
+    #[test]
+    fn test_error_exit_codes() {
+        assert_eq!(
+            Y2mdError::YtDlpNotFound.exit_code(),
+            EXIT_MISSING_DEPENDENCY
+        );
+        assert_eq!(
+            Y2mdError::FFmpegNotFound("ffmpeg".to_string()).exit_code(),
+            EXIT_MISSING_DEPENDENCY
+        );
+        assert_eq!(
+            classify_ytdlp_error("ERROR: [youtube] abc: Private video").exit_code(),
+            EXIT_VIDEO_UNAVAILABLE
+        );
+        assert_eq!(
+            Y2mdError::Llm("no API key".to_string()).exit_code(),
+            EXIT_CONFIG
+        );
+        assert_eq!(
+            Y2mdError::Timeout("yt-dlp".to_string()).exit_code(),
+            EXIT_NETWORK
+        );
+        assert_eq!(
+            classify_ytdlp_error("ERROR: Unable to download webpage: timed out").exit_code(),
+            EXIT_NETWORK
+        );
+        assert_eq!(
+            Y2mdError::EmptyTranscript("abc".to_string()).exit_code(),
+            EXIT_FAILURE
+        );
+    }
+
+    #[test]
+    fn test_caption_language_for() {
+        let tracks = CaptionTracks {
+            manual: vec!["de".to_string()],
+            automatic: vec!["en".to_string(), "en-US".to_string()],
+        };
+        assert_eq!(tracks.languages(), vec!["de", "en", "en-US"]);
+
+        assert_eq!(
+            tracks.language_for("en", CaptionLangFallback::Stt),
+            Some("en".to_string())
+        );
+        assert_eq!(tracks.language_for("es", CaptionLangFallback::Stt), None);
+        // Creator subtitles win over auto captions
+        assert_eq!(
+            tracks.language_for("es", CaptionLangFallback::Available),
+            Some("de".to_string())
+        );
+        assert_eq!(
+            CaptionTracks::default().language_for("es", CaptionLangFallback::Available),
+            None
+        );
+
+        assert_eq!(
+            "available".parse::<CaptionLangFallback>(),
+            Ok(CaptionLangFallback::Available)
+        );
+        assert!("english".parse::<CaptionLangFallback>().is_err());
+    }
+
+    #[test]
+    fn test_apply_replacements() {
+        let replacements = vec![
+            ("cube".to_string(), "Kube".to_string()),
+            ("cube on eddies".to_string(), "Kubernetes".to_string()),
+        ];
+        // Whole words only, and the longer phrase wins
+        assert_eq!(
+            apply_replacements("We run cube on eddies, not uncube or cubes.", &replacements),
+            "We run Kubernetes, not uncube or cubes."
+        );
+        assert_eq!(
+            apply_replacements("CUBE on\neddies and cube", &replacements),
+            "Kubernetes and Kube"
+        );
+        // A paragraph break ends the phrase
+        assert_eq!(
+            apply_replacements("cube\n\non eddies", &replacements),
+            "Kube\n\non eddies"
+        );
+
+        let replacements = vec![("gonna".to_string(), "going to".to_string())];
+        assert_eq!(
+            apply_replacements("Gonna try, gonna see.", &replacements),
+            "Going to try, going to see."
+        );
+        assert_eq!(apply_replacements("Agonna", &replacements), "Agonna");
+
+        assert_eq!(
+            parse_replacement("cube on eddies = Kubernetes"),
+            Ok(("cube on eddies".to_string(), "Kubernetes".to_string()))
+        );
+        assert!(parse_replacement("Kubernetes").is_err());
+        assert!(parse_replacement("=Kubernetes").is_err());
+    }
+
+    #[test]
+    fn test_redactor() {
+        let entries = vec!["Jane Doe".to_string(), r"/ACME-\d{4}/".to_string()];
+        let redactor = Redactor::new(&entries, false).unwrap();
+        assert_eq!(
+            redactor.redact("jane doe filed ACME-1234, not Janet Doe's"),
+            "[redacted] filed [redacted], not Janet Doe's"
+        );
+        assert_eq!(
+            redactor.redact("mail jane@example.com"),
+            "mail jane@example.com"
+        );
+
+        let redactor = Redactor::new(&[], true).unwrap();
+        assert_eq!(
+            redactor.redact("Mail jane.doe@example.com or call (555) 123-4567."),
+            "Mail [redacted] or call [redacted]."
+        );
+        assert_eq!(
+            redactor.redact("Call +44 20 7946 0958, 555-123-4567 or 555.123.4567"),
+            "Call [redacted], [redacted] or [redacted]"
+        );
+        for text in [
+            "On 2024-10-16 we hit 1,000,000 views",
+            "sales in 2019 2020 2021",
+            "costs 10 000 000 euros",
+            "version 10.000.000",
+        ] {
+            assert_eq!(redactor.redact(text), text);
+        }
+
+        assert!(Redactor::new(&[], false).unwrap().is_empty());
+        assert!(Redactor::new(&["/(unclosed/".to_string()], false).is_err());
+    }
+
+    #[test]
+    fn test_timezone_and_date_format() {
+        assert_eq!("UTC".parse::<Timezone>(), Ok(Timezone::Utc));
+        assert_eq!("local".parse::<Timezone>(), Ok(Timezone::Local));
+        let berlin: Timezone = "Europe/Berlin".parse().unwrap();
+        assert_eq!(berlin.to_string(), "Europe/Berlin");
+        assert!("Mars/Olympus".parse::<Timezone>().is_err());
+
+        let config: AppConfig = toml::from_str(
+            &toml::to_string(&AppConfig::default())
+                .unwrap()
+                .replace("timezone = \"utc\"", "timezone = \"America/Los_Angeles\""),
+        )
+        .unwrap();
+        assert_eq!(
+            config.timezone,
+            Timezone::Named(chrono_tz::America::Los_Angeles)
+        );
+
+        assert!(check_date_format("%Y-%m-%d %H:%M").is_ok());
+        assert!(check_date_format("%Y-%Q").is_err());
+        assert!(check_date_format("%d %b, week %U (%Z)").is_ok());
+        for format in ["%Y \"%m\"", "%Y\\%m", "%Y%n%m", "%Y%t%m", "%Y\n%m"] {
+            assert!(check_date_format(format).is_err(), "{}", format);
+        }
+    }
+
+    #[test]
+    fn test_normalize_output_dir() {
+        assert_eq!(normalize_output_dir(""), ".");
+        assert_eq!(normalize_output_dir("  "), ".");
+        assert_eq!(normalize_output_dir("transcripts"), "transcripts");
+        let home = shellexpand::tilde("~").to_string();
+        assert_eq!(
+            normalize_output_dir("~/notes/{channel}"),
+            format!("{}/notes/{{channel}}", home)
+        );
+
+        let dir = tempfile::tempdir().unwrap();
+        let nested = dir.path().join("a/b");
+        ensure_output_dir(&nested).unwrap();
+        assert!(nested.is_dir());
+
+        let file = dir.path().join("file");
+        std::fs::write(&file, "").unwrap();
+        let error = ensure_output_dir(file.join("sub")).unwrap_err().to_string();
+        assert!(error.contains("Cannot create output directory"));
+    }
+
+    #[test]
+    fn test_temp_download() {
+        let dir = tempfile::tempdir().unwrap();
+        let first = TempDownload::new(dir.path(), "abc_audio");
+        let second = TempDownload::new(dir.path(), "abc_audio");
+        assert_ne!(first.template(), second.template());
+
+        let audio = PathBuf::from(format!("{}.wav", first.template().display()));
+        std::fs::write(&audio, "RIFF").unwrap();
+        std::fs::write(dir.path().join("abc_audio.wav"), "RIFF").unwrap();
+        assert_eq!(first.files(), vec![audio.clone()]);
+        assert!(second.files().is_empty());
+
+        drop(first);
+        assert!(!audio.exists());
+        assert!(dir.path().join("abc_audio.wav").exists());
+    }
+
+    #[test]
+    fn test_llm_per_source_defaults() {
+        let config = AppConfig::default();
+        assert!(config.llm_for_captions && config.llm_for_stt);
+
+        let mut table: toml::Table =
+            toml::from_str(&toml::to_string(&AppConfig::default()).unwrap()).unwrap();
+        table.remove("llm_for_stt");
+        table.insert("llm_for_captions".into(), toml::Value::Boolean(false));
+        let config: AppConfig = toml::from_str(&toml::to_string(&table).unwrap()).unwrap();
+        assert!(!config.llm_for_captions);
+        assert!(config.llm_for_stt);
+    }
+
+    #[test]
+    fn test_expand_filename_template() {
+        let metadata = VideoMetadata {
+            title: "Rust: Ownership".to_string(),
+            channel: Some("Some Channel".to_string()),
+            duration: None,
+            duration_seconds: None,
+            video_id: "dQw4w9WgXcQ".to_string(),
+            url: "https://www.youtube.com/watch?v=dQw4w9WgXcQ".to_string(),
+            upload_date: Some("2009-10-25".to_string()),
+            has_audio: None,
+            language: None,
+            description: None,
+            chapters: Vec::new(),
+        };
+        let today = chrono::NaiveDate::from_ymd_opt(2024, 3, 5).unwrap();
+
+        assert_eq!(
+            expand_filename_template(DEFAULT_FILENAME_TEMPLATE, &metadata, today),
+            "2024-03-05_dQw4w9WgXcQ_Rust__Ownership"
+        );
+        assert_eq!(
+            expand_filename_template("{upload_date} - {title}", &metadata, today),
+            "2009-10-25 - Rust__Ownership"
+        );
+        assert_eq!(
+            expand_filename_template("{channel}_{video_id}", &metadata, today),
+            "Some_Channel_dQw4w9WgXcQ"
+        );
+
+        let config = AppConfig {
+            filename_template: "{channel}/{title}".to_string(),
+            ..AppConfig::default()
+        };
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_parse_srt_segments() {
+        let srt = "1\n00:00:01,000 --> 00:00:04,500\nHello there\nfriend\n\n2\n00:01:05,250 --> 00:01:07,000\nSecond line\n";
+        let segments = parse_subtitles(srt, SubtitleFormat::Srt);
+
+        assert_eq!(segments.len(), 2);
+        assert_eq!(segments[0].start, 1.0);
+        assert_eq!(segments[0].end, 4.5);
+        assert_eq!(segments[0].text, "Hello there friend");
+        assert_eq!(segments[1].start, 65.25);
+        assert_eq!(segments[1].text, "Second line");
+    }
+
+    #[test]
+    fn test_parse_vtt_cues() {
+        let vtt = "WEBVTT\nKind: captions\nLanguage: en\n\nNOTE generated by yt-dlp\n\nintro\n00:00:01.000 --> 00:00:03.000 align:start position:0%\nhello there\n\n00:03.000 --> 00:05.500\nhello there\ngeneral kenobi\n";
+        assert_eq!(
+            SubtitleFormat::detect(vtt, Some(std::path::Path::new("a.srt"))),
+            SubtitleFormat::Vtt
+        );
+        assert_eq!(
+            SubtitleFormat::detect("1\n", Some(std::path::Path::new("a.vtt"))),
+            SubtitleFormat::Vtt
+        );
+        assert_eq!(SubtitleFormat::detect("1\n", None), SubtitleFormat::Srt);
+
+        let cues = parse_subtitles(vtt, SubtitleFormat::Vtt);
+        assert_eq!(cues.len(), 2);
+        assert_eq!(cues[0].start, 1.0);
+        assert_eq!(cues[0].settings.as_deref(), Some("align:start position:0%"));
+        assert_eq!(cues[1].start, 3.0);
+        assert_eq!(cues[1].end, 5.5);
+        // The rolled-over line is not repeated
+        assert_eq!(cues[1].text, "general kenobi");
+        assert_eq!(vtt_to_plain_text(vtt), "hello there general kenobi");
+    }
+
+    #[test]
+    fn test_strip_subtitle_markup() {
+        let srt = "1\n00:00:01,000 --> 00:00:04,000\n{\\an8}<i>Hello</i> <font color=\"#ffff00\">there</font>\n<b>x < y</b>\n";
+        let cues = parse_subtitles(srt, SubtitleFormat::Srt);
+        assert_eq!(cues[0].text, "Hello there x < y");
+
+        // YouTube auto-captions: word timing spans, then the plain rolled-over line
+        let vtt = "WEBVTT\n\n00:00:00.000 --> 00:00:02.000 align:start position:0%\nwe<00:00:00.480><c> are</c><00:00:00.960><c> live</c>\n\n00:00:02.000 --> 00:00:04.000\nwe are live\n>> <v Roger>and welcome</v>\n";
+        let cues = parse_subtitles(vtt, SubtitleFormat::Vtt);
+        assert_eq!(cues.len(), 2);
+        assert_eq!(cues[0].text, "we are live");
+        assert_eq!(cues[1].text, "and welcome");
+    }
+
+    #[test]
+    fn test_render_segments() {
+        let segments = vec![TranscriptSegment {
+            start: 3661.5,
+            end: 3663.0,
+            text: "Hello".to_string(),
+            speaker: None,
+            avg_logprob: None,
+        }];
+
+        assert_eq!(
+            render_segments(&segments, RawFormat::Srt),
+            "1\n01:01:01,500 --> 01:01:03,000\nHello\n\n"
+        );
+        assert_eq!(
+            render_segments(&segments, RawFormat::Vtt),
+            "WEBVTT\n\n01:01:01.500 --> 01:01:03.000\nHello\n\n"
+        );
+        assert_eq!(render_segments(&segments, RawFormat::Txt), "Hello\n");
+        assert_eq!(
+            parse_srt_segments(&render_segments(&segments, RawFormat::Srt)),
+            segments
+        );
+    }
+
+    #[test]
+    fn test_parse_ytdlp_progress() {
+        assert_eq!(
+            parse_ytdlp_progress("[download]  42.5% of    3.45MiB at    1.23MiB/s ETA 00:02"),
+            Some(42.5)
+        );
+        assert_eq!(
+            parse_ytdlp_progress("[download] 100% of 3.45MiB"),
+            Some(100.0)
+        );
+        assert_eq!(
+            parse_ytdlp_progress("[download] Destination: abc_audio.webm"),
+            None
+        );
+        assert_eq!(
+            parse_ytdlp_progress("[youtube] abc: Downloading webpage"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_parse_openai_segments() {
+        let response = serde_json::json!({
+            "text": "Hello there. General Kenobi.",
+            "segments": [
+                {"start": 0.0, "end": 1.5, "text": " Hello there."},
+                {"start": 1.5, "end": 3.0, "text": " General Kenobi."}
+            ]
+        });
+        let segments = parse_openai_segments(&response, 1200.0);
+        assert_eq!(segments.len(), 2);
+        assert_eq!(segments[0].start, 1200.0);
+        assert_eq!(segments[1].end, 1203.0);
+        assert_eq!(segments[1].text, "General Kenobi.");
+
+        // Plain text responses still produce a single segment
+        let response = serde_json::json!({"text": " Just text ", "duration": 4.0});
+        let segments = parse_openai_segments(&response, 0.0);
+        assert_eq!(segments.len(), 1);
+        assert_eq!(segments[0].text, "Just text");
+        assert_eq!(segments[0].end, 4.0);
+
+        assert!(parse_openai_segments(&serde_json::json!({}), 0.0).is_empty());
+    }
+
+    #[test]
+    fn test_parse_deepgram_words() {
+        let alternative = serde_json::json!({
+            "transcript": "hello there how are you",
+            "words": [
+                {"word": "hello", "start": 0.1, "end": 0.4, "punctuated_word": "Hello"},
+                {"word": "there", "start": 0.4, "end": 0.8, "punctuated_word": "there."},
+                {"word": "how", "start": 1.2, "end": 1.4, "punctuated_word": "How"},
+                {"word": "are", "start": 1.4, "end": 1.5, "punctuated_word": "are"},
+                {"word": "you", "start": 1.5, "end": 1.9, "punctuated_word": "you?"}
+            ]
+        });
+        let segments = parse_deepgram_words(&alternative);
+        assert_eq!(
+            segments,
+            vec![
+                TranscriptSegment {
+                    start: 0.1,
+                    end: 0.8,
+                    text: "Hello there.".to_string(),
+                    speaker: None,
+                    avg_logprob: None,
+                },
+                TranscriptSegment {
+                    start: 1.2,
+                    end: 1.9,
+                    text: "How are you?".to_string(),
+                    speaker: None,
+                    avg_logprob: None,
+                },
+            ]
+        );
+
+        assert!(parse_deepgram_words(&serde_json::json!({})).is_empty());
+    }
+
+    #[test]
+    fn test_speaker_blocks() {
+        let segment = |start: f64, end: f64, text: &str| TranscriptSegment {
+            start,
+            end,
+            text: text.to_string(),
+            speaker: None,
+            avg_logprob: None,
+        };
+        let mut segments = vec![
+            segment(0.0, 2.0, "so what brings you here"),
+            segment(4.5, 6.0, "i wanted to talk about rust"),
+            segment(6.2, 8.0, "it is a great language"),
+            segment(11.0, 12.0, "agreed"),
+        ];
+
+        assign_speakers_by_pauses(&mut segments);
+        let speakers: Vec<_> = segments.iter().map(|s| s.speaker).collect();
+        assert_eq!(speakers, vec![Some(0), Some(1), Some(1), Some(0)]);
+
+        assert_eq!(
+            format_speaker_blocks(&segments, 4),
+            "**Speaker 1:** So what brings you here.\n\n\
+             **Speaker 2:** I wanted to talk about rust it is a great language.\n\n\
+             **Speaker 1:** Agreed."
+        );
+    }
+
+    #[test]
+    fn test_parse_video_metadata() {
+        let json = serde_json::json!({
+            "title": "Rust in 100 Seconds",
+            "uploader": "Fireship",
+            "duration": 149,
+            "upload_date": "20210920",
+            "language": "en-US",
+            "description": "  Chapters:\n0:00 Intro\n\n\nLinks below!\n"
+        });
+        let metadata = parse_video_metadata(&json, "5C_HPTJg5ek");
+        assert_eq!(metadata.title, "Rust in 100 Seconds");
+        assert_eq!(metadata.channel.as_deref(), Some("Fireship"));
+        assert_eq!(metadata.duration_seconds, Some(149));
+        assert_eq!(metadata.upload_date.as_deref(), Some("2021-09-20"));
+        assert_eq!(metadata.url, "https://www.youtube.com/watch?v=5C_HPTJg5ek");
+
+        assert_eq!(metadata.has_audio, None);
+        assert_eq!(metadata.language.as_deref(), Some("en"));
+        assert_eq!(
+            format_description(metadata.description.as_deref().unwrap()),
+            "## Description\n\nChapters:  \n0:00 Intro\n\nLinks below\\!\n\n"
+        );
+
+        let metadata = parse_video_metadata(&serde_json::json!({}), "abc");
+        assert_eq!(metadata.title, "Unknown Title");
+        assert_eq!(metadata.duration, None);
+        assert_eq!(metadata.language, None);
+        assert_eq!(metadata.description, None);
+
+        let native = serde_json::json!({
+            "description": "0:00 Intro\n1:00 Outro",
+            "chapters": [{"start_time": 0.0, "end_time": 42.0, "title": "Opening"}]
+        });
+        assert_eq!(
+            parse_video_metadata(&native, "abc").chapters,
+            vec![Chapter {
+                start: 0.0,
+                title: "Opening".to_string()
+            }]
+        );
+
+        let slideshow = serde_json::json!({
+            "formats": [{"acodec": "none", "vcodec": "avc1"}, {"vcodec": "vp9"}]
+        });
+        assert_eq!(
+            parse_video_metadata(&slideshow, "abc").has_audio,
+            Some(false)
+        );
+        let talk = serde_json::json!({
+            "formats": [{"acodec": "none"}, {"acodec": "opus", "vcodec": "none"}]
+        });
+        assert_eq!(parse_video_metadata(&talk, "abc").has_audio, Some(true));
+    }
+
+    #[test]
+    fn test_parse_description_chapters() {
+        let description = "Thanks for watching!\n\nChapters:\n0:00 Intro\n02:15 - Setting up\n(12:30) Testing | part 1\n1:02:03: Wrap-up\nDeploy \u{2013} 1:10:00\n\nFollow me at 5:00pm";
+        let chapters = parse_description_chapters(description);
+        let summary: Vec<(f64, &str)> = chapters
+            .iter()
+            .map(|chapter| (chapter.start, chapter.title.as_str()))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                (0.0, "Intro"),
+                (135.0, "Setting up"),
+                (750.0, "Testing | part 1"),
+                (3723.0, "Wrap-up"),
+                (4200.0, "Deploy"),
+            ]
+        );
+        assert_eq!(chapters[3].timestamp(), "01:02:03");
+
+        // Timestamps that do not start at 0:00 are references, not chapters
+        assert!(parse_description_chapters("See 3:15 for the demo\n4:00 the fix").is_empty());
+        assert!(parse_description_chapters("0:00 Only one").is_empty());
+    }
+
+    #[test]
+    fn test_caption_tracks_from_metadata() {
+        let json = serde_json::json!({
+            "subtitles": {
+                "de": [{"ext": "vtt", "url": "https://example.com/de"}],
+                "live_chat": [{"ext": "json", "url": "https://example.com/chat"}]
+            },
+            "automatic_captions": {
+                "en-orig": [{"ext": "vtt", "url": "https://example.com/timedtext?lang=en"}],
+                "fr": [{"ext": "vtt", "url": "https://example.com/timedtext?lang=en&tlang=fr"}]
+            }
+        });
+        let tracks = CaptionTracks::from_metadata_json(&json);
+        assert_eq!(tracks.manual, vec!["de".to_string()]);
+        assert_eq!(tracks.automatic, vec!["en-orig".to_string()]);
+
+        assert!(tracks.has_language("en"));
+        assert!(tracks.has_language("DE"));
+        assert!(!tracks.has_language("fr"));
+
+        let empty = CaptionTracks::from_metadata_json(&serde_json::json!({}));
+        assert_eq!(empty, CaptionTracks::default());
+        assert!(!empty.has_language("en"));
+    }
+
+    #[test]
+    fn test_ensure_transcript_not_empty() {
+        let captions = TranscriptionSource::AutoCaptions {
+            language: "en".to_string(),
+        };
+        assert!(ensure_transcript_not_empty("Hello.", &captions).is_ok());
+
+        let err = ensure_transcript_not_empty("  \n ", &captions).unwrap_err();
+        assert!(err.to_string().contains("caption file was empty"));
+
+        let whisper = TranscriptionSource::LocalWhisper {
+            model: "base.en".to_string(),
+        };
+        let err = ensure_transcript_not_empty("", &whisper).unwrap_err();
+        assert!(err.to_string().contains("no speech detected"));
+    }
+
+    #[test]
+    fn test_caption_paragraphs() {
+        let cue = |start: f64, end: f64, text: &str| TranscriptSegment {
+            start,
+            end,
+            text: text.to_string(),
+            speaker: None,
+            avg_logprob: None,
+        };
+        let segments = vec![
+            cue(0.0, 2.0, "Welcome back to the show."),
+            cue(2.1, 4.0, "Today we talk about"),
+            cue(4.1, 6.0, "compilers."),
+            cue(9.0, 11.0, "Let's start with parsing."),
+            cue(11.5, 13.0, ">> Sounds good."),
+        ];
+
+        assert_eq!(
+            caption_paragraphs(&segments),
+            "Welcome back to the show. Today we talk about compilers.\n\n\
+             Let's start with parsing.\n\n\
+             >> Sounds good."
+        );
+
+        let tracks = CaptionTracks {
+            manual: vec!["en-GB".to_string()],
+            automatic: vec!["de".to_string()],
+        };
+        assert!(tracks.has_manual("en"));
+        assert!(!tracks.has_manual("de"));
+    }
+
+    #[test]
+    fn test_transcription_source() {
+        assert_eq!(
+            TranscriptionSource::speech_to_text(&SttBackend::Local, Some("en")).to_string(),
+            "whisper (base.en)"
+        );
+        assert_eq!(
+            TranscriptionSource::speech_to_text(&SttBackend::Local, Some("de")).to_string(),
+            "whisper (base)"
+        );
+        assert_eq!(
+            TranscriptionSource::speech_to_text(&SttBackend::Deepgram, Some("de")).to_string(),
+            "remote-stt (deepgram)"
+        );
+        let captions = TranscriptionSource::AutoCaptions {
+            language: "en".to_string(),
+        };
+        assert_eq!(captions.to_string(), "auto-captions (en)");
+        assert!(captions.is_captions());
+    }
+
+    #[test]
+    fn test_metadata_footer() {
+        let metadata = VideoMetadata {
+            title: "Intro".to_string(),
+            channel: Some("Rustaceans".to_string()),
+            duration: None,
+            duration_seconds: None,
+            video_id: "abc".to_string(),
+            url: "https://www.youtube.com/watch?v=abc".to_string(),
+            upload_date: None,
+            has_audio: None,
+            language: None,
+            description: None,
+            chapters: Vec::new(),
+        };
+        let extracted_at = chrono::DateTime::parse_from_rfc3339("2024-03-05T10:00:00Z").unwrap();
+
+        let captions = TranscriptionSource::ManualCaptions {
+            language: "en".to_string(),
+        };
+        let whisper = TranscriptionSource::LocalWhisper {
+            model: "base.en".to_string(),
+        };
+        assert_eq!(
+            metadata_footer(&metadata, &captions, &extracted_at),
+            "\n\n---\n\n*Transcribed from [Intro](https://www.youtube.com/watch?v=abc) \
+             by Rustaceans on 2024-03-05. Transcript source: YouTube captions.*\n"
+        );
+        assert!(metadata_footer(&metadata, &whisper, &extracted_at)
+            .contains("speech-to-text (Whisper base.en)"));
+    }
+
+    #[test]
+    fn test_format_plain_text() {
+        let metadata = VideoMetadata {
+            title: "Intro *to* Rust".to_string(),
+            channel: None,
+            duration: None,
+            duration_seconds: None,
+            video_id: "abc".to_string(),
+            url: "https://www.youtube.com/watch?v=abc".to_string(),
+            upload_date: None,
+            has_audio: None,
+            language: None,
+            description: None,
+            chapters: Vec::new(),
+        };
+        let transcript = "First sentence. Second sentence. Third sentence.";
+
+        let text = format_plain_text(
+            &metadata,
+            transcript,
+            false,
+            ParagraphLength::Sentences(2),
+            None,
+            false,
+        );
+        assert_eq!(
+            text,
+            "First sentence. Second sentence.\n\nThird sentence.\n"
+        );
+        assert!(!text.contains("---"));
+
+        let titled = format_plain_text(
+            &metadata,
+            transcript,
+            false,
+            ParagraphLength::Sentences(2),
+            None,
+            true,
+        );
+        assert!(titled.starts_with("Intro *to* Rust\n\nFirst sentence."));
+    }
+
+    #[test]
+    fn test_clean_llm_output_fenced() {
+        let output = "```markdown\n# Intro\n\nFirst paragraph.\n\nSecond paragraph.\n```\n";
+        assert_eq!(
+            clean_llm_output(output),
+            "# Intro\n\nFirst paragraph.\n\nSecond paragraph."
+        );
+    }
+
+    #[test]
+    fn test_clean_llm_output_preamble() {
+        let output = "Here is the formatted markdown:\n\n```\nFirst paragraph.\n\n\n\n\nSecond paragraph.  \n```";
+        assert_eq!(
+            clean_llm_output(output),
+            "First paragraph.\n\n\nSecond paragraph."
+        );
 
-    result
-}
+        // Markdown line breaks survive
+        let output = "Speaker one  \nSpeaker two\n\n\nNext";
+        assert_eq!(clean_llm_output(output), output);
 
-/// Capitalize first letter of a string
-fn capitalize_first_letter(s: &str) -> String {
-    let mut chars = s.chars();
-    match chars.next() {
-        None => String::new(),
-        Some(first) => first.to_uppercase().collect::<String>() + chars.as_str(),
+        // Ordinary first lines are kept
+        let output = "Okay, so today we talk about Rust.\n\nIt is fast.";
+        assert_eq!(clean_llm_output(output), output);
+        let output = "Agenda:\n\n- Ownership";
+        assert_eq!(clean_llm_output(output), output);
     }
-}
 
-/// Determine if punctuation should be added
-fn should_add_punctuation(word: &str, index: usize, total_words: usize) -> bool {
-    // Don't add punctuation if it already ends with one
-    if word.ends_with(['.', '!', '?']) {
-        return false;
+    #[test]
+    fn test_model_listed() {
+        let listing = serde_json::json!({
+            "data": [{"id": "gpt-4o-mini"}, {"id": "whisper-1"}]
+        });
+        assert!(model_listed(&listing, "gpt-4o-mini"));
+        assert!(!model_listed(&listing, "gpt-4"));
+        assert!(!model_listed(&serde_json::json!({}), "gpt-4o-mini"));
+
+        assert_eq!(listed_model_ids(&listing), vec!["gpt-4o-mini", "whisper-1"]);
+        assert!(listed_model_ids(&serde_json::json!({})).is_empty());
     }
 
-    // Add punctuation at natural sentence boundaries
-    let is_long_phrase = index > 0 && index.is_multiple_of(12); // Every ~12 words
-    let is_near_end = index == total_words - 1;
+    #[test]
+    fn test_same_ollama_model() {
+        assert!(same_ollama_model("llama3:latest", "llama3"));
+        assert!(same_ollama_model("llama3", "llama3:latest"));
+        assert!(same_ollama_model("llama3:8b", "llama3:8b"));
+        assert!(!same_ollama_model("llama3:8b", "llama3"));
+        assert!(!same_ollama_model("llama3.1:latest", "llama3"));
+        assert!(!same_ollama_model("codellama:latest", "llama"));
+    }
 
-    is_long_phrase || is_near_end
-}
+    #[test]
+    fn test_parse_ollama_tags() {
+        let json = serde_json::json!({
+            "models": [
+                {"name": "llama3.2:3b", "size": 2019393189u64, "digest": "a80c4f17acd55265feec403c7aef86be0c25983ab279d83f3bcd3abbcb5b8b72"},
+                {"name": "mistral-nemo:12b"}
+            ]
+        });
+        let models = parse_ollama_tags(&json).unwrap();
+
+        assert_eq!(models.len(), 2);
+        assert_eq!(models[0].size_human().as_deref(), Some("1.9 GB"));
+        assert_eq!(models[0].short_digest(), Some("a80c4f17acd5"));
+        assert_eq!(models[1].size, None);
+        assert_eq!(models[1].short_digest(), None);
+        assert!(parse_ollama_tags(&serde_json::json!({})).is_err());
+    }
 
-/// Escape Markdown special characters
-fn escape_markdown(text: &str) -> String {
-    text.replace('*', "\\*")
-        .replace('_', "\\_")
-        .replace('`', "\\`")
-        .replace('[', "\\[")
-        .replace(']', "\\]")
-        .replace('(', "\\(")
-        .replace(')', "\\)")
-        .replace('#', "\\#")
-        .replace('+', "\\+")
-        .replace('-', "\\-")
-        .replace('.', "\\.")
-        .replace('!', "\\!")
-}
+    #[test]
+    fn test_line_buffer() {
+        let mut lines = LineBuffer::default();
+
+        assert!(lines.push(b"{\"status\":\"pulling").is_empty());
+        assert_eq!(
+            lines.push(b" manifest\"}\n{\"status\":\"verifying\"}\n{\"status\""),
+            vec![
+                b"{\"status\":\"pulling manifest\"}".to_vec(),
+                b"{\"status\":\"verifying\"}".to_vec()
+            ]
+        );
+        assert_eq!(lines.finish(), Some(b"{\"status\"".to_vec()));
+        assert_eq!(lines.finish(), None);
+    }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    #[test]
+    fn test_load_env_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("keys.env");
+        std::fs::write(&path, "Y2MD_ENVFILETEST_API_KEY=from-dotenv\n").unwrap();
+
+        let config_path = dir.path().join("config.toml");
+        assert_eq!(
+            load_env_file(Some(&path), &config_path).unwrap(),
+            Some(path.clone())
+        );
+        assert_eq!(
+            CredentialManager::new().get_key("envfiletest").unwrap(),
+            Some("from-dotenv".to_string())
+        );
+        assert!(load_env_file(Some(&dir.path().join("missing.env")), &config_path).is_err());
+    }
 
     #[test]
-    fn test_extract_video_id_youtube_com() {
-        let url = "https://www.youtube.com/watch?v=dQw4w9WgXcQ";
-        assert_eq!(extract_video_id(url).unwrap(), "dQw4w9WgXcQ");
+    fn test_credentials_file_backend() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("credentials.json");
+        let manager = CredentialManager::with_file(path.clone());
+
+        assert_eq!(manager.get_key("filebackendtest").unwrap(), None);
+        manager.set_key("filebackendtest", "secret").unwrap();
+        assert_eq!(
+            manager.get_key("filebackendtest").unwrap(),
+            Some("secret".to_string())
+        );
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
     }
 
     #[test]
-    fn test_extract_video_id_youtu_be() {
-        let url = "https://youtu.be/dQw4w9WgXcQ";
-        assert_eq!(extract_video_id(url).unwrap(), "dQw4w9WgXcQ");
+    fn test_keyring_error_guidance() {
+        let message = Y2mdError::Keyring("no secret service".to_string()).to_string();
+        assert!(message.starts_with("System keyring unavailable: no secret service"));
+        assert!(message.contains("Y2MD_<PROVIDER>_API_KEY"));
+        assert!(message.contains("credential_backend = \"file\""));
     }
 
     #[test]
-    fn test_extract_video_id_shorts() {
-        let url = "https://www.youtube.com/shorts/abc123def45";
-        assert_eq!(extract_video_id(url).unwrap(), "abc123def45");
+    fn test_combine_markdown() {
+        let output = |title: &str, markdown: &str| ProcessOutput {
+            metadata: VideoMetadata {
+                title: title.to_string(),
+                channel: Some("Course".to_string()),
+                duration: Some("10:00".to_string()),
+                duration_seconds: Some(600),
+                video_id: "abc".to_string(),
+                url: "https://www.youtube.com/watch?v=abc".to_string(),
+                upload_date: None,
+                has_audio: None,
+                language: None,
+                description: None,
+                chapters: Vec::new(),
+            },
+            markdown: markdown.to_string(),
+            transcript: String::new(),
+            raw_transcript: String::new(),
+            segments: Vec::new(),
+            source: TranscriptionSource::ManualCaptions {
+                language: "en".to_string(),
+            },
+            output_dir: ".".to_string(),
+            llm_model: None,
+            timings: StageTimings::default(),
+            language: "en".to_string(),
+            detected_language: None,
+        };
+        let outputs = vec![
+            output(
+                "Lesson 1: Basics",
+                "---\ntitle: \"Lesson 1\"\n---\n\n# Lesson 1: Basics\n\nFirst body.",
+            ),
+            output("Lesson 1: Basics", "# Lesson 1: Basics\n\nSecond body."),
+        ];
+
+        let combined = combine_markdown(&outputs);
+        assert!(combined.starts_with(
+            "# Contents\n\n1. [Lesson 1: Basics](#lesson-1-basics)\n\
+             2. [Lesson 1: Basics](#lesson-1-basics-1)\n"
+        ));
+        assert!(combined.contains(
+            "\n# Lesson 1: Basics\n\n*Course · 10:00 · https://www.youtube.com/watch?v=abc*\n\nFirst body.\n"
+        ));
+        assert!(combined.ends_with("Second body.\n"));
+        assert!(!combined.contains("title: "));
     }
 
     #[test]
-    fn test_extract_video_id_with_params() {
-        let url = "https://www.youtube.com/watch?v=dQw4w9WgXcQ&t=42";
-        assert_eq!(extract_video_id(url).unwrap(), "dQw4w9WgXcQ");
+    fn test_parse_duration_arg() {
+        assert_eq!(parse_duration_arg("90"), Ok(90));
+        assert_eq!(parse_duration_arg("45m"), Ok(2700));
+        assert_eq!(parse_duration_arg("3h"), Ok(10800));
+        assert_eq!(parse_duration_arg("1h30m"), Ok(5400));
+        assert_eq!(parse_duration_arg("2m30s"), Ok(150));
+        assert!(parse_duration_arg("").is_err());
+        assert!(parse_duration_arg("3d").is_err());
+        assert!(parse_duration_arg("1h30").is_err());
+        assert!(parse_duration_arg("9999999999999999h").is_err());
+        assert!(parse_duration_arg("18446744073709551615s1s").is_err());
     }
 
     #[test]
-    fn test_extract_video_id_direct() {
-        let url = "dQw4w9WgXcQ";
-        assert_eq!(extract_video_id(url).unwrap(), "dQw4w9WgXcQ");
+    fn test_check_duration_limits() {
+        let metadata = VideoMetadata {
+            title: "Test".to_string(),
+            channel: None,
+            duration: Some("05:00".to_string()),
+            duration_seconds: Some(300),
+            video_id: "dQw4w9WgXcQ".to_string(),
+            url: "https://www.youtube.com/watch?v=dQw4w9WgXcQ".to_string(),
+            upload_date: None,
+            has_audio: None,
+            language: None,
+            description: None,
+            chapters: Vec::new(),
+        };
+
+        assert!(check_duration_limits(&metadata, Some(60), Some(3600)).is_ok());
+        assert!(matches!(
+            check_duration_limits(&metadata, Some(600), None),
+            Err(Y2mdError::Skipped(_))
+        ));
+        assert!(matches!(
+            check_duration_limits(&metadata, None, Some(120)),
+            Err(Y2mdError::Skipped(_))
+        ));
     }
 
     #[test]
-    fn test_validate_youtube_url() {
-        let url = "https://www.youtube.com/watch?v=dQw4w9WgXcQ";
-        assert_eq!(validate_youtube_url(url).unwrap(), "dQw4w9WgXcQ");
+    fn test_find_existing_transcript() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(
+            dir.path().join("2024-01-01_dQw4w9WgXcQ_Old_Title.md"),
+            "---\ntitle: \"Old Title\"\nvideo_id: \"dQw4w9WgXcQ\"\n---\n\nHello\n",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.path().join("notes.md"),
+            "# Notes\n\nvideo_id: \"abc123def45\"\n",
+        )
+        .unwrap();
+
+        let markdown = OutputFormat::Markdown;
+        assert_eq!(
+            find_existing_transcript(dir.path(), "dQw4w9WgXcQ", markdown),
+            Some(dir.path().join("2024-01-01_dQw4w9WgXcQ_Old_Title.md"))
+        );
+        // Only front matter counts
+        assert_eq!(
+            find_existing_transcript(dir.path(), "abc123def45", markdown),
+            None
+        );
+        assert_eq!(
+            find_existing_transcript(&dir.path().join("missing"), "dQw4w9WgXcQ", markdown),
+            None
+        );
     }
 
     #[test]
-    fn test_invalid_url() {
-        let url = "https://example.com";
-        assert!(extract_video_id(url).is_err());
+    fn test_find_existing_transcript_txt() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(
+            dir.path().join("2024-01-01_dQw4w9WgXcQ_Old_Title.md"),
+            "---\nvideo_id: \"dQw4w9WgXcQ\"\n---\n\nHello\n",
+        )
+        .unwrap();
+
+        // A markdown transcript does not stop a plain-text run
+        assert_eq!(
+            find_existing_transcript(dir.path(), "dQw4w9WgXcQ", OutputFormat::Txt),
+            None
+        );
+
+        std::fs::write(
+            dir.path().join("2024-01-01_dQw4w9WgXcQ_Old_Title.txt"),
+            "Hello\n",
+        )
+        .unwrap();
+        assert_eq!(
+            find_existing_transcript(dir.path(), "dQw4w9WgXcQ", OutputFormat::Txt),
+            Some(dir.path().join("2024-01-01_dQw4w9WgXcQ_Old_Title.txt"))
+        );
+        assert_eq!(
+            find_existing_transcript(dir.path(), "abc123def45", OutputFormat::Txt),
+            None
+        );
     }
 
     #[test]
@@ -1949,6 +9742,10 @@ mod tests {
         let transcript = "hello world how are you";
         let cleaned = clean_transcript(transcript);
         assert_eq!(cleaned, "Hello world how are you.");
+        assert_eq!(
+            clean_transcript("is it on? yes it is! good"),
+            "Is it on? Yes it is! Good."
+        );
     }
 
     #[test]
@@ -1961,6 +9758,47 @@ mod tests {
         assert!(formatted.contains("Fifth."));
     }
 
+    #[test]
+    fn test_segment_sentences() {
+        let texts = |text: &str| -> Vec<String> {
+            segment_sentences(text)
+                .into_iter()
+                .map(|span| span.text)
+                .collect()
+        };
+
+        // Abbreviations, initials and decimals do not end a sentence
+        assert_eq!(
+            texts("Dr. Smith met J. Doe at 3.14 p.m. today. Then so did I. Done"),
+            vec![
+                "Dr. Smith met J. Doe at 3.14 p.m. today.",
+                "Then so did I.",
+                "Done"
+            ]
+        );
+        // Closing quotes stay with their sentence
+        assert_eq!(
+            texts("He said \"stop.\" She ran?! ok"),
+            vec!["He said \"stop.\"", "She ran?!", "ok"]
+        );
+        // An ellipsis only ends a sentence before a capitalized word
+        assert_eq!(
+            texts("well... i think so\u{2026} Then no."),
+            vec!["well... i think so\u{2026}", "Then no."]
+        );
+
+        let text = "  one.  two ";
+        let spans = segment_sentences(text);
+        assert_eq!(spans[0].byte_range, 2..6);
+        assert_eq!(&text[spans[1].byte_range.clone()], "two");
+        assert!(segment_sentences("   ").is_empty());
+
+        assert_eq!(
+            format_paragraphs("first. second? third", 2),
+            "First. Second?\n\nThird."
+        );
+    }
+
     #[test]
     fn test_formatting_pipeline() {
         // Test the complete formatting pipeline
@@ -2020,6 +9858,494 @@ mod tests {
         // They should be different due to different paragraph lengths
         assert_ne!(enhanced_short, enhanced_long);
     }
+
+    #[test]
+    fn test_zero_paragraph_length() {
+        assert!(parse_paragraph_length("0").is_err());
+        assert!(parse_paragraph_length("-1").is_err());
+        assert_eq!(parse_paragraph_length("3"), Ok(3));
+
+        let config = AppConfig {
+            paragraph_length: ParagraphLength::Sentences(0),
+            ..AppConfig::default()
+        };
+        assert!(config.validate().is_err());
+        assert!(AppConfig::default().validate().is_ok());
+
+        // Library callers passing zero get one sentence per paragraph
+        let transcript = "first sentence. second sentence. third sentence.";
+        assert_eq!(
+            format_transcript(transcript, true, 0),
+            format_transcript(transcript, true, 1)
+        );
+    }
+
+    #[test]
+    fn test_compact_paragraph_length() {
+        // Config files written before the option existed get the default of 8
+        let saved = toml::to_string(&AppConfig {
+            compact: true,
+            paragraph_length: ParagraphLength::Sentences(3),
+            ..AppConfig::default()
+        })
+        .unwrap();
+        let legacy: String = saved
+            .lines()
+            .filter(|line| !line.starts_with("compact_paragraph_length"))
+            .map(|line| format!("{}\n", line))
+            .collect();
+        let config: AppConfig = toml::from_str(&legacy).unwrap();
+
+        assert_eq!(
+            config.compact_paragraph_length,
+            ParagraphLength::Sentences(8)
+        );
+        assert_eq!(
+            config.paragraph_length_for(false),
+            ParagraphLength::Sentences(3)
+        );
+        assert_eq!(
+            config.paragraph_length_for(true),
+            ParagraphLength::Sentences(8)
+        );
+        assert_eq!(
+            ProcessOptions::from_config(&config).paragraph_length,
+            ParagraphLength::Sentences(8)
+        );
+
+        let config = AppConfig {
+            compact_paragraph_length: ParagraphLength::Sentences(0),
+            ..AppConfig::default()
+        };
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_load_config_from_path() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("work.toml");
+        let config = AppConfig {
+            output_dir: "~/work-notes".to_string(),
+            ..AppConfig::default()
+        };
+        std::fs::write(&path, toml::to_string(&config).unwrap()).unwrap();
+
+        let loaded = AppConfig::load_from(&path).unwrap();
+        assert_eq!(loaded.output_dir, "~/work-notes");
+        assert!(AppConfig::load_from(&dir.path().join("missing.toml")).is_err());
+    }
+
+    #[test]
+    fn test_load_config_normalizes_default_language() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        for (language, expected) in [("pt-BR", "pt"), ("EN_us", "en"), ("Auto", "auto")] {
+            let config = AppConfig {
+                default_language: language.to_string(),
+                ..AppConfig::default()
+            };
+            std::fs::write(&path, toml::to_string(&config).unwrap()).unwrap();
+
+            let loaded = AppConfig::load_from(&path).unwrap();
+            assert_eq!(loaded.default_language, expected);
+            assert_eq!(
+                ProcessOptions::from_config(&loaded).language.as_deref(),
+                Some(expected)
+            );
+        }
+    }
+
+    #[test]
+    fn test_migrate_v1_config() {
+        let v1 = r#"
+output_dir = "~/notes"
+default_language = "es"
+prefer_captions = true
+timestamps = false
+compact = false
+
+[llm]
+enabled = true
+provider = "openai"
+model = "gpt-4o-mini"
+endpoint = "https://api.openai.com/v1"
+
+[advanced]
+whisper_model = "base"
+whisper_threads = 8
+cache_audio = true
+"#;
+        let mut table: toml::Table = toml::from_str(v1).unwrap();
+        assert!(toml::from_str::<AppConfig>(v1).is_err());
+        assert!(migrate_config(&mut table));
+
+        let config: AppConfig = toml::from_str(&toml::to_string(&table).unwrap()).unwrap();
+        assert_eq!(config.version, CONFIG_VERSION);
+        assert_eq!(config.output_dir, "~/notes");
+        assert_eq!(config.default_language, "es");
+        assert!(config.llm.enabled);
+        assert_eq!(config.llm.provider, LlmProviderType::OpenAI);
+        assert_eq!(config.llm.openai.model, "gpt-4o-mini");
+        assert_eq!(config.llm.openai.endpoint, "https://api.openai.com/v1");
+        assert_eq!(
+            config.llm.local.model,
+            LocalLlmConfig::default().model,
+            "other providers keep their defaults"
+        );
+        assert_eq!(config.advanced.whisper_threads, 8);
+        assert!(config.validate().is_ok());
+
+        // A current config is left alone
+        let mut table: toml::Table =
+            toml::from_str(&toml::to_string(&AppConfig::default()).unwrap()).unwrap();
+        assert!(!migrate_config(&mut table));
+
+        // So is one without a version that does not use the old [llm] layout
+        let mut table: toml::Table =
+            toml::from_str("output_dir = \"~/notes\"\n\n[llm]\nprovider = \"openai\"\n").unwrap();
+        assert!(!migrate_config(&mut table));
+        assert!(!table.contains_key("version"));
+    }
+
+    #[test]
+    fn test_read_config_never_writes() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        let v1 = "# my notes\n[llm]\nprovider = \"openai\"\nmodel = \"gpt-4o-mini\"\n";
+        std::fs::write(&path, v1).unwrap();
+
+        let (config, migrated) = AppConfig::read_file(&path).unwrap();
+        assert!(migrated);
+        assert_eq!(config.llm.openai.model, "gpt-4o-mini");
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), v1);
+        assert!(!path.with_extension("toml.bak").exists());
+
+        // Loading upgrades the file it was given, not the default config
+        let config = AppConfig::load_from(&path).unwrap();
+        assert_eq!(config.path.as_deref(), Some(path.as_path()));
+        assert_eq!(
+            std::fs::read_to_string(path.with_extension("toml.bak")).unwrap(),
+            v1
+        );
+        let (upgraded, migrated) = AppConfig::read_file(&path).unwrap();
+        assert!(!migrated);
+        assert_eq!(upgraded.llm.openai.model, "gpt-4o-mini");
+        assert_eq!(
+            CredentialManager::for_config(&upgraded).file_path(),
+            Some(dir.path().join("credentials.json").as_path())
+        );
+
+        // Errors in an upgraded file point at the line as written
+        std::fs::write(
+            &path,
+            "# my notes\n\n[llm]\nmodel = \"gpt-4o-mini\"\n\n[advanced]\nwhisper_threads = \"eight\"\n",
+        )
+        .unwrap();
+        let message = AppConfig::read_file(&path).unwrap_err().to_string();
+        assert!(
+            message.contains("`advanced.whisper_threads`"),
+            "{}",
+            message
+        );
+        assert!(message.contains("line 7"), "{}", message);
+    }
+
+    #[test]
+    fn test_describe_config_error() {
+        let describe = |content: &str| {
+            let error = toml::from_str::<AppConfig>(content).unwrap_err();
+            describe_config_error(content, content, &error)
+        };
+
+        let message = describe("output_dir = \".\"\nparagraph_length = \"four\"\n");
+        assert!(message.contains("`paragraph_length`"), "{}", message);
+        assert!(message.contains("line 2"), "{}", message);
+
+        let message =
+            describe("[llm]\nprovider = \"local\"\n\n[llm.anthropic]\nmax_tokens = \"lots\"\n");
+        assert!(
+            message.contains("`llm.anthropic.max_tokens`"),
+            "{}",
+            message
+        );
+        assert!(message.contains("line 5"), "{}", message);
+
+        let message = describe("timestamps = yes\n");
+        assert!(message.contains("line 1"), "{}", message);
+    }
+
+    #[test]
+    fn test_normalize_language_code() {
+        assert_eq!(normalize_language_code("en"), Ok("en".to_string()));
+        assert_eq!(normalize_language_code("en-US"), Ok("en".to_string()));
+        assert_eq!(normalize_language_code("PT_br"), Ok("pt".to_string()));
+        assert_eq!(normalize_language_code("sw"), Ok("sw".to_string()));
+        assert!(normalize_language_code("english").is_err());
+        assert!(normalize_language_code("xx").is_err());
+        assert!(normalize_language_code("").is_err());
+    }
+
+    #[test]
+    fn test_parse_output_name() {
+        assert_eq!(parse_output_name("notes.md"), Ok("notes.md".to_string()));
+        assert_eq!(parse_output_name(" talk "), Ok("talk".to_string()));
+        assert!(parse_output_name("").is_err());
+        assert!(parse_output_name("..").is_err());
+        assert!(parse_output_name("sub/notes.md").is_err());
+        assert!(parse_output_name("sub\\notes.md").is_err());
+    }
+
+    #[test]
+    fn test_paragraph_length_auto() {
+        assert_eq!("auto".parse(), Ok(ParagraphLength::Auto));
+        assert_eq!("6".parse(), Ok(ParagraphLength::Sentences(6)));
+        assert!("0".parse::<ParagraphLength>().is_err());
+
+        let config: AppConfig = toml::from_str(
+            &toml::to_string(&AppConfig::default())
+                .unwrap()
+                .replace("paragraph_length = 4", "paragraph_length = \"auto\""),
+        )
+        .unwrap();
+        assert_eq!(config.paragraph_length, ParagraphLength::Auto);
+
+        // Short sentences are grouped until the word target is reached
+        let text = "one two three. four five. six seven eight. nine";
+        assert_eq!(
+            format_paragraphs_by_words(text, 5),
+            "One two three. Four five.\n\nSix seven eight. Nine."
+        );
+        assert_eq!(format_paragraphs_by_words("", 5), "");
+    }
+
+    /// Start a mock server answering `POST route` with `status` and a JSON body
+    async fn mock_llm_server(
+        route: &str,
+        status: u16,
+        body: serde_json::Value,
+    ) -> wiremock::MockServer {
+        let server = wiremock::MockServer::start().await;
+        wiremock::Mock::given(wiremock::matchers::method("POST"))
+            .and(wiremock::matchers::path(route))
+            .respond_with(wiremock::ResponseTemplate::new(status).set_body_json(body))
+            .mount(&server)
+            .await;
+        server
+    }
+
+    fn llm_error_message<T: std::fmt::Debug>(result: Result<T, Y2mdError>) -> String {
+        match result {
+            Err(Y2mdError::Llm(message)) => message,
+            other => panic!("expected an LLM error, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_openai_provider_responses() {
+        let client = HttpClient::default();
+        let config = |server: &wiremock::MockServer| OpenAiConfig {
+            endpoint: server.uri(),
+            model: "gpt-test".to_string(),
+        };
+
+        let server = wiremock::MockServer::start().await;
+        wiremock::Mock::given(wiremock::matchers::path("/chat/completions"))
+            .and(wiremock::matchers::header("authorization", "Bearer sk-test"))
+            .and(wiremock::matchers::body_partial_json(serde_json::json!({
+                "model": "gpt-test"
+            })))
+            .respond_with(
+                wiremock::ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                    "choices": [{"message": {"role": "assistant", "content": "  # Talk\n\nHello.  "}}],
+                    "usage": {"prompt_tokens": 12, "completion_tokens": 4}
+                })),
+            )
+            .mount(&server)
+            .await;
+        let (formatted, usage) =
+            format_with_openai(&client, "hello", &config(&server), "sk-test", 0.1, None)
+                .await
+                .unwrap();
+        assert_eq!(formatted, "# Talk\n\nHello.");
+        assert_eq!(
+            usage,
+            Some(TokenUsage {
+                input: 12,
+                output: 4
+            })
+        );
+
+        let server = mock_llm_server(
+            "/chat/completions",
+            401,
+            serde_json::json!({"error": {"message": "Incorrect API key"}}),
+        )
+        .await;
+        let message = llm_error_message(
+            format_with_openai(&client, "hello", &config(&server), "bad", 0.1, None).await,
+        );
+        assert!(message.contains("401"), "{}", message);
+
+        let server =
+            mock_llm_server("/chat/completions", 200, serde_json::json!({"choices": []})).await;
+        let message = llm_error_message(
+            format_with_openai(&client, "hello", &config(&server), "sk-test", 0.1, None).await,
+        );
+        assert!(message.contains("Invalid response format"), "{}", message);
+    }
+
+    #[tokio::test]
+    async fn test_anthropic_provider_responses() {
+        let client = HttpClient::default();
+        let config = |server: &wiremock::MockServer| AnthropicConfig {
+            endpoint: server.uri(),
+            model: "claude-test".to_string(),
+            max_tokens: 100,
+        };
+
+        let server = wiremock::MockServer::start().await;
+        wiremock::Mock::given(wiremock::matchers::path("/messages"))
+            .and(wiremock::matchers::header("x-api-key", "sk-ant-test"))
+            .and(wiremock::matchers::body_partial_json(serde_json::json!({
+                "system": "Keep it short"
+            })))
+            .respond_with(
+                wiremock::ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                    "content": [{"type": "text", "text": "# Talk\n\nHello."}],
+                    "stop_reason": "end_turn"
+                })),
+            )
+            .mount(&server)
+            .await;
+        let (formatted, _) = format_with_anthropic(
+            &client,
+            "hello",
+            &config(&server),
+            "sk-ant-test",
+            0.1,
+            Some("Keep it short"),
+        )
+        .await
+        .unwrap();
+        assert_eq!(formatted, "# Talk\n\nHello.");
+
+        // A cut-off response is kept, with a note about the limit
+        let server = mock_llm_server(
+            "/messages",
+            200,
+            serde_json::json!({
+                "content": [{"type": "text", "text": "# Talk\n\nHel"}],
+                "stop_reason": "max_tokens"
+            }),
+        )
+        .await;
+        let (formatted, _) =
+            format_with_anthropic(&client, "hello", &config(&server), "sk-ant-test", 0.1, None)
+                .await
+                .unwrap();
+        assert!(formatted.starts_with("# Talk\n\nHel"));
+        assert!(formatted.contains("max_tokens limit of 100"));
+
+        let server = mock_llm_server(
+            "/messages",
+            400,
+            serde_json::json!({"type": "error", "error": {"message": "prompt is too long"}}),
+        )
+        .await;
+        let message = llm_error_message(
+            format_with_anthropic(&client, "hello", &config(&server), "sk-ant-test", 0.1, None)
+                .await,
+        );
+        assert!(message.contains("400"), "{}", message);
+        assert!(message.contains("prompt is too long"), "{}", message);
+    }
+
+    #[tokio::test]
+    async fn test_ollama_provider_responses() {
+        let client = HttpClient::default();
+
+        let server = mock_llm_server(
+            "/api/generate",
+            200,
+            serde_json::json!({"model": "llama-test", "response": "# Talk\n\nHello.", "done": true}),
+        )
+        .await;
+        wiremock::Mock::given(wiremock::matchers::method("GET"))
+            .and(wiremock::matchers::path("/api/tags"))
+            .respond_with(
+                wiremock::ResponseTemplate::new(200)
+                    .set_body_json(serde_json::json!({"models": []})),
+            )
+            .mount(&server)
+            .await;
+        let config = LocalLlmConfig {
+            endpoint: server.uri(),
+            model: "llama-test".to_string(),
+        };
+        let (formatted, _) = format_with_local(&client, "hello", &config, 0.1, None)
+            .await
+            .unwrap();
+        assert_eq!(formatted, "# Talk\n\nHello.");
+
+        let server = mock_llm_server(
+            "/api/generate",
+            404,
+            serde_json::json!({"error": "model 'llama-test' not found"}),
+        )
+        .await;
+        let config = LocalLlmConfig {
+            endpoint: server.uri(),
+            model: "llama-test".to_string(),
+        };
+        let message =
+            llm_error_message(format_with_local(&client, "hello", &config, 0.1, None).await);
+        assert!(message.contains("404"), "{}", message);
+    }
+
+    #[tokio::test]
+    async fn test_custom_provider_responses() {
+        let client = HttpClient::default();
+
+        let server = mock_llm_server(
+            "/chat/completions",
+            200,
+            serde_json::json!({"choices": [{"message": {"content": "# Talk\n\nHello."}}]}),
+        )
+        .await;
+        let config = CustomLlmConfig {
+            endpoint: server.uri(),
+            model: "local-model".to_string(),
+        };
+        let (formatted, _) = format_with_custom(&client, "hello", &config, None, 0.1, None)
+            .await
+            .unwrap();
+        assert_eq!(formatted, "# Talk\n\nHello.");
+        // Without a key no Authorization header is sent
+        let requests = server.received_requests().await.unwrap();
+        assert!(requests[0].headers.get("authorization").is_none());
+
+        let server = mock_llm_server("/chat/completions", 503, serde_json::json!({})).await;
+        let config = CustomLlmConfig {
+            endpoint: server.uri(),
+            model: "local-model".to_string(),
+        };
+        let message =
+            llm_error_message(format_with_custom(&client, "hello", &config, None, 0.1, None).await);
+        assert!(message.contains("503"), "{}", message);
+
+        let message = llm_error_message(
+            format_with_custom(
+                &client,
+                "hello",
+                &CustomLlmConfig::default(),
+                None,
+                0.1,
+                None,
+            )
+            .await,
+        );
+        assert!(message.contains("not configured"), "{}", message);
+    }
 }
 
 // ============================================================================
@@ -2032,23 +10358,32 @@ use tokio::sync::Mutex;
 /// Ollama model management
 #[derive(Debug, Clone)]
 pub struct OllamaManager {
-    client: reqwest::Client,
+    client: HttpClient,
     endpoint: String,
     cache: Arc<Mutex<ModelCache>>,
 }
 
 #[derive(Debug, Clone, Default)]
 struct ModelCache {
-    local_models: Vec<String>,
+    local_models: Vec<ModelInfo>,
     last_updated: Option<std::time::SystemTime>,
 }
 
 impl OllamaManager {
-    /// Create a new Ollama manager
-    pub fn new(endpoint: Option<String>) -> Self {
+    /// Create a new Ollama manager with its own HTTP client
+    pub fn new(endpoint: Option<String>) -> Result<Self, Y2mdError> {
         let endpoint = endpoint.unwrap_or_else(|| "http://localhost:11434".to_string());
+        Ok(Self::with_client(
+            endpoint,
+            HttpClient::new(&AppConfig::default())?,
+        ))
+    }
+
+    /// Create a manager for `endpoint` that sends requests through `client`,
+    /// sharing its connections with the rest of the run
+    pub fn with_client(endpoint: String, client: HttpClient) -> Self {
         Self {
-            client: reqwest::Client::new(),
+            client,
             endpoint,
             cache: Arc::new(Mutex::new(ModelCache::default())),
         }
@@ -2065,6 +10400,12 @@ impl OllamaManager {
 
     /// Get list of locally available models
     pub async fn get_local_models(&self) -> Result<Vec<String>, Y2mdError> {
+        let models = self.get_local_model_details().await?;
+        Ok(models.into_iter().map(|model| model.name).collect())
+    }
+
+    /// Get locally available models with their size and digest
+    pub async fn get_local_model_details(&self) -> Result<Vec<ModelInfo>, Y2mdError> {
         let mut cache = self.cache.lock().await;
 
         // Use cache if recently updated (within 30 seconds)
@@ -2090,38 +10431,32 @@ impl OllamaManager {
             .await
             .map_err(|e| Y2mdError::Llm(format!("Failed to parse Ollama models: {}", e)))?;
 
-        let models = models_json["models"]
-            .as_array()
-            .ok_or_else(|| Y2mdError::Llm("Invalid response format from Ollama".to_string()))?;
-
-        let model_names: Vec<String> = models
-            .iter()
-            .filter_map(|model| model["name"].as_str().map(|s| s.to_string()))
-            .collect();
+        let models = parse_ollama_tags(&models_json)?;
 
         // Update cache
-        cache.local_models = model_names.clone();
+        cache.local_models = models.clone();
         cache.last_updated = Some(std::time::SystemTime::now());
 
-        Ok(model_names)
+        Ok(models)
     }
 
     /// Check if a specific model is available locally
     pub async fn is_model_available(&self, model_name: &str) -> Result<bool, Y2mdError> {
         let local_models = self.get_local_models().await?;
-        Ok(local_models.iter().any(|name| name.contains(model_name)))
+        Ok(local_models
+            .iter()
+            .any(|name| same_ollama_model(name, model_name)))
     }
 
     /// Get model information including size
     pub async fn get_model_info(&self, model_name: &str) -> Result<ModelInfo, Y2mdError> {
         // First check if model exists locally
-        let local_models = self.get_local_models().await?;
-        if let Some(full_name) = local_models.iter().find(|name| name.contains(model_name)) {
-            return Ok(ModelInfo {
-                name: full_name.clone(),
-                size: None, // Size not available from local models endpoint
-                available: true,
-            });
+        let local_models = self.get_local_model_details().await?;
+        if let Some(model) = local_models
+            .into_iter()
+            .find(|model| same_ollama_model(&model.name, model_name))
+        {
+            return Ok(model);
         }
 
         // For remote models, we'd need to query Ollama's model library
@@ -2129,12 +10464,22 @@ impl OllamaManager {
         Ok(ModelInfo {
             name: model_name.to_string(),
             size: None, // Would need to query Ollama's model library
+            digest: None,
             available: false,
         })
     }
 
     /// Download a model
     pub async fn download_model(&self, model_name: &str) -> Result<(), Y2mdError> {
+        self.download_model_with_progress(model_name, |_| {}).await
+    }
+
+    /// Download a model, reporting byte progress summed over all layers
+    pub async fn download_model_with_progress(
+        &self,
+        model_name: &str,
+        mut on_progress: impl FnMut(&PullProgress),
+    ) -> Result<(), Y2mdError> {
         let response = self
             .client
             .post(format!("{}/api/pull", self.endpoint))
@@ -2155,31 +10500,54 @@ impl OllamaManager {
             )));
         }
 
-        // Stream the response line by line
         let mut download_completed = false;
+        let mut layers: HashMap<String, (u64, u64)> = HashMap::new();
+        let mut handle_line = |line: &[u8]| -> Result<(), Y2mdError> {
+            let Ok(json) = serde_json::from_slice::<serde_json::Value>(line) else {
+                return Ok(());
+            };
+            if let Some(error) = json["error"].as_str() {
+                return Err(Y2mdError::Llm(format!(
+                    "Failed to download model: {}",
+                    error
+                )));
+            }
+            let Some(status) = json["status"].as_str() else {
+                return Ok(());
+            };
 
-        // Read the response as text and process line by line
-        let response_text = response
-            .text()
-            .await
-            .map_err(|e| Y2mdError::Llm(format!("Failed to read download response: {}", e)))?;
+            // Check for completion indicators
+            if status == "success" || status.contains("complete") || status.contains("done") {
+                download_completed = true;
+            }
 
-        for line in response_text.lines() {
-            let line = line.trim();
-            if line.is_empty() {
-                continue;
+            if let (Some(digest), Some(total)) = (json["digest"].as_str(), json["total"].as_u64()) {
+                let completed = json["completed"].as_u64().unwrap_or(0);
+                layers.insert(digest.to_string(), (total, completed));
             }
 
-            if let Ok(json) = serde_json::from_str::<serde_json::Value>(line) {
-                if let Some(status) = json["status"].as_str() {
-                    // Check for completion indicators
-                    if status == "success" || status.contains("complete") || status.contains("done")
-                    {
-                        download_completed = true;
-                    }
-                }
+            on_progress(&PullProgress {
+                status: status.to_string(),
+                total: layers.values().map(|(total, _)| total).sum(),
+                completed: layers.values().map(|(_, completed)| completed).sum(),
+            });
+            Ok(())
+        };
+
+        // Handle each status line as soon as it arrives instead of buffering
+        // the whole (possibly very long) response
+        let mut lines = LineBuffer::default();
+        let mut stream = response.bytes_stream();
+        while let Some(chunk) = stream.next().await {
+            let chunk = chunk
+                .map_err(|e| Y2mdError::Llm(format!("Failed to read download response: {}", e)))?;
+            for line in lines.push(&chunk) {
+                handle_line(&line)?;
             }
         }
+        if let Some(line) = lines.finish() {
+            handle_line(&line)?;
+        }
 
         // If we didn't get a clear completion signal, wait a bit and check
         if !download_completed {
@@ -2240,31 +10608,101 @@ impl OllamaManager {
     }
 }
 
+/// Splits a byte stream into newline-delimited lines, holding on to a
+/// partial line until the rest of it arrives
+#[derive(Debug, Default)]
+struct LineBuffer {
+    pending: Vec<u8>,
+}
+
+impl LineBuffer {
+    /// Add a chunk and return the lines it completed, without newlines
+    fn push(&mut self, chunk: &[u8]) -> Vec<Vec<u8>> {
+        self.pending.extend_from_slice(chunk);
+
+        let mut lines = Vec::new();
+        while let Some(newline) = self.pending.iter().position(|&b| b == b'\n') {
+            let mut line: Vec<u8> = self.pending.drain(..=newline).collect();
+            line.pop();
+            lines.push(line);
+        }
+        lines
+    }
+
+    /// The last line, when the stream did not end with a newline
+    fn finish(&mut self) -> Option<Vec<u8>> {
+        (!self.pending.is_empty()).then(|| std::mem::take(&mut self.pending))
+    }
+}
+
+/// Progress of an Ollama model download
+#[derive(Debug, Clone, PartialEq)]
+pub struct PullProgress {
+    /// Status line from Ollama, e.g. `pulling manifest` or `verifying sha256 digest`
+    pub status: String,
+    /// Bytes to download across all layers seen so far
+    pub total: u64,
+    /// Bytes downloaded across all layers seen so far
+    pub completed: u64,
+}
+
 /// Model information
 #[derive(Debug, Clone)]
 pub struct ModelInfo {
     pub name: String,
     pub size: Option<u64>, // Size in bytes
+    /// Content digest reported by Ollama (sha256 hex)
+    pub digest: Option<String>,
     pub available: bool,
 }
 
+/// Parse the model list returned by Ollama's `/api/tags`
+fn parse_ollama_tags(json: &serde_json::Value) -> Result<Vec<ModelInfo>, Y2mdError> {
+    let models = json["models"]
+        .as_array()
+        .ok_or_else(|| Y2mdError::Llm("Invalid response format from Ollama".to_string()))?;
+
+    Ok(models
+        .iter()
+        .filter_map(|model| {
+            Some(ModelInfo {
+                name: model["name"].as_str()?.to_string(),
+                size: model["size"].as_u64(),
+                digest: model["digest"].as_str().map(|s| s.to_string()),
+                available: true,
+            })
+        })
+        .collect())
+}
+
 impl ModelInfo {
+    /// Abbreviated digest, as shown by `ollama list`
+    pub fn short_digest(&self) -> Option<&str> {
+        self.digest.as_deref().map(|digest| {
+            let digest = digest.strip_prefix("sha256:").unwrap_or(digest);
+            &digest[..digest.len().min(12)]
+        })
+    }
+
     /// Get human-readable size
     pub fn size_human(&self) -> Option<String> {
-        self.size.map(|bytes| {
-            const KB: u64 = 1024;
-            const MB: u64 = KB * 1024;
-            const GB: u64 = MB * 1024;
-
-            if bytes >= GB {
-                format!("{:.1} GB", bytes as f64 / GB as f64)
-            } else if bytes >= MB {
-                format!("{:.1} MB", bytes as f64 / MB as f64)
-            } else if bytes >= KB {
-                format!("{:.1} KB", bytes as f64 / KB as f64)
-            } else {
-                format!("{} bytes", bytes)
-            }
-        })
+        self.size.map(format_bytes)
+    }
+}
+
+/// Human-readable byte count (binary units)
+pub fn format_bytes(bytes: u64) -> String {
+    const KB: u64 = 1024;
+    const MB: u64 = KB * 1024;
+    const GB: u64 = MB * 1024;
+
+    if bytes >= GB {
+        format!("{:.1} GB", bytes as f64 / GB as f64)
+    } else if bytes >= MB {
+        format!("{:.1} MB", bytes as f64 / MB as f64)
+    } else if bytes >= KB {
+        format!("{:.1} KB", bytes as f64 / KB as f64)
+    } else {
+        format!("{} bytes", bytes)
     }
 }
@@ -1,13 +1,31 @@
-use clap::{Parser, Subcommand};
+use clap::{CommandFactory, Parser, Subcommand};
+use indicatif::{ProgressBar, ProgressStyle};
 use std::fs;
 use std::io::Write;
+use std::process::ExitCode;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 use y2md::{
-    fetch_video_metadata, format_markdown, transcribe_video, validate_youtube_url, AppConfig,
-    CredentialManager, LlmProviderType, OllamaManager,
+    append_section, append_to_note, audio_only_formats, check_date_format, check_duration_limits,
+    check_llm_provider, check_models_dir, combine_markdown, compare_llm_provider,
+    ensure_output_dir, expand_filename_template, expand_output_dir, fetch_video_metadata,
+    format_bytes, list_caption_tracks, list_download_formats, list_provider_models, load_env_file,
+    normalize_language_code, normalize_output_dir, note_contains_video, parse_audio_format,
+    parse_audio_quality, parse_duration_arg, parse_language_arg, parse_line_wrap, parse_models_dir,
+    parse_output_name, parse_replacement, parse_temperature, process_video, render_segments,
+    same_ollama_model, subtitles_to_markdown, validate_youtube_url, write_atomic, AppConfig,
+    CaptionLangFallback, CredentialBackend, CredentialManager, EventHandler, FormatOptions,
+    HttpClient, LlmProviderType, LlmSettings, OllamaManager, OutputFormat, ParagraphLength,
+    PipelineEvent, ProcessOptions, ProcessOutput, ProviderComparison, RawFormat, Redactor,
+    RunStats, SttBackend, SubtitleFormat, Timezone, VideoMetadata, Y2mdError, AUTO_LANGUAGE,
+    EXIT_CONFIG, EXIT_FAILURE, EXIT_MISSING_DEPENDENCY,
 };
 
 mod diagnostics;
 mod setup;
+mod ui;
+
+use ui::{CHECKMARK, CROSS, DOWNLOAD, WARNING};
 
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
@@ -15,10 +33,10 @@ struct Args {
     #[command(subcommand)]
     command: Option<Commands>,
 
-    /// YouTube URL to transcribe
-    url: Option<String>,
+    /// YouTube URL(s) to transcribe
+    urls: Vec<String>,
 
-    /// Output directory for transcript
+    /// Output directory for transcript (supports {channel}, {video_id}, {year}, {month}, {upload_date})
     #[arg(short, long, default_value = ".")]
     out_dir: String,
 
@@ -26,21 +44,75 @@ struct Args {
     #[arg(long, default_value_t = true, action = clap::ArgAction::Set)]
     prefer_captions: bool,
 
-    /// Language code override
-    #[arg(long)]
+    /// Language code override (ISO 639-1, e.g. en, es, pt-BR), or `auto` to
+    /// let Whisper detect it
+    #[arg(long, value_parser = parse_language_arg)]
     lang: Option<String>,
 
+    /// When the video has captions, but not in --lang: transcribe the audio
+    /// (stt) or use the captions in a language it has (available)
+    #[arg(long, value_name = "POLICY")]
+    caption_lang_fallback: Option<CaptionLangFallback>,
+
+    /// Without --lang, transcribe in the language the video declares instead of
+    /// the configured default (when the metadata has one)
+    #[arg(long, default_value_t = false)]
+    lang_from_metadata: bool,
+
     /// Include timestamps in transcript
     #[arg(long, default_value_t = false)]
     timestamps: bool,
 
+    /// Mark the first paragraph after every SECS seconds with its time, e.g. [00:05:00]
+    #[arg(long, value_name = "SECS", value_parser = clap::value_parser!(u64).range(1..))]
+    timestamp_interval: Option<u64>,
+
     /// Compact output format
     #[arg(long, default_value_t = false)]
     compact: bool,
 
-    /// Paragraph length for enhanced formatting (sentences per paragraph)
-    #[arg(long, default_value_t = 4)]
-    paragraph_length: usize,
+    /// Sentences per paragraph, or "auto" to group by word count [default: from config, 4; 8 with --compact]
+    #[arg(long, value_name = "N|auto")]
+    paragraph_length: Option<ParagraphLength>,
+
+    /// Add the video description in a `## Description` section before the transcript
+    #[arg(long, default_value_t = false)]
+    include_description: bool,
+
+    /// Substitute a misheard word or phrase, whole words only and ignoring case
+    /// (repeatable, added to `replacements` in the config)
+    #[arg(long, value_name = "WRONG=RIGHT", value_parser = parse_replacement)]
+    replace: Vec<(String, String)>,
+
+    /// Hard-wrap the output at this column, leaving front matter and headings intact
+    #[arg(
+        long,
+        value_name = "WIDTH",
+        visible_alias = "max-line-width",
+        value_parser = parse_line_wrap
+    )]
+    wrap: Option<usize>,
+
+    /// Replace email addresses and phone numbers with [redacted], in addition
+    /// to the `redact` entries in the config
+    #[arg(long, default_value_t = false)]
+    redact_pii: bool,
+
+    /// Speech-to-text backend used when captions are unavailable (local, openai, deepgram)
+    #[arg(long, value_name = "BACKEND")]
+    stt_backend: Option<SttBackend>,
+
+    /// Append a footer crediting the video, channel and transcript source
+    #[arg(long, default_value_t = false)]
+    append_metadata_footer: bool,
+
+    /// Label speakers in the transcript (uses backend speaker labels, otherwise guesses from pauses)
+    #[arg(long, default_value_t = false)]
+    diarize: bool,
+
+    /// Keep paragraph breaks from manual (creator-uploaded) captions instead of re-flowing them
+    #[arg(long, default_value_t = false)]
+    preserve_caption_paragraphs: bool,
 
     /// Force enhanced formatting even for music content
     #[arg(long, default_value_t = false)]
@@ -50,13 +122,162 @@ struct Args {
     #[arg(long, value_name = "PROVIDER")]
     llm: Option<Option<String>>,
 
+    /// Use standard formatting on this run even if LLM formatting is enabled in the config
+    #[arg(long, default_value_t = false)]
+    no_llm: bool,
+
+    /// Use this model for the LLM provider on this run only (implies --llm)
+    #[arg(long, value_name = "NAME")]
+    llm_model: Option<String>,
+
+    /// Sampling temperature for LLM formatting, 0.0-2.0 (default from config: 0.1)
+    #[arg(long, value_name = "VALUE", value_parser = parse_temperature)]
+    temperature: Option<f32>,
+
     /// Dry run - don't write files
     #[arg(long, default_value_t = false)]
     dry_run: bool,
 
+    /// Only fetch metadata and print the planned source, output path and LLM provider
+    #[arg(long, default_value_t = false)]
+    plan: bool,
+
+    /// Ignore cached video metadata and fetch it again from YouTube
+    #[arg(long, default_value_t = false)]
+    refresh_metadata: bool,
+
+    /// Start Whisper transcription from scratch instead of resuming an interrupted run
+    #[arg(long, default_value_t = false)]
+    no_resume: bool,
+
+    /// With several URLs, stop at the first video that fails instead of
+    /// continuing and listing failures at the end. Missing tools and
+    /// configuration errors always stop the batch.
+    #[arg(long, default_value_t = false)]
+    fail_fast: bool,
+
+    /// Transcribe a video again even if the output directory already has a
+    /// transcript with the same video ID
+    #[arg(long, default_value_t = false)]
+    overwrite: bool,
+
+    /// Abort the whole job if it takes longer than this many seconds
+    #[arg(long, value_name = "SECS")]
+    timeout: Option<u64>,
+
+    /// Transcribe only the first part of the audio (e.g. 60, 2m) and print it
+    /// without writing a file, to check language and quality settings
+    #[arg(
+        long,
+        value_name = "DURATION",
+        value_parser = parse_duration_arg,
+        visible_alias = "first-n-seconds"
+    )]
+    sample: Option<u64>,
+
+    /// Skip videos shorter than this (e.g. 90, 45m, 3h)
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration_arg)]
+    min_duration: Option<u64>,
+
+    /// Skip videos longer than this (e.g. 90, 45m, 3h)
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration_arg)]
+    max_duration: Option<u64>,
+
+    /// Disable progress spinners (they are also hidden when stdout is not a terminal)
+    #[arg(long, default_value_t = false)]
+    no_progress: bool,
+
+    /// Use this config file instead of ~/.config/y2md/config.toml
+    #[arg(long, value_name = "FILE", global = true)]
+    config: Option<String>,
+
+    /// Load API keys (e.g. Y2MD_OPENAI_API_KEY) from this .env file
+    #[arg(long, value_name = "FILE", global = true)]
+    env_file: Option<String>,
+
+    /// Disable colored output (also honored via the NO_COLOR environment variable)
+    #[arg(long, global = true, default_value_t = false)]
+    no_color: bool,
+
+    /// Proxy URL for yt-dlp and all API requests (e.g. http://proxy.corp:8080)
+    #[arg(long, value_name = "URL", global = true)]
+    proxy: Option<String>,
+
+    /// Output format: md (markdown with front matter) or txt (plain paragraphs)
+    #[arg(long, value_name = "FORMAT", default_value = "md")]
+    format: OutputFormat,
+
+    /// With --format txt, put the video title on the first line
+    #[arg(long, default_value_t = false)]
+    txt_title: bool,
+
+    /// Write the unprocessed transcript (no paragraphs, capitalization fixes,
+    /// markdown, --replace or repeat collapsing) as the output .txt file, for
+    /// feeding other tools. Redaction still applies.
+    #[arg(long, default_value_t = false)]
+    raw_only: bool,
+
+    /// Exact output filename inside --out-dir (extension added when missing)
+    #[arg(long, value_name = "NAME", value_parser = parse_output_name)]
+    output_name: Option<String>,
+
+    /// Write all transcripts into this single file, with a table of contents
+    #[arg(long, value_name = "FILE")]
+    combine: Option<String>,
+
+    /// Append each transcript under a `## Title` heading to this markdown note
+    /// (created if missing) instead of writing new files
+    #[arg(long, value_name = "FILE")]
+    append: Option<String>,
+
+    /// Format the transcript with each of these LLM providers (e.g. openai,anthropic,local)
+    /// and save one file per provider with a timing summary
+    #[arg(long, value_name = "PROVIDERS", value_delimiter = ',')]
+    compare: Vec<LlmProviderType>,
+
+    /// Write word counts, source, LLM model and stage timings for each video as JSON
+    #[arg(long, value_name = "FILE")]
+    stats_json: Option<String>,
+
     /// Save raw transcript to separate txt file
     #[arg(long, default_value_t = false)]
     save_raw: bool,
+
+    /// Format for the raw transcript saved with --save-raw (txt, srt, vtt)
+    #[arg(long, value_name = "FORMAT", default_value = "txt")]
+    save_raw_format: RawFormat,
+
+    /// Cookies file (Netscape format) passed to yt-dlp for restricted videos
+    #[arg(long, value_name = "FILE")]
+    cookies: Option<String>,
+
+    /// Browser to load cookies from (e.g. firefox, chrome), passed to yt-dlp
+    #[arg(long, value_name = "BROWSER")]
+    cookies_from_browser: Option<String>,
+
+    /// Path to the yt-dlp executable
+    #[arg(long, value_name = "PATH")]
+    ytdlp_path: Option<String>,
+
+    /// Audio format to download for speech-to-text (e.g. m4a, opus; default: best)
+    #[arg(long, value_name = "FORMAT", value_parser = parse_audio_format)]
+    audio_format: Option<String>,
+
+    /// Audio quality for speech-to-text downloads: 0 (best) to 10, or a bitrate like 64K
+    #[arg(long, value_name = "QUALITY", value_parser = parse_audio_quality)]
+    audio_quality: Option<String>,
+
+    /// Retry yt-dlp this many times after a throttled or failed network request (default 2)
+    #[arg(long, value_name = "N")]
+    retries: Option<u32>,
+
+    /// Path to the ffmpeg executable
+    #[arg(long, value_name = "PATH")]
+    ffmpeg_path: Option<String>,
+
+    /// Directory with whisper.cpp models (ggml-*.bin), e.g. one shared with other tools
+    #[arg(long, value_name = "DIR", value_parser = parse_models_dir)]
+    models_dir: Option<String>,
 }
 
 #[derive(Subcommand, Debug)]
@@ -64,6 +285,42 @@ enum Commands {
     /// Check system dependencies and configuration
     Doctor,
 
+    /// Show video details and caption languages without transcribing
+    Info {
+        /// YouTube URL to inspect
+        url: String,
+
+        /// Language to check captions for (defaults to the configured language)
+        #[arg(long, value_parser = normalize_language_code)]
+        lang: Option<String>,
+    },
+
+    /// List the download formats yt-dlp offers for a video (yt-dlp -F)
+    Formats {
+        /// YouTube URL to inspect
+        url: String,
+
+        /// Only show audio-only formats
+        #[arg(long)]
+        audio_only: bool,
+    },
+
+    /// Convert an SRT or WebVTT subtitle file to a markdown transcript, with
+    /// the configured replacements, redaction and repeat collapsing
+    SubsToMd {
+        /// Subtitle file to convert (.srt or .vtt)
+        file: String,
+
+        /// Markdown file to write (defaults to the subtitle file with a .md extension)
+        #[arg(short, long, value_name = "FILE")]
+        output: Option<String>,
+
+        /// Use the compact paragraph length and do not break unpunctuated
+        /// captions into sentences every dozen or so words
+        #[arg(long)]
+        compact: bool,
+    },
+
     /// Run interactive setup wizard
     Init {
         /// Force re-initialization even if config exists
@@ -81,6 +338,17 @@ enum Commands {
         #[command(subcommand)]
         action: LlmCommands,
     },
+
+    /// Print a shell completion script to stdout
+    #[command(hide = true)]
+    Completions {
+        /// Shell to generate completions for
+        shell: clap_complete::Shell,
+    },
+
+    /// Print a roff man page to stdout
+    #[command(hide = true)]
+    Man,
 }
 
 #[derive(Subcommand, Debug)]
@@ -109,33 +377,281 @@ enum LlmCommands {
         /// Model name to remove
         model: String,
     },
+    /// List the models a provider serves (installed models for Ollama)
+    Models {
+        /// Provider to query (uses default if not specified)
+        provider: Option<String>,
+    },
     /// Test LLM connection
     Test {
         /// Provider to test (uses default if not specified)
         provider: Option<String>,
+
+        /// Model to test instead of the configured one
+        #[arg(long, value_name = "NAME")]
+        model: Option<String>,
+
+        /// Test the default provider and each llm_fallback provider in turn
+        #[arg(long)]
+        all: bool,
     },
     /// Set API key for a provider
     SetKey {
-        /// Provider name (openai, anthropic, custom)
+        /// Provider name (openai, anthropic, deepseek, custom, or deepgram for STT)
         provider: String,
     },
 }
 
+/// Command-line settings applied to the config of every command
+#[derive(Debug, Clone, Default)]
+struct ConfigOverrides {
+    /// `--config`
+    config_path: Option<std::path::PathBuf>,
+    /// `--proxy`
+    proxy: Option<String>,
+}
+
+impl ConfigOverrides {
+    /// Config file in use: `--config`, or the default location
+    fn config_path(&self) -> Result<std::path::PathBuf, Y2mdError> {
+        match &self.config_path {
+            Some(path) => Ok(path.clone()),
+            None => AppConfig::config_path(),
+        }
+    }
+
+    /// Load the config file with the overrides applied
+    fn load(&self) -> Result<AppConfig, Y2mdError> {
+        Ok(self.apply(AppConfig::load_or_default(&self.config_path()?)?))
+    }
+
+    /// Read the config file with the overrides applied, without upgrading an
+    /// older layout on disk; for `doctor`
+    fn read(&self) -> Result<AppConfig, Y2mdError> {
+        Ok(self.apply(AppConfig::read_or_default(&self.config_path()?)?))
+    }
+
+    /// The defaults, to be saved to the config file in use
+    fn defaults(&self) -> Result<AppConfig, Y2mdError> {
+        Ok(self.apply(AppConfig::default_at(&self.config_path()?)))
+    }
+
+    /// API keys for the config file in use; a config that does not load
+    /// falls back to the default credential backend
+    fn credentials(&self) -> CredentialManager {
+        let config = self.read().or_else(|_| self.defaults()).unwrap_or_default();
+        CredentialManager::for_config(&config)
+    }
+
+    /// Apply the overrides to a config that is already loaded
+    fn apply(&self, mut config: AppConfig) -> AppConfig {
+        if self.proxy.is_some() {
+            config.proxy_override = self.proxy.clone();
+        }
+        config
+    }
+}
+
+/// Arguments that do not make sense together; exits with [`EXIT_CONFIG`]
+/// like the argument errors clap reports itself
+#[derive(Debug, thiserror::Error)]
+#[error("{0}")]
+struct UsageError(String);
+
 #[tokio::main]
-async fn main() -> anyhow::Result<()> {
-    let args = Args::parse();
+async fn main() -> ExitCode {
+    match run().await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Error: {:?}", e);
+            if e.is::<UsageError>() {
+                return ExitCode::from(EXIT_CONFIG);
+            }
+            ExitCode::from(
+                e.downcast_ref::<Y2mdError>()
+                    .map_or(EXIT_FAILURE, Y2mdError::exit_code),
+            )
+        }
+    }
+}
+
+/// Check the arguments that clap cannot check on its own, such as options
+/// that do not combine
+fn validate_args(args: &mut Args) -> Result<(), UsageError> {
+    // If no URL provided, show help
+    if args.urls.is_empty() {
+        return Err(UsageError(
+            "YouTube URL is required. Use --help for usage information.".to_string(),
+        ));
+    }
+
+    if args.output_name.is_some() && args.urls.len() > 1 {
+        return Err(UsageError(
+            "--output-name can only be used with a single URL".to_string(),
+        ));
+    }
+
+    if !args.compare.is_empty() {
+        if args.format == OutputFormat::Txt || args.combine.is_some() {
+            return Err(UsageError("--compare writes one markdown file per provider and cannot be used with --format txt or --combine".to_string()));
+        }
+        if args.llm_model.is_some() {
+            return Err(UsageError("--compare uses each provider's configured model and cannot be used with --llm-model".to_string()));
+        }
+    }
+
+    if let Some(sample) = args.sample {
+        if sample == 0 {
+            return Err(UsageError(
+                "--sample must be at least one second".to_string(),
+            ));
+        }
+        if !args.compare.is_empty() || args.combine.is_some() {
+            return Err(UsageError(
+                "--sample prints a preview and cannot be used with --compare or --combine"
+                    .to_string(),
+            ));
+        }
+    }
+
+    if args.append.is_some() {
+        if args.format != OutputFormat::Markdown
+            || args.combine.is_some()
+            || !args.compare.is_empty()
+            || args.sample.is_some()
+        {
+            return Err(UsageError("--append adds markdown to a note and cannot be used with --format txt, --combine, --compare or --sample".to_string()));
+        }
+        if args.output_name.is_some() {
+            return Err(UsageError(
+                "--append writes to the given note and cannot be used with --output-name"
+                    .to_string(),
+            ));
+        }
+    }
+
+    if args.raw_only {
+        if args.llm.is_some() || args.llm_model.is_some() || !args.compare.is_empty() {
+            return Err(UsageError("--raw-only skips all formatting and cannot be used with --llm, --llm-model or --compare".to_string()));
+        }
+        if args.combine.is_some() || args.append.is_some() || args.timestamp_interval.is_some() {
+            return Err(UsageError("--raw-only writes a plain .txt file and cannot be used with --combine, --append or --timestamp-interval".to_string()));
+        }
+        if args.wrap.is_some() || args.txt_title {
+            return Err(UsageError("--raw-only writes the text unchanged and cannot be used with --wrap or --txt-title".to_string()));
+        }
+        args.format = OutputFormat::Txt;
+    }
+
+    if args.no_llm && (args.llm.is_some() || args.llm_model.is_some()) {
+        return Err(UsageError(
+            "--no-llm cannot be used with --llm or --llm-model".to_string(),
+        ));
+    }
+
+    if args.format == OutputFormat::Txt {
+        if args.llm.is_some() || args.llm_model.is_some() {
+            return Err(UsageError(
+                "--llm produces markdown and cannot be used with --format txt".to_string(),
+            ));
+        }
+        if args.combine.is_some() {
+            return Err(UsageError(
+                "--combine only supports markdown output".to_string(),
+            ));
+        }
+        if args.timestamp_interval.is_some() {
+            return Err(UsageError(
+                "--timestamp-interval only supports markdown output".to_string(),
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+async fn run() -> anyhow::Result<()> {
+    let mut args = Args::try_parse().unwrap_or_else(|e| {
+        // clap exits with 2 on bad arguments, which would collide with
+        // EXIT_MISSING_DEPENDENCY
+        if e.use_stderr() {
+            let _ = e.print();
+            std::process::exit(EXIT_CONFIG.into());
+        }
+        e.exit()
+    });
+    ui::configure_colors(args.no_color);
+
+    // Every config load and save after this point goes through `overrides`
+    let config_path = args
+        .config
+        .as_ref()
+        .map(|config| std::path::PathBuf::from(shellexpand::tilde(config).to_string()));
+    if let Some(path) = &config_path {
+        let creates_config = matches!(
+            args.command,
+            Some(Commands::Init { .. })
+                | Some(Commands::Config {
+                    action: Some(ConfigCommands::Edit | ConfigCommands::Reset)
+                })
+        );
+        if !path.exists() && !creates_config {
+            return Err(UsageError(format!("Config file not found: {}", path.display())).into());
+        }
+    }
+    let overrides = ConfigOverrides {
+        config_path,
+        proxy: args.proxy.clone(),
+    };
+
+    // Environment variables from a .env file take part in API key lookup
+    if let Some(env_file) = &args.env_file {
+        let path = std::path::PathBuf::from(shellexpand::tilde(env_file).to_string());
+        load_env_file(Some(&path), &overrides.config_path()?)?;
+    } else if overrides.read().is_ok_and(|config| config.load_env_file) {
+        load_env_file(None, &overrides.config_path()?)?;
+    }
+
+    if let Some(proxy) = &args.proxy {
+        reqwest::Proxy::all(proxy)
+            .map_err(|e| anyhow::anyhow!("Invalid --proxy URL '{}': {}", proxy, e))?;
+    }
 
     // Handle subcommands
-    if let Some(command) = args.command {
+    if let Some(command) = args.command.take() {
         match command {
             Commands::Doctor => {
-                let report = diagnostics::run_diagnostics().await;
+                let report = diagnostics::run_diagnostics(&overrides).await;
                 diagnostics::print_diagnostic_report(&report);
                 std::process::exit(if report.has_errors() { 1 } else { 0 });
             }
+            Commands::Info { url, lang } => {
+                return handle_info_command(&url, lang, &overrides).await;
+            }
+            Commands::Formats { url, audio_only } => {
+                let video_id = validate_youtube_url(&url)?;
+                let config = overrides.load()?;
+                let table = list_download_formats(&video_id, &config.ytdlp_config()).await?;
+                if audio_only {
+                    println!("{}", audio_only_formats(&table));
+                } else {
+                    print!("{}", table);
+                }
+                println!(
+                    "\nSet ytdlp.audio_format (or --audio-format) to an extension listed above to skip conversion"
+                );
+                return Ok(());
+            }
+            Commands::SubsToMd {
+                file,
+                output,
+                compact,
+            } => {
+                return handle_subs_to_md_command(&file, output.as_deref(), compact, &overrides);
+            }
             Commands::Init { force } => {
                 if !force {
-                    if let Ok(config_path) = AppConfig::config_path() {
+                    if let Ok(config_path) = overrides.config_path() {
                         if config_path.exists() {
                             println!("Configuration already exists at: {}", config_path.display());
                             println!("Use --force to overwrite, or edit with: y2md config edit");
@@ -144,53 +660,81 @@ async fn main() -> anyhow::Result<()> {
                     }
                 }
 
-                setup::SetupWizard::run().await?;
+                // A broken config is what --force is for, so fall back to the defaults
+                let config = overrides.load().or_else(|_| overrides.defaults())?;
+                setup::SetupWizard::run(&config, &HttpClient::new(&config)?).await?;
                 return Ok(());
             }
             Commands::Config { action } => {
-                return handle_config_command(action).await;
+                return handle_config_command(action, &overrides).await;
             }
             Commands::Llm { action } => {
-                return handle_llm_command(action).await;
+                return handle_llm_command(action, &overrides).await;
+            }
+            Commands::Completions { shell } => {
+                let mut command = Args::command();
+                let name = command.get_name().to_string();
+                clap_complete::generate(shell, &mut command, name, &mut std::io::stdout());
+                return Ok(());
+            }
+            Commands::Man => {
+                let man = clap_mangen::Man::new(Args::command());
+                man.render(&mut std::io::stdout())?;
+                return Ok(());
             }
         }
     }
 
-    // If no URL provided, show help
-    let url = args.url.ok_or_else(|| {
-        anyhow::anyhow!("YouTube URL is required. Use --help for usage information.")
-    })?;
+    validate_args(&mut args)?;
 
-    // Validate URL and extract video ID
-    let video_id = validate_youtube_url(&url)?;
+    // Validate every URL before doing any work
+    let video_ids = args
+        .urls
+        .iter()
+        .map(|url| validate_youtube_url(url))
+        .collect::<Result<Vec<_>, _>>()?;
 
-    // Fetch video metadata
-    let metadata = fetch_video_metadata(&video_id).await?;
+    // Load configuration
+    let config = overrides.load()?;
 
-    println!("Transcribing: {}", metadata.title);
-    println!(
-        "Channel: {}",
-        metadata.channel.as_deref().unwrap_or("Unknown")
-    );
-    println!("Video ID: {}", video_id);
-    println!("Output directory: {}", args.out_dir);
+    // CLI yt-dlp options take precedence over the configured defaults
+    let mut ytdlp = config.ytdlp_config();
+    if args.ytdlp_path.is_some() {
+        ytdlp.path = args.ytdlp_path.clone();
+    }
+    if args.cookies.is_some() {
+        ytdlp.cookies = args.cookies.clone();
+    }
+    if args.cookies_from_browser.is_some() {
+        ytdlp.cookies_from_browser = args.cookies_from_browser.clone();
+    }
+    if args.audio_format.is_some() {
+        ytdlp.audio_format = args.audio_format.clone();
+    }
+    if args.audio_quality.is_some() {
+        ytdlp.audio_quality = args.audio_quality.clone();
+    }
+    if args.retries.is_some() {
+        ytdlp.retries = args.retries;
+    }
+    ytdlp.sample_seconds = args.sample;
 
-    // Load configuration
-    let config = AppConfig::load()?;
+    let mut advanced = config.advanced.clone();
+    if args.ffmpeg_path.is_some() {
+        advanced.ffmpeg_path = args.ffmpeg_path.clone();
+    }
+    let ffmpeg_path = advanced.ffmpeg_binary();
 
     // Use configuration values with CLI args as overrides
-    let prefer_captions = args.prefer_captions;
-    let language = args.lang.as_deref().or(Some(&config.default_language));
-    let output_dir = if args.out_dir != "." {
-        &args.out_dir
+    // Expand `~` like the setup wizard does, for quoted or scripted paths
+    let output_dir_template = if args.out_dir != "." {
+        normalize_output_dir(&args.out_dir)
     } else {
-        &config.output_dir
+        normalize_output_dir(&config.output_dir)
     };
-    let paragraph_length = args.paragraph_length;
-    let timestamps = args.timestamps || config.timestamps;
-    let compact = args.compact || config.compact;
 
     // Determine if we should use LLM and which provider
+    let llm_requested = args.llm.is_some() || args.llm_model.is_some();
     let (use_llm, llm_provider) = match &args.llm {
         Some(Some(provider_str)) => {
             // --llm <provider> specified
@@ -206,77 +750,265 @@ async fn main() -> anyhow::Result<()> {
             // --llm flag without provider (use default from config)
             (true, None)
         }
+        None if args.no_llm => (false, None),
         None => {
-            // No --llm flag (check config)
-            (config.llm.enabled, None)
+            // No --llm flag (check config); a model override implies LLM formatting
+            (config.llm.enabled || args.llm_model.is_some(), None)
         }
     };
 
-    // Perform transcription
-    let (transcript, source, raw_transcript) = transcribe_video(
-        &video_id,
-        prefer_captions,
-        language,
-        output_dir,
-        paragraph_length,
-        args.force_formatting,
-    )
-    .await?;
+    // Reject a bad redact pattern or date format before anything is downloaded
+    Redactor::new(&config.redact, false)?;
+    if let Some(date_format) = &config.date_format {
+        check_date_format(date_format).map_err(Y2mdError::Config)?;
+    }
 
-    // Format as Markdown
-    let markdown = format_markdown(
-        &metadata,
-        &transcript,
-        &source,
-        timestamps,
+    let compact = args.compact || config.compact;
+    let options = ProcessOptions {
+        prefer_captions: args.prefer_captions,
+        language: args.lang.clone().or(Some(config.default_language.clone())),
+        caption_lang_fallback: args
+            .caption_lang_fallback
+            .unwrap_or(config.caption_lang_fallback),
+        output_dir: output_dir_template.clone(),
+        paragraph_length: args
+            .paragraph_length
+            .unwrap_or_else(|| config.paragraph_length_for(compact)),
+        format: args.format,
+        txt_title: args.txt_title,
+        raw_only: args.raw_only,
+        force_formatting: args.force_formatting,
+        preserve_caption_paragraphs: args.preserve_caption_paragraphs
+            || config.preserve_caption_paragraphs,
+        fix_capitalization: config.fix_capitalization,
+        proper_nouns: config.proper_nouns.clone(),
+        // --replace entries go first so they win over the config for the same phrase
+        replacements: args
+            .replace
+            .iter()
+            .cloned()
+            .chain(config.replacements.clone())
+            .collect(),
+        redact: config.redact.clone(),
+        redact_pii: args.redact_pii || config.redact_pii,
+        max_repeated_sentences: config.max_repeated_sentences,
+        model_dirs: match &args.models_dir {
+            Some(dir) => vec![std::path::PathBuf::from(dir)],
+            None => config.model_dirs(),
+        },
+        timestamps: args.timestamps || config.timestamps,
+        timestamp_interval: args.timestamp_interval,
         compact,
-        paragraph_length,
-        use_llm,
+        // With --compare the pipeline runs once without an LLM; providers format afterwards
+        use_llm: use_llm && args.format == OutputFormat::Markdown && args.compare.is_empty(),
+        // An explicit --llm applies whatever the transcript source
+        llm_for_captions: llm_requested || config.llm_for_captions,
+        llm_for_stt: llm_requested || config.llm_for_stt,
         llm_provider,
-    )
-    .await;
-
-    // Generate filename
-    let sanitized_title = metadata
-        .title
-        .chars()
-        .map(|c| {
-            if c.is_alphanumeric() || c == '-' || c == '_' {
-                c
-            } else {
-                '_'
-            }
-        })
-        .collect::<String>();
-    let filename = format!(
-        "{}_{}_{}.md",
-        chrono::Utc::now().format("%Y-%m-%d"),
-        video_id,
-        sanitized_title
+        llm_model: args.llm_model.clone(),
+        llm_temperature: args.temperature,
+        http_client: Some(HttpClient::new(&config)?),
+        ytdlp,
+        ffmpeg_path,
+        stt_backend: args
+            .stt_backend
+            .clone()
+            .unwrap_or_else(|| config.stt.backend.clone()),
+        config: config.clone(),
+        resume: !args.no_resume,
+        refresh_metadata: args.refresh_metadata,
+        timeout: args.timeout,
+        append_metadata_footer: args.append_metadata_footer || config.append_metadata_footer,
+        line_wrap: args.wrap.or(config.line_wrap),
+        diarize: args.diarize,
+        min_duration: args.min_duration,
+        max_duration: args.max_duration,
+        lang_from_metadata: args.lang_from_metadata && args.lang.is_none(),
+        include_description: args.include_description,
+        date_format: config.date_format.clone(),
+        timezone: config.timezone,
+        // Samples, comparisons, combined documents and notes do not add to the library
+        overwrite: args.overwrite
+            || args.sample.is_some()
+            || args.combine.is_some()
+            || args.append.is_some()
+            || !args.compare.is_empty(),
+    };
+
+    if args.plan {
+        for video_id in &video_ids {
+            print_plan(video_id, &options, &config, args.output_name.as_deref()).await?;
+        }
+        return Ok(());
+    }
+
+    // Only animate progress when a person is watching the terminal
+    let show_progress = !args.no_progress && console::Term::stdout().is_term();
+    let handler = show_progress.then(progress_handler);
+
+    let mut combined = Vec::new();
+    let mut run_stats = Vec::new();
+    let mut failures: Vec<(&str, Y2mdError)> = Vec::new();
+    for (url, video_id) in args.urls.iter().zip(&video_ids) {
+        if let Some(note_path) = &args.append {
+            let note_path = shellexpand::tilde(note_path).to_string();
+            let note = fs::read_to_string(&note_path).unwrap_or_default();
+            if !args.overwrite && note_contains_video(&note, video_id) {
+                println!("Skipping video: {} is already in {}", video_id, note_path);
+                continue;
+            }
+        }
+
+        // Run the full pipeline
+        let output = match process_video(url, &options, handler.as_ref()).await {
+            Ok(output) => output,
+            Err(Y2mdError::Skipped(reason)) => {
+                println!("Skipping video: {}", reason);
+                continue;
+            }
+            // One silent video should not stop the rest of a batch
+            Err(e @ Y2mdError::NoAudio(_)) if args.urls.len() > 1 => {
+                println!("Skipping video: {}", e);
+                continue;
+            }
+            Err(e) if args.fail_fast || args.urls.len() == 1 => return Err(e.into()),
+            // A missing tool or bad setting would fail every remaining video the same way
+            Err(e) if matches!(e.exit_code(), EXIT_MISSING_DEPENDENCY | EXIT_CONFIG) => {
+                return Err(e.into())
+            }
+            Err(e) => {
+                println!("{} Failed: {}: {}", CROSS, url, e);
+                failures.push((url, e));
+                continue;
+            }
+        };
+
+        if let Some(sample) = args.sample {
+            println!(
+                "\n--- Sample: first {} seconds of {} ---\n",
+                sample, output.metadata.title
+            );
+            println!("{}", output.markdown);
+            continue;
+        }
+
+        let stats = RunStats::from_output(&output);
+        if !args.compare.is_empty() {
+            let mut comparisons = Vec::new();
+            for provider in &args.compare {
+                println!("\nFormatting with {}...", provider);
+                comparisons.push(compare_llm_provider(&output, &options, provider).await?);
+            }
+            save_comparison(
+                &args,
+                &config.filename_template,
+                config.timezone,
+                &output,
+                &comparisons,
+            )?;
+        } else if args.combine.is_some() {
+            combined.push(output);
+        } else if let Some(note_path) = &args.append {
+            append_output(&args, note_path, &output)?;
+        } else {
+            save_output(
+                &args,
+                &config.filename_template,
+                config.timezone,
+                output,
+                &stats,
+            )?;
+        }
+        run_stats.push(stats);
+    }
+
+    if let Some(combine_path) = &args.combine {
+        write_combined(&args, combine_path, &combined)?;
+    }
+
+    if let Some(stats_path) = &args.stats_json {
+        let stats_path = shellexpand::tilde(stats_path).to_string();
+        if args.dry_run {
+            println!("Dry run - would save run statistics to: {}", stats_path);
+        } else {
+            write_atomic(&stats_path, serde_json::to_string_pretty(&run_stats)?)?;
+            println!("Run statistics saved to: {}", stats_path);
+        }
+    }
+
+    if !failures.is_empty() {
+        println!("\n{} of {} videos failed:", failures.len(), args.urls.len());
+        for (url, error) in &failures {
+            // Errors such as a missing yt-dlp carry install help after the first line
+            let reason = error.to_string();
+            println!(
+                "  {} {}: {}",
+                CROSS,
+                url,
+                reason.lines().next().unwrap_or_default()
+            );
+        }
+        let summary = format!("{} of {} videos failed", failures.len(), args.urls.len());
+        // Exit with the failures' code when they all agree, e.g. every
+        // video was unavailable
+        let code = failures[0].1.exit_code();
+        if failures.iter().all(|(_, error)| error.exit_code() == code) {
+            let (_, error) = failures.swap_remove(0);
+            return Err(anyhow::Error::new(error).context(summary));
+        }
+        anyhow::bail!(summary);
+    }
+
+    Ok(())
+}
+
+/// Write one video's markdown (and raw transcript) and print statistics
+fn save_output(
+    args: &Args,
+    filename_template: &str,
+    timezone: Timezone,
+    output: ProcessOutput,
+    stats: &RunStats,
+) -> anyhow::Result<()> {
+    let ProcessOutput {
+        metadata,
+        markdown,
+        raw_transcript,
+        segments,
+        output_dir,
+        ..
+    } = output;
+
+    let file_name = output_file_name(
+        &metadata,
+        filename_template,
+        timezone,
+        args.output_name.as_deref(),
+        args.format,
     );
-    let output_path = std::path::Path::new(&args.out_dir).join(&filename);
+    let output_path = std::path::Path::new(&output_dir).join(&file_name);
+    let output_stem = output_path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().to_string())
+        .unwrap_or(file_name);
 
     if args.dry_run {
         println!("Dry run - would save to: {}", output_path.display());
         println!(
-            "Markdown preview (first 500 chars):\n{}",
-            &markdown[..markdown.len().min(500)]
+            "Output preview (first 500 chars):\n{}",
+            preview(&markdown, 500)
         );
     } else {
         // Save to file
-        fs::write(&output_path, &markdown)?;
+        ensure_output_dir(&output_dir)?;
+        write_atomic(&output_path, &markdown)?;
         println!("Transcription saved to: {}", output_path.display());
     }
 
     // Save raw transcript if requested
     if args.save_raw {
-        let raw_filename = format!(
-            "{}_{}_{}_raw.txt",
-            chrono::Utc::now().format("%Y-%m-%d"),
-            video_id,
-            sanitized_title
-        );
-        let raw_output_path = std::path::Path::new(&args.out_dir).join(&raw_filename);
+        let raw_filename = format!("{}_raw.{}", output_stem, args.save_raw_format.extension());
+        let raw_output_path = std::path::Path::new(&output_dir).join(&raw_filename);
 
         if args.dry_run {
             println!(
@@ -284,37 +1016,541 @@ async fn main() -> anyhow::Result<()> {
                 raw_output_path.display()
             );
         } else {
-            fs::write(&raw_output_path, &raw_transcript)?;
+            let raw_content = match args.save_raw_format {
+                RawFormat::Txt => raw_transcript,
+                format => render_segments(&segments, format),
+            };
+            write_atomic(&raw_output_path, raw_content)?;
             println!("Raw transcript saved to: {}", raw_output_path.display());
         }
     }
 
-    // Calculate formatting statistics
-    let word_count = transcript.split_whitespace().count();
-    let char_count = transcript.chars().count();
-    let paragraph_count = markdown.matches("\n\n").count() + 1;
-
-    println!("Transcription completed using: {}", source);
+    println!("Transcription completed using: {}", stats.source);
     println!("Formatting statistics:");
-    println!("  - Word count: {}", word_count);
-    println!("  - Character count: {}", char_count);
-    println!("  - Paragraph count: {}", paragraph_count);
+    println!("  - Word count: {}", stats.word_count);
+    println!("  - Character count: {}", stats.character_count);
+    println!("  - Paragraph count: {}", stats.paragraph_count);
+
+    Ok(())
+}
+
+/// Write one file per provider for --compare and print a timing summary
+fn save_comparison(
+    args: &Args,
+    filename_template: &str,
+    timezone: Timezone,
+    output: &ProcessOutput,
+    comparisons: &[ProviderComparison],
+) -> anyhow::Result<()> {
+    let file_name = output_file_name(
+        &output.metadata,
+        filename_template,
+        timezone,
+        args.output_name.as_deref(),
+        args.format,
+    );
+    let stem = std::path::Path::new(&file_name)
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().to_string())
+        .unwrap_or_else(|| file_name.clone());
+
+    if !args.dry_run {
+        ensure_output_dir(&output.output_dir)?;
+    }
+
+    println!("\nProvider comparison for: {}", output.metadata.title);
+    for comparison in comparisons {
+        let path = std::path::Path::new(&output.output_dir).join(format!(
+            "{}.{}.{}",
+            stem,
+            comparison.provider,
+            args.format.extension()
+        ));
+        if !args.dry_run {
+            write_atomic(&path, &comparison.markdown)?;
+        }
+
+        let status = if comparison.succeeded {
+            format!("{} {:>7.1}s", CHECKMARK, comparison.seconds)
+        } else {
+            format!("{} failed, standard formatting", CROSS)
+        };
+        let tokens = comparison
+            .usage
+            .map(|usage| usage.to_string())
+            .unwrap_or_else(|| "-".to_string());
+        let cost = comparison
+            .cost
+            .map(|cost| format!("${:.4}", cost))
+            .unwrap_or_else(|| "-".to_string());
+        println!(
+            "  {:<10} {:<32} model: {:<28} tokens: {:<20} cost: {:<9} {}",
+            comparison.provider.to_string(),
+            status,
+            comparison.llm_model.as_deref().unwrap_or("-"),
+            tokens,
+            cost,
+            path.display()
+        );
+    }
+    let costs: Vec<f64> = comparisons.iter().filter_map(|c| c.cost).collect();
+    if !costs.is_empty() {
+        println!(
+            "Estimated total: ${:.4} (list prices; unknown models not included)",
+            costs.iter().sum::<f64>()
+        );
+    }
+    if args.dry_run {
+        println!("Dry run - no files written");
+    }
+
+    Ok(())
+}
+
+/// The first `chars` characters of `text`, for previews
+fn preview(text: &str, chars: usize) -> &str {
+    match text.char_indices().nth(chars) {
+        Some((end, _)) => &text[..end],
+        None => text,
+    }
+}
+
+/// Append one video's transcript as a section at the end of a note
+fn append_output(args: &Args, path: &str, output: &ProcessOutput) -> anyhow::Result<()> {
+    let path = std::path::PathBuf::from(shellexpand::tilde(path).to_string());
+    let section = append_section(output);
+
+    if args.dry_run {
+        println!("Dry run - would append to: {}", path.display());
+        println!(
+            "Section preview (first 500 chars):\n{}",
+            preview(&section, 500)
+        );
+        return Ok(());
+    }
+
+    let note = match fs::read_to_string(&path) {
+        Ok(note) => note,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(e.into()),
+    };
+    if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        fs::create_dir_all(parent)?;
+    }
+    write_atomic(&path, append_to_note(&note, &section))?;
+    println!("Transcript appended to: {}", path.display());
+
+    Ok(())
+}
+
+/// Write every transcript into one document with a table of contents
+fn write_combined(args: &Args, path: &str, outputs: &[ProcessOutput]) -> anyhow::Result<()> {
+    if outputs.is_empty() {
+        println!("No transcripts to combine");
+        return Ok(());
+    }
+
+    let path = std::path::PathBuf::from(shellexpand::tilde(path).to_string());
+    let document = combine_markdown(outputs);
+
+    if args.dry_run {
+        println!(
+            "Dry run - would save {} transcripts to: {}",
+            outputs.len(),
+            path.display()
+        );
+        return Ok(());
+    }
+
+    if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        fs::create_dir_all(parent)?;
+    }
+    write_atomic(&path, document)?;
+    println!(
+        "Combined {} transcripts into: {}",
+        outputs.len(),
+        path.display()
+    );
+
+    Ok(())
+}
+
+/// Create a spinner in the style used across the CLI
+fn spinner(color: &str, ticks: &[&str], message: &'static str) -> ProgressBar {
+    let progress_bar = ProgressBar::new_spinner();
+    progress_bar.set_style(
+        ProgressStyle::default_spinner()
+            .template(&format!("{{spinner:.{}}} {{msg}}", color))
+            .unwrap()
+            .tick_strings(ticks),
+    );
+    progress_bar.set_message(message);
+    progress_bar.enable_steady_tick(std::time::Duration::from_millis(100));
+    progress_bar
+}
+
+/// Drive the CLI spinners from pipeline events
+fn progress_handler() -> EventHandler {
+    const DOWNLOAD_TICKS: &[&str] = &["⣾", "⣽", "⣻", "⢿", "⡿", "⣟", "⣯", "⣷"];
+    const TICKS: &[&str] = &["⠋", "⠙", "⠹", "⠸", "⠼", "⠴", "⠦", "⠧", "⠇", "⠏"];
+
+    let current: Mutex<Option<ProgressBar>> = Mutex::new(None);
+
+    Arc::new(move |event: PipelineEvent| {
+        let mut current = current.lock().unwrap_or_else(|e| e.into_inner());
+
+        match event {
+            PipelineEvent::DownloadStarted => {
+                *current = Some(spinner(
+                    "blue",
+                    DOWNLOAD_TICKS,
+                    "Downloading audio from YouTube...",
+                ));
+            }
+            PipelineEvent::DownloadProgress(percent) => {
+                if let Some(bar) = current.as_ref() {
+                    bar.set_message(format!("Downloading audio from YouTube... {:.0}%", percent));
+                }
+            }
+            PipelineEvent::DownloadFinished => {
+                if let Some(bar) = current.take() {
+                    bar.finish_with_message("Audio download completed");
+                }
+            }
+            PipelineEvent::ConversionStarted => {
+                *current = Some(spinner("yellow", TICKS, "Converting audio format..."));
+            }
+            PipelineEvent::ConversionFinished => {
+                if let Some(bar) = current.take() {
+                    bar.finish_with_message("Audio conversion completed");
+                }
+            }
+            PipelineEvent::TranscribeStarted => {
+                *current = Some(spinner("green", TICKS, "Transcribing audio..."));
+            }
+            PipelineEvent::TranscribeProgress(percent) => {
+                if let Some(bar) = current.as_ref() {
+                    bar.set_message(format!("Transcribing audio... {:.0}%", percent));
+                }
+            }
+            PipelineEvent::TranscribeFinished => {
+                if let Some(bar) = current.take() {
+                    bar.finish_with_message("Transcription completed");
+                }
+            }
+            _ => {}
+        }
+    })
+}
+
+/// Build the output filename for a video: `--output-name` verbatim (with the
+/// format's extension added when it has none), or the configured filename template
+fn output_file_name(
+    metadata: &VideoMetadata,
+    filename_template: &str,
+    timezone: Timezone,
+    output_name: Option<&str>,
+    format: OutputFormat,
+) -> String {
+    match output_name {
+        Some(name) if std::path::Path::new(name).extension().is_some() => name.to_string(),
+        Some(name) => format!("{}.{}", name, format.extension()),
+        None => format!(
+            "{}.{}",
+            expand_filename_template(filename_template, metadata, timezone.now().date_naive()),
+            format.extension()
+        ),
+    }
+}
+
+/// Fetch metadata and print how the video would be processed, without
+/// downloading audio, calling an LLM or writing files
+async fn print_plan(
+    video_id: &str,
+    options: &ProcessOptions,
+    config: &AppConfig,
+    output_name: Option<&str>,
+) -> anyhow::Result<()> {
+    let metadata = fetch_video_metadata(video_id, &options.ytdlp, options.refresh_metadata).await?;
+
+    println!("Transcribing: {}", metadata.title);
+    println!(
+        "Channel: {}",
+        metadata.channel.as_deref().unwrap_or("Unknown")
+    );
+    println!("Video ID: {}", video_id);
+
+    if let Err(e) = check_duration_limits(&metadata, options.min_duration, options.max_duration) {
+        println!("\nPlan: {}", e);
+        return Ok(());
+    }
+
+    let output_dir = expand_output_dir(&options.output_dir, &metadata);
+    let output_path = std::path::Path::new(&output_dir).join(output_file_name(
+        &metadata,
+        &config.filename_template,
+        config.timezone,
+        output_name,
+        options.format,
+    ));
+
+    let planned_source = if options.language.as_deref() == Some(AUTO_LANGUAGE) {
+        format!(
+            "whisper via {} backend (language auto-detection)",
+            options.stt_backend
+        )
+    } else if options.prefer_captions {
+        let requested = options.language.as_deref().unwrap_or("en");
+        match list_caption_tracks(video_id, &options.ytdlp)
+            .await
+            .map(|tracks| tracks.language_for(requested, options.caption_lang_fallback))
+        {
+            Ok(Some(language)) if language == requested => "captions".to_string(),
+            Ok(Some(language)) => {
+                format!("captions in '{}' (no '{}' captions)", language, requested)
+            }
+            _ => format!(
+                "whisper via {} backend (no '{}' captions available)",
+                options.stt_backend, requested
+            ),
+        }
+    } else {
+        format!("whisper via {} backend", options.stt_backend)
+    };
+
+    println!("\nPlan (nothing will be downloaded or written):");
+    println!("  Source: {}", planned_source);
+    println!("  Output file: {}", output_path.display());
+    if options.raw_only {
+        println!("  Formatting: none (raw transcript)");
+    } else if options.use_llm {
+        let provider = options
+            .llm_provider
+            .clone()
+            .unwrap_or_else(|| config.llm.provider.clone());
+        println!(
+            "  Formatting: llm (provider: {}, model: {}, temperature: {})",
+            provider,
+            options
+                .llm_model
+                .as_deref()
+                .unwrap_or_else(|| config.llm.model_for(&provider)),
+            options.llm_temperature.unwrap_or(config.llm_temperature)
+        );
+    } else {
+        println!("  Formatting: standard");
+    }
+
+    Ok(())
+}
+
+/// Check a provider and format a test transcript with it, printing the
+/// elapsed time and token usage. Returns how long formatting took.
+async fn test_llm_provider(
+    http_client: &HttpClient,
+    config: &AppConfig,
+    cred_manager: &CredentialManager,
+    provider_type: &LlmProviderType,
+    model: Option<&str>,
+) -> anyhow::Result<Duration> {
+    println!("Testing provider: {}", provider_type);
+
+    let mut settings = LlmSettings {
+        provider: provider_type.clone(),
+        ..config.llm.clone()
+    };
+    if let Some(model) = model {
+        settings.set_model_for(provider_type, model.to_string());
+    }
+    check_llm_provider(http_client, &settings, cred_manager)
+        .await
+        .map_err(|e| anyhow::anyhow!("Provider check failed: {}", e))?;
+    println!(
+        "{} Provider reachable, model '{}' available",
+        CHECKMARK,
+        settings.model_for(provider_type)
+    );
+
+    let test_transcript =
+        "This is a test transcript to verify the LLM connection is working properly.";
+
+    let started = Instant::now();
+    let output = y2md::format_with_llm(
+        test_transcript,
+        config,
+        http_client,
+        Some(provider_type.clone()),
+        model,
+        None,
+    )
+    .await
+    .map_err(|e| anyhow::anyhow!("Provider test failed: {}", e))?;
+    let elapsed = started.elapsed();
+
+    let result = output.text;
+    println!(
+        "{} Provider test successful in {:.1}s",
+        CHECKMARK,
+        elapsed.as_secs_f64()
+    );
+    match output.usage {
+        Some(usage) => println!("Tokens: {}", usage),
+        None => println!("Tokens: not reported by {}", provider_type),
+    }
+    println!("\nTest output preview:");
+    let shown = preview(&result, 200);
+    println!("{}", shown);
+    if shown.len() < result.len() {
+        println!("...");
+    }
+
+    Ok(elapsed)
+}
+
+/// Tell the user where `llm set-key` put the key
+fn print_key_location(cred_manager: &CredentialManager) {
+    match cred_manager.backend() {
+        CredentialBackend::Keyring => {
+            println!("\nThe API key is securely stored in your system keychain.")
+        }
+        CredentialBackend::File => {
+            if let Some(path) = cred_manager.file_path() {
+                println!(
+                    "\nThe API key is stored in {} (readable only by you).",
+                    path.display()
+                );
+            }
+        }
+    }
+}
+
+/// Print video details and whether captions cover the requested language
+async fn handle_info_command(
+    url: &str,
+    lang: Option<String>,
+    overrides: &ConfigOverrides,
+) -> anyhow::Result<()> {
+    let video_id = validate_youtube_url(url)?;
+    let config = overrides.load()?;
+    let language = lang.unwrap_or_else(|| config.default_language.clone());
+
+    let ytdlp = config.ytdlp_config();
+    let metadata = fetch_video_metadata(&video_id, &ytdlp, false).await?;
+    let tracks = list_caption_tracks(&video_id, &ytdlp).await?;
+
+    let list = |languages: &[String]| {
+        if languages.is_empty() {
+            "none".to_string()
+        } else {
+            languages.join(", ")
+        }
+    };
+
+    println!("Title:          {}", metadata.title);
+    println!(
+        "Channel:        {}",
+        metadata.channel.as_deref().unwrap_or("Unknown")
+    );
+    println!(
+        "Duration:       {}",
+        metadata.duration.as_deref().unwrap_or("Unknown")
+    );
+    println!(
+        "Uploaded:       {}",
+        metadata.upload_date.as_deref().unwrap_or("Unknown")
+    );
+    println!("Video ID:       {}", metadata.video_id);
+    if !metadata.chapters.is_empty() {
+        println!("Chapters:");
+        for chapter in &metadata.chapters {
+            println!("  {}  {}", chapter.timestamp(), chapter.title);
+        }
+    }
+    println!("Manual subs:    {}", list(&tracks.manual));
+    println!("Auto captions:  {}", list(&tracks.automatic));
+
+    if tracks.has_language(&language) {
+        println!("Transcript:     captions available ({})", language);
+    } else {
+        println!(
+            "Transcript:     speech-to-text needed (no {} captions)",
+            language
+        );
+    }
+
+    Ok(())
+}
+
+/// Convert a subtitle file to a markdown transcript
+fn handle_subs_to_md_command(
+    file: &str,
+    output: Option<&str>,
+    compact: bool,
+    overrides: &ConfigOverrides,
+) -> anyhow::Result<()> {
+    let path = std::path::PathBuf::from(shellexpand::tilde(file).to_string());
+    let content = fs::read_to_string(&path)
+        .map_err(|e| anyhow::anyhow!("Failed to read {}: {}", path.display(), e))?;
+    let config = overrides.load()?;
+
+    let format = SubtitleFormat::detect(&content, Some(&path));
+    let options = FormatOptions::new()
+        .compact(compact)
+        .paragraph_length(config.paragraph_length_for(compact))
+        .max_repeated_sentences(config.max_repeated_sentences)
+        .replacements(config.replacements.clone().into_iter().collect())
+        .redactor(Redactor::new(&config.redact, config.redact_pii)?);
+    let body = subtitles_to_markdown(&content, format, &options);
+    if body.trim().is_empty() {
+        anyhow::bail!("No subtitle cues found in {}", path.display());
+    }
+
+    let title = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let markdown = format!("# {}\n\n{}", title, body);
+
+    let output_path = match output {
+        Some(output) => std::path::PathBuf::from(shellexpand::tilde(output).to_string()),
+        None => path.with_extension("md"),
+    };
+    write_atomic(&output_path, markdown)?;
+    println!(
+        "{} Converted {} to: {}",
+        CHECKMARK,
+        path.display(),
+        output_path.display()
+    );
 
     Ok(())
 }
 
 /// Handle configuration commands
-async fn handle_config_command(action: Option<ConfigCommands>) -> anyhow::Result<()> {
+async fn handle_config_command(
+    action: Option<ConfigCommands>,
+    overrides: &ConfigOverrides,
+) -> anyhow::Result<()> {
     match action.unwrap_or(ConfigCommands::Show) {
         ConfigCommands::Show => {
-            let config = AppConfig::load()?;
+            let config = overrides.load()?;
             println!("Current configuration:");
             println!("  Output directory: {}", config.output_dir);
             println!("  Default language: {}", config.default_language);
             println!("  Prefer captions: {}", config.prefer_captions);
+            println!(
+                "  Caption language fallback: {}",
+                config.caption_lang_fallback
+            );
             println!("  Timestamps: {}", config.timestamps);
             println!("  Compact: {}", config.compact);
             println!("  Paragraph length: {}", config.paragraph_length);
+            println!(
+                "  Compact paragraph length: {}",
+                config.compact_paragraph_length
+            );
+            println!("  STT backend: {}", config.stt.backend);
+            println!("  Credential backend: {}", config.credential_backend);
             println!("\nLLM Settings:");
             println!("  Enabled: {}", config.llm.enabled);
             println!("  Default provider: {}", config.llm.provider);
@@ -328,17 +1564,17 @@ async fn handle_config_command(action: Option<ConfigCommands>) -> anyhow::Result
                 println!("  Custom model: {}", config.llm.custom.model);
             }
 
-            let config_path = AppConfig::config_path()?;
+            let config_path = config.file_path()?;
             println!("\nConfiguration file: {}", config_path.display());
             println!("\nTo edit: y2md config edit");
             println!("Or edit directly: {}", config_path.display());
         }
         ConfigCommands::Edit => {
-            let config_path = AppConfig::config_path()?;
+            let config_path = overrides.config_path()?;
 
             // Create config if it doesn't exist
             if !config_path.exists() {
-                let config = AppConfig::default();
+                let config = overrides.defaults()?;
                 config.save()?;
                 println!("Created default configuration file");
             }
@@ -354,35 +1590,39 @@ async fn handle_config_command(action: Option<ConfigCommands>) -> anyhow::Result
             }
 
             // Validate the edited config
-            match AppConfig::load() {
-                Ok(_) => println!("✓ Configuration is valid"),
+            match AppConfig::load_from(&config_path) {
+                Ok(_) => println!("{} Configuration is valid", CHECKMARK),
                 Err(e) => {
-                    eprintln!("✗ Configuration has errors: {}", e);
+                    eprintln!("{} Configuration has errors: {}", CROSS, e);
                     eprintln!("Please fix the errors in: {}", config_path.display());
                     anyhow::bail!("Invalid configuration");
                 }
             }
         }
         ConfigCommands::Path => {
-            let config_path = AppConfig::config_path()?;
+            let config_path = overrides.config_path()?;
             println!("{}", config_path.display());
         }
         ConfigCommands::Reset => {
-            let default_config = AppConfig::default();
+            let default_config = overrides.defaults()?;
             default_config.save()?;
-            println!("✓ Configuration reset to defaults");
-            let config_path = AppConfig::config_path()?;
-            println!("  Location: {}", config_path.display());
+            println!("{} Configuration reset to defaults", CHECKMARK);
+            println!("  Location: {}", default_config.file_path()?.display());
         }
     }
     Ok(())
 }
 
 /// Handle LLM management commands
-async fn handle_llm_command(command: LlmCommands) -> anyhow::Result<()> {
-    let config = AppConfig::load()?;
-    let ollama_manager = OllamaManager::new(Some(config.llm.local.endpoint.clone()));
-    let cred_manager = CredentialManager::new();
+async fn handle_llm_command(
+    command: LlmCommands,
+    overrides: &ConfigOverrides,
+) -> anyhow::Result<()> {
+    let config = overrides.load()?;
+    let http_client = HttpClient::new(&config)?;
+    let ollama_manager =
+        OllamaManager::with_client(config.llm.local.endpoint.clone(), http_client.clone());
+    let cred_manager = CredentialManager::for_config(&config);
 
     match command {
         LlmCommands::List => {
@@ -395,21 +1635,33 @@ async fn handle_llm_command(command: LlmCommands) -> anyhow::Result<()> {
                 );
             }
 
-            match ollama_manager.get_local_models().await {
+            match ollama_manager.get_local_model_details().await {
                 Ok(models) => {
                     if models.is_empty() {
                         println!("No local models found.");
                         println!("\nTo download a model, use: y2md llm pull <model-name>");
                     } else {
                         println!("Local models ({} total):", models.len());
-                        for model in models {
-                            let marker = if model.contains(&config.llm.local.model) {
+                        let name_width = models.iter().map(|m| m.name.len()).max().unwrap_or(0);
+                        for model in &models {
+                            let marker = if same_ollama_model(&model.name, &config.llm.local.model)
+                            {
                                 " (configured)"
                             } else {
                                 ""
                             };
-                            println!("  - {}{}", model, marker);
+                            println!(
+                                "  - {:<width$}  {:>9}  {}{}",
+                                model.name,
+                                model.size_human().unwrap_or_else(|| "?".to_string()),
+                                model.short_digest().unwrap_or("-"),
+                                marker,
+                                width = name_width
+                            );
                         }
+
+                        let total: u64 = models.iter().filter_map(|m| m.size).sum();
+                        println!("\nTotal disk usage: {}", format_bytes(total));
                     }
                 }
                 Err(e) => {
@@ -429,13 +1681,13 @@ async fn handle_llm_command(command: LlmCommands) -> anyhow::Result<()> {
 
             // Check if model already exists
             if ollama_manager.is_model_available(&model).await? {
-                println!("✓ Model '{}' is already available", model);
+                println!("{} Model '{}' is already available", CHECKMARK, model);
                 return Ok(());
             }
 
             println!(
-                "\n⚠️  This will download '{}' from Ollama's library.",
-                model
+                "\n{}  This will download '{}' from Ollama's library.",
+                WARNING, model
             );
             println!("   This may take several minutes. Continue? [y/N]");
 
@@ -448,10 +1700,28 @@ async fn handle_llm_command(command: LlmCommands) -> anyhow::Result<()> {
                 return Ok(());
             }
 
-            println!("\n📥 Downloading model...");
-            match ollama_manager.download_model(&model).await {
+            println!("\n{}Downloading model...", DOWNLOAD);
+            let progress_bar = ProgressBar::new(0);
+            progress_bar.set_style(
+                ProgressStyle::default_bar()
+                    .template("{msg}\n[{bar:40.cyan/blue}] {bytes}/{total_bytes} ({eta})")
+                    .unwrap()
+                    .progress_chars("=> "),
+            );
+            let result = ollama_manager
+                .download_model_with_progress(&model, |progress| {
+                    progress_bar.set_message(progress.status.clone());
+                    if progress.total > 0 {
+                        progress_bar.set_length(progress.total);
+                        progress_bar.set_position(progress.completed);
+                    }
+                })
+                .await;
+            progress_bar.finish_and_clear();
+
+            match result {
                 Ok(()) => {
-                    println!("✓ Model '{}' downloaded successfully", model);
+                    println!("{} Model '{}' downloaded successfully", CHECKMARK, model);
                 }
                 Err(e) => {
                     anyhow::bail!("Download failed: {}", e);
@@ -459,7 +1729,10 @@ async fn handle_llm_command(command: LlmCommands) -> anyhow::Result<()> {
             }
         }
         LlmCommands::Remove { model } => {
-            println!("⚠️  This will permanently remove the model '{}'.", model);
+            println!(
+                "{}  This will permanently remove the model '{}'.",
+                WARNING, model
+            );
             println!("   Continue? [y/N]");
 
             let mut input = String::new();
@@ -473,14 +1746,14 @@ async fn handle_llm_command(command: LlmCommands) -> anyhow::Result<()> {
 
             match ollama_manager.remove_model(&model).await {
                 Ok(()) => {
-                    println!("✓ Model '{}' removed successfully", model);
+                    println!("{} Model '{}' removed successfully", CHECKMARK, model);
                 }
                 Err(e) => {
                     anyhow::bail!("Removal failed: {}", e);
                 }
             }
         }
-        LlmCommands::Test { provider } => {
+        LlmCommands::Models { provider } => {
             let provider_type = if let Some(p) = provider {
                 p.parse::<LlmProviderType>()
                     .map_err(|e| anyhow::anyhow!("Invalid provider: {}", e))?
@@ -488,25 +1761,112 @@ async fn handle_llm_command(command: LlmCommands) -> anyhow::Result<()> {
                 config.llm.provider.clone()
             };
 
-            println!("Testing provider: {}", provider_type);
+            let models =
+                list_provider_models(&http_client, &config.llm, &cred_manager, &provider_type)
+                    .await
+                    .map_err(|e| anyhow::anyhow!("Failed to list models: {}", e))?;
+
+            if models.is_empty() {
+                println!("No models available from {}.", provider_type);
+            } else {
+                let configured = config.llm.model_for(&provider_type);
+                println!(
+                    "Models available from {} ({} total):",
+                    provider_type,
+                    models.len()
+                );
+                for model in &models {
+                    let marker = if model == configured {
+                        " (configured)"
+                    } else {
+                        ""
+                    };
+                    println!("  - {}{}", model, marker);
+                }
+                println!(
+                    "\nUse one for a single run with: y2md <URL> --llm {} --llm-model <name>",
+                    provider_type
+                );
+            }
+        }
+        LlmCommands::Test {
+            provider,
+            model,
+            all,
+        } => {
+            if all && (provider.is_some() || model.is_some()) {
+                return Err(UsageError("--all tests each configured provider with its own model; drop the provider and --model".to_string()).into());
+            }
+
+            let providers = if all {
+                config.llm_provider_chain()
+            } else if let Some(p) = provider {
+                vec![p
+                    .parse::<LlmProviderType>()
+                    .map_err(|e| anyhow::anyhow!("Invalid provider: {}", e))?]
+            } else {
+                vec![config.llm.provider.clone()]
+            };
 
-            let test_transcript =
-                "This is a test transcript to verify the LLM connection is working properly.";
+            let mut results = Vec::new();
+            for provider_type in providers {
+                let result = test_llm_provider(
+                    &http_client,
+                    &config,
+                    &cred_manager,
+                    &provider_type,
+                    model.as_deref(),
+                )
+                .await;
+                match result {
+                    Err(e) if !all => return Err(e),
+                    Err(e) => {
+                        println!("{} {}", CROSS, e);
+                        results.push((provider_type, None));
+                    }
+                    Ok(elapsed) => results.push((provider_type, Some(elapsed))),
+                }
+                if all {
+                    println!();
+                }
+            }
 
-            match y2md::format_with_llm(test_transcript, Some(provider_type)).await {
-                Ok(result) => {
-                    println!("✓ Provider test successful!");
-                    println!("\nTest output preview:");
-                    println!("{}", &result[..result.len().min(200)]);
-                    if result.len() > 200 {
-                        println!("...");
+            if all {
+                println!("Summary:");
+                for (provider_type, elapsed) in &results {
+                    match elapsed {
+                        Some(elapsed) => println!(
+                            "  {} {:<10} {:.1}s",
+                            CHECKMARK,
+                            provider_type.to_string(),
+                            elapsed.as_secs_f64()
+                        ),
+                        None => println!("  {} {:<10} failed", CROSS, provider_type.to_string()),
                     }
                 }
-                Err(e) => {
-                    anyhow::bail!("Provider test failed: {}", e);
+                let failed = results
+                    .iter()
+                    .filter(|(_, elapsed)| elapsed.is_none())
+                    .count();
+                if failed > 0 {
+                    anyhow::bail!("{} of {} providers failed", failed, results.len());
                 }
             }
         }
+        LlmCommands::SetKey { provider } if provider.eq_ignore_ascii_case("deepgram") => {
+            print!("Enter API key for 'deepgram': ");
+            std::io::stdout().flush()?;
+
+            let key = rpassword::read_password()?;
+
+            if key.trim().is_empty() {
+                anyhow::bail!("API key cannot be empty");
+            }
+
+            cred_manager.set_key("deepgram", &key)?;
+            println!("{} API key set for provider 'deepgram'", CHECKMARK);
+            print_key_location(&cred_manager);
+        }
         LlmCommands::SetKey { provider } => {
             let provider_type = provider.parse::<LlmProviderType>().map_err(|e| {
                 anyhow::anyhow!(
@@ -529,8 +1889,8 @@ async fn handle_llm_command(command: LlmCommands) -> anyhow::Result<()> {
             }
 
             cred_manager.set_api_key(&provider_type, &key)?;
-            println!("✓ API key set for provider '{}'", provider);
-            println!("\nThe API key is securely stored in your system keychain.");
+            println!("{} API key set for provider '{}'", CHECKMARK, provider);
+            print_key_location(&cred_manager);
         }
     }
 
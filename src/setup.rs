@@ -1,21 +1,29 @@
+use crate::ui::{BULLET, CHECKMARK, CROSS, INFO, PARTY, WARNING};
 use anyhow::Result;
 use console::style;
 use dialoguer::{Confirm, Input, Select};
 use y2md::{
-    AnthropicConfig, AppConfig, CredentialManager, CustomLlmConfig, DeepSeekConfig,
-    LlmProviderType, LlmSettings, LocalLlmConfig, OllamaManager, OpenAiConfig,
+    normalize_language_code, AnthropicConfig, AppConfig, CredentialManager, CustomLlmConfig,
+    DeepSeekConfig, HttpClient, LlmProviderType, LlmSettings, LocalLlmConfig, OllamaManager,
+    OpenAiConfig, ParagraphLength,
 };
 
 pub struct SetupWizard;
 
 impl SetupWizard {
-    pub async fn run() -> Result<AppConfig> {
-        println!("\n{}", style("Welcome to y2md Setup! 🎉").bold().cyan());
+    pub async fn run(existing: &AppConfig, client: &HttpClient) -> Result<AppConfig> {
+        println!(
+            "\n{}",
+            style(format!("Welcome to y2md Setup!{}", PARTY))
+                .bold()
+                .cyan()
+        );
         println!("{}", style("Let's configure y2md for your needs.\n").dim());
 
         let output_dir = Self::prompt_output_directory()?;
         let default_language = Self::prompt_default_language()?;
-        let llm_settings = Self::prompt_llm_setup().await?;
+        let credentials = CredentialManager::for_config(existing);
+        let llm_settings = Self::prompt_llm_setup(client, &credentials).await?;
 
         let config = AppConfig {
             output_dir,
@@ -24,17 +32,20 @@ impl SetupWizard {
             prefer_captions: true,
             timestamps: false,
             compact: false,
-            paragraph_length: 4,
+            paragraph_length: ParagraphLength::Sentences(4),
+            path: existing.path.clone(),
             ..Default::default()
         };
 
         config.save()?;
 
-        println!("\n{}", style("✓ Setup complete!").bold().green());
         println!(
-            "Configuration saved to: {}",
-            AppConfig::config_path()?.display()
+            "\n{}",
+            style(format!("{} Setup complete!", CHECKMARK))
+                .bold()
+                .green()
         );
+        println!("Configuration saved to: {}", config.file_path()?.display());
         println!("\n{}", style("Next steps:").bold());
         println!("  1. Test your setup: {}", style("y2md doctor").cyan());
         println!(
@@ -70,7 +81,7 @@ impl SetupWizard {
                 .interact()?
             {
                 std::fs::create_dir_all(&expanded)?;
-                println!("  {} Created directory", style("✓").green());
+                println!("  {} Created directory", style(CHECKMARK).green());
             }
         }
 
@@ -116,8 +127,9 @@ impl SetupWizard {
             10 => {
                 let custom: String = Input::new()
                     .with_prompt("Enter language code (e.g., 'ar' for Arabic)")
+                    .validate_with(|input: &String| normalize_language_code(input).map(|_| ()))
                     .interact_text()?;
-                return Ok(custom);
+                return normalize_language_code(&custom).map_err(anyhow::Error::msg);
             }
             _ => "en",
         };
@@ -126,7 +138,10 @@ impl SetupWizard {
         Ok(lang_code.to_string())
     }
 
-    async fn prompt_llm_setup() -> Result<LlmSettings> {
+    async fn prompt_llm_setup(
+        client: &HttpClient,
+        credentials: &CredentialManager,
+    ) -> Result<LlmSettings> {
         println!("{}", style("LLM Formatting (Optional)").bold());
         println!("LLMs can improve transcript readability by fixing grammar,");
         println!("removing filler words, and organizing content.\n");
@@ -149,13 +164,13 @@ impl SetupWizard {
         println!();
 
         match selection {
-            0 => Self::setup_ollama().await,
-            1 => Self::setup_openai().await,
-            2 => Self::setup_anthropic().await,
-            3 => Self::setup_deepseek().await,
-            4 => Self::setup_custom().await,
+            0 => Self::setup_ollama(client).await,
+            1 => Self::setup_openai(client, credentials).await,
+            2 => Self::setup_anthropic(client, credentials).await,
+            3 => Self::setup_deepseek(credentials).await,
+            4 => Self::setup_custom(credentials).await,
             5 => {
-                println!("  {} LLM formatting disabled", style("ℹ").cyan());
+                println!("  {} LLM formatting disabled", style(INFO).cyan());
                 println!(
                     "  You can enable it later with: {}\n",
                     style("y2md setup-llm").cyan()
@@ -169,16 +184,17 @@ impl SetupWizard {
         }
     }
 
-    async fn setup_ollama() -> Result<LlmSettings> {
+    async fn setup_ollama(client: &HttpClient) -> Result<LlmSettings> {
         println!("{}", style("Setting up Ollama (Local LLM)").bold());
         println!();
 
-        let ollama = OllamaManager::new(Some("http://localhost:11434".to_string()));
+        let ollama =
+            OllamaManager::with_client("http://localhost:11434".to_string(), client.clone());
 
         if !ollama.is_available().await {
             println!(
                 "  {} Ollama is not running or not installed.",
-                style("⚠").yellow()
+                style(WARNING).yellow()
             );
             println!();
 
@@ -208,7 +224,7 @@ impl SetupWizard {
                     tokio::time::sleep(tokio::time::Duration::from_secs(2)).await;
 
                     if !ollama.is_available().await {
-                        println!("  {} Could not connect to Ollama", style("✗").red());
+                        println!("  {} Could not connect to Ollama", style(CROSS).red());
                         println!(
                             "  Start Ollama manually, then run: {}",
                             style("y2md init").cyan()
@@ -217,7 +233,7 @@ impl SetupWizard {
                     }
                 }
                 2 => {
-                    println!("  {} Skipping Ollama setup\n", style("ℹ").cyan());
+                    println!("  {} Skipping Ollama setup\n", style(INFO).cyan());
                     return Ok(LlmSettings {
                         enabled: false,
                         ..Default::default()
@@ -227,7 +243,7 @@ impl SetupWizard {
             }
         }
 
-        println!("  {} Ollama is running", style("✓").green());
+        println!("  {} Ollama is running", style(CHECKMARK).green());
         println!();
 
         let models = ollama.get_local_models().await.unwrap_or_default();
@@ -236,11 +252,13 @@ impl SetupWizard {
             println!("No models installed yet.");
             println!("Recommended models:");
             println!(
-                "  • {} - Fast, small download (2GB)",
+                "  {} {} - Fast, small download (2GB)",
+                BULLET,
                 style("llama3.2:3b").cyan()
             );
             println!(
-                "  • {} - High quality (7GB)",
+                "  {} {} - High quality (7GB)",
+                BULLET,
                 style("mistral-nemo:12b").cyan()
             );
             println!();
@@ -262,7 +280,7 @@ impl SetupWizard {
                 2 => {
                     println!(
                         "\n  {} Download a model later with: {}",
-                        style("ℹ").cyan(),
+                        style(INFO).cyan(),
                         style("y2md llm pull <model-name>").cyan()
                     );
                     return Ok(LlmSettings {
@@ -277,7 +295,7 @@ impl SetupWizard {
             println!("  This may take several minutes depending on your connection.\n");
 
             if let Err(e) = ollama.download_model(model_name).await {
-                println!("  {} Failed to download model: {}", style("✗").red(), e);
+                println!("  {} Failed to download model: {}", style(CROSS).red(), e);
                 println!(
                     "  Try downloading manually: {}",
                     style(format!("ollama pull {}", model_name)).cyan()
@@ -288,7 +306,10 @@ impl SetupWizard {
                 });
             }
 
-            println!("\n  {} Model downloaded successfully", style("✓").green());
+            println!(
+                "\n  {} Model downloaded successfully",
+                style(CHECKMARK).green()
+            );
 
             Ok(LlmSettings {
                 enabled: true,
@@ -302,7 +323,7 @@ impl SetupWizard {
         } else {
             println!("Available models:");
             for model in &models {
-                println!("  • {}", style(model).cyan());
+                println!("  {} {}", BULLET, style(model).cyan());
             }
             println!();
 
@@ -314,7 +335,7 @@ impl SetupWizard {
 
             println!(
                 "  {} Using model: {}",
-                style("✓").green(),
+                style(CHECKMARK).green(),
                 style(&models[model_idx]).cyan()
             );
             println!();
@@ -331,7 +352,10 @@ impl SetupWizard {
         }
     }
 
-    async fn setup_openai() -> Result<LlmSettings> {
+    async fn setup_openai(
+        client: &HttpClient,
+        credentials: &CredentialManager,
+    ) -> Result<LlmSettings> {
         println!("{}", style("Setting up OpenAI").bold());
         println!();
         println!("You'll need an OpenAI API key from: https://platform.openai.com/api-keys");
@@ -345,7 +369,6 @@ impl SetupWizard {
 
         println!("\n  Testing API key...");
 
-        let client = reqwest::Client::new();
         let response = client
             .get("https://api.openai.com/v1/models")
             .header("Authorization", format!("Bearer {}", api_key.trim()))
@@ -355,24 +378,27 @@ impl SetupWizard {
 
         match response {
             Ok(resp) if resp.status().is_success() => {
-                println!("  {} API key is valid", style("✓").green());
+                println!("  {} API key is valid", style(CHECKMARK).green());
             }
             Ok(resp) => {
                 println!(
                     "  {} Invalid API key or API error: {}",
-                    style("✗").red(),
+                    style(CROSS).red(),
                     resp.status()
                 );
                 return Err(anyhow::anyhow!("Invalid API key"));
             }
             Err(e) => {
-                println!("  {} Could not connect to OpenAI: {}", style("✗").red(), e);
+                println!(
+                    "  {} Could not connect to OpenAI: {}",
+                    style(CROSS).red(),
+                    e
+                );
                 return Err(anyhow::anyhow!("Connection error"));
             }
         }
 
-        let cred_manager = CredentialManager::new();
-        cred_manager.set_api_key(&LlmProviderType::OpenAI, api_key.trim())?;
+        credentials.set_api_key(&LlmProviderType::OpenAI, api_key.trim())?;
 
         let models = vec![
             "gpt-4o - Latest, best quality",
@@ -395,7 +421,7 @@ impl SetupWizard {
 
         println!(
             "\n  {} OpenAI configured with {}",
-            style("✓").green(),
+            style(CHECKMARK).green(),
             style(model_name).cyan()
         );
         println!();
@@ -411,7 +437,10 @@ impl SetupWizard {
         })
     }
 
-    async fn setup_anthropic() -> Result<LlmSettings> {
+    async fn setup_anthropic(
+        client: &HttpClient,
+        credentials: &CredentialManager,
+    ) -> Result<LlmSettings> {
         println!("{}", style("Setting up Anthropic Claude").bold());
         println!();
         println!("You'll need an Anthropic API key from: https://console.anthropic.com/");
@@ -427,7 +456,6 @@ impl SetupWizard {
 
         println!("\n  Testing API key...");
 
-        let client = reqwest::Client::new();
         let test_body = serde_json::json!({
             "model": "claude-3-haiku-20240307",
             "max_tokens": 10,
@@ -445,12 +473,12 @@ impl SetupWizard {
 
         match response {
             Ok(resp) if resp.status().is_success() => {
-                println!("  {} API key is valid", style("✓").green());
+                println!("  {} API key is valid", style(CHECKMARK).green());
             }
             Ok(resp) => {
                 println!(
                     "  {} Invalid API key or API error: {}",
-                    style("✗").red(),
+                    style(CROSS).red(),
                     resp.status()
                 );
                 return Err(anyhow::anyhow!("Invalid API key"));
@@ -458,15 +486,14 @@ impl SetupWizard {
             Err(e) => {
                 println!(
                     "  {} Could not connect to Anthropic: {}",
-                    style("✗").red(),
+                    style(CROSS).red(),
                     e
                 );
                 return Err(anyhow::anyhow!("Connection error"));
             }
         }
 
-        let cred_manager = CredentialManager::new();
-        cred_manager.set_api_key(&LlmProviderType::Anthropic, api_key.trim())?;
+        credentials.set_api_key(&LlmProviderType::Anthropic, api_key.trim())?;
 
         let models = vec![
             "claude-3-opus-20240229 - Most capable",
@@ -489,7 +516,7 @@ impl SetupWizard {
 
         println!(
             "\n  {} Anthropic configured with {}",
-            style("✓").green(),
+            style(CHECKMARK).green(),
             style(model_name).cyan()
         );
         println!();
@@ -498,14 +525,14 @@ impl SetupWizard {
             enabled: true,
             provider: LlmProviderType::Anthropic,
             anthropic: AnthropicConfig {
-                endpoint: "https://api.anthropic.com/v1".to_string(),
                 model: model_name.to_string(),
+                ..Default::default()
             },
             ..Default::default()
         })
     }
 
-    async fn setup_deepseek() -> Result<LlmSettings> {
+    async fn setup_deepseek(credentials: &CredentialManager) -> Result<LlmSettings> {
         println!("{}", style("Setting up DeepSeek").bold());
         println!();
         println!("You'll need a DeepSeek API key from: https://platform.deepseek.com/");
@@ -519,10 +546,9 @@ impl SetupWizard {
             return Err(anyhow::anyhow!("API key cannot be empty"));
         }
 
-        let cred_manager = CredentialManager::new();
-        cred_manager.set_api_key(&LlmProviderType::DeepSeek, api_key.trim())?;
+        credentials.set_api_key(&LlmProviderType::DeepSeek, api_key.trim())?;
 
-        println!("\n  {} DeepSeek configured", style("✓").green());
+        println!("\n  {} DeepSeek configured", style(CHECKMARK).green());
         println!();
 
         Ok(LlmSettings {
@@ -536,7 +562,7 @@ impl SetupWizard {
         })
     }
 
-    async fn setup_custom() -> Result<LlmSettings> {
+    async fn setup_custom(credentials: &CredentialManager) -> Result<LlmSettings> {
         println!(
             "{}",
             style("Setting up Custom OpenAI-compatible API").bold()
@@ -557,11 +583,10 @@ impl SetupWizard {
         if needs_key {
             let api_key: String = Input::new().with_prompt("API Key").interact_text()?;
 
-            let cred_manager = CredentialManager::new();
-            cred_manager.set_api_key(&LlmProviderType::Custom, api_key.trim())?;
+            credentials.set_api_key(&LlmProviderType::Custom, api_key.trim())?;
         }
 
-        println!("\n  {} Custom API configured", style("✓").green());
+        println!("\n  {} Custom API configured", style(CHECKMARK).green());
         println!();
 
         Ok(LlmSettings {
@@ -0,0 +1,23 @@
+use console::Emoji;
+
+// Status glyphs shared by every command so plain terminals get ASCII fallbacks
+
+pub static CHECKMARK: Emoji = Emoji("✓", "+");
+pub static CROSS: Emoji = Emoji("✗", "x");
+pub static WARNING: Emoji = Emoji("⚠", "!");
+pub static INFO: Emoji = Emoji("ℹ", "i");
+pub static BULLET: Emoji = Emoji("•", "-");
+pub static DOWNLOAD: Emoji = Emoji("📥 ", "");
+pub static PARTY: Emoji = Emoji(" 🎉", "");
+pub static RULE: Emoji = Emoji("━", "-");
+
+/// Turn off colored output when requested with `--no-color` or the
+/// `NO_COLOR` environment variable (https://no-color.org)
+pub fn configure_colors(no_color: bool) {
+    let no_color_env = std::env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty());
+
+    if no_color || no_color_env {
+        console::set_colors_enabled(false);
+        console::set_colors_enabled_stderr(false);
+    }
+}
@@ -0,0 +1,246 @@
+//! End-to-end tests of the yt-dlp integration against a fake `yt-dlp`
+//! script, wired in through `YtDlpConfig::path`.
+#![cfg(unix)]
+
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use y2md::{
+    check_captions_available, download_audio, extract_captions, fetch_video_metadata,
+    list_caption_tracks, EventHandler, PipelineEvent, Y2mdError, YtDlpConfig,
+};
+
+const METADATA: &str = r#"{
+    "title": "Mock Talk",
+    "uploader": "Mock Channel",
+    "duration": 754,
+    "upload_date": "20240131",
+    "formats": [{"acodec": "opus", "vcodec": "none"}],
+    "subtitles": {},
+    "automatic_captions": {
+        "en": [{"ext": "vtt", "url": "https://example.com/captions?lang=en"}],
+        "fr": [{"ext": "vtt", "url": "https://example.com/captions?lang=fr"}],
+        "de": [{"ext": "vtt", "url": "https://example.com/captions?lang=en&tlang=de"}]
+    }
+}"#;
+
+const CAPTIONS: &str = "1
+00:00:00,000 --> 00:00:02,500
+hello and welcome to the talk
+
+2
+00:00:02,500 --> 00:00:05,000
+today we look at testing
+";
+
+/// A fake yt-dlp that answers `--dump-json` with canned metadata, writes a
+/// canned SRT for caption downloads and a small file for audio downloads.
+/// [`MockYtDlp::failing`] builds one that fails every call instead.
+struct MockYtDlp {
+    dir: tempfile::TempDir,
+}
+
+impl MockYtDlp {
+    fn new() -> Self {
+        Self::with_script(
+            r#"
+mode=""
+output=""
+lang="en"
+id=""
+previous=""
+for arg in "$@"; do
+    case "$previous" in
+        -o) output="$arg" ;;
+        --sub-lang) lang="$arg" ;;
+    esac
+    case "$arg" in
+        --dump-json) mode="metadata" ;;
+        --write-sub) mode="captions" ;;
+        -x) mode="audio" ;;
+        *watch?v=*) id="${arg##*v=}" ;;
+    esac
+    previous="$arg"
+done
+output=$(printf '%s' "$output" | sed "s/%(id)s/$id/")
+
+case "$mode" in
+    metadata) cat "$MOCK_DIR/metadata.json" ;;
+    captions) cp "$MOCK_DIR/captions.srt" "$output.$lang.srt" ;;
+    audio)
+        echo "[download]  50.0% of 1.00MiB"
+        echo "[download] 100.0% of 1.00MiB"
+        printf 'RIFF' > "$output.wav"
+        ;;
+esac
+"#,
+        )
+    }
+
+    fn failing(stderr: &str) -> Self {
+        Self::with_script(&format!("echo '{}' >&2\nexit 1\n", stderr))
+    }
+
+    fn with_script(body: &str) -> Self {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("metadata.json"), METADATA).unwrap();
+        std::fs::write(dir.path().join("captions.srt"), CAPTIONS).unwrap();
+
+        let script = dir.path().join("yt-dlp");
+        std::fs::write(
+            &script,
+            format!("#!/bin/sh\nMOCK_DIR='{}'\n{}", dir.path().display(), body),
+        )
+        .unwrap();
+        std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        Self { dir }
+    }
+
+    fn config(&self) -> YtDlpConfig {
+        YtDlpConfig {
+            path: Some(self.script().display().to_string()),
+            // Keep the metadata cache out of the real user cache directory
+            cache_dir: Some(self.dir.path().join("cache")),
+            ..YtDlpConfig::default()
+        }
+    }
+
+    fn script(&self) -> PathBuf {
+        self.dir.path().join("yt-dlp")
+    }
+
+    fn path(&self) -> &Path {
+        self.dir.path()
+    }
+}
+
+#[tokio::test]
+async fn fetches_metadata() {
+    let mock = MockYtDlp::new();
+    let metadata = fetch_video_metadata("mockmeta001", &mock.config(), true)
+        .await
+        .unwrap();
+
+    assert_eq!(metadata.title, "Mock Talk");
+    assert_eq!(metadata.channel.as_deref(), Some("Mock Channel"));
+    assert_eq!(metadata.duration_seconds, Some(754));
+    assert_eq!(metadata.upload_date.as_deref(), Some("2024-01-31"));
+    assert_eq!(metadata.has_audio, Some(true));
+}
+
+#[tokio::test]
+async fn lists_caption_tracks() {
+    let mock = MockYtDlp::new();
+    let ytdlp = mock.config();
+
+    let tracks = list_caption_tracks("mocktracks1", &ytdlp).await.unwrap();
+    assert_eq!(tracks.automatic, vec!["en".to_string(), "fr".to_string()]);
+    assert!(tracks.manual.is_empty());
+
+    assert!(check_captions_available("mocktracks1", Some("fr"), &ytdlp)
+        .await
+        .unwrap());
+    // Machine-translated tracks do not count
+    assert!(!check_captions_available("mocktracks1", Some("de"), &ytdlp)
+        .await
+        .unwrap());
+}
+
+#[tokio::test]
+async fn extracts_captions() {
+    let mock = MockYtDlp::new();
+    let (formatted, raw, segments) = extract_captions(
+        "mockcapts01",
+        Some("en"),
+        false,
+        false,
+        None,
+        &mock.config(),
+    )
+    .await
+    .unwrap();
+
+    assert!(raw.contains("hello and welcome to the talk"));
+    assert!(formatted.starts_with("Hello and welcome"));
+    assert_eq!(segments.len(), 2);
+    assert_eq!(segments[1].start, 2.5);
+    // The caption file is removed from the temporary directory once read
+    let leftovers: Vec<_> = std::fs::read_dir(std::env::temp_dir())
+        .unwrap()
+        .flatten()
+        .map(|entry| entry.file_name().to_string_lossy().into_owned())
+        .filter(|name| name.starts_with(".y2md-") && name.contains("mockcapts01"))
+        .collect();
+    assert!(leftovers.is_empty(), "{:?}", leftovers);
+}
+
+#[tokio::test]
+async fn downloads_audio_with_progress() {
+    let mock = MockYtDlp::new();
+    let output_dir = mock.path().join("out");
+
+    let progress = Arc::new(Mutex::new(Vec::new()));
+    let recorded = progress.clone();
+    let handler: EventHandler = Arc::new(move |event: PipelineEvent| {
+        if let PipelineEvent::DownloadProgress(percent) = event {
+            recorded.lock().unwrap().push(percent);
+        }
+    });
+
+    let audio_path = download_audio(
+        "mockaudio01",
+        output_dir.to_str().unwrap(),
+        &mock.config(),
+        Some(&handler),
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(audio_path, output_dir.join("mockaudio01_audio.wav"));
+    assert_eq!(*progress.lock().unwrap(), vec![50.0, 100.0]);
+
+    // A second run reuses the downloaded file without calling yt-dlp
+    std::fs::remove_file(mock.script()).unwrap();
+    let cached = download_audio(
+        "mockaudio01",
+        output_dir.to_str().unwrap(),
+        &mock.config(),
+        None,
+    )
+    .await
+    .unwrap();
+    assert_eq!(cached, audio_path);
+}
+
+#[tokio::test]
+async fn maps_ytdlp_failures() {
+    let mock =
+        MockYtDlp::failing("ERROR: [youtube] mockprivat1: Private video. Sign in for access");
+    let error = fetch_video_metadata("mockprivat1", &mock.config(), true)
+        .await
+        .unwrap_err();
+    assert!(matches!(error, Y2mdError::PrivateVideo(_)), "{:?}", error);
+
+    let error = extract_captions(
+        "mockprivat1",
+        Some("en"),
+        false,
+        false,
+        None,
+        &mock.config(),
+    )
+    .await
+    .unwrap_err();
+    assert!(matches!(error, Y2mdError::PrivateVideo(_)), "{:?}", error);
+
+    let missing = YtDlpConfig {
+        path: Some(mock.path().join("no-such-yt-dlp").display().to_string()),
+        ..mock.config()
+    };
+    let error = fetch_video_metadata("mockmissin1", &missing, true)
+        .await
+        .unwrap_err();
+    assert!(matches!(error, Y2mdError::YtDlpNotFound), "{:?}", error);
+}